@@ -0,0 +1,90 @@
+extern crate rotor;
+extern crate rotor_http;
+
+use std::sync::Arc;
+use std::time::Duration;
+
+use rotor::{Scope, Time};
+use rotor_http::server::{RecvMode, Server, Head, Response, Fsm};
+use rotor_http::server::router::{Router, Routes, Route};
+use rotor::mio::tcp::TcpListener;
+
+
+#[derive(Debug, Clone)]
+enum Page {
+    Index,
+    User,
+    Static,
+}
+
+struct Handler(String);
+
+fn send_string(res: &mut Response, data: &[u8]) {
+    res.status(200, "OK");
+    res.add_length(data.len() as u64).unwrap();
+    res.done_headers().unwrap();
+    res.write_body(data);
+    res.done();
+}
+
+impl Server for Handler {
+    type Seed = Route<Page>;
+    type Context = ();
+    fn headers_received(route: Route<Page>, _head: Head, _res: &mut Response,
+        scope: &mut Scope<()>)
+        -> Option<(Self, RecvMode, Time)>
+    {
+        let text = match route.seed {
+            Page::Index => "Hello World!".to_string(),
+            Page::User => format!("Hello {}!",
+                route.params.get("name").unwrap()),
+            Page::Static => format!("No file {:?} here",
+                route.params.get("path").unwrap()),
+        };
+        Some((Handler(text), RecvMode::Buffered(1024),
+            scope.now() + Duration::new(10, 0)))
+    }
+    fn request_received(self, _data: &[u8], res: &mut Response,
+        _scope: &mut Scope<()>)
+        -> Option<Self>
+    {
+        send_string(res, self.0.as_bytes());
+        None
+    }
+    fn request_chunk(self, _chunk: &[u8], _response: &mut Response,
+        _scope: &mut Scope<()>)
+        -> Option<Self>
+    {
+        unreachable!();
+    }
+    fn request_end(self, _response: &mut Response, _scope: &mut Scope<()>)
+        -> Option<Self>
+    {
+        unreachable!();
+    }
+    fn timeout(self, _response: &mut Response, _scope: &mut Scope<()>)
+        -> Option<(Self, Time)>
+    {
+        unimplemented!();
+    }
+    fn wakeup(self, _response: &mut Response, _scope: &mut Scope<()>)
+        -> Option<Self>
+    {
+        unimplemented!();
+    }
+}
+
+fn main() {
+    let event_loop = rotor::Loop::new(&rotor::Config::new()).unwrap();
+    let mut loop_inst = event_loop.instantiate(());
+    let mut routes = Routes::new(Page::Index);
+    routes.get("/", Page::Index)
+          .get("/users/:name", Page::User)
+          .get("/static/*path", Page::Static);
+    let routes = Arc::new(routes);
+    let lst = TcpListener::bind(&"127.0.0.1:3000".parse().unwrap()).unwrap();
+    loop_inst.add_machine_with(|scope| {
+        Fsm::<Router<Handler>, _>::new(lst, routes, scope)
+    }).unwrap();
+    loop_inst.run().unwrap();
+}
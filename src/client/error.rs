@@ -1,3 +1,5 @@
+use std::io;
+
 use httparse;
 
 
@@ -19,5 +21,28 @@ quick_error!{
             from()
             description("error parsing chunk size")
         }
+        ResponseTooLarge(size: u64, limit: usize) {
+            description("response body is larger than allowed")
+            display("response body is at least {} bytes but maximum is {}",
+                    size, limit)
+        }
+        HeadersAreTooLarge {
+            description("response headers are larger than allowed")
+        }
+        InvalidResponse {
+            description("invalid response headers")
+        }
+        StreamReset(code: u32) {
+            description("HTTP/2 stream is reset by the server")
+            display("HTTP/2 stream is reset by the server, error code {}",
+                    code)
+        }
+        ConnectionClosed {
+            description("connection is closed before response is received")
+        }
+        Tls(err: io::Error) {
+            description("TLS error")
+            display("TLS error: {}", err)
+        }
     }
 }
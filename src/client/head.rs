@@ -1,4 +1,6 @@
 use httparse;
+use headers::{Fields, Values, List, MediaType, MediaTypes};
+use headers::{Authorization, HeaderMap, HeaderLookup};
 use version::Version;
 
 
@@ -7,6 +9,9 @@ pub enum BodyKind {
     Fixed(u64),
     Chunked,
     Eof,
+    /// A `101 Switching Protocols` response, the rest of the connection
+    /// belongs to another protocol
+    Upgrade,
 }
 
 pub struct Head<'a> {
@@ -17,3 +22,127 @@ pub struct Head<'a> {
     pub body_kind: BodyKind,
     pub close: bool,
 }
+
+/// An owned copy of the response head
+///
+/// Returned by `Head::to_owned()`, so you can store it in the state
+/// machine. All headers are kept in a single buffer and are accessed with
+/// the same methods as in `Head`.
+#[derive(Debug, Clone)]
+pub struct OwnedHead {
+    pub version: Version,
+    pub code: u16,
+    pub reason: String,
+    pub headers: HeaderMap,
+    pub body_kind: BodyKind,
+    pub close: bool,
+}
+
+impl<'a> Head<'a> {
+    /// Returns the value of the first header with the name
+    ///
+    /// Header names are case-insensitive in all of the methods, these are
+    /// the same as in `HeaderLookup`.
+    pub fn get(&self, name: &str) -> Option<&'a [u8]> {
+        HeaderLookup::get(self, name)
+    }
+    /// Iterates over values of all headers with the name
+    pub fn get_all<'n>(&self, name: &'n str) -> Values<'a, 'n> {
+        HeaderLookup::get_all(self, name)
+    }
+    /// Returns true if there is a header with the name
+    pub fn contains(&self, name: &str) -> bool {
+        HeaderLookup::contains(self, name)
+    }
+    /// Iterates over elements of comma-separated lists of all headers with
+    /// the name
+    pub fn get_list<'n>(&self, name: &'n str) -> List<'a, 'n> {
+        HeaderLookup::get_list(self, name)
+    }
+    /// The value of the `Host` header
+    pub fn host(&self) -> Option<&'a str> {
+        HeaderLookup::host(self)
+    }
+    /// The value of the `Content-Type` header
+    pub fn content_type(&self) -> Option<MediaType<'a>> {
+        HeaderLookup::content_type(self)
+    }
+    /// The value of the `User-Agent` header
+    pub fn user_agent(&self) -> Option<&'a str> {
+        HeaderLookup::user_agent(self)
+    }
+    /// Iterates over media ranges of all `Accept` headers
+    pub fn accept(&self) -> MediaTypes<'a> {
+        HeaderLookup::accept(self)
+    }
+    /// The value of the `Authorization` header
+    pub fn authorization(&self) -> Option<Authorization<'a>> {
+        HeaderLookup::authorization(self)
+    }
+    /// Copies the head, so it can be stored in the state machine
+    pub fn to_owned(&self) -> OwnedHead {
+        OwnedHead {
+            version: self.version,
+            code: self.code,
+            reason: self.reason.to_string(),
+            headers: HeaderMap::new(self.headers),
+            body_kind: self.body_kind,
+            close: self.close,
+        }
+    }
+}
+
+impl OwnedHead {
+    /// Returns the value of the first header with the name
+    ///
+    /// Header names are case-insensitive in all of the methods, these are
+    /// the same as in `HeaderLookup`.
+    pub fn get(&self, name: &str) -> Option<&[u8]> {
+        HeaderLookup::get(self, name)
+    }
+    /// Iterates over values of all headers with the name
+    pub fn get_all<'a, 'n>(&'a self, name: &'n str) -> Values<'a, 'n> {
+        HeaderLookup::get_all(self, name)
+    }
+    /// Returns true if there is a header with the name
+    pub fn contains(&self, name: &str) -> bool {
+        HeaderLookup::contains(self, name)
+    }
+    /// Iterates over elements of comma-separated lists of all headers with
+    /// the name
+    pub fn get_list<'a, 'n>(&'a self, name: &'n str) -> List<'a, 'n> {
+        HeaderLookup::get_list(self, name)
+    }
+    /// The value of the `Host` header
+    pub fn host(&self) -> Option<&str> {
+        HeaderLookup::host(self)
+    }
+    /// The value of the `Content-Type` header
+    pub fn content_type(&self) -> Option<MediaType> {
+        HeaderLookup::content_type(self)
+    }
+    /// The value of the `User-Agent` header
+    pub fn user_agent(&self) -> Option<&str> {
+        HeaderLookup::user_agent(self)
+    }
+    /// Iterates over media ranges of all `Accept` headers
+    pub fn accept(&self) -> MediaTypes {
+        HeaderLookup::accept(self)
+    }
+    /// The value of the `Authorization` header
+    pub fn authorization(&self) -> Option<Authorization> {
+        HeaderLookup::authorization(self)
+    }
+}
+
+impl<'a, 'b> HeaderLookup<'a> for &'b Head<'a> {
+    fn fields(self) -> Fields<'a> {
+        Fields::new(self.headers)
+    }
+}
+
+impl<'a> HeaderLookup<'a> for &'a OwnedHead {
+    fn fields(self) -> Fields<'a> {
+        self.headers.iter()
+    }
+}
@@ -0,0 +1,320 @@
+//! HTTP/2 client implementation
+//!
+//! The HTTP/2 connection is driven by the same `Parser` (and the same
+//! `Client` and `Requester` handlers) as HTTP/1. It's enabled by returning
+//! settings from `Client::http2_settings()`, in which case the connection
+//! preface is sent right after the connection is established (i.e. the
+//! server must support HTTP/2 over cleartext with "prior knowledge").
+//!
+//! Unlike HTTP/1, the `Client` may start multiple requests concurrently:
+//! `connection_idle()` is called again after each `Task::Request` as long
+//! as the number of active streams is below the limit, and then each time
+//! a stream is finished. Each `Requester` gets its own stream. Requests are
+//! written the usual way, rotor-http converts them into HEADERS and DATA
+//! frames. So for the handler the only visible differences are:
+//!
+//! 1. `head.version` is `Version::Http20` and `head.reason` is empty
+//! 2. Header names are lowercase
+//! 3. The response body of `Progressive` mode is passed as it is received
+//!    in DATA frames (the size hint is ignored)
+//! 4. `Task::Close` aborts requests which are still in progress, their
+//!    `bad_response()` is called with `ResponseError::ConnectionClosed`
+use std::cmp::min;
+use std::collections::BTreeMap;
+use std::fmt;
+use std::mem;
+
+use rotor::{Scope, Time};
+use rotor_stream::{Buf, Expectation, StreamSocket, Transport};
+
+use hpack::HeaderList;
+use http2::frame::{self, ErrorCode};
+use http2::{Session, Event, update_window};
+use super::{Requester, ResponseError};
+use self::stream::Stream;
+
+pub use http2::Settings;
+
+mod stream;
+
+
+/// Number of concurrent streams if the server doesn't limit them
+pub const MAX_CONCURRENT_STREAMS: usize = 100;
+
+/// The largest stream identifier
+const MAX_STREAM_ID: u32 = 0x7FFF_FFFF;
+
+/// A HTTP/2 connection which multiplexes `Requester` state machines
+pub struct Connection<M: Requester> {
+    session: Session,
+    streams: BTreeMap<u32, Stream<M>>,
+    next_stream_id: u32,
+    /// The `:scheme` of requests
+    scheme: &'static str,
+    /// `Client` should be asked for a request when there is a free slot
+    wants_request: bool,
+    /// Deadline returned by the `Client` in `Task::Sleep`
+    deadline: Time,
+    /// GOAWAY is received, no new streams can be started
+    goaway_received: bool,
+}
+
+impl<M: Requester> Connection<M> {
+    /// Creates a connection, the preface is written into `out` immediately
+    ///
+    /// Server push is not supported, so it's always disabled in `settings`
+    pub fn new(mut settings: Settings, scheme: &'static str, out: &mut Buf,
+        deadline: Time)
+        -> Connection<M>
+    {
+        settings.enable_push = false;
+        Connection {
+            session: Session::client(settings, out),
+            streams: BTreeMap::new(),
+            next_stream_id: 1,
+            scheme: scheme,
+            wants_request: true,
+            deadline: deadline,
+            goaway_received: false,
+        }
+    }
+    /// Returns true if a new request may be started
+    pub fn is_idle(&self) -> bool {
+        let limit = self.session.peer.max_concurrent_streams
+            .map(|x| min(x as usize, MAX_CONCURRENT_STREAMS))
+            .unwrap_or(MAX_CONCURRENT_STREAMS);
+        !self.session.closing && !self.goaway_received &&
+            self.next_stream_id <= MAX_STREAM_ID &&
+            self.streams.len() < limit
+    }
+    /// Returns true if `Client::connection_idle()` should be called
+    pub fn needs_request(&self) -> bool {
+        self.wants_request && self.is_idle()
+    }
+    /// Starts a new stream for the request
+    pub fn start(&mut self, machine: M, out: &mut Buf,
+        scope: &mut Scope<M::Context>)
+    {
+        let id = self.next_stream_id;
+        let window = self.session.peer.initial_window_size as i64;
+        if let Some(stream) = Stream::new(id, machine, window, scope) {
+            self.next_stream_id += 2;
+            self.streams.insert(id, stream);
+        }
+        self.wants_request = true;
+        self.flush(out);
+    }
+    /// `Client` has nothing to do until the `deadline`
+    pub fn sleep(&mut self, deadline: Time) {
+        self.wants_request = false;
+        self.deadline = deadline;
+    }
+    /// Returns the deadline of the `Client`
+    pub fn deadline(&self) -> Time {
+        self.deadline
+    }
+    /// Connection is closing, only waiting for the buffer to be flushed
+    pub fn is_closing(&self) -> bool {
+        self.session.closing
+    }
+    pub fn intent(&self, input: &Buf) -> (Expectation, Time)
+    {
+        if self.session.closing {
+            // GOAWAY is sent, connection is closed when it's flushed
+            return (Expectation::Flush(0), self.deadline);
+        }
+        let deadline = self.streams.values().map(|s| s.deadline())
+            .fold(self.deadline, min);
+        (Expectation::Bytes(self.session.bytes_needed(&input[..])), deadline)
+    }
+    /// Converts requests into frames and removes finished streams
+    fn flush(&mut self, out: &mut Buf) {
+        let max_frame_size = self.session.peer.max_frame_size as usize;
+        let mut done = Vec::new();
+        for (&id, stream) in self.streams.iter_mut() {
+            stream.flush(out, self.scheme, &mut self.session.encoder,
+                         &mut self.session.send_window, max_frame_size);
+            if stream.is_done() {
+                if stream.is_remote_open() {
+                    // Handler is not interested in the rest of response
+                    frame::write_rst_stream(out, id, ErrorCode::Cancel);
+                }
+                done.push(id);
+            }
+        }
+        for id in done {
+            self.streams.remove(&id);
+            self.wants_request = true;
+        }
+        if self.goaway_received && self.streams.len() == 0 &&
+            !self.session.closing
+        {
+            self.session.go_away(out, ErrorCode::NoError);
+        }
+    }
+    pub fn bytes_read<S: StreamSocket>(&mut self,
+        transport: &mut Transport<S>, scope: &mut Scope<M::Context>)
+    {
+        let (inp, out) = transport.buffers();
+        while !self.session.closing {
+            let result = match self.session.read_frame(&inp[..], out) {
+                Ok(Some((bytes, event))) => {
+                    let result = self.event(event, out, scope);
+                    inp.consume(bytes);
+                    result
+                }
+                Ok(None) => break,
+                Err(code) => Err(code),
+            };
+            if let Err(code) = result {
+                self.session.go_away(out, code);
+                self.end(&ResponseError::ConnectionClosed, scope);
+            }
+        }
+        self.flush(out);
+    }
+    /// Calls `timeout()` of the streams which deadline has passed
+    pub fn timeout(&mut self, out: &mut Buf, scope: &mut Scope<M::Context>)
+    {
+        let now = scope.now();
+        for stream in self.streams.values_mut() {
+            if stream.deadline() <= now && !stream.timeout(scope) {
+                stream.reset(out, ErrorCode::Cancel);
+            }
+        }
+        self.flush(out);
+    }
+    pub fn wakeup(&mut self, out: &mut Buf, scope: &mut Scope<M::Context>) {
+        for stream in self.streams.values_mut() {
+            if !stream.wakeup(scope) {
+                stream.reset(out, ErrorCode::Cancel);
+            }
+        }
+        self.flush(out);
+    }
+    /// Connection is closed, notifies handlers of all active streams
+    pub fn end(&mut self, err: &ResponseError,
+        scope: &mut Scope<M::Context>)
+    {
+        for (_, mut stream) in mem::replace(&mut self.streams,
+                                            BTreeMap::new())
+        {
+            stream.abort(err, scope);
+        }
+    }
+    fn event(&mut self, event: Event, out: &mut Buf,
+        scope: &mut Scope<M::Context>)
+        -> Result<(), ErrorCode>
+    {
+        match event {
+            Event::Handled => Ok(()),
+            Event::Headers { id, end_stream, list, too_large } => {
+                self.headers(id, end_stream, &list, too_large, out, scope)
+            }
+            Event::Data { id, data, length, end_stream } => {
+                try!(self.check_id(id));
+                let result = match self.streams.get_mut(&id) {
+                    Some(ref mut stream) if stream.is_remote_open() => {
+                        if length > 0 && !end_stream {
+                            frame::write_window_update(out, id,
+                                                       length as u32);
+                        }
+                        stream.data(data, end_stream, scope)
+                    }
+                    _ => Err(ErrorCode::StreamClosed),
+                };
+                if let Err(code) = result {
+                    self.reset(out, id, code);
+                }
+                Ok(())
+            }
+            Event::Reset { id, code } => {
+                try!(self.check_id(id));
+                if let Some(mut stream) = self.streams.remove(&id) {
+                    stream.abort(&ResponseError::StreamReset(code), scope);
+                    self.wants_request = true;
+                }
+                Ok(())
+            }
+            Event::WindowUpdate { id, increment } => {
+                let result = match self.streams.get_mut(&id) {
+                    Some(stream) => {
+                        update_window(&mut stream.send_window, increment)
+                    }
+                    // Frames for closed streams are ignored
+                    None => Ok(()),
+                };
+                if let Err(code) = result {
+                    self.reset(out, id, code);
+                }
+                Ok(())
+            }
+            Event::WindowDelta(delta) => {
+                for stream in self.streams.values_mut() {
+                    stream.send_window += delta;
+                }
+                Ok(())
+            }
+            Event::GoAway { last_id } => {
+                self.goaway_received = true;
+                // Streams above `last_id` are not processed by the server
+                // and may be safely retried on another connection
+                let ids = self.streams.keys().cloned()
+                    .filter(|&id| id > last_id).collect::<Vec<_>>();
+                for id in ids {
+                    if let Some(mut stream) = self.streams.remove(&id) {
+                        stream.abort(&ResponseError::ConnectionClosed, scope);
+                    }
+                }
+                Ok(())
+            }
+        }
+    }
+    /// Checks that the stream id is one of ours
+    fn check_id(&self, id: u32) -> Result<(), ErrorCode> {
+        if id % 2 == 0 || id >= self.next_stream_id {
+            Err(ErrorCode::ProtocolError)
+        } else {
+            Ok(())
+        }
+    }
+    fn reset(&mut self, out: &mut Buf, id: u32, code: ErrorCode) {
+        frame::write_rst_stream(out, id, code);
+        if let Some(mut stream) = self.streams.remove(&id) {
+            stream.close();
+            self.wants_request = true;
+        }
+    }
+    /// Complete header block is received
+    fn headers(&mut self, id: u32, end_stream: bool, list: &HeaderList,
+        too_large: bool, out: &mut Buf, scope: &mut Scope<M::Context>)
+        -> Result<(), ErrorCode>
+    {
+        try!(self.check_id(id));
+        let result = match self.streams.get_mut(&id) {
+            Some(ref mut stream) if too_large => {
+                stream.abort(&ResponseError::HeadersAreTooLarge, scope);
+                Err(ErrorCode::Cancel)
+            }
+            Some(ref mut stream) if stream.is_remote_open() => {
+                stream.headers(list, end_stream, scope)
+            }
+            _ => Err(ErrorCode::StreamClosed),
+        };
+        if let Err(code) = result {
+            self.reset(out, id, code);
+        }
+        Ok(())
+    }
+}
+
+impl<M: Requester> fmt::Debug for Connection<M> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Connection")
+            .field("session", &self.session)
+            .field("streams", &self.streams)
+            .field("next_stream_id", &self.next_stream_id)
+            .field("goaway_received", &self.goaway_received)
+            .finish()
+    }
+}
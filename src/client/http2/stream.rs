@@ -0,0 +1,432 @@
+use std::fmt;
+use std::mem;
+use std::str::from_utf8;
+
+use httparse::Header;
+use rotor::{Scope, Time};
+use rotor_stream::Buf;
+
+use hpack::{Encoder, HeaderList};
+use http2::frame::{self, ErrorCode};
+use http2::convert::{take_head, is_connection_specific};
+use http2::convert::{BodyProgress, BodySender};
+use headers;
+use message::MessageState;
+use recvmode::RecvMode;
+use version::Version;
+use super::super::{Requester, Request, Head, BodyKind, ResponseError};
+use super::super::request::state;
+
+
+/// How the response body is delivered to the handler
+#[derive(Debug)]
+enum ResponseBody {
+    /// Response headers are not received yet
+    Headers,
+    /// Body is collected into the vector up to the limit
+    Buffered(usize, Vec<u8>),
+    Progressive,
+    /// Handler has finished or has failed, the body is discarded
+    Ignored,
+}
+
+/// Progress of converting the request into frames
+#[derive(Debug)]
+enum Output {
+    Head,
+    Body(BodySender),
+    /// END_STREAM or RST_STREAM is sent
+    Closed,
+}
+
+/// A single request-response exchange on the HTTP/2 connection
+///
+/// The `Request` writes the message into the stream's own buffer in
+/// HTTP/1 format, which is converted into frames by `flush()`.
+pub struct Stream<M: Requester> {
+    id: u32,
+    machine: Option<M>,
+    request: MessageState,
+    buf: Buf,
+    body: ResponseBody,
+    output: Output,
+    /// END_STREAM is received from the peer
+    remote_closed: bool,
+    is_head: bool,
+    pub send_window: i64,
+    deadline: Time,
+}
+
+impl<M: Requester> Stream<M> {
+    /// Calls `prepare_request()` of the handler
+    ///
+    /// Returns `None` if the handler has refused to send the request, no
+    /// frames are sent for the stream in this case.
+    pub fn new(id: u32, machine: M, send_window: i64,
+        scope: &mut Scope<M::Context>)
+        -> Option<Stream<M>>
+    {
+        let mut buf = Buf::new();
+        let (machine, is_head, request) = {
+            let mut req = Request::new(&mut buf);
+            let machine = machine.prepare_request(&mut req, scope);
+            let is_head = req.1.unwrap_or(false);
+            (machine, is_head, state(req))
+        };
+        machine.map(|m| Stream {
+            id: id,
+            deadline: scope.now() + m.byte_timeout(scope),
+            machine: Some(m),
+            request: request,
+            buf: buf,
+            body: ResponseBody::Headers,
+            output: Output::Head,
+            remote_closed: false,
+            is_head: is_head,
+            send_window: send_window,
+        })
+    }
+    fn with_request<F, R>(&mut self, f: F) -> R
+        where F: FnOnce(&mut Request) -> R
+    {
+        let mut req: Request = mem::replace(&mut self.request,
+            MessageState::Done).with(&mut self.buf);
+        let result = f(&mut req);
+        self.request = state(req);
+        result
+    }
+    /// Calls the handler, returns `false` if stream should be reset
+    fn call<F>(&mut self, f: F) -> bool
+        where F: FnOnce(M, &mut Request) -> Option<M>
+    {
+        if let Some(m) = self.machine.take() {
+            self.machine = self.with_request(|req| f(m, req));
+            if self.machine.is_none() {
+                self.body = ResponseBody::Ignored;
+                return false;
+            }
+        }
+        true
+    }
+    /// The response is received and the request is sent
+    pub fn is_done(&self) -> bool {
+        self.machine.is_none() && matches!(self.output, Output::Closed)
+    }
+    /// The peer is still allowed to send frames on the stream
+    pub fn is_remote_open(&self) -> bool {
+        !self.remote_closed
+    }
+    pub fn deadline(&self) -> Time {
+        self.deadline
+    }
+    /// Complete header block is received
+    ///
+    /// Returns error code if stream should be reset
+    pub fn headers(&mut self, list: &HeaderList, end_stream: bool,
+        scope: &mut Scope<M::Context>)
+        -> Result<(), ErrorCode>
+    {
+        if end_stream {
+            self.remote_closed = true;
+        }
+        match self.body {
+            ResponseBody::Headers => {}
+            _ if end_stream => return self.trailers(list, scope),
+            _ => {
+                self.abort(&ResponseError::InvalidResponse, scope);
+                return Err(ErrorCode::ProtocolError);
+            }
+        }
+        let fields = match list.headers() {
+            Ok(fields) => fields,
+            Err(_) => {
+                self.abort(&ResponseError::InvalidResponse, scope);
+                return Err(ErrorCode::ProtocolError);
+            }
+        };
+        let mut code = None;
+        let mut content_length = None;
+        let mut headers: Vec<Header> = Vec::with_capacity(fields.len());
+        for field in &fields {
+            if field.name == ":status" && code.is_none() &&
+                headers.len() == 0
+            {
+                code = from_utf8(field.value).ok()
+                    .and_then(|x| x.parse::<u16>().ok())
+                    .or(Some(0));
+            } else if field.name.starts_with(":") ||
+                is_connection_specific(field.name)
+            {
+                code = Some(0);
+                break;
+            } else {
+                if headers::is_content_length(field.name) {
+                    content_length = from_utf8(field.value).ok()
+                        .and_then(|x| x.parse::<u64>().ok());
+                }
+                headers.push(*field);
+            }
+        }
+        let code = match code {
+            // There are no upgrades in HTTP/2
+            Some(code) if code >= 100 && code < 1000 && code != 101 => code,
+            _ => {
+                self.abort(&ResponseError::InvalidResponse, scope);
+                return Err(ErrorCode::ProtocolError);
+            }
+        };
+        if code < 200 {
+            // Interim response, e.g. 100 Continue
+            return Ok(());
+        }
+        let body_kind = if self.is_head || code == 204 || code == 304 ||
+            end_stream
+        {
+            BodyKind::Fixed(0)
+        } else {
+            content_length.map(BodyKind::Fixed).unwrap_or(BodyKind::Eof)
+        };
+        let head = Head {
+            version: Version::Http20,
+            code: code,
+            reason: "",
+            headers: &headers,
+            body_kind: body_kind,
+            close: false,
+        };
+        let machine = self.machine.take();
+        let res = self.with_request(|req| {
+            machine.and_then(|m| m.headers_received(head, req, scope))
+        });
+        match res {
+            Some((m, mode, deadline)) => {
+                self.machine = Some(m);
+                self.deadline = deadline;
+                self.body = match mode {
+                    RecvMode::Buffered(limit) => {
+                        ResponseBody::Buffered(limit, Vec::new())
+                    }
+                    RecvMode::Progressive(_) => ResponseBody::Progressive,
+                };
+            }
+            None => {
+                self.body = ResponseBody::Ignored;
+                return Err(ErrorCode::Cancel);
+            }
+        }
+        if end_stream {
+            self.data(b"", true, scope)
+        } else {
+            Ok(())
+        }
+    }
+    /// Trailer section is received, which also ends the response
+    fn trailers(&mut self, list: &HeaderList, scope: &mut Scope<M::Context>)
+        -> Result<(), ErrorCode>
+    {
+        let fields = match list.headers() {
+            // Pseudo-headers are not allowed in trailers
+            Ok(ref fields) if fields.iter().any(|h| h.name.starts_with(":"))
+            => None,
+            Ok(fields) => Some(fields),
+            Err(_) => None,
+        };
+        let fields = match fields {
+            Some(fields) => fields,
+            None => {
+                self.abort(&ResponseError::InvalidResponse, scope);
+                return Err(ErrorCode::ProtocolError);
+            }
+        };
+        if fields.len() > 0 && !matches!(self.body, ResponseBody::Ignored) &&
+            !self.call(|m, req| m.response_trailers(&fields, req, scope))
+        {
+            return Err(ErrorCode::Cancel);
+        }
+        self.data(b"", true, scope)
+    }
+    /// Passes the response body to the handler
+    ///
+    /// Progressive handlers receive a chunk per DATA frame (the size hint
+    /// is not used, as data is already buffered in the frame anyway).
+    ///
+    /// Returns error code if stream should be reset
+    pub fn data(&mut self, data: &[u8], end_stream: bool,
+        scope: &mut Scope<M::Context>)
+        -> Result<(), ErrorCode>
+    {
+        if end_stream {
+            self.remote_closed = true;
+        }
+        match mem::replace(&mut self.body, ResponseBody::Ignored) {
+            ResponseBody::Headers => {
+                self.abort(&ResponseError::InvalidResponse, scope);
+                Err(ErrorCode::ProtocolError)
+            }
+            ResponseBody::Buffered(limit, mut buf) => {
+                if buf.len() + data.len() > limit {
+                    let size = (buf.len() + data.len()) as u64;
+                    self.abort(&ResponseError::ResponseTooLarge(size, limit),
+                               scope);
+                    Err(ErrorCode::Cancel)
+                } else if end_stream {
+                    buf.extend(data);
+                    if let Some(m) = self.machine.take() {
+                        self.with_request(|req| {
+                            m.response_received(&buf, req, scope)
+                        });
+                    }
+                    Ok(())
+                } else {
+                    buf.extend(data);
+                    self.body = ResponseBody::Buffered(limit, buf);
+                    Ok(())
+                }
+            }
+            ResponseBody::Progressive => {
+                if data.len() > 0 &&
+                    !self.call(|m, req| m.response_chunk(data, req, scope))
+                {
+                    return Err(ErrorCode::Cancel);
+                }
+                if end_stream {
+                    if let Some(m) = self.machine.take() {
+                        self.with_request(|req| m.response_end(req, scope));
+                    }
+                } else {
+                    self.body = ResponseBody::Progressive;
+                }
+                Ok(())
+            }
+            ResponseBody::Ignored => Ok(()),
+        }
+    }
+    /// Returns `false` if stream should be reset
+    pub fn wakeup(&mut self, scope: &mut Scope<M::Context>) -> bool {
+        self.call(|m, req| m.wakeup(req, scope))
+    }
+    /// The deadline of the stream has passed
+    ///
+    /// Returns `false` if the stream should be reset
+    pub fn timeout(&mut self, scope: &mut Scope<M::Context>) -> bool {
+        let machine = match self.machine.take() {
+            Some(m) => m,
+            // Request is not flushed in time
+            None => return false,
+        };
+        match self.with_request(|req| machine.timeout(req, scope)) {
+            Some((m, deadline)) => {
+                self.machine = Some(m);
+                self.deadline = deadline;
+                true
+            }
+            None => {
+                self.body = ResponseBody::Ignored;
+                false
+            }
+        }
+    }
+    /// Converts the request into frames and writes them into `out`
+    ///
+    /// DATA frames are limited by both the stream and connection flow
+    /// control windows, the rest of the body stays in the stream buffer
+    /// until WINDOW_UPDATE is received.
+    pub fn flush(&mut self, out: &mut Buf, scheme: &str,
+        encoder: &mut Encoder, conn_window: &mut i64, max_frame_size: usize)
+    {
+        loop {
+            match self.output {
+                Output::Head => {
+                    let head = match take_head(&mut self.buf) {
+                        Some(head) => head,
+                        None => break,
+                    };
+                    let mut parts = head.line.split(' ');
+                    let method = parts.next().unwrap_or("");
+                    let path = parts.next().unwrap_or("");
+                    let authority = head.headers.iter()
+                        .find(|&&(ref name, _)| name == "host")
+                        .map(|&(_, ref value)| &value[..])
+                        .unwrap_or(b"");
+                    let mut fields = vec![
+                        (&b":method"[..], method.as_bytes()),
+                        (&b":scheme"[..], scheme.as_bytes()),
+                        (&b":authority"[..], authority),
+                    ];
+                    if method != "CONNECT" {
+                        fields.push((&b":path"[..], path.as_bytes()));
+                    }
+                    fields.extend(head.headers.iter()
+                        .filter(|&&(ref name, _)| {
+                            name != "host" && !is_connection_specific(name)
+                        })
+                        .map(|&(ref name, ref value)| {
+                            (name.as_bytes(), &value[..])
+                        }));
+                    let mut block = Vec::new();
+                    encoder.encode(fields, &mut block);
+                    let body = BodyProgress::new(&head.headers);
+                    let end = body == BodyProgress::Done;
+                    frame::write_headers(out, self.id, &block, end,
+                                         max_frame_size);
+                    self.output = if end {
+                        Output::Closed
+                    } else {
+                        Output::Body(BodySender::new(body))
+                    };
+                }
+                Output::Body(ref mut sender) => {
+                    if sender.send(&mut self.buf, out, self.id, encoder,
+                        &mut self.send_window, conn_window, max_frame_size)
+                    {
+                        self.output = Output::Closed;
+                        return;
+                    }
+                    break;
+                }
+                Output::Closed => return,
+            }
+        }
+        if self.machine.is_none() &&
+            !self.with_request(|req| req.is_complete())
+        {
+            // Handler has finished in the middle of the request
+            self.reset(out, ErrorCode::Cancel);
+        }
+    }
+    /// Sends RST_STREAM and drops the state machine
+    pub fn reset(&mut self, out: &mut Buf, code: ErrorCode) {
+        frame::write_rst_stream(out, self.id, code);
+        self.close();
+    }
+    /// Drops the state machine, no more frames are sent or expected
+    pub fn close(&mut self) {
+        self.machine = None;
+        self.body = ResponseBody::Ignored;
+        self.output = Output::Closed;
+        self.remote_closed = true;
+    }
+    /// Stream can't be finished, notifies the handler with `bad_response`
+    pub fn abort(&mut self, err: &ResponseError,
+        scope: &mut Scope<M::Context>)
+    {
+        self.body = ResponseBody::Ignored;
+        if let Some(m) = self.machine.take() {
+            m.bad_response(err, scope);
+        }
+    }
+}
+
+impl<M: Requester> fmt::Debug for Stream<M> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Stream")
+            .field("id", &self.id)
+            .field("active", &self.machine.is_some())
+            .field("request", &self.request)
+            .field("body", &self.body)
+            .field("output", &self.output)
+            .field("remote_closed", &self.remote_closed)
+            .field("send_window", &self.send_window)
+            .finish()
+    }
+}
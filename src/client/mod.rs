@@ -1,8 +1,9 @@
 //! HTTP Client implementation
 //!
-//! Currently we aim to provide only HTTP/1.x implementation. We want to
-//! provide HTTP/2.0 and TLS implementation with exactly the same protocol.
-//! But it's yet unproven if it is possible.
+//! The same `Client` and `Requester` state machines are used for both
+//! HTTP/1.x and HTTP/2 (see the `http2` module). In the latter case multiple
+//! requests share a single connection. HTTPS is supported with the `tls`
+//! feature, see `connect_tls`.
 //!
 //! Also DNS resolving is not implemented yet.
 //!
@@ -12,6 +13,7 @@ use std::net::SocketAddr;
 use rotor::{Scope, Response, Void};
 use rotor::mio::tcp::TcpStream;
 use rotor_stream;
+#[cfg(feature="tls")] use tls::{TlsStream, ClientContext};
 
 mod request;
 mod head;
@@ -19,23 +21,22 @@ mod protocol;
 mod parser;
 mod connection;
 mod error;
+pub mod http2;
 
 pub use version::Version;
 pub use self::request::{Request};
 pub use self::protocol::{Client, Requester, Task};
-pub use self::head::Head;
+pub use self::head::{Head, OwnedHead, BodyKind};
+pub use headers::{Fields, Values, List, MediaType, MediaTypes};
+pub use headers::{Authorization, HeaderMap, HeaderLookup};
 pub use self::error::ResponseError;
 pub use recvmode::RecvMode;
+pub use limits::Limits;
 
 use self::parser::Parser;
 
-// TODO(tailhook) MAX_HEADERS_SIZE can be moved to Context
-// (i.e. made non-constant), but it's more of a problem for MAX_HEADERS_NUM
-// because that would mean we can't allocate array of headers on the stack
-// so performance will degrade. Customizing MAX_HEADERS_SIZE is not very
-// useful on it's own
-
-/// Note httparse requires we preallocate array of this size so be wise
+/// Note httparse requires we preallocate array of this size so be wise,
+/// larger `Limits::max_headers_num` are allocated on the heap
 pub const MAX_HEADERS_NUM: usize = 256;
 /// This one is not preallocated, but too large buffer is of limited use
 /// because of previous parameter.
@@ -47,6 +48,8 @@ pub const MAX_HEADERS_SIZE: usize = 16384;
 /// it's limited by either memory or artificial limit returned from handler.
 /// In unbuffered mode we can process chunk of unlimited size as long as
 /// request handler is able to handle it.
+///
+/// These are the defaults, use `Client::limits()` to change them.
 pub const MAX_CHUNK_HEAD: usize = 128;
 
 /// A state machine for ad-hoc requests
@@ -73,3 +76,25 @@ pub fn connect_tcp<P: Client>(
     };
     rotor_stream::Stream::new(sock, seed, scope)
 }
+
+/// Connects to the `addr` using TLS
+///
+/// The `hostname` is sent in SNI extension and is used for the certificate
+/// verification (see `tls::ClientContext` for options).
+#[cfg(feature="tls")]
+pub fn connect_tls<P: Client>(
+    scope: &mut Scope<<P::Requester as Requester>::Context>,
+    addr: &SocketAddr, hostname: &str, context: &ClientContext,
+    seed: P::Seed)
+    -> Response<Fsm<P, TlsStream<TcpStream>>, Void>
+{
+    let sock = match TcpStream::connect(&addr) {
+        Ok(sock) => sock,
+        Err(e) => return Response::error(Box::new(e)),
+    };
+    let sock = match TlsStream::connect(context, hostname, sock) {
+        Ok(sock) => sock,
+        Err(e) => return Response::error(Box::new(e)),
+    };
+    rotor_stream::Stream::new(sock, seed, scope)
+}
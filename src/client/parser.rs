@@ -4,17 +4,18 @@ use std::cmp::min;
 use std::fmt;
 
 use rotor::{Scope, Time};
-use rotor_stream::{Protocol, StreamSocket};
+use rotor_stream::{Protocol, StreamSocket, Exception};
 use rotor_stream::{Intent, Expectation as E, Transport};
 use rotor_stream::Buf;
 use httparse;
 use httparse::parse_chunk_size;
 
-use super::{MAX_HEADERS_SIZE, MAX_HEADERS_NUM, MAX_CHUNK_HEAD};
+use super::MAX_HEADERS_NUM;
 use super::{Client, Requester, Connection, Task, ResponseError};
 use super::head::Head;
 use super::request::{Request, state};
 use super::head::BodyKind;
+use super::http2::Connection as Http2Connection;
 use message::{MessageState};
 use recvmode::RecvMode;
 use headers;
@@ -37,6 +38,10 @@ pub enum BodyProgress {
     /// Progressive with chunked encoding
     /// (hint, offset, bytes left for current chunk)
     ProgressiveChunked(usize, usize, u64),
+    /// Trailer section of buffered chunked response (bytes buffered)
+    BufferTrailers(usize),
+    /// Trailer section of progressive chunked response
+    ProgressiveTrailers,
 }
 
 #[derive(Debug)]
@@ -61,6 +66,10 @@ enum ParserImpl<M: Requester> {
     // in fact if request is not flushed yet when response is fully received
     // this is actually useful thing
     Flushing(Time),
+    /// Connection is switched to another protocol by `101` response
+    Upgraded(M),
+    /// HTTP/2 connection, see `http2_settings()`
+    Http2(Box<Http2Connection<M>>),
 }
 
 impl<M: Requester> fmt::Debug for ParserImpl<M> {
@@ -74,6 +83,8 @@ impl<M: Requester> fmt::Debug for ParserImpl<M> {
                 fmt.debug_tuple("Flushing").field(&tm).finish()
             }
             Idle(tm) => fmt.debug_tuple("Idle").field(&tm).finish(),
+            Upgraded(..) => fmt.debug_tuple("Upgraded").finish(),
+            Http2(ref conn) => fmt.debug_tuple("Http2").field(conn).finish(),
             ReadHeaders { ref request, ref is_head, .. } => {
                 fmt.debug_struct("ReadHeaders")
                 .field("request", request)
@@ -99,13 +110,18 @@ fn scan_headers(is_head: bool, code: u16, headers: &[httparse::Header])
     ///
     /// Algorithm:
     ///
-    /// 1. For HEAD, 1xx, 204, 304 -- no body
-    /// 2. If last transfer encoding is chunked -> Chunked
-    /// 3. If Content-Length -> Fixed
-    /// 4. Else Eof
+    /// 1. For 101 -- connection is upgraded
+    /// 2. For HEAD, 1xx, 204, 304 -- no body
+    /// 3. If last transfer encoding is chunked -> Chunked
+    /// 4. If Content-Length -> Fixed
+    /// 5. Else Eof
     use super::head::BodyKind::*;
     let mut has_content_length = false;
     let mut close = false;
+    if code == 101 {
+        // Connection can't be reused anyway
+        return Ok((Upgrade, true));
+    }
     if is_head || (code > 100 && code < 200) || code == 204 || code == 304 {
         for header in headers.iter() {
             // TODO(tailhook) check for transfer encoding and content-length
@@ -152,6 +168,82 @@ fn scan_headers(is_head: bool, code: u16, headers: &[httparse::Header])
     Ok((result, close))
 }
 
+/// Returns true if the connection is encrypted
+#[cfg(feature="tls")]
+fn is_tls<S: StreamSocket>(sock: &S) -> bool {
+    use std::any::Any;
+    use rotor::mio::tcp::TcpStream;
+    use tls::TlsStream;
+    Any::downcast_ref::<TlsStream<TcpStream>>(sock).is_some()
+}
+
+/// Returns true if the connection is encrypted
+#[cfg(not(feature="tls"))]
+fn is_tls<S: StreamSocket>(_sock: &S) -> bool {
+    false
+}
+
+/// Returns an error for requester if the connection is closed because of
+/// TLS failure (e.g. certificate verification has failed on handshake)
+#[cfg(feature="tls")]
+fn tls_error(reason: Exception) -> Option<ResponseError> {
+    use openssl::ssl::error::SslError;
+    match reason {
+        Exception::ReadError(e) | Exception::WriteError(e) => {
+            let is_tls = e.get_ref().map(|x| x.is::<SslError>())
+                .unwrap_or(false);
+            if is_tls { Some(ResponseError::Tls(e)) } else { None }
+        }
+        _ => None,
+    }
+}
+
+/// Returns an error for requester if the connection is closed because of
+/// TLS failure (e.g. certificate verification has failed on handshake)
+#[cfg(not(feature="tls"))]
+fn tls_error(_reason: Exception) -> Option<ResponseError> {
+    None
+}
+
+/// Parses the trailer section and passes it to the handler
+///
+/// The `block` starts with CRLF of the last chunk line, so that the empty
+/// trailer section ends with `\r\n\r\n` too. The handler is only called
+/// if there is at least one trailer field. On error `bad_response()` is
+/// called.
+fn parse_trailers<M: Requester>(machine: M, block: &[u8],
+    max_headers_num: usize, req: &mut Request,
+    scope: &mut Scope<M::Context>)
+    -> Result<Option<M>, ()>
+{
+    use httparse::Status::*;
+    if block.len() == 4 {
+        return Ok(Some(machine));
+    }
+    let mut stack = [httparse::EMPTY_HEADER; MAX_HEADERS_NUM];
+    let mut heap;
+    let headers = if max_headers_num <= MAX_HEADERS_NUM {
+        &mut stack[..max_headers_num]
+    } else {
+        heap = vec![httparse::EMPTY_HEADER; max_headers_num];
+        &mut heap[..]
+    };
+    match httparse::parse_headers(&block[2..], headers) {
+        Ok(Complete((_, trailers))) => {
+            Ok(machine.response_trailers(trailers, req, scope))
+        }
+        Ok(Partial) => unreachable!(),
+        Err(httparse::Error::TooManyHeaders) => {
+            machine.bad_response(&ResponseError::HeadersAreTooLarge, scope);
+            Err(())
+        }
+        Err(_) => {
+            machine.bad_response(&ResponseError::InvalidResponse, scope);
+            Err(())
+        }
+    }
+}
+
 #[inline]
 fn consumed(off: usize) -> usize {
     // If buffer is not empty it has final '\r\n' at the
@@ -173,19 +265,29 @@ fn start_body(mode: RecvMode, body: BodyKind) -> BodyProgress {
         (Progressive(x), Fixed(y)) => ProgressiveFixed(x, y),
         (Progressive(x), Chunked) => ProgressiveChunked(x, 0, 0),
         (Progressive(x), Eof) => ProgressiveEOF(x),
+        // Upgraded connection has no body, see parse_headers
+        (_, Upgrade) => unreachable!(),
     }
 }
 
 fn parse_headers<M>(buffer: &mut Buf, end: usize,
-    proto: M, mut req: Request, is_head: bool,
+    proto: M, mut req: Request, is_head: bool, max_headers_num: usize,
     scope: &mut Scope<M::Context>)
     -> Result<ParserImpl<M>, ()>
     where M: Requester
 {
     let resp = {
-        let mut headers = [httparse::EMPTY_HEADER; MAX_HEADERS_NUM];
+        // The common case doesn't need an allocation
+        let mut stack = [httparse::EMPTY_HEADER; MAX_HEADERS_NUM];
+        let mut heap;
+        let headers = if max_headers_num <= MAX_HEADERS_NUM {
+            &mut stack[..max_headers_num]
+        } else {
+            heap = vec![httparse::EMPTY_HEADER; max_headers_num];
+            &mut heap[..]
+        };
         let (ver, code, reason, headers) = {
-            let mut raw = httparse::Response::new(&mut headers);
+            let mut raw = httparse::Response::new(headers);
             match raw.parse(&buffer[..end+4]) {
                 Ok(httparse::Status::Complete(x)) => {
                     assert!(x == end+4);
@@ -219,12 +321,20 @@ fn parse_headers<M>(buffer: &mut Buf, end: usize,
             Some(triple) => triple,
             None => return Err(()),
         };
-        let progress = start_body(mode, body);
-        ParserImpl::Response {
-            machine: mach,
-            deadline: dline,
-            progress: progress,
-            request: state(req),
+        if body == BodyKind::Upgrade {
+            if !req.is_complete() {
+                // Can't switch protocols in the middle of the request
+                return Err(());
+            }
+            ParserImpl::Upgraded(mach)
+        } else {
+            let progress = start_body(mode, body);
+            ParserImpl::Response {
+                machine: mach,
+                deadline: dline,
+                progress: progress,
+                request: state(req),
+            }
         }
     };
     buffer.consume(end+4);
@@ -232,6 +342,56 @@ fn parse_headers<M>(buffer: &mut Buf, end: usize,
 }
 
 impl<M: Client, S: StreamSocket> Parser<M, S> {
+    fn intent_upgraded(cli: M,
+        res: Option<(M::Requester, E, Time)>)
+        -> Intent<Parser<M, S>>
+    {
+        match res {
+            Some((m, exp, deadline)) => {
+                Intent::of(ParserImpl::Upgraded(m).wrap(cli))
+                    .expect(exp)
+                    .deadline(deadline)
+            }
+            None => Intent::done(),
+        }
+    }
+    fn intent_http2(cli: M, conn: Box<Http2Connection<M::Requester>>,
+        transport: &mut Transport<S>,
+        scope: &mut Scope<<M::Requester as Requester>::Context>)
+        -> Intent<Parser<M, S>>
+    {
+        if conn.needs_request() {
+            let task = cli.connection_idle(&Connection {
+                idle: true,
+            }, scope);
+            return Parser::http2_task(task, conn, transport, scope);
+        }
+        let (exp, deadline) = conn.intent(transport.input());
+        Intent::of(ParserImpl::Http2(conn).wrap(cli))
+            .expect(exp)
+            .deadline(deadline)
+    }
+    fn http2_task(task: Task<M>, mut conn: Box<Http2Connection<M::Requester>>,
+        transport: &mut Transport<S>,
+        scope: &mut Scope<<M::Requester as Requester>::Context>)
+        -> Intent<Parser<M, S>>
+    {
+        let cli = match task {
+            Task::Close => {
+                conn.end(&ResponseError::ConnectionClosed, scope);
+                return Intent::done();
+            }
+            Task::Sleep(cli, deadline) => {
+                conn.sleep(deadline);
+                cli
+            }
+            Task::Request(cli, m) => {
+                conn.start(m, transport.output(), scope);
+                cli
+            }
+        };
+        Parser::intent_http2(cli, conn, transport, scope)
+    }
     fn finish(cli: M, req: Request,
         scope: &mut Scope<<M::Requester as Requester>::Context>)
         -> Intent<Parser<M, S>>
@@ -262,10 +422,12 @@ impl<M: Requester> ParserImpl<M> {
         use rotor_stream::Expectation::*;
         use self::ParserImpl::*;
         use self::BodyProgress::*;
+        let limits = cli.limits(scope);
         let (exp, dline) = match self {
             Connecting(dline) | Flushing(dline) => (E::Flush(0), dline),
             ReadHeaders { ref machine, ..} => (
-                        E::Delimiter(0, b"\r\n\r\n", MAX_HEADERS_SIZE),
+                        E::Delimiter(0, b"\r\n\r\n",
+                                     limits.max_headers_size),
                         scope.now() + machine.byte_timeout(scope)),
             Response { ref progress, ref deadline, ref machine, .. } => {
                 let exp = match *progress {
@@ -273,20 +435,29 @@ impl<M: Requester> ParserImpl<M> {
                     BufferEOF(x) => Bytes(x),
                     BufferChunked(_, off, 0) => {
                         Delimiter(consumed(off), b"\r\n",
-                                  consumed(off) + MAX_CHUNK_HEAD)
+                                  consumed(off) + limits.max_chunk_head)
                     }
                     BufferChunked(_, off, y) => Bytes(off + y + 2),
                     ProgressiveFixed(hint, left)
                     => Bytes(min(hint as u64, left) as usize),
                     ProgressiveEOF(hint) => Bytes(hint),
                     ProgressiveChunked(_, off, 0)
-                    => Delimiter(off, b"\r\n", off+MAX_CHUNK_HEAD),
+                    => Delimiter(off, b"\r\n", off+limits.max_chunk_head),
                     ProgressiveChunked(hint, off, left)
-                    => Bytes(min(hint as u64, off as u64 +left) as usize)
+                    => Bytes(min(hint as u64, off as u64 +left) as usize),
+                    BufferTrailers(off)
+                    => Delimiter(off, b"\r\n\r\n",
+                                 off + limits.max_headers_size),
+                    ProgressiveTrailers
+                    => Delimiter(0, b"\r\n\r\n", limits.max_headers_size),
                 };
                 (exp, min(*deadline, scope.now() + machine.byte_timeout(scope)))
             }
             Idle(x) => (Sleep, x),
+            // Expectation is returned by the handler, see intent_upgraded
+            Upgraded(..) => unreachable!(),
+            // Expectation depends on the input, see intent_http2
+            Http2(..) => unreachable!(),
         };
         Intent::of(self.wrap(cli)).expect(exp).deadline(dline)
     }
@@ -308,12 +479,13 @@ fn maybe_new_request<M: Client, S: StreamSocket>(
     match m.prepare_request(&mut req, scope) {
         Some(m) => {
             let deadline = scope.now() + m.byte_timeout(scope);
+            let limits = cli.limits(scope);
             Intent::of(Parser(cli, ParserImpl::ReadHeaders {
                     machine: m,
                     is_head: req.1,
                     request: state(req),
                 }, PhantomData))
-            .expect_delimiter(b"\r\n\r\n", MAX_HEADERS_SIZE)
+            .expect_delimiter(b"\r\n\r\n", limits.max_headers_size)
             .deadline(deadline)
         }
         None => unimplemented!(),
@@ -343,11 +515,18 @@ impl<M, S> Protocol for Parser<M, S>
         use super::ResponseError::*;
         match self.1 {
             ReadHeaders { machine, request, is_head } => {
-                let (inb, outb) = transport.buffers();
-                let is_head = is_head.unwrap();
-                let hdr = parse_headers(inb, end, machine,
-                    request.with(outb), is_head, scope);
+                let max_headers_num = self.0.limits(scope).max_headers_num;
+                let hdr = {
+                    let (inb, outb) = transport.buffers();
+                    let is_head = is_head.unwrap();
+                    parse_headers(inb, end, machine,
+                        request.with(outb), is_head, max_headers_num, scope)
+                };
                 match hdr {
+                    Ok(Upgraded(m)) => {
+                        let res = m.upgrade_started(transport, scope);
+                        Parser::intent_upgraded(self.0, res)
+                    }
                     Ok(me) => me.intent(self.0, scope),
                     Err(()) => Intent::done(), // Close the connection
                 }
@@ -370,11 +549,10 @@ impl<M, S> Protocol for Parser<M, S>
                             &inp[lenstart..lenstart + end + 2])
                         {
                             Ok(Complete((_, 0))) => {
-                                inp.remove_range(off..lenstart + end + 2);
-                                machine.response_received(
-                                    &inp[..off], &mut req, scope);
-                                inp.consume(off);
-                                return Parser::finish(self.0, req, scope);
+                                // Keep CRLF of the line to find the end of
+                                // (possibly empty) trailer section
+                                inp.remove_range(off..lenstart + end);
+                                (Some(machine), BufferTrailers(off))
                             }
                             Ok(Complete((_, chunk_len))) => {
                                 if off as u64 + chunk_len > limit as u64 {
@@ -426,12 +604,18 @@ impl<M, S> Protocol for Parser<M, S>
                         use httparse::Status::*;
                         match parse_chunk_size(&inp[off..off + end + 2]) {
                             Ok(Complete((_, 0))) => {
-                                inp.remove_range(off..end+2);
-                                let m = machine.response_chunk(
-                                    &inp[..off], &mut req, scope);
-                                m.map(|m| m.response_end(&mut req, scope));
+                                inp.remove_range(off..off + end);
+                                let m = if off > 0 {
+                                    machine.response_chunk(
+                                        &inp[..off], &mut req, scope)
+                                } else {
+                                    Some(machine)
+                                };
                                 inp.consume(off);
-                                return Parser::finish(self.0, req, scope);
+                                match m {
+                                    Some(m) => (Some(m), ProgressiveTrailers),
+                                    None => return Intent::done(),
+                                }
                             }
                             Ok(Complete((_, chunk_len))) => {
                                 inp.remove_range(off..end+2);
@@ -460,6 +644,30 @@ impl<M, S> Protocol for Parser<M, S>
                             (m, ProgressiveChunked(hint, 0, left))
                         }
                     }
+                    BufferTrailers(off) => {
+                        let num = self.0.limits(scope).max_headers_num;
+                        let res = parse_trailers(machine,
+                            &inp[off..off + end + 4], num, &mut req, scope);
+                        if let Ok(Some(m)) = res {
+                            m.response_received(&inp[..off], &mut req, scope);
+                            inp.consume(off + end + 4);
+                            return Parser::finish(self.0, req, scope);
+                        }
+                        // Connection is closed on errors
+                        return Intent::done();
+                    }
+                    ProgressiveTrailers => {
+                        let num = self.0.limits(scope).max_headers_num;
+                        let res = parse_trailers(machine,
+                            &inp[..end + 4], num, &mut req, scope);
+                        if let Ok(Some(m)) = res {
+                            m.response_end(&mut req, scope);
+                            inp.consume(end + 4);
+                            return Parser::finish(self.0, req, scope);
+                        }
+                        // Connection is closed on errors
+                        return Intent::done();
+                    }
                 };
                 match m {
                     None => {
@@ -479,6 +687,14 @@ impl<M, S> Protocol for Parser<M, S>
             Idle(..) => Intent::done(),
             Connecting(..) => unreachable!(),
             Flushing(..) => unreachable!(),
+            Upgraded(m) => {
+                let res = m.upgrade_bytes_read(transport, end, scope);
+                Parser::intent_upgraded(self.0, res)
+            }
+            Http2(mut conn) => {
+                conn.bytes_read(transport, scope);
+                Parser::intent_http2(self.0, conn, transport, scope)
+            }
         }
     }
     fn bytes_flushed(self, transport: &mut Transport<Self::Socket>,
@@ -487,7 +703,22 @@ impl<M, S> Protocol for Parser<M, S>
     {
         use self::ParserImpl::*;
         match self.1 {
-            Connecting(..) | Flushing(..) => {
+            Connecting(..) => {
+                if let Some(settings) = self.0.http2_settings(scope) {
+                    let deadline = scope.now() + self.0.idle_timeout(scope);
+                    let scheme = if is_tls(transport.socket()) { "https" }
+                                 else { "http" };
+                    let conn = Box::new(Http2Connection::new(settings,
+                        scheme, transport.output(), deadline));
+                    return Parser::intent_http2(self.0, conn,
+                                                transport, scope);
+                }
+                maybe_new_request(transport,
+                    self.0.connection_idle(&Connection {
+                        idle: true,
+                    }, scope), scope)
+            }
+            Flushing(..) => {
                 maybe_new_request(transport,
                     self.0.connection_idle(&Connection {
                         idle: true,
@@ -498,6 +729,12 @@ impl<M, S> Protocol for Parser<M, S>
             Response { .. }  => {
                 unimplemented!();
             }
+            Upgraded(m) => {
+                let res = m.upgrade_bytes_flushed(transport, scope);
+                Parser::intent_upgraded(self.0, res)
+            }
+            // Only closing connection waits for the flush
+            Http2(..) => Intent::done(),
         }
     }
     fn timeout(self, transport: &mut Transport<Self::Socket>,
@@ -513,6 +750,24 @@ impl<M, S> Protocol for Parser<M, S>
                         idle: true,
                     }, scope), scope)
             }
+            Upgraded(m) => {
+                let res = m.upgrade_timeout(transport, scope);
+                Parser::intent_upgraded(self.0, res)
+            }
+            Http2(mut conn) => {
+                if conn.is_closing() {
+                    return Intent::done();
+                }
+                conn.timeout(transport.output(), scope);
+                if conn.deadline() <= scope.now() {
+                    let task = self.0.timeout(&Connection {
+                        idle: conn.is_idle(),
+                    }, scope);
+                    Parser::http2_task(task, conn, transport, scope)
+                } else {
+                    Parser::intent_http2(self.0, conn, transport, scope)
+                }
+            }
             _ => {
                 unimplemented!();
             }
@@ -535,20 +790,70 @@ impl<M, S> Protocol for Parser<M, S>
                         idle: true,
                     }, scope), scope)
             }
+            Upgraded(m) => {
+                let res = m.upgrade_wakeup(transport, scope);
+                Parser::intent_upgraded(self.0, res)
+            }
+            Http2(mut conn) => {
+                conn.wakeup(transport.output(), scope);
+                let task = self.0.wakeup(&Connection {
+                    idle: conn.is_idle(),
+                }, scope);
+                Parser::http2_task(task, conn, transport, scope)
+            }
             _ => {
                 unimplemented!();
             }
         }
     }
+    fn exception(self, transport: &mut Transport<Self::Socket>,
+        reason: Exception, scope: &mut Scope<Self::Context>)
+        -> Intent<Self>
+    {
+        match self.1 {
+            ParserImpl::Upgraded(m) => m.upgrade_end(transport, reason, scope),
+            ParserImpl::Http2(mut conn) => {
+                let err = tls_error(reason)
+                    .unwrap_or(ResponseError::ConnectionClosed);
+                conn.end(&err, scope);
+            }
+            ParserImpl::ReadHeaders { machine, .. } => {
+                let err = match reason {
+                    Exception::LimitReached
+                    => Some(ResponseError::HeadersAreTooLarge),
+                    reason => tls_error(reason),
+                };
+                if let Some(err) = err {
+                    machine.bad_response(&err, scope);
+                }
+            }
+            ParserImpl::Response { machine, progress, .. } => {
+                let err = match (reason, progress) {
+                    (Exception::LimitReached, BodyProgress::BufferTrailers(_)) |
+                    (Exception::LimitReached, BodyProgress::ProgressiveTrailers)
+                    => Some(ResponseError::HeadersAreTooLarge),
+                    (reason, _) => tls_error(reason),
+                };
+                if let Some(err) = err {
+                    machine.bad_response(&err, scope);
+                }
+            }
+            _ => {}
+        }
+        Intent::done()
+    }
 }
 
 #[cfg(test)]
 mod test {
     use std::time::Duration;
     use rotor::{Scope, EventSet, Time, Machine};
+    use rotor_stream::{Transport, Expectation, StreamSocket};
     use rotor_test::{MemIo, MockLoop};
     use client::{Client, Requester, Connection, Task, Request, Version};
     use client::{Head, RecvMode, Fsm, ResponseError};
+    use client::http2::Settings;
+    use httparse::Header;
 
     #[derive(Debug, Default, PartialEq, Eq)]
     struct Context {
@@ -558,6 +863,7 @@ mod test {
         chunks_received: usize,
         bytes_received: usize,
         errors: usize,
+        trailers_received: usize,
     }
 
     #[derive(Debug)]
@@ -601,6 +907,51 @@ mod test {
         }
     }
 
+    /// The same as `Cli` but uses HTTP/2 with prior knowledge
+    #[derive(Debug)]
+    struct Http2Cli(usize);
+
+    impl Client for Http2Cli {
+        type Requester = Req;
+        type Seed = usize;
+        fn create(seed: Self::Seed,
+            _scope: &mut Scope<<Self::Requester as Requester>::Context>)
+            -> Self
+        {
+            Http2Cli(seed)
+        }
+        fn connection_idle(mut self, _conn: &Connection,
+            scope: &mut Scope<Context>)
+            -> Task<Http2Cli>
+        {
+            if self.0 > 0 {
+                self.0 -= 1;
+                Task::Request(self, Req)
+            } else {
+                Task::Sleep(self, scope.now() + Duration::new(100, 0))
+            }
+        }
+        fn wakeup(self,
+            _connection: &Connection,
+            _scope: &mut Scope<<Self::Requester as Requester>::Context>)
+            -> Task<Http2Cli>
+        {
+            unimplemented!();
+        }
+        fn timeout(self,
+            _connection: &Connection,
+            _scope: &mut Scope<<Self::Requester as Requester>::Context>)
+            -> Task<Http2Cli>
+        {
+            unimplemented!();
+        }
+        fn http2_settings(&self, _scope: &mut Scope<Context>)
+            -> Option<Settings>
+        {
+            Some(Settings::default())
+        }
+    }
+
     impl Requester for Req {
         type Context = Context;
         fn prepare_request(self, req: &mut Request,
@@ -656,6 +1007,31 @@ mod test {
         {
             scope.errors += 1;
         }
+        fn response_trailers(self, trailers: &[Header],
+            _request: &mut Request, scope: &mut Scope<Self::Context>)
+            -> Option<Self>
+        {
+            scope.trailers_received += trailers.len();
+            Some(self)
+        }
+        fn upgrade_started<S: StreamSocket>(self,
+            _transport: &mut Transport<S>, scope: &mut Scope<Self::Context>)
+            -> Option<(Self, Expectation, Time)>
+        {
+            Some((Req, Expectation::Bytes(5),
+                scope.now() + Duration::new(10, 0)))
+        }
+        fn upgrade_bytes_read<S: StreamSocket>(self,
+            transport: &mut Transport<S>, end: usize,
+            scope: &mut Scope<Self::Context>)
+            -> Option<(Self, Expectation, Time)>
+        {
+            scope.chunks_received += 1;
+            scope.bytes_received += end;
+            transport.input().consume(end);
+            Some((Req, Expectation::Bytes(5),
+                scope.now() + Duration::new(10, 0)))
+        }
     }
 
     #[test]
@@ -675,6 +1051,7 @@ mod test {
             chunks_received: 0,
             bytes_received: 0,
             errors: 0,
+            trailers_received: 0,
         });
     }
 
@@ -695,6 +1072,7 @@ mod test {
             chunks_received: 0,
             bytes_received: 0,
             errors: 0,
+            trailers_received: 0,
         });
         io.push_bytes("0\r\n\r\n".as_bytes());
         m.ready(EventSet::readable(), &mut lp.scope(1))
@@ -706,6 +1084,7 @@ mod test {
             chunks_received: 0,
             bytes_received: 0,
             errors: 0,
+            trailers_received: 0,
         });
     }
 
@@ -726,6 +1105,7 @@ mod test {
             chunks_received: 0,
             bytes_received: 0,
             errors: 0,
+            trailers_received: 0,
         });
         io.push_bytes("5\r\nrotor\r\n0\r\n\r\n".as_bytes());
         m.ready(EventSet::readable(), &mut lp.scope(1))
@@ -737,6 +1117,7 @@ mod test {
             chunks_received: 0,
             bytes_received: 5,
             errors: 0,
+            trailers_received: 0,
         });
     }
 
@@ -757,6 +1138,7 @@ mod test {
             chunks_received: 0,
             bytes_received: 0,
             errors: 0,
+            trailers_received: 0,
         });
         io.push_bytes("4\r\n\
                        Wiki\r\n\
@@ -776,6 +1158,102 @@ mod test {
             chunks_received: 0,
             bytes_received: 23,
             errors: 0,
+            trailers_received: 0,
+        });
+    }
+
+    #[test]
+    fn test_chunked_trailers() {
+        let mut io = MemIo::new();
+        let mut lp = MockLoop::new(Default::default());
+        io.push_bytes("HTTP/1.1 200 OK\r\nTransfer-Encoding: chunked\r\n\
+                       Trailer: Digest, X-Status\r\n\r\n\
+                       5\r\nrotor\r\n0\r\n\
+                       Digest: x\r\n".as_bytes());
+        let m = Fsm::<Cli, MemIo>::connected(
+            io.clone(), 1, &mut lp.scope(1)).expect_machine();
+        let m = m.ready(EventSet::readable(), &mut lp.scope(1))
+            .expect_machine();
+        assert_eq!(lp.ctx().responses_received, 0);
+        io.push_bytes("X-Status: 0\r\n\r\n".as_bytes());
+        m.ready(EventSet::readable(), &mut lp.scope(1))
+            .expect_machine();
+        assert_eq!(*lp.ctx(), Context {
+            requests: 1,
+            headers_received: 1,
+            responses_received: 1,
+            chunks_received: 0,
+            bytes_received: 5,
+            errors: 0,
+            trailers_received: 2,
+        });
+    }
+
+    #[test]
+    fn test_upgrade() {
+        let mut io = MemIo::new();
+        let mut lp = MockLoop::new(Default::default());
+        io.push_bytes("HTTP/1.1 101 Switching Protocols\r\n\
+                       Connection: Upgrade\r\nUpgrade: echo\r\n\r\n\
+                       hello".as_bytes());
+        let m = Fsm::<Cli, MemIo>::connected(
+            io.clone(), 1, &mut lp.scope(1)).expect_machine();
+        let m = m.ready(EventSet::readable(), &mut lp.scope(1))
+            .expect_machine();
+        assert_eq!(*lp.ctx(), Context {
+            requests: 1,
+            headers_received: 1,
+            responses_received: 0,
+            chunks_received: 1,
+            bytes_received: 5,
+            errors: 0,
+            trailers_received: 0,
+        });
+        io.push_bytes("world".as_bytes());
+        m.ready(EventSet::readable(), &mut lp.scope(1))
+            .expect_machine();
+        assert_eq!(*lp.ctx(), Context {
+            requests: 1,
+            headers_received: 1,
+            responses_received: 0,
+            chunks_received: 2,
+            bytes_received: 10,
+            errors: 0,
+            trailers_received: 0,
+        });
+    }
+
+    #[test]
+    fn test_http2_concurrent() {
+        let mut io = MemIo::new();
+        let mut lp = MockLoop::new(Default::default());
+        let m = Fsm::<Http2Cli, MemIo>::connected(
+            io.clone(), 2, &mut lp.scope(1)).expect_machine();
+        io.push_bytes(b"\0\0\0\x04\0\0\0\0\0\
+                        \0\0\x01\x01\x04\0\0\0\x01\x88\
+                        \0\0\x01\x01\x05\0\0\0\x03\x88");
+        let m = m.ready(EventSet::readable(), &mut lp.scope(1))
+            .expect_machine();
+        assert_eq!(*lp.ctx(), Context {
+            requests: 2,
+            headers_received: 2,
+            responses_received: 1,
+            chunks_received: 0,
+            bytes_received: 0,
+            errors: 0,
+            trailers_received: 0,
+        });
+        io.push_bytes(b"\0\0\x05\0\x01\0\0\0\x01hello");
+        m.ready(EventSet::readable(), &mut lp.scope(1))
+            .expect_machine();
+        assert_eq!(*lp.ctx(), Context {
+            requests: 2,
+            headers_received: 2,
+            responses_received: 2,
+            chunks_received: 0,
+            bytes_received: 5,
+            errors: 0,
+            trailers_received: 0,
         });
     }
 }
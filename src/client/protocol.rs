@@ -1,10 +1,14 @@
 use std::time::Duration;
 
+use httparse::Header;
 use rotor::{Scope, Time};
+use rotor_stream::{Exception, Expectation, StreamSocket, Transport};
 
 use recvmode::RecvMode;
 use super::{Head, Request, ResponseError};
 use super::{Connection};
+use super::http2::Settings;
+use super::Limits;
 
 pub enum Task<M: Client> {
     Sleep(M, Time),
@@ -27,6 +31,10 @@ pub trait Client: Sized {
     /// request has just finished
     ///
     /// To initiate a request, return `Requester` as part of a return value.
+    ///
+    /// For HTTP/2 connections the handler is also called right after a
+    /// request is started as long as there is room for more concurrent
+    /// requests.
     fn connection_idle(self,
         connection: &Connection,
         scope: &mut Scope<<Self::Requester as Requester>::Context>)
@@ -74,6 +82,30 @@ pub trait Client: Sized {
     {
         Duration::new(120, 0)
     }
+    /// Limits on the size of response headers
+    ///
+    /// Responses exceeding limits are reported to `bad_response()`.
+    ///
+    /// Default is `Limits::default()`
+    fn limits(&self,
+        _scope: &mut Scope<<Self::Requester as Requester>::Context>)
+        -> Limits
+    {
+        Limits::default()
+    }
+    /// Returns HTTP/2 settings if the connection should use HTTP/2
+    ///
+    /// The protocol is used with "prior knowledge", i.e. without any
+    /// negotiation, so return settings only if the server is known to
+    /// support HTTP/2 over cleartext. See `http2` module for details.
+    ///
+    /// Default is `None` which means HTTP/1.1 is used.
+    fn http2_settings(&self,
+        _scope: &mut Scope<<Self::Requester as Requester>::Context>)
+        -> Option<Settings>
+    {
+        None
+    }
 }
 
 /// A handler of a single client-side HTTP
@@ -120,6 +152,11 @@ pub trait Requester: Sized {
     /// we need to read response body by chunk. It's recommended to return
     /// Buffered up to certain size, or at least for zero-length response.
     ///
+    /// If `head.body_kind` is `BodyKind::Upgrade` (the `101 Switching
+    /// Protocols` response) the returned mode is ignored, and the connection
+    /// is handed over to the `upgrade_started()` handler. Return `None` if
+    /// you didn't ask for upgrade or response is not what you expect.
+    ///
     /// Note that `head` is passed here once, and forgotten by the
     /// protocol. If you need it later it's your responsibility to store it
     /// somewhere.
@@ -168,6 +205,21 @@ pub trait Requester: Sized {
     fn response_end(self, request: &mut Request,
         scope: &mut Scope<Self::Context>);
 
+    /// Trailer fields of the response are received
+    ///
+    /// Only called for chunked (or HTTP/2) responses which have non-empty
+    /// trailer section, right before `response_received()` or
+    /// `response_end()`. Trailer section is limited the same way as
+    /// response headers (see `Client::limits()`).
+    ///
+    /// Default implementation ignores trailers.
+    fn response_trailers(self, _trailers: &[Header],
+        _request: &mut Request, _scope: &mut Scope<Self::Context>)
+        -> Option<Self>
+    {
+        Some(self)
+    }
+
     /// Request timeout occured
     ///
     /// Unless you've returned the new timeout connection will be closed after
@@ -185,4 +237,75 @@ pub trait Requester: Sized {
     fn byte_timeout(&self, _scope: &mut Scope<Self::Context>) -> Duration {
         Duration::new(120, 0)
     }
+
+    /// Connection is upgraded to another protocol
+    ///
+    /// Called right after `headers_received()` has accepted the
+    /// `101 Switching Protocols` response. From now on the connection is
+    /// not HTTP any more and is driven by the `upgrade_*` handlers, which
+    /// receive raw `transport`: everything the server has sent after the
+    /// response headers is in the input buffer, and anything you put into
+    /// the output buffer is sent as is.
+    ///
+    /// Returns the new state, the expectation for the next event and a
+    /// deadline for it, much like `rotor_stream::Protocol` does. Returning
+    /// `None` closes the connection.
+    ///
+    /// Connection is never returned to the `Client` after upgrade.
+    ///
+    /// Default implementation closes the connection, you only need to
+    /// implement `upgrade_*` handlers if you send requests with `Upgrade`
+    /// header.
+    fn upgrade_started<S: StreamSocket>(self, _transport: &mut Transport<S>,
+        _scope: &mut Scope<Self::Context>)
+        -> Option<(Self, Expectation, Time)>
+    {
+        None
+    }
+
+    /// Expected number of bytes (or delimiter) received on upgraded
+    /// connection
+    ///
+    /// The `end` is the same as in `rotor_stream::Protocol::bytes_read`
+    fn upgrade_bytes_read<S: StreamSocket>(self,
+        _transport: &mut Transport<S>, _end: usize,
+        _scope: &mut Scope<Self::Context>)
+        -> Option<(Self, Expectation, Time)>
+    {
+        None
+    }
+
+    /// Output buffer is flushed on upgraded connection
+    ///
+    /// Only called if `Expectation::Flush` is returned from one of
+    /// `upgrade_*` handlers
+    fn upgrade_bytes_flushed<S: StreamSocket>(self,
+        _transport: &mut Transport<S>, _scope: &mut Scope<Self::Context>)
+        -> Option<(Self, Expectation, Time)>
+    {
+        None
+    }
+
+    /// Deadline returned from one of `upgrade_*` handlers is reached
+    fn upgrade_timeout<S: StreamSocket>(self, _transport: &mut Transport<S>,
+        _scope: &mut Scope<Self::Context>)
+        -> Option<(Self, Expectation, Time)>
+    {
+        None
+    }
+
+    /// Standard rotor's wakeup handler for upgraded connection
+    fn upgrade_wakeup<S: StreamSocket>(self, _transport: &mut Transport<S>,
+        _scope: &mut Scope<Self::Context>)
+        -> Option<(Self, Expectation, Time)>
+    {
+        None
+    }
+
+    /// Upgraded connection is closed because of an error or end of stream
+    ///
+    /// The state machine is dropped after the call.
+    fn upgrade_end<S: StreamSocket>(self, _transport: &mut Transport<S>,
+        _reason: Exception, _scope: &mut Scope<Self::Context>)
+    {}
 }
@@ -44,6 +44,10 @@ impl<'a> Request<'a> {
     /// Note that there is currently no way to use a transfer encoding other
     /// than chunked.
     ///
+    /// The `Trailer` header announces fields which are sent with
+    /// `add_trailer()` after the body, the fields listed there are checked
+    /// to be allowed in trailers.
+    ///
     /// We return Result here to make implementing proxies easier. In the
     /// application handler it's okay to unwrap the result and to get
     /// a meaningful panic (that is basically an assertion).
@@ -130,6 +134,41 @@ impl<'a> Request<'a> {
     pub fn write_body(&mut self, data: &[u8]) {
         self.0.write_body(data)
     }
+    /// Add a trailer field after the chunked body
+    ///
+    /// The first call terminates the body with the last chunk, so no more
+    /// `write_body()` calls are allowed afterwards. The trailer section is
+    /// finished by `done()`. Fields should be announced in the `Trailer`
+    /// header beforehand.
+    ///
+    /// Fields needed for framing, routing or authentication (e.g.
+    /// `Content-Length`, `Host`, `Authorization`) are rejected with
+    /// `ForbiddenTrailer`. When the body is not chunked,
+    /// `TrailersWithoutChunked` is returned, except for the ignored body
+    /// (HEAD request or 304 response) where the trailer is just skipped.
+    ///
+    /// # Panics
+    ///
+    /// When message is in the wrong state.
+    pub fn add_trailer(&mut self, name: &str, value: &[u8])
+        -> Result<(), HeaderError>
+    {
+        self.0.add_trailer(name, value)
+    }
+    /// Writes the trailer fields and finishes the message
+    ///
+    /// All the fields are validated before anything is written, so on
+    /// error the message is left intact. See `add_trailer()` for details.
+    ///
+    /// # Panics
+    ///
+    /// When message is in the wrong state or when Content-Length bytes
+    /// are not written yet
+    pub fn done_with_trailers(&mut self, trailers: &[(&str, &[u8])])
+        -> Result<(), HeaderError>
+    {
+        self.0.done_with_trailers(trailers)
+    }
     /// Returns true if `done()` method is already called and everything
     /// was okay.
     pub fn is_complete(&self) -> bool {
@@ -0,0 +1,188 @@
+//! The value of the `Date` header
+//!
+//! Formatting the date for every response is wasteful, so the value is
+//! cached per thread (i.e. per event loop). The system clock is read once
+//! per event loop iteration by `refresh()`, and the value is only
+//! reformatted when the second changes.
+use std::cell::RefCell;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use rotor::Time;
+
+
+const DAYS: [&'static str; 7] = ["Sun", "Mon", "Tue", "Wed", "Thu", "Fri", "Sat"];
+const MONTHS: [&'static str; 12] = ["Jan", "Feb", "Mar", "Apr", "May", "Jun",
+                                   "Jul", "Aug", "Sep", "Oct", "Nov", "Dec"];
+
+struct Cache {
+    /// The event loop time of the last `refresh()`
+    tick: Time,
+    /// The unix timestamp of `value`
+    secs: u64,
+    value: String,
+}
+
+thread_local!(static CACHE: RefCell<Option<Cache>> = RefCell::new(None));
+
+/// Formats the IMF-fixdate, e.g. `Sun, 06 Nov 1994 08:49:37 GMT`
+pub fn format_date(timestamp: u64) -> String {
+    let secs = timestamp % 86400;
+    let days = timestamp / 86400;
+    // Civil date from the number of days since 1970-01-01, the algorithm
+    // counts years from March, so leap day is the last day of a year
+    let z = days + 719468;
+    let era = z / 146097;
+    let doe = z - era * 146097;
+    let yoe = (doe - doe/1460 + doe/36524 - doe/146096) / 365;
+    let doy = doe - (365*yoe + yoe/4 - yoe/100);
+    let mp = (5*doy + 2) / 153;
+    let day = doy - (153*mp + 2)/5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + if month <= 2 { 1 } else { 0 };
+    format!("{}, {:02} {} {} {:02}:{:02}:{:02} GMT",
+        // 1970-01-01 is Thursday
+        DAYS[((days + 4) % 7) as usize], day, MONTHS[(month - 1) as usize],
+        year, secs / 3600, secs % 3600 / 60, secs % 60)
+}
+
+fn number(digits: &[u8]) -> Option<u64> {
+    if digits.iter().all(|&x| x >= b'0' && x <= b'9') {
+        Some(digits.iter().fold(0, |acc, &x| acc*10 + (x - b'0') as u64))
+    } else {
+        None
+    }
+}
+
+/// Parses the IMF-fixdate, returns the unix timestamp
+///
+/// Obsolete RFC 850 and asctime formats are not supported, `None` is
+/// returned for them as well as for any invalid date. The day of the week
+/// is not checked.
+pub fn parse_date(value: &str) -> Option<u64> {
+    let b = value.trim().as_bytes();
+    if b.len() != 29 || &b[3..5] != b", " || b[7] != b' ' || b[11] != b' ' ||
+        b[16] != b' ' || b[19] != b':' || b[22] != b':' || &b[25..] != b" GMT"
+    {
+        return None;
+    }
+    let month = match MONTHS.iter().position(|m| m.as_bytes() == &b[8..11]) {
+        Some(idx) => idx as u64 + 1,
+        None => return None,
+    };
+    let (day, year, h, m, s) = match (number(&b[5..7]), number(&b[12..16]),
+        number(&b[17..19]), number(&b[20..22]), number(&b[23..25]))
+    {
+        (Some(day), Some(year), Some(h), Some(m), Some(s)) => {
+            (day, year, h, m, s)
+        }
+        _ => return None,
+    };
+    if day < 1 || day > 31 || year < 1970 || h > 23 || m > 59 || s > 60 {
+        return None;
+    }
+    // Inverse of the algorithm in `format_date()`
+    let y = if month <= 2 { year - 1 } else { year };
+    let era = y / 400;
+    let yoe = y - era * 400;
+    let mp = if month > 2 { month - 3 } else { month + 9 };
+    let doy = (153*mp + 2)/5 + day - 1;
+    let doe = yoe * 365 + yoe/4 - yoe/100 + doy;
+    let days = era * 146097 + doe - 719468;
+    Some(days * 86400 + h * 3600 + m * 60 + s)
+}
+
+fn unix_now() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH)
+        .map(|x| x.as_secs()).unwrap_or(0)
+}
+
+/// Updates the cached date unless it's already done at this `tick`
+///
+/// Called with `scope.now()` by every callback of the server protocol, so
+/// responses written at the same iteration of the event loop share the
+/// single clock reading, and the headers written later (e.g. on wakeup)
+/// don't get a stale date.
+pub fn refresh(tick: Time) {
+    CACHE.with(|cache| {
+        let mut cache = cache.borrow_mut();
+        if cache.as_ref().map(|c| c.tick == tick).unwrap_or(false) {
+            return;
+        }
+        let now = unix_now();
+        match *cache {
+            Some(ref mut c) if c.secs == now => c.tick = tick,
+            _ => {
+                *cache = Some(Cache { tick: tick, secs: now,
+                                      value: format_date(now) });
+            }
+        }
+    })
+}
+
+/// Calls `f` with the date formatted for the `Date` header
+///
+/// The date is the one of the last `refresh()`, the clock is only read
+/// here if `refresh()` has never been called in this thread.
+pub fn with_date<F, R>(f: F) -> R
+    where F: FnOnce(&[u8]) -> R
+{
+    CACHE.with(|cache| {
+        match *cache.borrow() {
+            Some(ref cache) => f(cache.value.as_bytes()),
+            None => f(format_date(unix_now()).as_bytes()),
+        }
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use std::time::Duration;
+    use rotor_test::MockLoop;
+    use super::{CACHE, format_date, parse_date, with_date, refresh};
+
+    #[test]
+    fn format() {
+        assert_eq!(format_date(0), "Thu, 01 Jan 1970 00:00:00 GMT");
+        assert_eq!(format_date(784111777), "Sun, 06 Nov 1994 08:49:37 GMT");
+        assert_eq!(format_date(951868799), "Tue, 29 Feb 2000 23:59:59 GMT");
+        assert_eq!(format_date(951868800), "Wed, 01 Mar 2000 00:00:00 GMT");
+        assert_eq!(format_date(1483228799), "Sat, 31 Dec 2016 23:59:59 GMT");
+    }
+
+    #[test]
+    fn parse() {
+        for &ts in &[0, 784111777, 951868799, 951868800, 1483228799] {
+            assert_eq!(parse_date(&format_date(ts)), Some(ts));
+        }
+        assert_eq!(parse_date(" Sun, 06 Nov 1994 08:49:37 GMT "),
+                   Some(784111777));
+        assert_eq!(parse_date("Sunday, 06-Nov-94 08:49:37 GMT"), None);
+        assert_eq!(parse_date("Sun Nov  6 08:49:37 1994"), None);
+        assert_eq!(parse_date("Sun, 06 Foo 1994 08:49:37 GMT"), None);
+        assert_eq!(parse_date("Sun, 06 Nov 1994 24:49:37 GMT"), None);
+        assert_eq!(parse_date("Sun, 06 Nov 19x4 08:49:37 GMT"), None);
+    }
+
+    #[test]
+    fn cached() {
+        let date = with_date(|x| x.to_vec());
+        assert_eq!(date.len(), 29);
+        assert!(date.ends_with(b" GMT"));
+    }
+
+    #[test]
+    fn refreshed_once_per_tick() {
+        let tick = MockLoop::new(()).scope(1).now();
+        refresh(tick);
+        CACHE.with(|cache| {
+            let mut cache = cache.borrow_mut();
+            let cache = cache.as_mut().unwrap();
+            cache.secs = 0;
+            cache.value = format_date(0);
+        });
+        refresh(tick);
+        with_date(|x| assert_eq!(x, b"Thu, 01 Jan 1970 00:00:00 GMT"));
+        refresh(tick + Duration::new(1, 0));
+        with_date(|x| assert!(x != b"Thu, 01 Jan 1970 00:00:00 GMT"));
+    }
+}
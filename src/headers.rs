@@ -1,4 +1,8 @@
 use std::ascii::AsciiExt;
+use std::slice;
+use std::str::{from_utf8, from_utf8_unchecked};
+
+use httparse::Header;
 
 pub fn is_transfer_encoding(val: &str) -> bool {
     val.eq_ignore_ascii_case("Transfer-Encoding")
@@ -16,6 +20,49 @@ pub fn is_expect(val: &str) -> bool {
     val.eq_ignore_ascii_case("Expect")
 }
 
+pub fn is_upgrade(val: &str) -> bool {
+    val.eq_ignore_ascii_case("Upgrade")
+}
+
+pub fn is_host(val: &str) -> bool {
+    val.eq_ignore_ascii_case("Host")
+}
+
+/// Fields which must not be sent in the trailer section
+///
+/// These are either needed for framing, routing or authentication, or
+/// they control how the rest of the message is processed (RFC 7230,
+/// section 4.1.2).
+pub fn is_forbidden_trailer(val: &str) -> bool {
+    const FORBIDDEN: &'static [&'static str] = &[
+        "Transfer-Encoding", "Content-Length", "Trailer", "Host",
+        "Cache-Control", "Expect", "Max-Forwards", "Pragma", "Range", "TE",
+        "Authorization", "Proxy-Authenticate", "Proxy-Authorization",
+        "WWW-Authenticate", "Set-Cookie", "Cookie",
+        "Content-Encoding", "Content-Type", "Content-Range",
+        "Age", "Expires", "Date", "Location", "Retry-After", "Vary",
+        "Warning", "Connection", "Upgrade", "Keep-Alive",
+    ];
+    val.len() > 3 && val.as_bytes()[..3].eq_ignore_ascii_case(b"If-") ||
+        FORBIDDEN.iter().any(|x| val.eq_ignore_ascii_case(x))
+}
+
+// header value is byte sequence
+// we need case insensitive comparison and strip out of the whitespace
+pub fn is_upgrade_token(val: &[u8]) -> bool {
+    let val = trim(val);
+    val.len() == "upgrade".len() &&
+        val.iter().zip(b"upgrade".iter())
+        .all(|(&a, &b)| a.to_ascii_lowercase() == b)
+}
+
+pub fn trim(val: &[u8]) -> &[u8] {
+    let is_ws = |ch: &u8| matches!(*ch, b'\r' | b'\n' | b' ' | b'\t');
+    let start = val.iter().position(|x| !is_ws(x)).unwrap_or(val.len());
+    let end = val.iter().rposition(|x| !is_ws(x)).map(|x| x+1).unwrap_or(0);
+    if start < end { &val[start..end] } else { &val[0..0] }
+}
+
 // header value is byte sequence
 // we need case insensitive comparison and strip out of the whitespace
 pub fn is_close(val: &[u8]) -> bool {
@@ -112,11 +159,356 @@ pub fn is_continue(val: &[u8]) -> bool {
     return true;
 }
 
+/// An iterator over `(name, value)` pairs of headers
+///
+/// Returned by `HeaderMap::iter()`
+#[derive(Debug, Clone)]
+pub struct Fields<'a>(Source<'a>);
+
+#[derive(Debug, Clone)]
+enum Source<'a> {
+    Borrowed(slice::Iter<'a, Header<'a>>),
+    Owned(&'a [u8], slice::Iter<'a, (usize, usize, usize)>),
+}
+
+/// An owned collection of headers
+///
+/// All names and values are stored in a single buffer. Lookup methods are
+/// in the `HeaderLookup` trait, names are case-insensitive.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct HeaderMap {
+    data: Vec<u8>,
+    /// Start of the name, start of the value and end of the value
+    ///
+    /// Names are copied from `str`, so the ranges are valid utf-8
+    index: Vec<(usize, usize, usize)>,
+}
+
+/// An iterator over values of all headers with the name
+///
+/// Returned by `HeaderLookup::get_all()`
+#[derive(Debug, Clone)]
+pub struct Values<'a, 'n> {
+    fields: Fields<'a>,
+    name: &'n str,
+}
+
+/// An iterator over elements of comma-separated header lists
+///
+/// Elements of all headers with the name are concatenated, whitespace is
+/// trimmed and empty elements are skipped. Commas inside quoted strings
+/// don't split elements. Returned by `HeaderLookup::get_list()`.
+#[derive(Debug, Clone)]
+pub struct List<'a, 'n> {
+    values: Values<'a, 'n>,
+    current: Option<&'a [u8]>,
+}
+
+/// The value of the `Content-Type` header or an element of `Accept`
+///
+/// Type and subtype are case-insensitive, use `is()` to compare them.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MediaType<'a> {
+    pub type_: &'a str,
+    pub subtype: &'a str,
+    params: &'a str,
+}
+
+/// An iterator over media ranges of the `Accept` header
+///
+/// Elements which can't be parsed are skipped.
+#[derive(Debug, Clone)]
+pub struct MediaTypes<'a>(List<'a, 'static>);
+
+/// The value of the `Authorization` header
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Authorization<'a> {
+    /// Authentication scheme, e.g. `Basic` or `Bearer` (case-insensitive)
+    pub scheme: &'a str,
+    /// The rest of the value (token or parameters), may be empty
+    pub credentials: &'a str,
+}
+
+/// Lookup methods shared by the request and response heads
+///
+/// Only `fields()` has to be implemented. The trait is implemented for
+/// references, so the values borrow the headers rather than the head
+/// itself. Header names are case-insensitive in all of the methods.
+///
+/// Server and client heads have the same methods as inherent ones, so the
+/// trait needs to be imported only to use `HeaderMap` or for generic code.
+pub trait HeaderLookup<'a>: Sized {
+    /// Iterates over `(name, value)` pairs in the original order
+    fn fields(self) -> Fields<'a>;
+    /// Returns the value of the first header with the name
+    fn get(self, name: &str) -> Option<&'a [u8]> {
+        get(self.fields(), name)
+    }
+    /// Iterates over values of all headers with the name
+    fn get_all<'n>(self, name: &'n str) -> Values<'a, 'n> {
+        values(self.fields(), name)
+    }
+    /// Returns true if there is a header with the name
+    fn contains(self, name: &str) -> bool {
+        self.get(name).is_some()
+    }
+    /// Iterates over elements of comma-separated lists of all headers with
+    /// the name
+    fn get_list<'n>(self, name: &'n str) -> List<'a, 'n> {
+        list(self.fields(), name)
+    }
+    /// The value of the `Host` header
+    fn host(self) -> Option<&'a str> {
+        get_str(self.fields(), "Host")
+    }
+    /// The value of the `Content-Type` header
+    fn content_type(self) -> Option<MediaType<'a>> {
+        get_str(self.fields(), "Content-Type").and_then(MediaType::parse)
+    }
+    /// The value of the `User-Agent` header
+    fn user_agent(self) -> Option<&'a str> {
+        get_str(self.fields(), "User-Agent")
+    }
+    /// Iterates over media ranges of all `Accept` headers
+    fn accept(self) -> MediaTypes<'a> {
+        MediaTypes::new(self.fields())
+    }
+    /// The value of the `Authorization` header
+    fn authorization(self) -> Option<Authorization<'a>> {
+        get_str(self.fields(), "Authorization")
+            .and_then(Authorization::parse)
+    }
+}
+
+pub fn values<'a, 'n>(fields: Fields<'a>, name: &'n str) -> Values<'a, 'n> {
+    Values { fields: fields, name: name }
+}
+
+pub fn list<'a, 'n>(fields: Fields<'a>, name: &'n str) -> List<'a, 'n> {
+    List { values: values(fields, name), current: None }
+}
+
+/// Returns the value of the first header with the name
+pub fn get<'a>(fields: Fields<'a>, name: &str) -> Option<&'a [u8]> {
+    values(fields, name).next()
+}
+
+/// Returns the trimmed value of the first header if it's valid utf-8
+pub fn get_str<'a>(fields: Fields<'a>, name: &str) -> Option<&'a str> {
+    get(fields, name).and_then(|x| from_utf8(trim(x)).ok())
+}
+
+impl<'a> Fields<'a> {
+    pub fn new(headers: &'a [Header<'a>]) -> Fields<'a> {
+        Fields(Source::Borrowed(headers.iter()))
+    }
+}
+
+impl<'a> Iterator for Fields<'a> {
+    type Item = (&'a str, &'a [u8]);
+    fn next(&mut self) -> Option<(&'a str, &'a [u8])> {
+        match self.0 {
+            Source::Borrowed(ref mut iter) => {
+                iter.next().map(|h| (h.name, h.value))
+            }
+            Source::Owned(data, ref mut iter) => {
+                iter.next().map(|&(name, value, end)| {
+                    // names are copied from `str` in `HeaderMap::new`
+                    let name = unsafe {
+                        from_utf8_unchecked(&data[name..value])
+                    };
+                    (name, &data[value..end])
+                })
+            }
+        }
+    }
+}
+
+impl<'a, 'n> Iterator for Values<'a, 'n> {
+    type Item = &'a [u8];
+    fn next(&mut self) -> Option<&'a [u8]> {
+        let name = self.name;
+        self.fields.by_ref()
+            .find(|&(n, _)| n.eq_ignore_ascii_case(name))
+            .map(|(_, value)| value)
+    }
+}
+
+impl HeaderMap {
+    /// Copies the headers into a single buffer
+    pub fn new(headers: &[Header]) -> HeaderMap {
+        let size = headers.iter().map(|h| h.name.len() + h.value.len()).sum();
+        let mut data = Vec::with_capacity(size);
+        let mut index = Vec::with_capacity(headers.len());
+        for h in headers {
+            let start = data.len();
+            data.extend(h.name.as_bytes());
+            let value = data.len();
+            data.extend(h.value);
+            index.push((start, value, data.len()));
+        }
+        HeaderMap { data: data, index: index }
+    }
+    /// The number of headers
+    pub fn len(&self) -> usize {
+        self.index.len()
+    }
+    /// Iterates over `(name, value)` pairs in the original order
+    pub fn iter(&self) -> Fields {
+        Fields(Source::Owned(&self.data, self.index.iter()))
+    }
+}
+
+impl<'a> HeaderLookup<'a> for &'a HeaderMap {
+    fn fields(self) -> Fields<'a> {
+        self.iter()
+    }
+}
+
+impl<'a, 'n> Iterator for List<'a, 'n> {
+    type Item = &'a [u8];
+    fn next(&mut self) -> Option<&'a [u8]> {
+        loop {
+            let value = match self.current.take() {
+                Some(value) => value,
+                None => match self.values.next() {
+                    Some(value) => value,
+                    None => return None,
+                },
+            };
+            let item = match find_comma(value) {
+                Some(idx) => {
+                    self.current = Some(&value[idx+1..]);
+                    &value[..idx]
+                }
+                None => value,
+            };
+            let item = trim(item);
+            if item.len() > 0 {
+                return Some(item);
+            }
+        }
+    }
+}
+
+/// Position of the first comma which is not inside of a quoted string
+fn find_comma(value: &[u8]) -> Option<usize> {
+    let mut quoted = false;
+    let mut escaped = false;
+    for (idx, &ch) in value.iter().enumerate() {
+        if escaped {
+            escaped = false;
+        } else if quoted {
+            match ch {
+                b'\\' => escaped = true,
+                b'"' => quoted = false,
+                _ => {}
+            }
+        } else {
+            match ch {
+                b'"' => quoted = true,
+                b',' => return Some(idx),
+                _ => {}
+            }
+        }
+    }
+    None
+}
+
+impl<'a> MediaType<'a> {
+    /// Parses `type/subtype; name=value; ...`
+    pub fn parse(value: &'a str) -> Option<MediaType<'a>> {
+        let (essence, params) = match value.find(';') {
+            Some(idx) => (&value[..idx], &value[idx+1..]),
+            None => (value, ""),
+        };
+        let mut parts = essence.trim().splitn(2, '/');
+        let type_ = parts.next().unwrap_or("");
+        let subtype = parts.next().unwrap_or("");
+        if type_.len() == 0 || subtype.len() == 0 ||
+            type_.contains(char::is_whitespace) ||
+            subtype.contains(char::is_whitespace)
+        {
+            return None;
+        }
+        Some(MediaType { type_: type_, subtype: subtype, params: params })
+    }
+    /// Returns true if the media type is `type_/subtype` (case-insensitive)
+    pub fn is(&self, type_: &str, subtype: &str) -> bool {
+        self.type_.eq_ignore_ascii_case(type_) &&
+            self.subtype.eq_ignore_ascii_case(subtype)
+    }
+    /// Returns the value of the parameter, quotes are stripped
+    pub fn param(&self, name: &str) -> Option<&'a str> {
+        self.params.split(';')
+            .filter_map(|p| {
+                let mut pair = p.splitn(2, '=');
+                let key = pair.next().unwrap_or("").trim();
+                pair.next().map(|value| (key, value.trim()))
+            })
+            .find(|&(key, _)| key.eq_ignore_ascii_case(name))
+            .map(|(_, value)| {
+                if value.len() >= 2 && value.starts_with('"') &&
+                    value.ends_with('"')
+                {
+                    &value[1..value.len()-1]
+                } else {
+                    value
+                }
+            })
+    }
+}
+
+impl<'a> MediaTypes<'a> {
+    pub fn new(fields: Fields<'a>) -> MediaTypes<'a> {
+        MediaTypes(list(fields, "Accept"))
+    }
+}
+
+impl<'a> Iterator for MediaTypes<'a> {
+    type Item = MediaType<'a>;
+    fn next(&mut self) -> Option<MediaType<'a>> {
+        self.0.by_ref()
+            .filter_map(|x| from_utf8(x).ok().and_then(MediaType::parse))
+            .next()
+    }
+}
+
+impl<'a> Authorization<'a> {
+    /// Parses `scheme credentials`
+    pub fn parse(value: &'a str) -> Option<Authorization<'a>> {
+        let value = value.trim();
+        let (scheme, credentials) = match value.find(char::is_whitespace) {
+            Some(idx) => (&value[..idx], value[idx..].trim()),
+            None => (value, ""),
+        };
+        if scheme.len() == 0 {
+            return None;
+        }
+        Some(Authorization { scheme: scheme, credentials: credentials })
+    }
+}
+
 #[cfg(test)]
 mod test {
     use super::{is_content_length, is_transfer_encoding, is_connection};
-    use super::{is_expect};
-    use super::{is_chunked, is_close, is_continue};
+    use super::{is_expect, is_upgrade};
+    use super::{is_chunked, is_close, is_continue, is_upgrade_token};
+    use super::is_forbidden_trailer;
+    use httparse::Header;
+    use super::{get, get_str, values, list, MediaType, MediaTypes};
+    use super::{Authorization, Fields, HeaderMap, HeaderLookup};
+
+    fn headers() -> Vec<Header<'static>> {
+        vec![
+            Header { name: "Host", value: b" example.com " },
+            Header { name: "Accept", value: b"text/html, application/json;q=0.9" },
+            Header { name: "X-List", value: b"a, b,," },
+            Header { name: "accept", value: b"*/*; q=0.1, bad" },
+            Header { name: "x-list", value: b" c" },
+            Header { name: "Authorization", value: b"Bearer  abc=" },
+        ]
+    }
 
     #[test]
     fn test_content_len() {
@@ -150,6 +542,26 @@ mod test {
         assert!(is_expect("ExpECT"));
     }
 
+    #[test]
+    fn test_upgrade() {
+        assert!(is_upgrade("Upgrade"));
+        assert!(is_upgrade("UPGRADE"));
+        assert!(is_upgrade("upgrade"));
+        assert!(!is_upgrade("Upgrade-Insecure-Requests"));
+    }
+
+    #[test]
+    fn test_forbidden_trailer() {
+        assert!(is_forbidden_trailer("Content-Length"));
+        assert!(is_forbidden_trailer("transfer-encoding"));
+        assert!(is_forbidden_trailer("TRAILER"));
+        assert!(is_forbidden_trailer("If-None-Match"));
+        assert!(is_forbidden_trailer("if-modified-since"));
+        assert!(!is_forbidden_trailer("X-Checksum"));
+        assert!(!is_forbidden_trailer("Server-Timing"));
+        assert!(!is_forbidden_trailer("If"));
+    }
+
     #[test]
     fn test_chunked() {
         assert!(is_chunked(b"chunked"));
@@ -186,4 +598,93 @@ mod test {
         assert!(!is_continue(b"100-continue y  "));
         assert!(!is_continue(b"100-coztinue   "));
     }
+
+    #[test]
+    fn test_upgrade_token() {
+        assert!(is_upgrade_token(b"upgrade"));
+        assert!(is_upgrade_token(b"Upgrade"));
+        assert!(is_upgrade_token(b"UPGRADE"));
+        assert!(is_upgrade_token(b"  Upgrade "));
+        assert!(!is_upgrade_token(b"upgrade 1"));
+        assert!(!is_upgrade_token(b"keep-alive"));
+        assert!(!is_upgrade_token(b""));
+    }
+
+    #[test]
+    fn test_lookup() {
+        let headers = headers();
+        let fields = Fields::new(&headers);
+        assert_eq!(get(fields.clone(), "HOST"), Some(&b" example.com "[..]));
+        assert_eq!(get_str(fields.clone(), "host"), Some("example.com"));
+        assert_eq!(get(fields.clone(), "Cookie"), None);
+        assert_eq!(values(fields.clone(), "X-LIST").collect::<Vec<_>>(),
+                   vec![&b"a, b,,"[..], &b" c"[..]]);
+        assert_eq!(list(fields.clone(), "x-list").collect::<Vec<_>>(),
+                   vec![&b"a"[..], &b"b"[..], &b"c"[..]]);
+        assert_eq!(list(fields, "Cookie").count(), 0);
+    }
+
+    #[test]
+    fn test_quoted_list() {
+        let headers = [
+            Header { name: "Accept",
+                     value: b"text/html;foo=\"a,b\", text/plain" },
+            Header { name: "X-List", value: b"\"a\\\",b\",c, \"d" },
+        ];
+        let fields = Fields::new(&headers);
+        assert_eq!(list(fields.clone(), "Accept").collect::<Vec<_>>(),
+                   vec![&b"text/html;foo=\"a,b\""[..], &b"text/plain"[..]]);
+        assert_eq!(list(fields.clone(), "X-List").collect::<Vec<_>>(),
+                   vec![&b"\"a\\\",b\""[..], &b"c"[..], &b"\"d"[..]]);
+        let accept = MediaTypes::new(fields).collect::<Vec<_>>();
+        assert_eq!(accept.len(), 2);
+        assert_eq!(accept[0].param("foo"), Some("a,b"));
+    }
+
+    #[test]
+    fn test_media_type() {
+        let mime = MediaType::parse("Text/HTML; charset=\"utf-8\"").unwrap();
+        assert!(mime.is("text", "html"));
+        assert_eq!(mime.param("Charset"), Some("utf-8"));
+        assert_eq!(mime.param("q"), None);
+        assert!(MediaType::parse("text").is_none());
+        assert!(MediaType::parse("text/").is_none());
+        let headers = headers();
+        let accept = MediaTypes::new(Fields::new(&headers))
+            .collect::<Vec<_>>();
+        assert_eq!(accept.len(), 3);
+        assert!(accept[1].is("application", "json"));
+        assert_eq!(accept[1].param("q"), Some("0.9"));
+        assert!(accept[2].is("*", "*"));
+    }
+
+    #[test]
+    fn test_authorization() {
+        assert_eq!(Authorization::parse("Bearer  abc="),
+            Some(Authorization { scheme: "Bearer", credentials: "abc=" }));
+        assert_eq!(Authorization::parse("Negotiate"),
+            Some(Authorization { scheme: "Negotiate", credentials: "" }));
+        assert_eq!(Authorization::parse(" "), None);
+    }
+
+    #[test]
+    fn test_header_map() {
+        let map = HeaderMap::new(&headers());
+        assert_eq!(map.len(), 6);
+        assert_eq!(map.iter().next(), Some(("Host", &b" example.com "[..])));
+        assert_eq!(map.iter().map(|(n, _)| n).collect::<Vec<_>>(),
+                   vec!["Host", "Accept", "X-List", "accept", "x-list",
+                        "Authorization"]);
+        assert_eq!(map.iter().last(),
+                   Some(("Authorization", &b"Bearer  abc="[..])));
+        assert_eq!(map.host(), Some("example.com"));
+        assert!(map.contains("x-LIST"));
+        assert!(!map.contains("Cookie"));
+        assert_eq!(map.get_all("accept").count(), 2);
+        assert_eq!(map.get_list("X-List").collect::<Vec<_>>(),
+                   vec![&b"a"[..], &b"b"[..], &b"c"[..]]);
+        assert_eq!(map.accept().count(), 3);
+        assert_eq!(map.authorization().map(|x| x.credentials), Some("abc="));
+        assert_eq!(HeaderMap::new(&[]).iter().count(), 0);
+    }
 }
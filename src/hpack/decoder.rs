@@ -0,0 +1,272 @@
+use std::str::{from_utf8, Utf8Error};
+
+use httparse::Header;
+
+use server::MAX_HEADERS_SIZE;
+use super::error::DecodeError;
+use super::huffman;
+use super::table::{DynamicTable, ENTRY_OVERHEAD};
+
+
+/// A list of decoded headers
+///
+/// All names and values are stored in a single buffer
+#[derive(Debug, Default)]
+pub struct HeaderList {
+    buf: Vec<u8>,
+    /// Offsets of (name start, value start, value end) in the buffer
+    fields: Vec<(usize, usize, usize)>,
+}
+
+/// Iterator over (name, value) pairs of the `HeaderList`
+pub struct Iter<'a> {
+    list: &'a HeaderList,
+    index: usize,
+}
+
+impl HeaderList {
+    pub fn new() -> HeaderList {
+        HeaderList::default()
+    }
+    /// Number of header fields in the list
+    pub fn len(&self) -> usize {
+        self.fields.len()
+    }
+    /// Iterates over (name, value) pairs in the order they were received
+    pub fn iter(&self) -> Iter {
+        Iter { list: self, index: 0 }
+    }
+    /// Returns headers in the form suitable for the `Head` structures
+    ///
+    /// Fails if any header name is not valid utf-8
+    pub fn headers(&self) -> Result<Vec<Header>, Utf8Error> {
+        let mut result = Vec::with_capacity(self.len());
+        for (name, value) in self.iter() {
+            result.push(Header { name: try!(from_utf8(name)), value: value });
+        }
+        Ok(result)
+    }
+    /// Removes all the headers, so the list can be reused
+    pub fn clear(&mut self) {
+        self.buf.clear();
+        self.fields.clear();
+    }
+    fn push(&mut self, name: &[u8], value: &[u8]) {
+        let start = self.buf.len();
+        self.buf.extend(name);
+        let mid = self.buf.len();
+        self.buf.extend(value);
+        self.fields.push((start, mid, self.buf.len()));
+    }
+}
+
+impl<'a> Iterator for Iter<'a> {
+    type Item = (&'a [u8], &'a [u8]);
+    fn next(&mut self) -> Option<(&'a [u8], &'a [u8])> {
+        self.list.fields.get(self.index).map(|&(start, mid, end)| {
+            self.index += 1;
+            (&self.list.buf[start..mid], &self.list.buf[mid..end])
+        })
+    }
+}
+
+/// Decodes an integer with `prefix` bits (RFC 7541 Section 5.1)
+///
+/// Returns the value and number of bytes consumed
+pub fn decode_int(data: &[u8], prefix: u8)
+    -> Result<(usize, usize), DecodeError>
+{
+    let mask = (1usize << prefix) - 1;
+    let first = match data.first() {
+        Some(&x) => x as usize & mask,
+        None => return Err(DecodeError::Truncated),
+    };
+    if first < mask {
+        return Ok((first, 1));
+    }
+    let mut value = first;
+    let mut shift = 0;
+    for (idx, &byte) in data[1..].iter().enumerate() {
+        if shift > 28 {
+            return Err(DecodeError::IntegerOverflow);
+        }
+        value = try!(((byte & 0x7F) as usize).checked_shl(shift)
+            .and_then(|x| value.checked_add(x))
+            .ok_or(DecodeError::IntegerOverflow));
+        shift += 7;
+        if byte & 0x80 == 0 {
+            return Ok((value, idx + 2));
+        }
+    }
+    Err(DecodeError::Truncated)
+}
+
+/// Decodes a string literal (RFC 7541 Section 5.2) into the `out`
+///
+/// Returns number of bytes consumed
+fn decode_string(data: &[u8], out: &mut Vec<u8>)
+    -> Result<usize, DecodeError>
+{
+    let (len, off) = try!(decode_int(data, 7));
+    if data.len() - off < len {
+        return Err(DecodeError::Truncated);
+    }
+    let raw = &data[off..off + len];
+    if data[0] & 0x80 != 0 {
+        try!(huffman::decode(raw, out));
+    } else {
+        out.extend(raw);
+    }
+    Ok(off + len)
+}
+
+/// HPACK decoder, there should be one per connection
+///
+/// The size of the decoded header list is limited to `MAX_HEADERS_SIZE`
+/// by default, computed as described in RFC 7540 Section 6.5.2 (i.e. each
+/// field has 32 bytes of overhead).
+#[derive(Debug)]
+pub struct Decoder {
+    table: DynamicTable,
+    /// The SETTINGS_HEADER_TABLE_SIZE we've sent to the peer
+    max_size: usize,
+    max_list_size: usize,
+}
+
+impl Decoder {
+    /// Creates a decoder with the dynamic table of at most `max_size`
+    ///
+    /// This is the value of SETTINGS_HEADER_TABLE_SIZE sent to the peer
+    pub fn new(max_size: usize) -> Decoder {
+        Decoder {
+            table: DynamicTable::new(max_size),
+            max_size: max_size,
+            max_list_size: MAX_HEADERS_SIZE,
+        }
+    }
+    /// Sets the limit on the decoded size of a single header block
+    pub fn set_max_list_size(&mut self, size: usize) {
+        self.max_list_size = size;
+    }
+    /// Decodes a complete header block appending headers to the `list`
+    ///
+    /// When the header list is too large, the rest of the block is still
+    /// decoded to keep the table in sync, but headers are not added to the
+    /// list.
+    pub fn decode(&mut self, mut data: &[u8], list: &mut HeaderList)
+        -> Result<(), DecodeError>
+    {
+        let mut name = Vec::new();
+        let mut value = Vec::new();
+        let mut headers_seen = false;
+        let mut list_size = 0;
+        while data.len() > 0 {
+            let byte = data[0];
+            if byte & 0x80 != 0 {
+                // Indexed header field
+                let (index, n) = try!(decode_int(data, 7));
+                let (name, value) = try!(self.table.get(index)
+                    .ok_or(DecodeError::BadIndex(index)));
+                list_size += name.len() + value.len() + ENTRY_OVERHEAD;
+                if list_size <= self.max_list_size {
+                    list.push(name, value);
+                }
+                data = &data[n..];
+            } else if byte & 0xE0 == 0x20 {
+                // Dynamic table size update
+                let (size, n) = try!(decode_int(data, 5));
+                if headers_seen {
+                    return Err(DecodeError::LateTableSize);
+                }
+                if size > self.max_size {
+                    return Err(DecodeError::BadTableSize(size));
+                }
+                self.table.set_max_size(size);
+                data = &data[n..];
+                continue;
+            } else {
+                // Literal header field, either with incremental indexing
+                // (6-bit prefix), without indexing or never indexed (4-bit)
+                let indexing = byte & 0xC0 == 0x40;
+                let prefix = if indexing { 6 } else { 4 };
+                let (index, mut n) = try!(decode_int(data, prefix));
+                name.clear();
+                value.clear();
+                if index == 0 {
+                    n += try!(decode_string(&data[n..], &mut name));
+                } else {
+                    let (x, _) = try!(self.table.get(index)
+                        .ok_or(DecodeError::BadIndex(index)));
+                    name.extend(x);
+                }
+                n += try!(decode_string(&data[n..], &mut value));
+                list_size += name.len() + value.len() + ENTRY_OVERHEAD;
+                if list_size <= self.max_list_size {
+                    list.push(&name, &value);
+                }
+                if indexing {
+                    self.table.insert(name.clone(), value.clone());
+                }
+                data = &data[n..];
+            }
+            headers_seen = true;
+        }
+        if list_size > self.max_list_size {
+            return Err(DecodeError::HeaderListTooLarge);
+        }
+        Ok(())
+    }
+    /// The current size of the dynamic table
+    pub fn table_size(&self) -> usize {
+        self.table.size()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{Decoder, HeaderList, decode_int};
+
+    fn decode(dec: &mut Decoder, data: &[u8]) -> Vec<(String, String)> {
+        let mut list = HeaderList::new();
+        dec.decode(data, &mut list).unwrap();
+        list.iter().map(|(n, v)| {
+            (String::from_utf8(n.to_vec()).unwrap(),
+             String::from_utf8(v.to_vec()).unwrap())
+        }).collect()
+    }
+
+    #[test]
+    fn integers() {
+        // RFC 7541 C.1
+        assert_eq!(decode_int(b"\x0a", 5).unwrap(), (10, 1));
+        assert_eq!(decode_int(b"\x1f\x9a\x0a", 5).unwrap(), (1337, 3));
+        assert_eq!(decode_int(b"\x2a", 8).unwrap(), (42, 1));
+        assert!(decode_int(b"\x1f\x9a", 5).is_err());
+        assert!(decode_int(b"\x1f\xff\xff\xff\xff\xff\xff\x01", 5).is_err());
+    }
+
+    #[test]
+    fn literals_and_indexing() {
+        let mut dec = Decoder::new(4096);
+        // Literal with indexing, then indexed reference to the new entry
+        assert_eq!(decode(&mut dec,
+            b"\x40\x0acustom-key\x0dcustom-header\xbe\x82"), vec![
+            ("custom-key".to_string(), "custom-header".to_string()),
+            ("custom-key".to_string(), "custom-header".to_string()),
+            (":method".to_string(), "GET".to_string()),
+        ]);
+        // Never indexed literal with indexed name
+        assert_eq!(decode(&mut dec, b"\x14\x0c/sample/path"), vec![
+            (":path".to_string(), "/sample/path".to_string()),
+        ]);
+    }
+
+    #[test]
+    fn table_size() {
+        let mut dec = Decoder::new(4096);
+        let mut list = HeaderList::new();
+        assert!(dec.decode(b"\x3f\xe2\x1f", &mut list).is_err());
+        assert!(dec.decode(b"\x82\x20", &mut list).is_err());
+        assert!(dec.decode(b"\x20\x82", &mut list).is_ok());
+    }
+}
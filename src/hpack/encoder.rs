@@ -0,0 +1,177 @@
+use std::cmp::min;
+
+use super::huffman;
+use super::table::{DynamicTable, ENTRY_OVERHEAD};
+
+
+/// Encodes an integer with `prefix` bits (RFC 7541 Section 5.1)
+///
+/// The `flags` are put into the bits of the first byte not covered by
+/// the prefix.
+pub fn encode_int(value: usize, prefix: u8, flags: u8, out: &mut Vec<u8>) {
+    let mask = (1usize << prefix) - 1;
+    if value < mask {
+        out.push(flags | value as u8);
+        return;
+    }
+    out.push(flags | mask as u8);
+    let mut rest = value - mask;
+    while rest >= 0x80 {
+        out.push((rest & 0x7F) as u8 | 0x80);
+        rest >>= 7;
+    }
+    out.push(rest as u8);
+}
+
+/// Encodes a string literal (RFC 7541 Section 5.2)
+///
+/// Huffman encoding is used unless it's longer.
+pub fn encode_string(data: &[u8], out: &mut Vec<u8>) {
+    let huffman_len = huffman::encoded_len(data);
+    if huffman_len <= data.len() {
+        encode_int(huffman_len, 7, 0x80, out);
+        huffman::encode(data, out);
+    } else {
+        encode_int(data.len(), 7, 0, out);
+        out.extend(data);
+    }
+}
+
+/// The table size we start with and never exceed
+///
+/// This is the protocol default, the peer may only allow smaller table.
+const DEFAULT_TABLE_SIZE: usize = 4096;
+
+/// HPACK encoder, there should be one per connection
+///
+/// All fields are added to the dynamic table, except credentials which
+/// are sent as never indexed literals.
+#[derive(Debug)]
+pub struct Encoder {
+    table: DynamicTable,
+    /// Size updates to send at the start of the next block
+    /// (smallest size, final size)
+    size_update: Option<(usize, usize)>,
+}
+
+fn is_sensitive(name: &[u8]) -> bool {
+    name == b"authorization" || name == b"proxy-authorization"
+}
+
+impl Encoder {
+    pub fn new() -> Encoder {
+        Encoder {
+            table: DynamicTable::new(DEFAULT_TABLE_SIZE),
+            size_update: None,
+        }
+    }
+    /// Applies SETTINGS_HEADER_TABLE_SIZE received from the peer
+    ///
+    /// The change is signalled to the peer in the next header block
+    pub fn set_max_size(&mut self, size: usize) {
+        let size = min(size, DEFAULT_TABLE_SIZE);
+        if size == self.table.max_size() {
+            return;
+        }
+        self.table.set_max_size(size);
+        self.size_update = Some(match self.size_update {
+            Some((smallest, _)) => (min(smallest, size), size),
+            None => (size, size),
+        });
+    }
+    /// Encodes a complete header block appending it to the `out`
+    ///
+    /// Header names must be lowercase already
+    pub fn encode<'x, I>(&mut self, headers: I, out: &mut Vec<u8>)
+        where I: IntoIterator<Item=(&'x [u8], &'x [u8])>
+    {
+        if let Some((smallest, size)) = self.size_update.take() {
+            if smallest < size {
+                encode_int(smallest, 5, 0x20, out);
+            }
+            encode_int(size, 5, 0x20, out);
+        }
+        for (name, value) in headers {
+            self.encode_field(name, value, out);
+        }
+    }
+    fn encode_field(&mut self, name: &[u8], value: &[u8], out: &mut Vec<u8>) {
+        let name_index = match self.table.find(name, value) {
+            Some((index, true)) => {
+                // Indexed header field
+                encode_int(index, 7, 0x80, out);
+                return;
+            }
+            Some((index, false)) => index,
+            None => 0,
+        };
+        let size = name.len() + value.len() + ENTRY_OVERHEAD;
+        if is_sensitive(name) {
+            // Literal header field never indexed
+            encode_int(name_index, 4, 0x10, out);
+        } else if size <= self.table.max_size() {
+            // Literal header field with incremental indexing
+            encode_int(name_index, 6, 0x40, out);
+            self.table.insert(name.to_vec(), value.to_vec());
+        } else {
+            // Literal header field without indexing, as the field would
+            // just empty the table
+            encode_int(name_index, 4, 0, out);
+        }
+        if name_index == 0 {
+            encode_string(name, out);
+        }
+        encode_string(value, out);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use hpack::decoder::{Decoder, HeaderList};
+    use super::{Encoder, encode_int};
+
+    #[test]
+    fn integers() {
+        let mut buf = Vec::new();
+        encode_int(10, 5, 0, &mut buf);
+        encode_int(1337, 5, 0, &mut buf);
+        encode_int(42, 8, 0, &mut buf);
+        assert_eq!(buf, b"\x0a\x1f\x9a\x0a\x2a");
+    }
+
+    #[test]
+    fn roundtrip() {
+        let headers: &[(&[u8], &[u8])] = &[
+            (b":status", b"200"),
+            (b":status", b"201"),
+            (b"content-type", b"text/html"),
+            (b"x-custom", b"value"),
+            (b"authorization", b"secret"),
+            (b"x-custom", b"value"),
+        ];
+        let mut enc = Encoder::new();
+        let mut dec = Decoder::new(4096);
+        for _ in 0..2 {
+            let mut buf = Vec::new();
+            enc.encode(headers.iter().cloned(), &mut buf);
+            assert_eq!(buf[0], 0x88);
+            let mut list = HeaderList::new();
+            dec.decode(&buf, &mut list).unwrap();
+            assert_eq!(list.iter().collect::<Vec<_>>(), headers);
+        }
+    }
+
+    #[test]
+    fn size_update() {
+        let mut enc = Encoder::new();
+        enc.set_max_size(0);
+        enc.set_max_size(100);
+        let mut buf = Vec::new();
+        enc.encode(vec![(&b"x-custom"[..], &b"value"[..])], &mut buf);
+        assert_eq!(&buf[..3], b"\x20\x3f\x45");
+        let mut list = HeaderList::new();
+        let mut dec = Decoder::new(4096);
+        dec.decode(&buf, &mut list).unwrap();
+        assert_eq!(dec.table_size(), 45);
+    }
+}
@@ -0,0 +1,36 @@
+use std::error::Error;
+
+quick_error! {
+    /// Error decoding header block
+    ///
+    /// Any error except `HeaderListTooLarge` is a connection error of type
+    /// `COMPRESSION_ERROR` in HTTP/2, because the decoding context can't be
+    /// kept in sync anymore. When the list is too large the whole block is
+    /// still decoded, so only the request (stream) should be rejected.
+    #[derive(Debug)]
+    pub enum DecodeError {
+        Truncated {
+            description("header block is truncated")
+        }
+        IntegerOverflow {
+            description("integer is too large")
+        }
+        BadIndex(index: usize) {
+            description("invalid table index")
+            display(me) -> ("{}: {}", me.description(), index)
+        }
+        BadHuffman {
+            description("invalid huffman-encoded string")
+        }
+        BadTableSize(size: usize) {
+            description("table size update exceeds the limit")
+            display(me) -> ("{}: {}", me.description(), size)
+        }
+        LateTableSize {
+            description("table size update after header field")
+        }
+        HeaderListTooLarge {
+            description("header list is larger than the limit")
+        }
+    }
+}
@@ -0,0 +1,18 @@
+//! HPACK header compression for HTTP/2 (RFC 7541)
+//!
+//! Both `Decoder` and `Encoder` keep a dynamic table, so there should be
+//! exactly one of each per connection direction, and header blocks must be
+//! processed in the order they are sent.
+//!
+//! Decoded headers can be converted into `httparse::Header` slices with
+//! `HeaderList::headers()`, the same type which is used in `Head`.
+pub use self::decoder::{Decoder, HeaderList, Iter};
+pub use self::encoder::Encoder;
+pub use self::error::DecodeError;
+
+mod huffman;
+mod table;
+mod decoder;
+mod encoder;
+mod error;
+#[cfg(test)] mod rfc7541;
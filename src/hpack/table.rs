@@ -0,0 +1,155 @@
+use std::collections::VecDeque;
+
+
+/// The static table from RFC 7541 Appendix A
+///
+/// Note the table is indexed from one in the protocol.
+pub const STATIC_TABLE: [(&'static str, &'static str); 61] = [
+    (":authority", ""),
+    (":method", "GET"),
+    (":method", "POST"),
+    (":path", "/"),
+    (":path", "/index.html"),
+    (":scheme", "http"),
+    (":scheme", "https"),
+    (":status", "200"),
+    (":status", "204"),
+    (":status", "206"),
+    (":status", "304"),
+    (":status", "400"),
+    (":status", "404"),
+    (":status", "500"),
+    ("accept-charset", ""),
+    ("accept-encoding", "gzip, deflate"),
+    ("accept-language", ""),
+    ("accept-ranges", ""),
+    ("accept", ""),
+    ("access-control-allow-origin", ""),
+    ("age", ""),
+    ("allow", ""),
+    ("authorization", ""),
+    ("cache-control", ""),
+    ("content-disposition", ""),
+    ("content-encoding", ""),
+    ("content-language", ""),
+    ("content-length", ""),
+    ("content-location", ""),
+    ("content-range", ""),
+    ("content-type", ""),
+    ("cookie", ""),
+    ("date", ""),
+    ("etag", ""),
+    ("expect", ""),
+    ("expires", ""),
+    ("from", ""),
+    ("host", ""),
+    ("if-match", ""),
+    ("if-modified-since", ""),
+    ("if-none-match", ""),
+    ("if-range", ""),
+    ("if-unmodified-since", ""),
+    ("last-modified", ""),
+    ("link", ""),
+    ("location", ""),
+    ("max-forwards", ""),
+    ("proxy-authenticate", ""),
+    ("proxy-authorization", ""),
+    ("range", ""),
+    ("referer", ""),
+    ("refresh", ""),
+    ("retry-after", ""),
+    ("server", ""),
+    ("set-cookie", ""),
+    ("strict-transport-security", ""),
+    ("transfer-encoding", ""),
+    ("user-agent", ""),
+    ("vary", ""),
+    ("via", ""),
+    ("www-authenticate", ""),
+];
+
+/// The size of the entry overhead as defined in RFC 7541 Section 4.1
+pub const ENTRY_OVERHEAD: usize = 32;
+
+/// A dynamic table, newest entries first
+#[derive(Debug)]
+pub struct DynamicTable {
+    entries: VecDeque<(Vec<u8>, Vec<u8>)>,
+    size: usize,
+    max_size: usize,
+}
+
+impl DynamicTable {
+    pub fn new(max_size: usize) -> DynamicTable {
+        DynamicTable {
+            entries: VecDeque::new(),
+            size: 0,
+            max_size: max_size,
+        }
+    }
+    /// Returns entry by the protocol index (i.e. static table included)
+    pub fn get(&self, index: usize) -> Option<(&[u8], &[u8])> {
+        if index == 0 {
+            None
+        } else if index <= STATIC_TABLE.len() {
+            let (name, value) = STATIC_TABLE[index - 1];
+            Some((name.as_bytes(), value.as_bytes()))
+        } else {
+            self.entries.get(index - STATIC_TABLE.len() - 1)
+                .map(|&(ref name, ref value)| (&name[..], &value[..]))
+        }
+    }
+    /// Adds an entry evicting old ones as needed
+    ///
+    /// Entry which is larger than the table just empties the table
+    pub fn insert(&mut self, name: Vec<u8>, value: Vec<u8>) {
+        let size = name.len() + value.len() + ENTRY_OVERHEAD;
+        while self.size + size > self.max_size && self.entries.len() > 0 {
+            self.evict();
+        }
+        if size <= self.max_size {
+            self.size += size;
+            self.entries.push_front((name, value));
+        }
+    }
+    /// Finds the best match for the header field
+    ///
+    /// Returns the protocol index and whether the value matches too. Exact
+    /// match is preferred, then the static table.
+    pub fn find(&self, name: &[u8], value: &[u8]) -> Option<(usize, bool)> {
+        let mut name_index = None;
+        let statics = STATIC_TABLE.iter()
+            .map(|&(n, v)| (n.as_bytes(), v.as_bytes()));
+        let dynamics = self.entries.iter()
+            .map(|&(ref n, ref v)| (&n[..], &v[..]));
+        for (idx, (n, v)) in statics.chain(dynamics).enumerate() {
+            if n == name {
+                if v == value {
+                    return Some((idx + 1, true));
+                }
+                if name_index.is_none() {
+                    name_index = Some(idx + 1);
+                }
+            }
+        }
+        name_index.map(|idx| (idx, false))
+    }
+    /// The size of the table as defined in RFC 7541 Section 4.1
+    pub fn size(&self) -> usize {
+        self.size
+    }
+    pub fn max_size(&self) -> usize {
+        self.max_size
+    }
+    pub fn set_max_size(&mut self, max_size: usize) {
+        self.max_size = max_size;
+        while self.size > self.max_size {
+            self.evict();
+        }
+    }
+    fn evict(&mut self) {
+        let (name, value) = self.entries.pop_back()
+            .expect("size is non-zero so table is non-empty");
+        self.size -= name.len() + value.len() + ENTRY_OVERHEAD;
+    }
+}
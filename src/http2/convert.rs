@@ -0,0 +1,309 @@
+//! Conversion of HTTP/1 formatted messages into HTTP/2 frames
+//!
+//! `Response` and `Request` objects write messages in HTTP/1 format
+//! regardless of the protocol. For HTTP/2 streams they write into a
+//! per-stream buffer, and the head is then re-encoded with HPACK while
+//! the body is stripped of chunked encoding and is sent in DATA frames.
+//! Trailer fields of the chunked body are sent in the final HEADERS frame.
+use std::cmp::min;
+use std::ascii::AsciiExt;
+use std::str::from_utf8;
+
+use httparse::{Status, parse_chunk_size};
+use rotor_stream::Buf;
+
+use headers;
+use hpack::Encoder;
+use super::frame;
+
+
+/// Message head taken from the buffer
+#[derive(Debug)]
+pub struct RawHead {
+    /// The status line or the request line
+    pub line: String,
+    /// Headers with lowercase names
+    pub headers: Vec<(String, Vec<u8>)>,
+}
+
+/// Progress of converting the message body
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BodyProgress {
+    /// Fixed-size body (bytes left)
+    Fixed(u64),
+    /// Chunked body, waiting for the chunk size line
+    ChunkSize,
+    /// Chunked body (bytes left in the current chunk)
+    ChunkData(u64),
+    /// Chunked body, the last chunk is received, waiting for the trailers
+    Trailers,
+    /// Whole body is in the buffer
+    Done,
+}
+
+fn find(haystack: &[u8], needle: &[u8]) -> Option<usize> {
+    haystack.windows(needle.len()).position(|x| x == needle)
+}
+
+/// Headers which are not allowed in HTTP/2
+pub fn is_connection_specific(name: &str) -> bool {
+    headers::is_connection(name) ||
+        headers::is_transfer_encoding(name) ||
+        headers::is_upgrade(name) ||
+        name.eq_ignore_ascii_case("Keep-Alive") ||
+        name.eq_ignore_ascii_case("Proxy-Connection")
+}
+
+/// Splits the header fields into names (lowercased) and values
+fn parse_fields(block: &[u8]) -> Vec<(String, Vec<u8>)> {
+    let mut headers = Vec::new();
+    let lines = block.split(|&x| x == b'\n')
+        .map(|x| if x.ends_with(b"\r") { &x[..x.len()-1] } else { x });
+    for line in lines {
+        let colon = line.iter().position(|&x| x == b':')
+            .unwrap_or(line.len());
+        let name = from_utf8(&line[..colon]).unwrap_or("");
+        let value = &line[min(colon + 1, line.len())..];
+        let skip = value.iter().take_while(|&&x| x == b' ').count();
+        headers.push((name.to_ascii_lowercase(), value[skip..].to_vec()));
+    }
+    headers
+}
+
+/// Takes a complete message head from the start of the buffer
+///
+/// Returns `None` if the head is not complete yet. The head is written by
+/// `Message` so it's always well-formed.
+pub fn take_head(buf: &mut Buf) -> Option<RawHead> {
+    let end = match find(&buf[..], b"\r\n\r\n") {
+        Some(end) => end,
+        None => return None,
+    };
+    let head = {
+        let line_end = find(&buf[..end+2], b"\r\n").unwrap_or(end);
+        let line = from_utf8(&buf[..line_end]).unwrap_or("").to_string();
+        let headers = if line_end < end {
+            parse_fields(&buf[line_end+2..end])
+        } else {
+            Vec::new()
+        };
+        RawHead { line: line, headers: headers }
+    };
+    buf.consume(end + 4);
+    Some(head)
+}
+
+/// Takes the trailer section which follows the first `ready` bytes
+///
+/// Returns `None` if the section is not complete yet. Everything after
+/// the section is dropped, like anything after the last chunk.
+pub fn take_trailers(buf: &mut Buf, ready: usize)
+    -> Option<Vec<(String, Vec<u8>)>>
+{
+    let trailers = if buf[ready..].starts_with(b"\r\n") {
+        Vec::new()
+    } else {
+        match find(&buf[ready..], b"\r\n\r\n") {
+            Some(end) => parse_fields(&buf[ready..ready+end]),
+            None => return None,
+        }
+    };
+    let end = buf.len();
+    buf.remove_range(ready..end);
+    Some(trailers)
+}
+
+impl BodyProgress {
+    /// Determines the body size from the headers written by `Message`
+    ///
+    /// `Message` only allows `Transfer-Encoding: chunked` so the value
+    /// isn't checked.
+    pub fn new(headers: &[(String, Vec<u8>)]) -> BodyProgress {
+        if headers.iter().any(|&(ref name, _)| name == "transfer-encoding") {
+            return BodyProgress::ChunkSize;
+        }
+        headers.iter()
+            .find(|&&(ref name, _)| name == "content-length")
+            .and_then(|&(_, ref value)| from_utf8(value).ok())
+            .and_then(|x| x.parse().ok())
+            .map(|x| if x > 0 { BodyProgress::Fixed(x) }
+                     else { BodyProgress::Done })
+            .unwrap_or(BodyProgress::Done)
+    }
+    /// Strips chunked encoding from the data after the first `ready` bytes
+    ///
+    /// Returns the number of bytes at the start of the buffer which are
+    /// the body data.
+    pub fn advance(&mut self, buf: &mut Buf, mut ready: usize) -> usize {
+        use self::BodyProgress::*;
+        loop {
+            match *self {
+                Fixed(left) | ChunkData(left) => {
+                    let bytes = min(left, (buf.len() - ready) as u64);
+                    ready += bytes as usize;
+                    *self = match (*self, left - bytes) {
+                        (Fixed(_), 0) => Done,
+                        (Fixed(_), x) => Fixed(x),
+                        (_, 0) => ChunkSize,
+                        (_, x) => ChunkData(x),
+                    };
+                    if *self != ChunkSize {
+                        return ready;
+                    }
+                }
+                ChunkSize => {
+                    // Skip the line end after the previous chunk
+                    if buf[ready..].starts_with(b"\r\n") {
+                        buf.remove_range(ready..ready+2);
+                    }
+                    match parse_chunk_size(&buf[ready..]) {
+                        Ok(Status::Complete((bytes, 0))) => {
+                            buf.remove_range(ready..ready+bytes);
+                            *self = Trailers;
+                        }
+                        Err(_) => {
+                            // Everything after the bad chunk is dropped
+                            let end = buf.len();
+                            buf.remove_range(ready..end);
+                            *self = Done;
+                        }
+                        Ok(Status::Complete((bytes, size))) => {
+                            buf.remove_range(ready..ready+bytes);
+                            *self = ChunkData(size);
+                        }
+                        Ok(Status::Partial) => return ready,
+                    }
+                }
+                Trailers | Done => return ready,
+            }
+        }
+    }
+}
+
+/// Sends the message body which is in the buffer as DATA frames
+#[derive(Debug)]
+pub struct BodySender {
+    progress: BodyProgress,
+    /// Number of bytes of the body at the start of the buffer
+    ready: usize,
+    /// Trailer fields, sent after the body
+    trailers: Vec<(String, Vec<u8>)>,
+}
+
+impl BodySender {
+    pub fn new(progress: BodyProgress) -> BodySender {
+        BodySender { progress: progress, ready: 0, trailers: Vec::new() }
+    }
+    /// Writes DATA frames for the stream `id` into `out`
+    ///
+    /// Frames are limited by both the stream and the connection flow
+    /// control windows, the rest of the body stays in the `buf` until
+    /// WINDOW_UPDATE is received. Trailers, if any, are encoded with the
+    /// `encoder` into the HEADERS frame which ends the stream. Returns
+    /// `true` when END_STREAM is sent.
+    pub fn send(&mut self, buf: &mut Buf, out: &mut Buf, id: u32,
+        encoder: &mut Encoder, stream_window: &mut i64, conn_window: &mut i64,
+        max_frame_size: usize)
+        -> bool
+    {
+        self.ready = self.progress.advance(buf, self.ready);
+        if self.progress == BodyProgress::Trailers {
+            if let Some(trailers) = take_trailers(buf, self.ready) {
+                self.trailers = trailers;
+                self.progress = BodyProgress::Done;
+            }
+        }
+        loop {
+            let window = min(*stream_window, *conn_window);
+            let bytes = min(min(self.ready, max_frame_size),
+                            if window > 0 { window as usize } else { 0 });
+            let end = self.progress == BodyProgress::Done &&
+                      bytes == self.ready;
+            if bytes == 0 && !end {
+                return false;
+            }
+            let end_data = end && self.trailers.is_empty();
+            if bytes > 0 || end_data {
+                frame::write_data(out, id, &buf[..bytes], end_data);
+            }
+            buf.consume(bytes);
+            self.ready -= bytes;
+            *stream_window -= bytes as i64;
+            *conn_window -= bytes as i64;
+            if end {
+                if !end_data {
+                    let mut block = Vec::new();
+                    encoder.encode(self.trailers.iter()
+                        .map(|&(ref name, ref value)| {
+                            (name.as_bytes(), &value[..])
+                        }), &mut block);
+                    frame::write_headers(out, id, &block, true,
+                                         max_frame_size);
+                }
+                return true;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use rotor_stream::Buf;
+    use super::{take_head, take_trailers, BodyProgress};
+
+    #[test]
+    fn head() {
+        let mut buf = Buf::new();
+        buf.extend(b"HTTP/2 200 OK\r\nContent-Type: text/plain\r\n\
+                     Connection: close\r\nContent-Length: 5\r\n");
+        assert!(take_head(&mut buf).is_none());
+        buf.extend(b"\r\nhello");
+        let head = take_head(&mut buf).unwrap();
+        assert_eq!(head.line, "HTTP/2 200 OK");
+        assert_eq!(head.headers, vec![
+            ("content-type".to_string(), b"text/plain".to_vec()),
+            ("connection".to_string(), b"close".to_vec()),
+            ("content-length".to_string(), b"5".to_vec()),
+        ]);
+        assert_eq!(&buf[..], b"hello");
+        let mut body = BodyProgress::new(&head.headers);
+        assert_eq!(body, BodyProgress::Fixed(5));
+        assert_eq!(body.advance(&mut buf, 0), 5);
+        assert_eq!(body, BodyProgress::Done);
+    }
+
+    #[test]
+    fn chunked() {
+        let mut buf = Buf::new();
+        let mut body = BodyProgress::new(&[
+            ("transfer-encoding".to_string(), b"chunked".to_vec()),
+        ]);
+        buf.extend(b"5\r\nhello");
+        assert_eq!(body.advance(&mut buf, 0), 5);
+        assert_eq!(&buf[..], b"hello");
+        buf.extend(b"\r\n6\r\n world\r\n");
+        assert_eq!(body.advance(&mut buf, 5), 11);
+        assert_eq!(body, BodyProgress::ChunkSize);
+        buf.extend(b"0\r\n\r\n");
+        assert_eq!(body.advance(&mut buf, 11), 11);
+        assert_eq!(body, BodyProgress::Trailers);
+        assert_eq!(take_trailers(&mut buf, 11), Some(vec![]));
+        assert_eq!(&buf[..], b"hello world");
+    }
+
+    #[test]
+    fn trailers() {
+        let mut buf = Buf::new();
+        let mut body = BodyProgress::ChunkSize;
+        buf.extend(b"5\r\nhello\r\n0\r\nX-Checksum: 5d41402a\r\n");
+        assert_eq!(body.advance(&mut buf, 0), 5);
+        assert_eq!(body, BodyProgress::Trailers);
+        assert_eq!(take_trailers(&mut buf, 5), None);
+        buf.extend(b"X-Status: ok\r\n\r\n");
+        assert_eq!(take_trailers(&mut buf, 5), Some(vec![
+            ("x-checksum".to_string(), b"5d41402a".to_vec()),
+            ("x-status".to_string(), b"ok".to_vec()),
+        ]));
+        assert_eq!(&buf[..], b"hello");
+    }
+}
@@ -0,0 +1,231 @@
+use std::cmp::min;
+
+use rotor_stream::Buf;
+
+
+/// The size of the frame header
+pub const HEADER_SIZE: usize = 9;
+
+// Frame types
+pub const DATA: u8 = 0x0;
+pub const HEADERS: u8 = 0x1;
+pub const PRIORITY: u8 = 0x2;
+pub const RST_STREAM: u8 = 0x3;
+pub const SETTINGS: u8 = 0x4;
+pub const PUSH_PROMISE: u8 = 0x5;
+pub const PING: u8 = 0x6;
+pub const GOAWAY: u8 = 0x7;
+pub const WINDOW_UPDATE: u8 = 0x8;
+pub const CONTINUATION: u8 = 0x9;
+
+// Frame flags
+pub const END_STREAM: u8 = 0x1;
+pub const ACK: u8 = 0x1;
+pub const END_HEADERS: u8 = 0x4;
+pub const PADDED: u8 = 0x8;
+pub const PRIORITY_FLAG: u8 = 0x20;
+
+/// Error codes for RST_STREAM and GOAWAY frames
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ErrorCode {
+    NoError = 0x0,
+    ProtocolError = 0x1,
+    InternalError = 0x2,
+    FlowControlError = 0x3,
+    SettingsTimeout = 0x4,
+    StreamClosed = 0x5,
+    FrameSizeError = 0x6,
+    RefusedStream = 0x7,
+    Cancel = 0x8,
+    CompressionError = 0x9,
+    ConnectError = 0xa,
+    EnhanceYourCalm = 0xb,
+    InadequateSecurity = 0xc,
+    Http11Required = 0xd,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FrameHeader {
+    /// Length of the payload
+    pub length: usize,
+    pub kind: u8,
+    pub flags: u8,
+    pub stream_id: u32,
+}
+
+fn read_u32(data: &[u8]) -> u32 {
+    (data[0] as u32) << 24 | (data[1] as u32) << 16 |
+    (data[2] as u32) << 8 | data[3] as u32
+}
+
+fn write_u32(buf: &mut Buf, value: u32) {
+    buf.extend(&[(value >> 24) as u8, (value >> 16) as u8,
+                 (value >> 8) as u8, value as u8]);
+}
+
+impl FrameHeader {
+    /// Parses frame header, `data` must be at least `HEADER_SIZE` bytes
+    pub fn parse(data: &[u8]) -> FrameHeader {
+        FrameHeader {
+            length: (data[0] as usize) << 16 | (data[1] as usize) << 8 |
+                    data[2] as usize,
+            kind: data[3],
+            flags: data[4],
+            stream_id: read_u32(&data[5..9]) & 0x7FFF_FFFF,
+        }
+    }
+    pub fn write(&self, buf: &mut Buf) {
+        buf.extend(&[(self.length >> 16) as u8, (self.length >> 8) as u8,
+                     self.length as u8, self.kind, self.flags]);
+        write_u32(buf, self.stream_id);
+    }
+}
+
+/// Reads a 31-bit value (stream id or window increment)
+pub fn read_u31(data: &[u8]) -> u32 {
+    read_u32(data) & 0x7FFF_FFFF
+}
+
+/// Reads a 32-bit value (error code or setting)
+pub fn read_code(data: &[u8]) -> u32 {
+    read_u32(data)
+}
+
+pub fn write_frame(buf: &mut Buf, kind: u8, flags: u8, stream_id: u32,
+    payload: &[u8])
+{
+    FrameHeader {
+        length: payload.len(),
+        kind: kind,
+        flags: flags,
+        stream_id: stream_id,
+    }.write(buf);
+    buf.extend(payload);
+}
+
+/// Writes a header block splitting it into CONTINUATION frames if needed
+pub fn write_headers(buf: &mut Buf, stream_id: u32, block: &[u8],
+    end_stream: bool, max_frame_size: usize)
+{
+    let first = min(block.len(), max_frame_size);
+    let mut flags = if end_stream { END_STREAM } else { 0 };
+    if first == block.len() {
+        flags |= END_HEADERS;
+    }
+    write_frame(buf, HEADERS, flags, stream_id, &block[..first]);
+    let mut off = first;
+    while off < block.len() {
+        let end = min(block.len(), off + max_frame_size);
+        let flags = if end == block.len() { END_HEADERS } else { 0 };
+        write_frame(buf, CONTINUATION, flags, stream_id, &block[off..end]);
+        off = end;
+    }
+}
+
+pub fn write_data(buf: &mut Buf, stream_id: u32, data: &[u8],
+    end_stream: bool)
+{
+    write_frame(buf, DATA, if end_stream { END_STREAM } else { 0 },
+                stream_id, data);
+}
+
+pub fn write_rst_stream(buf: &mut Buf, stream_id: u32, code: ErrorCode) {
+    FrameHeader {
+        length: 4,
+        kind: RST_STREAM,
+        flags: 0,
+        stream_id: stream_id,
+    }.write(buf);
+    write_u32(buf, code as u32);
+}
+
+pub fn write_window_update(buf: &mut Buf, stream_id: u32, increment: u32) {
+    FrameHeader {
+        length: 4,
+        kind: WINDOW_UPDATE,
+        flags: 0,
+        stream_id: stream_id,
+    }.write(buf);
+    write_u32(buf, increment);
+}
+
+pub fn write_ping(buf: &mut Buf, data: &[u8], ack: bool) {
+    write_frame(buf, PING, if ack { ACK } else { 0 }, 0, data);
+}
+
+pub fn write_goaway(buf: &mut Buf, last_stream_id: u32, code: ErrorCode) {
+    FrameHeader {
+        length: 8,
+        kind: GOAWAY,
+        flags: 0,
+        stream_id: 0,
+    }.write(buf);
+    write_u32(buf, last_stream_id);
+    write_u32(buf, code as u32);
+}
+
+/// Removes padding (and priority fields for HEADERS) from the payload
+pub fn strip_padding<'a>(head: &FrameHeader, payload: &'a [u8])
+    -> Result<&'a [u8], ErrorCode>
+{
+    let mut start = 0;
+    let mut end = payload.len();
+    if head.flags & PADDED != 0 {
+        if payload.len() < 1 {
+            return Err(ErrorCode::FrameSizeError);
+        }
+        start = 1;
+        end = try!(end.checked_sub(payload[0] as usize + 1)
+            .ok_or(ErrorCode::ProtocolError)) + 1;
+    }
+    if head.kind == HEADERS && head.flags & PRIORITY_FLAG != 0 {
+        start += 5;
+    }
+    if start > end {
+        return Err(ErrorCode::FrameSizeError);
+    }
+    Ok(&payload[start..end])
+}
+
+#[cfg(test)]
+mod test {
+    use rotor_stream::Buf;
+    use super::{FrameHeader, strip_padding, write_headers};
+    use super::{HEADERS, CONTINUATION, PADDED, PRIORITY_FLAG, END_HEADERS};
+
+    #[test]
+    fn header() {
+        let head = FrameHeader::parse(b"\x00\x01\x02\x01\x05\x80\x00\x00\x03");
+        assert_eq!(head, FrameHeader {
+            length: 258,
+            kind: HEADERS,
+            flags: 5,
+            stream_id: 3,
+        });
+        let mut buf = Buf::new();
+        head.write(&mut buf);
+        assert_eq!(&buf[..], b"\x00\x01\x02\x01\x05\x00\x00\x00\x03");
+    }
+
+    #[test]
+    fn padding() {
+        let mut head = FrameHeader::parse(b"\x00\x00\x00\x01\x00\x00\x00\x00\x01");
+        head.flags = PADDED | PRIORITY_FLAG;
+        assert_eq!(strip_padding(&head, b"\x02\x00\x00\x00\x01\x10abc\x00\x00")
+                   .unwrap(), b"abc");
+        assert!(strip_padding(&head, b"\x09\x00\x00\x00\x01\x10abc").is_err());
+        head.flags = 0;
+        assert_eq!(strip_padding(&head, b"abc").unwrap(), b"abc");
+    }
+
+    #[test]
+    fn continuation() {
+        let mut buf = Buf::new();
+        write_headers(&mut buf, 1, b"abcde", true, 3);
+        assert_eq!(&buf[..], &b"\x00\x00\x03\x01\x01\x00\x00\x00\x01abc\
+                                \x00\x00\x02\x09\x04\x00\x00\x00\x01de"[..]);
+        let head = FrameHeader::parse(&buf[12..]);
+        assert_eq!(head.kind, CONTINUATION);
+        assert_eq!(head.flags, END_HEADERS);
+    }
+}
@@ -0,0 +1,21 @@
+//! Parts of HTTP/2 which are shared between server and client
+pub use self::settings::Settings;
+pub use self::session::{Session, Event, update_window};
+
+pub mod frame;
+pub mod convert;
+mod settings;
+mod session;
+
+
+/// The client connection preface
+pub const PREFACE: &'static [u8] = b"PRI * HTTP/2.0\r\n\r\nSM\r\n\r\n";
+
+/// The initial (and the minimal maximum) size of the frame payload
+pub const DEFAULT_FRAME_SIZE: usize = 16384;
+
+/// The initial size of the flow control windows
+pub const DEFAULT_WINDOW_SIZE: u32 = 65535;
+
+/// The largest value of the flow control window
+pub const MAX_WINDOW_SIZE: i64 = 0x7FFF_FFFF;
@@ -0,0 +1,349 @@
+use rotor_stream::Buf;
+
+use hpack::{Decoder, Encoder, HeaderList, DecodeError};
+use server::MAX_HEADERS_SIZE;
+use super::frame::{self, FrameHeader, ErrorCode, HEADER_SIZE};
+use super::{Settings, PREFACE, MAX_WINDOW_SIZE};
+
+
+/// A frame which should be handled by the client or server code
+///
+/// Everything which concerns the connection as a whole (settings, pings,
+/// connection flow control, header block reassembly) is handled by the
+/// `Session` itself.
+#[derive(Debug)]
+pub enum Event<'a> {
+    /// Frame is fully processed by the session
+    Handled,
+    /// Complete header block is received
+    ///
+    /// If `too_large` is set the list is truncated, the block is decoded
+    /// anyway to keep the decoder in sync.
+    Headers {
+        id: u32,
+        end_stream: bool,
+        list: HeaderList,
+        too_large: bool,
+    },
+    /// DATA frame with the padding removed
+    ///
+    /// Connection flow control window is already updated, only the
+    /// stream window needs an update.
+    Data {
+        id: u32,
+        data: &'a [u8],
+        length: usize,
+        end_stream: bool,
+    },
+    Reset {
+        id: u32,
+        code: u32,
+    },
+    WindowUpdate {
+        id: u32,
+        increment: u32,
+    },
+    /// SETTINGS_INITIAL_WINDOW_SIZE has changed by the `delta`
+    ///
+    /// The value must be added to the send window of every stream.
+    WindowDelta(i64),
+    /// The peer will not accept (or initiate) streams after `last_id`
+    GoAway {
+        last_id: u32,
+    },
+}
+
+/// The connection-level state of the HTTP/2 protocol
+#[derive(Debug)]
+pub struct Session {
+    /// Settings we've sent to the peer
+    pub settings: Settings,
+    /// Settings received from the peer
+    pub peer: Settings,
+    decoder: Decoder,
+    pub encoder: Encoder,
+    /// Connection-level flow control window for sending
+    pub send_window: i64,
+    /// The largest peer-initiated stream id processed, sent in GOAWAY
+    pub last_stream_id: u32,
+    /// Client connection preface is received (always true for clients)
+    preface_received: bool,
+    /// First SETTINGS frame is received
+    settings_received: bool,
+    /// Header block which waits for CONTINUATION frames
+    /// (stream id, flags, block)
+    continuation: Option<(u32, u8, Vec<u8>)>,
+    /// GOAWAY is sent, waiting for the buffer to be flushed
+    pub closing: bool,
+}
+
+/// Adds the WINDOW_UPDATE increment to the flow control window
+pub fn update_window(window: &mut i64, increment: u32)
+    -> Result<(), ErrorCode>
+{
+    if increment == 0 {
+        return Err(ErrorCode::ProtocolError);
+    }
+    *window += increment as i64;
+    if *window > MAX_WINDOW_SIZE {
+        return Err(ErrorCode::FlowControlError);
+    }
+    Ok(())
+}
+
+impl Session {
+    fn new(settings: Settings, out: &mut Buf) -> Session {
+        settings.write(out);
+        let mut decoder = Decoder::new(settings.header_table_size as usize);
+        decoder.set_max_list_size(settings.max_header_list_size
+            .map(|x| x as usize).unwrap_or(MAX_HEADERS_SIZE));
+        Session {
+            settings: settings,
+            peer: Settings::default(),
+            decoder: decoder,
+            encoder: Encoder::new(),
+            send_window: Settings::default().initial_window_size as i64,
+            last_stream_id: 0,
+            preface_received: true,
+            settings_received: false,
+            continuation: None,
+            closing: false,
+        }
+    }
+    /// Server side session, the client preface is expected first
+    ///
+    /// Our SETTINGS frame is written into the `out` immediately
+    pub fn server(settings: Settings, out: &mut Buf) -> Session {
+        let mut session = Session::new(settings, out);
+        session.preface_received = false;
+        session
+    }
+    /// Client side session, writes the connection preface into `out`
+    pub fn client(settings: Settings, out: &mut Buf) -> Session {
+        out.extend(PREFACE);
+        Session::new(settings, out)
+    }
+    /// Applies settings received out of band (`HTTP2-Settings` header)
+    pub fn set_peer_settings(&mut self, peer: Settings) {
+        self.peer = peer;
+        self.encoder.set_max_size(peer.header_table_size as usize);
+        self.settings_received = true;
+    }
+    /// Number of bytes needed to read the next frame
+    pub fn bytes_needed(&self, input: &[u8]) -> usize {
+        if !self.preface_received {
+            PREFACE.len()
+        } else if input.len() < HEADER_SIZE {
+            HEADER_SIZE
+        } else {
+            HEADER_SIZE + FrameHeader::parse(&input[..HEADER_SIZE]).length
+        }
+    }
+    /// Sends GOAWAY, the connection should be closed after flushing
+    pub fn go_away(&mut self, out: &mut Buf, code: ErrorCode) {
+        frame::write_goaway(out, self.last_stream_id, code);
+        self.closing = true;
+    }
+    /// Reads a frame from the start of the `input`
+    ///
+    /// Returns the number of bytes to consume and the event, or `None` if
+    /// the frame is not complete yet. Error is a connection error which
+    /// should be sent in GOAWAY.
+    pub fn read_frame<'a>(&mut self, input: &'a [u8], out: &mut Buf)
+        -> Result<Option<(usize, Event<'a>)>, ErrorCode>
+    {
+        if !self.preface_received {
+            if input.len() < PREFACE.len() {
+                return Ok(None);
+            }
+            if &input[..PREFACE.len()] != PREFACE {
+                return Err(ErrorCode::ProtocolError);
+            }
+            self.preface_received = true;
+            return Ok(Some((PREFACE.len(), Event::Handled)));
+        }
+        if input.len() < HEADER_SIZE {
+            return Ok(None);
+        }
+        let head = FrameHeader::parse(&input[..HEADER_SIZE]);
+        if head.length > self.settings.max_frame_size as usize {
+            return Err(ErrorCode::FrameSizeError);
+        }
+        let end = HEADER_SIZE + head.length;
+        if input.len() < end {
+            return Ok(None);
+        }
+        let event = try!(self.frame(head, &input[HEADER_SIZE..end], out));
+        Ok(Some((end, event)))
+    }
+    fn frame<'a>(&mut self, head: FrameHeader, payload: &'a [u8],
+        out: &mut Buf)
+        -> Result<Event<'a>, ErrorCode>
+    {
+        use http2::frame::*;
+        use http2::frame::ErrorCode::*;
+        if let Some((id, _, _)) = self.continuation {
+            if head.kind != CONTINUATION || head.stream_id != id {
+                return Err(ProtocolError);
+            }
+        }
+        if !self.settings_received &&
+            (head.kind != SETTINGS || head.flags & ACK != 0)
+        {
+            // The first frame must be SETTINGS
+            return Err(ProtocolError);
+        }
+        match head.kind {
+            DATA => {
+                if head.stream_id == 0 {
+                    return Err(ProtocolError);
+                }
+                let data = try!(strip_padding(&head, payload));
+                // Received data is consumed immediately, so the window is
+                // restored right away. Padding counts too.
+                if head.length > 0 {
+                    write_window_update(out, 0, head.length as u32);
+                }
+                Ok(Event::Data {
+                    id: head.stream_id,
+                    data: data,
+                    length: head.length,
+                    end_stream: head.flags & END_STREAM != 0,
+                })
+            }
+            HEADERS => {
+                if head.stream_id == 0 {
+                    return Err(ProtocolError);
+                }
+                let block = try!(strip_padding(&head, payload));
+                if head.flags & END_HEADERS != 0 {
+                    self.headers(head.stream_id, head.flags, block)
+                } else {
+                    self.continuation = Some((head.stream_id, head.flags,
+                                              block.to_vec()));
+                    Ok(Event::Handled)
+                }
+            }
+            CONTINUATION => {
+                let (id, flags, mut block) = match self.continuation.take() {
+                    Some(x) => x,
+                    None => return Err(ProtocolError),
+                };
+                block.extend(payload);
+                if let Some(limit) = self.settings.max_header_list_size {
+                    // Compressed block can't be larger than the list
+                    if block.len() > limit as usize {
+                        return Err(EnhanceYourCalm);
+                    }
+                }
+                if head.flags & END_HEADERS != 0 {
+                    self.headers(id, flags, &block)
+                } else {
+                    self.continuation = Some((id, flags, block));
+                    Ok(Event::Handled)
+                }
+            }
+            PRIORITY => {
+                if head.stream_id == 0 {
+                    return Err(ProtocolError);
+                }
+                if head.length != 5 {
+                    write_rst_stream(out, head.stream_id, FrameSizeError);
+                }
+                Ok(Event::Handled)
+            }
+            RST_STREAM => {
+                if head.stream_id == 0 {
+                    return Err(ProtocolError);
+                }
+                if head.length != 4 {
+                    return Err(FrameSizeError);
+                }
+                Ok(Event::Reset {
+                    id: head.stream_id,
+                    code: read_code(payload),
+                })
+            }
+            SETTINGS => {
+                if head.stream_id != 0 {
+                    return Err(ProtocolError);
+                }
+                if head.flags & ACK != 0 {
+                    return if head.length == 0 {
+                        Ok(Event::Handled)
+                    } else {
+                        Err(FrameSizeError)
+                    };
+                }
+                let old_window = self.peer.initial_window_size as i64;
+                try!(self.peer.apply(payload));
+                self.settings_received = true;
+                self.encoder.set_max_size(
+                    self.peer.header_table_size as usize);
+                Settings::write_ack(out);
+                let delta = self.peer.initial_window_size as i64 - old_window;
+                if delta != 0 {
+                    Ok(Event::WindowDelta(delta))
+                } else {
+                    Ok(Event::Handled)
+                }
+            }
+            // Server push is never enabled
+            PUSH_PROMISE => Err(ProtocolError),
+            PING => {
+                if head.stream_id != 0 {
+                    return Err(ProtocolError);
+                }
+                if head.length != 8 {
+                    return Err(FrameSizeError);
+                }
+                if head.flags & ACK == 0 {
+                    write_ping(out, payload, true);
+                }
+                Ok(Event::Handled)
+            }
+            GOAWAY => {
+                if head.stream_id != 0 {
+                    return Err(ProtocolError);
+                }
+                if head.length < 8 {
+                    return Err(FrameSizeError);
+                }
+                Ok(Event::GoAway { last_id: read_u31(payload) })
+            }
+            WINDOW_UPDATE => {
+                if head.length != 4 {
+                    return Err(FrameSizeError);
+                }
+                let increment = read_u31(payload);
+                if head.stream_id == 0 {
+                    try!(update_window(&mut self.send_window, increment));
+                    Ok(Event::Handled)
+                } else {
+                    Ok(Event::WindowUpdate {
+                        id: head.stream_id,
+                        increment: increment,
+                    })
+                }
+            }
+            // Unknown frames must be ignored
+            _ => Ok(Event::Handled),
+        }
+    }
+    fn headers<'a>(&mut self, id: u32, flags: u8, block: &[u8])
+        -> Result<Event<'a>, ErrorCode>
+    {
+        let mut list = HeaderList::new();
+        let too_large = match self.decoder.decode(block, &mut list) {
+            Ok(()) => false,
+            Err(DecodeError::HeaderListTooLarge) => true,
+            Err(_) => return Err(ErrorCode::CompressionError),
+        };
+        Ok(Event::Headers {
+            id: id,
+            end_stream: flags & frame::END_STREAM != 0,
+            list: list,
+            too_large: too_large,
+        })
+    }
+}
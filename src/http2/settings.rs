@@ -0,0 +1,168 @@
+use rotor_stream::Buf;
+
+use super::frame::{self, ErrorCode, FrameHeader, SETTINGS, ACK};
+use super::{DEFAULT_FRAME_SIZE, DEFAULT_WINDOW_SIZE, MAX_WINDOW_SIZE};
+
+
+const HEADER_TABLE_SIZE: u16 = 0x1;
+const ENABLE_PUSH: u16 = 0x2;
+const MAX_CONCURRENT_STREAMS: u16 = 0x3;
+const INITIAL_WINDOW_SIZE: u16 = 0x4;
+const MAX_FRAME_SIZE: u16 = 0x5;
+const MAX_HEADER_LIST_SIZE: u16 = 0x6;
+
+/// HTTP/2 connection settings
+///
+/// The `Default` value contains initial values defined by the protocol,
+/// i.e. the ones which are used until SETTINGS frame is received.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Settings {
+    /// The size of HPACK dynamic table the decoder is ready to keep
+    pub header_table_size: u32,
+    /// Whether server push is allowed (only sent by clients)
+    pub enable_push: bool,
+    /// Maximum number of concurrent streams (`None` is unlimited)
+    pub max_concurrent_streams: Option<u32>,
+    /// Initial flow control window size for streams
+    pub initial_window_size: u32,
+    /// Maximum size of the frame payload
+    pub max_frame_size: u32,
+    /// Maximum size of the (uncompressed) header list
+    pub max_header_list_size: Option<u32>,
+}
+
+impl Default for Settings {
+    fn default() -> Settings {
+        Settings {
+            header_table_size: 4096,
+            enable_push: true,
+            max_concurrent_streams: None,
+            initial_window_size: DEFAULT_WINDOW_SIZE,
+            max_frame_size: DEFAULT_FRAME_SIZE as u32,
+            max_header_list_size: None,
+        }
+    }
+}
+
+impl Settings {
+    /// Writes SETTINGS frame with all the values which differ from initial
+    pub fn write(&self, buf: &mut Buf) {
+        let initial = Settings::default();
+        let mut values = Vec::new();
+        if self.header_table_size != initial.header_table_size {
+            values.push((HEADER_TABLE_SIZE, self.header_table_size));
+        }
+        if self.enable_push != initial.enable_push {
+            values.push((ENABLE_PUSH, self.enable_push as u32));
+        }
+        if let Some(x) = self.max_concurrent_streams {
+            values.push((MAX_CONCURRENT_STREAMS, x));
+        }
+        if self.initial_window_size != initial.initial_window_size {
+            values.push((INITIAL_WINDOW_SIZE, self.initial_window_size));
+        }
+        if self.max_frame_size != initial.max_frame_size {
+            values.push((MAX_FRAME_SIZE, self.max_frame_size));
+        }
+        if let Some(x) = self.max_header_list_size {
+            values.push((MAX_HEADER_LIST_SIZE, x));
+        }
+        FrameHeader {
+            length: values.len() * 6,
+            kind: SETTINGS,
+            flags: 0,
+            stream_id: 0,
+        }.write(buf);
+        for (id, value) in values {
+            buf.extend(&[(id >> 8) as u8, id as u8,
+                         (value >> 24) as u8, (value >> 16) as u8,
+                         (value >> 8) as u8, value as u8]);
+        }
+    }
+    /// Writes acknowledgement of the received SETTINGS frame
+    pub fn write_ack(buf: &mut Buf) {
+        frame::write_frame(buf, SETTINGS, ACK, 0, b"");
+    }
+    /// Updates settings from the payload of SETTINGS frame
+    ///
+    /// Unknown settings are ignored as required by the spec
+    pub fn apply(&mut self, payload: &[u8]) -> Result<(), ErrorCode> {
+        if payload.len() % 6 != 0 {
+            return Err(ErrorCode::FrameSizeError);
+        }
+        for item in payload.chunks(6) {
+            let id = (item[0] as u16) << 8 | item[1] as u16;
+            let value = frame::read_code(&item[2..]);
+            match id {
+                HEADER_TABLE_SIZE => self.header_table_size = value,
+                ENABLE_PUSH => {
+                    if value > 1 {
+                        return Err(ErrorCode::ProtocolError);
+                    }
+                    self.enable_push = value == 1;
+                }
+                MAX_CONCURRENT_STREAMS => {
+                    self.max_concurrent_streams = Some(value);
+                }
+                INITIAL_WINDOW_SIZE => {
+                    if value as i64 > MAX_WINDOW_SIZE {
+                        return Err(ErrorCode::FlowControlError);
+                    }
+                    self.initial_window_size = value;
+                }
+                MAX_FRAME_SIZE => {
+                    if value < DEFAULT_FRAME_SIZE as u32 ||
+                        value > 0xFF_FFFF
+                    {
+                        return Err(ErrorCode::ProtocolError);
+                    }
+                    self.max_frame_size = value;
+                }
+                MAX_HEADER_LIST_SIZE => {
+                    self.max_header_list_size = Some(value);
+                }
+                _ => {}
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use rotor_stream::Buf;
+    use http2::frame::ErrorCode;
+    use super::Settings;
+
+    #[test]
+    fn roundtrip() {
+        let settings = Settings {
+            enable_push: false,
+            max_concurrent_streams: Some(100),
+            .. Settings::default()
+        };
+        let mut buf = Buf::new();
+        settings.write(&mut buf);
+        assert_eq!(&buf[..], &b"\x00\x00\x0c\x04\x00\x00\x00\x00\x00\
+                                \x00\x02\x00\x00\x00\x00\
+                                \x00\x03\x00\x00\x00\x64"[..]);
+        let mut parsed = Settings::default();
+        parsed.apply(&buf[9..]).unwrap();
+        assert_eq!(parsed, settings);
+    }
+
+    #[test]
+    fn bad_values() {
+        let mut settings = Settings::default();
+        assert_eq!(settings.apply(b"\x00\x02\x00\x00\x00\x02"),
+                   Err(ErrorCode::ProtocolError));
+        assert_eq!(settings.apply(b"\x00\x04\x80\x00\x00\x00"),
+                   Err(ErrorCode::FlowControlError));
+        assert_eq!(settings.apply(b"\x00\x05\x00\x00\x10\x00"),
+                   Err(ErrorCode::ProtocolError));
+        assert_eq!(settings.apply(b"\x00\x05\x00"),
+                   Err(ErrorCode::FrameSizeError));
+        // Unknown setting
+        assert_eq!(settings.apply(b"\x00\x10\x00\x00\x00\x01"), Ok(()));
+    }
+}
@@ -3,14 +3,26 @@
 extern crate rotor;
 extern crate httparse;
 extern crate rotor_stream;
+extern crate sha1;
+extern crate rustc_serialize;
+extern crate rand;
 #[cfg(feature="nightly")] extern crate test;
+#[cfg(feature="tls")] extern crate openssl;
+#[cfg(feature="tls")] extern crate openssl_verify;
+#[cfg(feature="compression")] extern crate flate2;
 #[cfg(test)] extern crate rotor_test;
 #[macro_use] extern crate quick_error;
 #[macro_use] extern crate matches;
 
 pub mod server;
 pub mod client;
+pub mod websocket;
+pub mod tls;
 mod message;
+pub mod hpack;
+mod http2;
 mod recvmode;
+mod limits;
 mod headers;
+mod date;
 mod version;
@@ -0,0 +1,49 @@
+use std::default::Default;
+
+use server::{MAX_HEADERS_NUM, MAX_HEADERS_SIZE, MAX_CHUNK_HEAD};
+use server::{MAX_REQUEST_LINE, MAX_DECODED_CHUNK};
+
+
+/// Limits on the size of the message head which are applied by the parser
+///
+/// Returned by `Server::limits()` and `Client::limits()`. The default values
+/// are the `MAX_*` constants of the `server` (and `client`) module.
+///
+/// Up to `MAX_HEADERS_NUM` headers are parsed into an array on the stack.
+/// If you allow more headers, the array is allocated on the heap for each
+/// message, so keep the value low unless you really need more headers.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Limits {
+    /// The maximum number of headers in a message
+    pub max_headers_num: usize,
+    /// The maximum size of the message head in bytes
+    ///
+    /// This includes request or status line. For HTTP/2 it's the default
+    /// `SETTINGS_MAX_HEADER_LIST_SIZE` on the server.
+    pub max_headers_size: usize,
+    /// The maximum length of the chunk size line in bytes
+    pub max_chunk_head: usize,
+    /// The maximum length of the request line (method, request target and
+    /// version) in bytes, larger requests are replied with `414 URI Too Long`
+    ///
+    /// Only used by the server.
+    pub max_request_line: usize,
+    /// The maximum size of the decoded request body which is passed to
+    /// a single `request_chunk()` in the `Progressive` mode, larger pieces
+    /// are replied with `413 Payload Too Large`
+    ///
+    /// Only used by the server, and only when the body is encoded.
+    pub max_decoded_chunk: usize,
+}
+
+impl Default for Limits {
+    fn default() -> Limits {
+        Limits {
+            max_headers_num: MAX_HEADERS_NUM,
+            max_headers_size: MAX_HEADERS_SIZE,
+            max_chunk_head: MAX_CHUNK_HEAD,
+            max_request_line: MAX_REQUEST_LINE,
+            max_decoded_chunk: MAX_DECODED_CHUNK,
+        }
+    }
+}
@@ -1,8 +1,11 @@
 use std::io::Write;
+use std::str;
 use std::ascii::AsciiExt;
 
 use rotor_stream::Buf;
 
+use date;
+use headers;
 use version::Version;
 
 quick_error! {
@@ -29,31 +32,52 @@ quick_error! {
             description("Content-Length and Transfer-Encoding must be set \
                 using the specialized methods")
         }
+        ForbiddenTrailer {
+            description("The header field is not allowed in trailers")
+        }
+        TrailersWithoutChunked {
+            description("Trailers can only be sent with the chunked body")
+        }
     }
 }
 
 #[derive(Debug)]
 pub enum MessageState {
     /// Nothing has been sent
-    ResponseStart { version: Version, body: Body, close: bool },
+    ///
+    /// `date` and `server` are the headers which are added automatically,
+    /// `upgrade` is true if the request asks for a connection upgrade
+    ResponseStart { version: Version, body: Body, close: bool,
+                    date: bool, server: Option<&'static str>,
+                    upgrade: bool },
     /// A continuation line has been sent
-    FinalResponseStart { version: Version, body: Body, close: bool },
+    FinalResponseStart { version: Version, body: Body, close: bool,
+                         date: bool, server: Option<&'static str>,
+                         upgrade: bool },
     RequestStart,
     /// Status line is already in the buffer
+    ///
+    /// `date` is true if `Date` header should be added by `done_headers()`
     Headers { body: Body, chunked: bool, close: bool, request: bool,
-              content_length: Option<u64> },
+              date: bool, content_length: Option<u64> },
     ZeroBodyMessage,  // When response body is Denied
     IgnoredBody, // When response body is Ignored
     FixedSizeBody(u64),
     ChunkedBody,
+    /// The last chunk is written, trailer fields may follow
+    Trailers,
     Done,
+    /// A `101 Switching Protocols` response is sent, the rest of the
+    /// connection belongs to another protocol
+    Upgraded,
 }
 
 #[derive(Debug, Copy, Clone, PartialEq, Eq)]
 pub enum Body {
     Normal,
     Ignored,  // HEAD requests, 304 responses
-    Denied,  // 101, 204 responses (100 too if it is used here)
+    Denied,  // 204 responses (100 too if it is used here)
+    Upgrade,  // 101 responses, no body but connection is switched
 }
 
 /// Represents both request message and response message
@@ -81,13 +105,20 @@ impl<'a> Message<'a> {
     /// When status line is already written. It's expected that your request
     /// handler state machine will never call the method twice.
     ///
-    /// When status is 10x we don't assert yet
+    /// When status is 101 and the request doesn't ask for an upgrade.
+    /// When status is 100 or 102 we don't assert yet
     pub fn response_status(&mut self, code: u16, reason: &str) {
         use self::Body::*;
         use self::MessageState::*;
         match self.1 {
-            ResponseStart { version, mut body, close } |
-            FinalResponseStart { version, mut body, close } => {
+            ResponseStart { version, mut body, close, date, server,
+                            upgrade } |
+            FinalResponseStart { version, mut body, close, date, server,
+                                 upgrade } => {
+                if code == 101 && !upgrade {
+                    panic!("Status 101 is sent for a request without \
+                            an upgrade");
+                }
                 // Note we don't expect code 100 and 102 here, but
                 // we don't assert on that for now. The point is that
                 // responses 100 and 102 are interim. 100 is generated by
@@ -98,13 +129,18 @@ impl<'a> Message<'a> {
                 // TODO(tailhook) should we assert?
                 //
                 write!(self.0, "{} {} {}\r\n", version, code, reason).unwrap();
-                if code == 101 || code == 204 {
+                if let Some(name) = server {
+                    self.write_header("Server", name.as_bytes());
+                }
+                if code == 101 {
+                    body = Upgrade;
+                } else if code == 204 {
                     body = Denied;
                 } else if body == Normal && code == 304 {
                     body = Ignored;
                 }
                 self.1 = Headers { body: body, request: false,
-                                   content_length: None,
+                                   content_length: None, date: date,
                                    chunked: false, close: close };
             }
             ref state => {
@@ -133,7 +169,7 @@ impl<'a> Message<'a> {
                 // expected for the HEAD too? Other methods?
                 self.1 = Headers { body: Normal, request: true,
                                    content_length: None, chunked: false,
-                                   close: false };
+                                   date: false, close: false };
             }
             ref state => {
                 panic!("Called status() method on request in state {:?}",
@@ -153,11 +189,14 @@ impl<'a> Message<'a> {
     pub fn response_continue(&mut self) {
         use self::MessageState::*;
         match self.1 {
-            ResponseStart { version, body, close } => {
+            ResponseStart { version, body, close, date, server, upgrade } => {
                 write!(self.0, "{} 100 Continue\r\n\r\n", version).unwrap();
                 self.1 = FinalResponseStart { version: version,
                                               body: body,
-                                              close: close }
+                                              close: close,
+                                              date: date,
+                                              server: server,
+                                              upgrade: upgrade }
             }
             ref state => {
                 panic!("Called continue_line() method on response in state {:?}",
@@ -185,6 +224,10 @@ impl<'a> Message<'a> {
     /// Note that there is currently no way to use a transfer encoding other
     /// than chunked.
     ///
+    /// The `Trailer` header announces fields which are sent with
+    /// `add_trailer()` after the body, the fields listed there are checked
+    /// to be allowed in trailers.
+    ///
     /// We return Result here to make implementing proxies easier. In the
     /// application handler it's okay to unwrap the result and to get
     /// a meaningful panic (that is basically an assertion).
@@ -201,16 +244,27 @@ impl<'a> Message<'a> {
             || name.eq_ignore_ascii_case("Transfer-Encoding") {
             return Err(BodyLengthHeader)
         }
+        if name.eq_ignore_ascii_case("Trailer") {
+            let forbidden = value.split(|&x| x == b',')
+                .filter_map(|x| str::from_utf8(x).ok())
+                .any(|x| headers::is_forbidden_trailer(x.trim()));
+            if forbidden {
+                return Err(ForbiddenTrailer);
+            }
+        }
         match self.1 {
-            Headers { .. } => {
-                self.write_header(name, value);
-                Ok(())
+            Headers { ref mut date, .. } => {
+                if name.eq_ignore_ascii_case("Date") {
+                    *date = false;
+                }
             }
             ref state => {
                 panic!("Called add_header() method on a message in state {:?}",
                        state)
             }
         }
+        self.write_header(name, value);
+        Ok(())
     }
 
     /// Add a content length to the message.
@@ -304,6 +358,9 @@ impl<'a> Message<'a> {
     pub fn done_headers(&mut self) -> Result<bool, HeaderError> {
         use self::Body::*;
         use self::MessageState::*;
+        if let Headers { date: true, .. } = self.1 {
+            date::with_date(|x| self.add_header("Date", x)).unwrap();
+        }
         if let Headers { close: true, .. } = self.1 {
             self.add_header("Connection", b"close").unwrap();
         }
@@ -316,14 +373,18 @@ impl<'a> Message<'a> {
                 self.1 = ZeroBodyMessage;
                 Ok(false)
             }
+            Headers { body: Upgrade, .. } => {
+                self.1 = Upgraded;
+                Ok(false)
+            }
             Headers { body: Normal, content_length: Some(cl),
-                      chunked: false, request: _, close: _}
+                      chunked: false, .. }
             => {
                 self.1 = FixedSizeBody(cl);
                 Ok(true)
             }
             Headers { body: Normal, content_length: None, chunked: true,
-                      request: _, close: _ }
+                      .. }
             => {
                 self.1 = ChunkedBody;
                 Ok(true)
@@ -331,13 +392,13 @@ impl<'a> Message<'a> {
             Headers { content_length: Some(_), chunked: true, .. }
             => unreachable!(),
             Headers { body: Normal, content_length: None, chunked: false,
-                      request: true, close: _ }
+                      request: true, .. }
             => {
                 self.1 = ZeroBodyMessage;
                 Ok(false)
             }
             Headers { body: Normal, content_length: None, chunked: false,
-                      request: false, close: _ }
+                      request: false, .. }
             => Err(HeaderError::CantDetermineBodySize),
             ref state => {
                 panic!("Called done_headers() method on  in a state {:?}",
@@ -384,8 +445,12 @@ impl<'a> Message<'a> {
                 *x -= data.len() as u64;
             }
             ChunkedBody => {
-                write!(self.0, "{:x}\r\n", data.len()).unwrap();
-                self.0.write(data).unwrap();
+                // Zero-length chunk would terminate the body
+                if data.len() != 0 {
+                    write!(self.0, "{:x}\r\n", data.len()).unwrap();
+                    self.0.write(data).unwrap();
+                    self.0.write(b"\r\n").unwrap();
+                }
             }
             ref state => {
                 panic!("Called write_body() method on message \
@@ -393,10 +458,114 @@ impl<'a> Message<'a> {
             }
         }
     }
+    /// Add a trailer field after the chunked body
+    ///
+    /// The first call terminates the body with the last chunk, so no more
+    /// `write_body()` calls are allowed afterwards. The trailer section is
+    /// finished by `done()`. Fields should be announced in the `Trailer`
+    /// header beforehand.
+    ///
+    /// Fields needed for framing, routing or authentication (e.g.
+    /// `Content-Length`, `Host`, `Authorization`) are rejected with
+    /// `ForbiddenTrailer`. When the body is not chunked,
+    /// `TrailersWithoutChunked` is returned, except for the ignored body
+    /// (HEAD request or 304 response) where the trailer is just skipped.
+    ///
+    /// # Panics
+    ///
+    /// When message is in the wrong state.
+    pub fn add_trailer(&mut self, name: &str, value: &[u8])
+        -> Result<(), HeaderError>
+    {
+        use self::MessageState::*;
+        if headers::is_forbidden_trailer(name) {
+            return Err(HeaderError::ForbiddenTrailer);
+        }
+        match self.1 {
+            ChunkedBody => {
+                self.0.write(b"0\r\n").unwrap();
+                self.1 = Trailers;
+            }
+            Trailers => {}
+            IgnoredBody => return Ok(()),
+            FixedSizeBody(_) | ZeroBodyMessage => {
+                return Err(HeaderError::TrailersWithoutChunked);
+            }
+            ref state => {
+                panic!("Called add_trailer() method on message \
+                    in a state {:?}", state)
+            }
+        }
+        self.write_header(name, value);
+        Ok(())
+    }
+    /// Writes the trailer fields and finishes the message
+    ///
+    /// All the fields are validated before anything is written, so on
+    /// error the message is left intact. See `add_trailer()` for details.
+    ///
+    /// # Panics
+    ///
+    /// When message is in the wrong state or when Content-Length bytes
+    /// are not written yet
+    pub fn done_with_trailers(&mut self, trailers: &[(&str, &[u8])])
+        -> Result<(), HeaderError>
+    {
+        use self::MessageState::*;
+        if trailers.iter().any(|&(name, _)| headers::is_forbidden_trailer(name))
+        {
+            return Err(HeaderError::ForbiddenTrailer);
+        }
+        if trailers.len() > 0 &&
+            matches!(self.1, FixedSizeBody(_) | ZeroBodyMessage)
+        {
+            return Err(HeaderError::TrailersWithoutChunked);
+        }
+        for &(name, value) in trailers {
+            try!(self.add_trailer(name, value));
+        }
+        self.done();
+        Ok(())
+    }
     /// Returns true if `done()` method is already called and everything
     /// was okay.
     pub fn is_complete(&self) -> bool {
-        matches!(self.1, MessageState::Done)
+        matches!(self.1, MessageState::Done | MessageState::Upgraded)
+    }
+    /// Returns true if `101 Switching Protocols` response has been sent
+    ///
+    /// I.e. the status is 101 and `done_headers()` is already called.
+    pub fn is_upgraded(&self) -> bool {
+        matches!(self.1, MessageState::Upgraded)
+    }
+    /// Makes the response close the connection
+    ///
+    /// The `Connection: close` header is added by `done_headers()`, so
+    /// nothing is changed if headers are already sent.
+    pub fn close_connection(&mut self) {
+        use self::MessageState::*;
+        match self.1 {
+            ResponseStart { ref mut close, .. } |
+            FinalResponseStart { ref mut close, .. } |
+            Headers { ref mut close, .. } => {
+                *close = true;
+            }
+            _ => {}
+        }
+    }
+    /// Allows the `101 Switching Protocols` status for the response
+    ///
+    /// Should be called when the request asks for a connection upgrade.
+    pub fn allow_upgrade(&mut self) {
+        match self.1 {
+            MessageState::ResponseStart { ref mut upgrade, .. } => {
+                *upgrade = true;
+            }
+            ref state => {
+                panic!("Called allow_upgrade() method on a message \
+                        in state {:?}", state)
+            }
+        }
     }
     /// Writes needed final finalization data into the buffer and asserts
     /// that response is in the appropriate state for that.
@@ -411,13 +580,18 @@ impl<'a> Message<'a> {
         use self::MessageState::*;
         match self.1 {
             ChunkedBody => {
-                self.0.write(b"0\r\n").unwrap();
+                self.0.write(b"0\r\n\r\n").unwrap();
+                self.1 = Done;
+            }
+            Trailers => {
+                self.0.write(b"\r\n").unwrap();
                 self.1 = Done;
             }
             FixedSizeBody(0) => self.1 = Done,
             ZeroBodyMessage => self.1 = Done,
             IgnoredBody => self.1 = Done,
             Done => {}  // multiple invocations are okay
+            Upgraded => {}  // nothing to finish, connection is switched
             ref state => {
                 panic!("Called done() method on response in a state {:?}",
                        state);
@@ -456,6 +630,9 @@ mod test {
             version: Version::Http10,
             body: Body::Normal,
             close: false,
+            date: false,
+            server: None,
+            upgrade: false,
         }.with(&mut buf));
         return buf;
     }
@@ -465,6 +642,21 @@ mod test {
             version: Version::Http11,
             body: Body::Normal,
             close: close,
+            date: false,
+            server: None,
+            upgrade: false,
+        }.with(&mut buf));
+        return buf;
+    }
+    fn do_response_auto<F: FnOnce(Message)>(fun: F) -> Buf {
+        let mut buf = Buf::new();
+        fun(MessageState::ResponseStart {
+            version: Version::Http11,
+            body: Body::Normal,
+            close: false,
+            date: true,
+            server: Some("rotor-http"),
+            upgrade: false,
         }.with(&mut buf));
         return buf;
     }
@@ -508,4 +700,191 @@ mod test {
         })[..], concat!("HTTP/1.1 200 OK\r\nContent-Length: 0\r\n",
                         "Connection: close\r\n\r\n").as_bytes());
     }
+
+    #[test]
+    fn switching_protocols() {
+        let mut upgraded = false;
+        let buf = do_response11(false, |mut msg| {
+            msg.allow_upgrade();
+            msg.response_status(101, "Switching Protocols");
+            msg.add_header("Connection", b"upgrade").unwrap();
+            msg.add_header("Upgrade", b"websocket").unwrap();
+            assert_eq!(msg.done_headers().unwrap(), false);
+            msg.done();
+            assert!(msg.is_complete());
+            upgraded = msg.is_upgraded();
+        });
+        assert!(upgraded);
+        assert_eq!(&buf[..], concat!("HTTP/1.1 101 Switching Protocols\r\n",
+                                     "Connection: upgrade\r\n",
+                                     "Upgrade: websocket\r\n\r\n").as_bytes());
+    }
+
+    #[test]
+    #[should_panic(expected="without an upgrade")]
+    fn switching_protocols_without_upgrade() {
+        do_response11(false, |mut msg| {
+            msg.response_status(101, "Switching Protocols");
+        });
+    }
+
+    #[test]
+    fn close_connection() {
+        assert_eq!(&do_response11(false, |mut msg| {
+            msg.close_connection();
+            msg.response_status(413, "Payload Too Large");
+            msg.add_length(0).unwrap();
+            msg.done_headers().unwrap();
+            msg.done();
+        })[..], concat!("HTTP/1.1 413 Payload Too Large\r\n",
+                        "Content-Length: 0\r\n",
+                        "Connection: close\r\n\r\n").as_bytes());
+    }
+
+    #[test]
+    fn date_and_server() {
+        let buf = do_response_auto(|mut msg| {
+            msg.response_status(200, "OK");
+            msg.add_length(0).unwrap();
+            msg.done_headers().unwrap();
+            msg.done();
+        });
+        let text = String::from_utf8(buf[..].to_vec()).unwrap();
+        let lines = text.split("\r\n").collect::<Vec<_>>();
+        assert_eq!(lines[..3], ["HTTP/1.1 200 OK", "Server: rotor-http",
+                                "Content-Length: 0"]);
+        assert!(lines[3].starts_with("Date: "));
+        assert!(lines[3].ends_with(" GMT"));
+        assert_eq!(lines[4..], ["", ""]);
+    }
+
+    #[test]
+    fn custom_date() {
+        assert_eq!(&do_response_auto(|mut msg| {
+            msg.response_status(200, "OK");
+            msg.add_header("date", b"Sun, 06 Nov 1994 08:49:37 GMT").unwrap();
+            msg.add_length(0).unwrap();
+            msg.done_headers().unwrap();
+            msg.done();
+        })[..], concat!("HTTP/1.1 200 OK\r\n",
+                        "Server: rotor-http\r\n",
+                        "date: Sun, 06 Nov 1994 08:49:37 GMT\r\n",
+                        "Content-Length: 0\r\n\r\n").as_bytes());
+    }
+
+    #[test]
+    fn chunked_response() {
+        assert_eq!(&do_response11(false, |mut msg| {
+            msg.response_status(200, "OK");
+            msg.add_chunked().unwrap();
+            msg.done_headers().unwrap();
+            msg.write_body(b"hello");
+            msg.write_body(b"");
+            msg.write_body(b" world");
+            msg.done();
+        })[..], concat!("HTTP/1.1 200 OK\r\n",
+                        "Transfer-Encoding: chunked\r\n\r\n",
+                        "5\r\nhello\r\n6\r\n world\r\n0\r\n\r\n").as_bytes());
+    }
+
+    #[test]
+    fn chunked_body_is_decodable() {
+        use httparse::{parse_chunk_size, Status};
+        let buf = do_response11(false, |mut msg| {
+            msg.response_status(200, "OK");
+            msg.add_chunked().unwrap();
+            msg.done_headers().unwrap();
+            msg.write_body(b"hello");
+            msg.write_body(b"");
+            msg.write_body(b" world");
+            msg.done();
+        });
+        let start = buf[..].windows(4)
+            .position(|x| x == b"\r\n\r\n").unwrap();
+        let mut body = &buf[start+4..];
+        let mut decoded: Vec<u8> = Vec::new();
+        // Each chunk must be followed by CRLF, and an empty write must not
+        // produce the last chunk
+        loop {
+            let (bytes, size) = match parse_chunk_size(body) {
+                Ok(Status::Complete(pair)) => pair,
+                res => panic!("bad chunk size {:?}", res),
+            };
+            let size = size as usize;
+            body = &body[bytes..];
+            if size == 0 {
+                break;
+            }
+            decoded.extend(&body[..size]);
+            assert_eq!(&body[size..size+2], b"\r\n");
+            body = &body[size+2..];
+        }
+        assert_eq!(body, b"\r\n");
+        assert_eq!(&decoded[..], b"hello world");
+    }
+
+    #[test]
+    fn chunked_trailers() {
+        assert_eq!(&do_response11(false, |mut msg| {
+            msg.response_status(200, "OK");
+            msg.add_chunked().unwrap();
+            msg.add_header("Trailer", b"X-Checksum, X-Status").unwrap();
+            msg.done_headers().unwrap();
+            msg.write_body(b"hello");
+            msg.add_trailer("X-Checksum", b"5d41402a").unwrap();
+            msg.add_trailer("X-Status", b"ok").unwrap();
+            msg.done();
+            assert!(msg.is_complete());
+        })[..], concat!("HTTP/1.1 200 OK\r\n",
+                        "Transfer-Encoding: chunked\r\n",
+                        "Trailer: X-Checksum, X-Status\r\n\r\n",
+                        "5\r\nhello\r\n0\r\n",
+                        "X-Checksum: 5d41402a\r\n",
+                        "X-Status: ok\r\n\r\n").as_bytes());
+    }
+
+    #[test]
+    fn request_trailers() {
+        assert_eq!(&do_request(|mut msg| {
+            msg.request_line("PUT", "/file", Version::Http11);
+            msg.add_chunked().unwrap();
+            msg.done_headers().unwrap();
+            msg.write_body(b"data");
+            msg.done_with_trailers(&[("X-Checksum", b"8d777f38")]).unwrap();
+        })[..], concat!("PUT /file HTTP/1.1\r\n",
+                        "Transfer-Encoding: chunked\r\n\r\n",
+                        "4\r\ndata\r\n0\r\n",
+                        "X-Checksum: 8d777f38\r\n\r\n").as_bytes());
+    }
+
+    #[test]
+    fn forbidden_trailers() {
+        assert_eq!(&do_response11(false, |mut msg| {
+            msg.response_status(200, "OK");
+            msg.add_chunked().unwrap();
+            assert!(msg.add_header("Trailer", b"X-Checksum, Content-Length")
+                    .is_err());
+            msg.done_headers().unwrap();
+            assert!(msg.add_trailer("Content-Length", b"5").is_err());
+            assert!(msg.done_with_trailers(&[
+                ("X-Checksum", b"5d41402a"),
+                ("Transfer-Encoding", b"gzip"),
+            ]).is_err());
+            assert!(!msg.is_complete());
+            msg.done();
+        })[..], concat!("HTTP/1.1 200 OK\r\n",
+                        "Transfer-Encoding: chunked\r\n\r\n",
+                        "0\r\n\r\n").as_bytes());
+    }
+
+    #[test]
+    fn trailers_without_chunked() {
+        assert_eq!(&do_response11(false, |mut msg| {
+            msg.response_status(200, "OK");
+            msg.add_length(0).unwrap();
+            msg.done_headers().unwrap();
+            assert!(msg.add_trailer("X-Checksum", b"0").is_err());
+            msg.done();
+        })[..], "HTTP/1.1 200 OK\r\nContent-Length: 0\r\n\r\n".as_bytes());
+    }
 }
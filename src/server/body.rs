@@ -14,6 +14,12 @@ pub enum BodyKind {
     ///
     /// The size of the message body is not yet known.
     Chunked,
-    /// Reserved for future usage.
+    /// The client asks to switch the connection to another protocol.
+    ///
+    /// Set for HTTP/1.1 requests that have no body, have an `Upgrade`
+    /// header and `upgrade` token in the `Connection` header. If handler
+    /// writes a `101 Switching Protocols` response in `headers_received()`
+    /// the connection is handed over to the `upgrade_*` handlers of the
+    /// `Server`, otherwise it's an ordinary request with an empty body.
     Upgrade,
 }
@@ -0,0 +1,594 @@
+//! Transparent response compression
+//!
+//! `Compressor` picks the content coding from the `Accept-Encoding` header
+//! of the request. Store it in your state machine in `headers_received()`
+//! and write the response through `Compressor::wrap()`, which has the same
+//! methods as `Response`:
+//!
+//! ```ignore
+//! let mut resp = self.compressor.wrap(response);
+//! resp.status(200, "OK");
+//! resp.add_header("Content-Type", b"application/json").unwrap();
+//! resp.add_length(data.len() as u64).unwrap();
+//! if resp.done_headers().unwrap() {
+//!     resp.write_body(data);
+//! }
+//! resp.done();
+//! ```
+//!
+//! When the body is compressed, `Content-Encoding` is added and the body
+//! is sent chunked (the compressed size is not known in advance), so
+//! `add_length()` is just a hint here. Bodies smaller than the minimal
+//! size, already compressed content types (images, archives, ...),
+//! responses which have `Content-Encoding` set by the handler, partial
+//! content (`206` or `Content-Range`) and responses to HTTP/1.0 clients
+//! (which don't support chunked encoding) are sent as is.
+//! `Vary: Accept-Encoding` is added whenever the body could have been
+//! compressed.
+use std::ascii::AsciiExt;
+use std::io::Write;
+use std::mem;
+use std::str::from_utf8;
+
+use flate2::Compression;
+use flate2::write::{GzEncoder, ZlibEncoder};
+
+use message::HeaderError;
+use version::Version;
+use super::{Head, Response};
+
+
+/// Bodies with smaller `Content-Length` are not compressed by default
+pub const MIN_SIZE: u64 = 1024;
+
+/// Content coding which is used for the response
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Coding {
+    Gzip,
+    /// The zlib format (the `deflate` content coding of HTTP)
+    Deflate,
+}
+
+enum Encoder {
+    Gzip(GzEncoder<Vec<u8>>),
+    Deflate(ZlibEncoder<Vec<u8>>),
+}
+
+/// Compression state of a single response
+pub struct Compressor {
+    coding: Option<Coding>,
+    version: Version,
+    is_head: bool,
+    /// Zero until `status()` is called
+    status: u16,
+    min_size: u64,
+    level: Compression,
+    /// Content type may be compressed and body is not encoded yet
+    compressible: bool,
+    content_length: Option<u64>,
+    chunked: bool,
+    encoder: Option<Encoder>,
+}
+
+/// A `Response` which compresses the body, see `Compressor::wrap()`
+pub struct Compressed<'a, 'b: 'a> {
+    compressor: &'a mut Compressor,
+    response: &'a mut Response<'b>,
+}
+
+impl Coding {
+    /// The value of the `Content-Encoding` header
+    pub fn name(&self) -> &'static str {
+        match *self {
+            Coding::Gzip => "gzip",
+            Coding::Deflate => "deflate",
+        }
+    }
+}
+
+/// Picks the content coding from the value of `Accept-Encoding`
+///
+/// The coding with the highest quality value wins, `gzip` is preferred
+/// when qualities are equal. Explicitly listed codings take precedence
+/// over `*`. The `identity` is only chosen if it's listed (or matched by
+/// `*`) with a higher quality than any other coding. `None` means the
+/// identity coding.
+pub fn negotiate(accept_encoding: &[u8]) -> Option<Coding> {
+    let value = match from_utf8(accept_encoding) {
+        Ok(value) => value,
+        Err(_) => return None,
+    };
+    let mut gzip = None;
+    let mut deflate = None;
+    let mut identity = None;
+    let mut any = None;
+    for item in value.split(',') {
+        let mut parts = item.split(';');
+        let name = parts.next().unwrap_or("").trim();
+        let quality = parts
+            .filter_map(|p| {
+                let p = p.trim();
+                if p.len() > 2 &&
+                    p.as_bytes()[..2].eq_ignore_ascii_case(b"q=")
+                {
+                    p[2..].trim().parse::<f32>().ok()
+                } else {
+                    None
+                }
+            })
+            .next().unwrap_or(1.0);
+        let slot = if name.eq_ignore_ascii_case("gzip") ||
+                      name.eq_ignore_ascii_case("x-gzip")
+        {
+            &mut gzip
+        } else if name.eq_ignore_ascii_case("deflate") {
+            &mut deflate
+        } else if name.eq_ignore_ascii_case("identity") {
+            &mut identity
+        } else if name == "*" {
+            &mut any
+        } else {
+            continue;
+        };
+        *slot = Some(quality);
+    }
+    let gzip = gzip.or(any).unwrap_or(0.0);
+    let deflate = deflate.or(any).unwrap_or(0.0);
+    let identity = identity.or(any).unwrap_or(0.0);
+    let (coding, quality) = if deflate > gzip {
+        (Coding::Deflate, deflate)
+    } else {
+        (Coding::Gzip, gzip)
+    };
+    if quality > 0.0 && quality >= identity {
+        Some(coding)
+    } else {
+        None
+    }
+}
+
+/// Returns false for content types which are already compressed
+pub fn is_compressible(content_type: &[u8]) -> bool {
+    let value = match from_utf8(content_type) {
+        Ok(value) => value,
+        Err(_) => return false,
+    };
+    let mime = value.split(';').next().unwrap_or("").trim()
+        .to_ascii_lowercase();
+    if mime.starts_with("text/") {
+        return true;
+    }
+    if mime.starts_with("image/") {
+        return mime == "image/svg+xml" || mime == "image/bmp" ||
+               mime == "image/x-icon" || mime == "image/vnd.microsoft.icon";
+    }
+    if mime.starts_with("audio/") || mime.starts_with("video/") ||
+       mime.starts_with("font/woff")
+    {
+        return false;
+    }
+    !matches!(&mime[..],
+        "application/zip" | "application/gzip" | "application/x-gzip" |
+        "application/x-bzip2" | "application/x-xz" |
+        "application/x-7z-compressed" | "application/x-rar-compressed" |
+        "application/pdf" | "application/octet-stream" |
+        "application/font-woff")
+}
+
+impl Compressor {
+    /// Creates a compressor for the request
+    ///
+    /// Only `Accept-Encoding`, the version and the method are used, so
+    /// it's fine to create it in `headers_received()` and keep until the
+    /// response is written.
+    pub fn new(head: &Head) -> Compressor {
+        let coding = head.get("Accept-Encoding").and_then(negotiate);
+        Compressor {
+            coding: coding,
+            version: head.version,
+            is_head: head.method == "HEAD",
+            status: 0,
+            min_size: MIN_SIZE,
+            level: Compression::Default,
+            compressible: true,
+            content_length: None,
+            chunked: false,
+            encoder: None,
+        }
+    }
+    /// Set the minimal `Content-Length` of the body which is compressed
+    ///
+    /// Chunked bodies are always compressed as the size is unknown.
+    pub fn min_size(&mut self, size: u64) -> &mut Compressor {
+        self.min_size = size;
+        self
+    }
+    /// Set the compression level (`Compression::Default` by default)
+    pub fn level(&mut self, level: Compression) -> &mut Compressor {
+        self.level = level;
+        self
+    }
+    /// Returns the coding accepted by the client
+    pub fn coding(&self) -> Option<Coding> {
+        self.coding
+    }
+    /// Wraps the response for writing
+    ///
+    /// Call this for each event the response is written in, the state
+    /// is kept in the `Compressor` itself.
+    pub fn wrap<'a, 'b>(&'a mut self, response: &'a mut Response<'b>)
+        -> Compressed<'a, 'b>
+    {
+        Compressed { compressor: self, response: response }
+    }
+}
+
+impl Encoder {
+    fn new(coding: Coding, level: Compression) -> Encoder {
+        match coding {
+            Coding::Gzip => Encoder::Gzip(GzEncoder::new(Vec::new(), level)),
+            Coding::Deflate => {
+                Encoder::Deflate(ZlibEncoder::new(Vec::new(), level))
+            }
+        }
+    }
+    fn write(&mut self, data: &[u8]) -> Vec<u8> {
+        // Writing into a vector never fails
+        match *self {
+            Encoder::Gzip(ref mut enc) => {
+                enc.write_all(data).unwrap();
+                mem::replace(enc.get_mut(), Vec::new())
+            }
+            Encoder::Deflate(ref mut enc) => {
+                enc.write_all(data).unwrap();
+                mem::replace(enc.get_mut(), Vec::new())
+            }
+        }
+    }
+    fn flush(&mut self) -> Vec<u8> {
+        match *self {
+            Encoder::Gzip(ref mut enc) => {
+                enc.flush().unwrap();
+                mem::replace(enc.get_mut(), Vec::new())
+            }
+            Encoder::Deflate(ref mut enc) => {
+                enc.flush().unwrap();
+                mem::replace(enc.get_mut(), Vec::new())
+            }
+        }
+    }
+    fn finish(self) -> Vec<u8> {
+        match self {
+            Encoder::Gzip(enc) => enc.finish().unwrap(),
+            Encoder::Deflate(enc) => enc.finish().unwrap(),
+        }
+    }
+}
+
+impl<'a, 'b: 'a> Compressed<'a, 'b> {
+    /// Write status line, see `Response::status()`
+    pub fn status(&mut self, code: u16, reason: &str) {
+        self.response.status(code, reason);
+        self.compressor.status = code;
+    }
+    /// Add header to message, see `Response::add_header()`
+    ///
+    /// `Content-Type`, `Content-Encoding` and `Content-Range` headers are
+    /// inspected to find out whether the body should be compressed.
+    pub fn add_header(&mut self, name: &str, value: &[u8])
+        -> Result<(), HeaderError>
+    {
+        try!(self.response.add_header(name, value));
+        if name.eq_ignore_ascii_case("Content-Encoding") ||
+           name.eq_ignore_ascii_case("Content-Range")
+        {
+            self.compressor.compressible = false;
+        } else if name.eq_ignore_ascii_case("Content-Type") {
+            if !is_compressible(value) {
+                self.compressor.compressible = false;
+            }
+        }
+        Ok(())
+    }
+    /// Add a content length to the message
+    ///
+    /// Unlike `Response::add_length()` the header is written in
+    /// `done_headers()`, and only if the body isn't compressed.
+    pub fn add_length(&mut self, n: u64) -> Result<(), HeaderError> {
+        if self.compressor.content_length.is_some() {
+            return Err(HeaderError::DuplicateContentLength);
+        }
+        if self.compressor.chunked {
+            return Err(HeaderError::ContentLengthAfterTransferEncoding);
+        }
+        self.compressor.content_length = Some(n);
+        Ok(())
+    }
+    /// Sets the transfer encoding to chunked
+    ///
+    /// The header is written in `done_headers()`.
+    pub fn add_chunked(&mut self) -> Result<(), HeaderError> {
+        if self.compressor.content_length.is_some() {
+            return Err(HeaderError::TransferEncodingAfterContentLength);
+        }
+        if self.compressor.chunked {
+            return Err(HeaderError::DuplicateTransferEncoding);
+        }
+        self.compressor.chunked = true;
+        Ok(())
+    }
+    /// Returns true if at least `status()` method has been called
+    pub fn is_started(&self) -> bool {
+        self.response.is_started()
+    }
+    /// Decides whether to compress the body and finishes the headers
+    ///
+    /// See `Response::done_headers()`
+    pub fn done_headers(&mut self) -> Result<bool, HeaderError> {
+        let comp = &mut *self.compressor;
+        let negotiable = comp.compressible && !comp.is_head &&
+            (comp.chunked || comp.content_length.is_some());
+        let big_enough = comp.content_length
+            .map(|x| x >= comp.min_size).unwrap_or(true);
+        // The compressed body is chunked which HTTP/1.0 doesn't support,
+        // 206 is a range of the uncompressed body, and there is no body
+        // in 204 and 304 responses at all
+        let allowed = comp.version != Version::Http10 &&
+            !matches!(comp.status, 204 | 206 | 304);
+        if negotiable {
+            try!(self.response.add_header("Vary", b"Accept-Encoding"));
+        }
+        match comp.coding {
+            Some(coding) if negotiable && big_enough && allowed => {
+                try!(self.response.add_header("Content-Encoding",
+                                              coding.name().as_bytes()));
+                try!(self.response.add_chunked());
+                comp.encoder = Some(Encoder::new(coding, comp.level));
+            }
+            _ => {
+                if let Some(n) = comp.content_length {
+                    try!(self.response.add_length(n));
+                } else if comp.chunked {
+                    try!(self.response.add_chunked());
+                }
+            }
+        }
+        let result = self.response.done_headers();
+        if let Ok(false) = result {
+            // Body is not sent at all (e.g. 304 or 204)
+            comp.encoder = None;
+        }
+        result
+    }
+    /// Returns true if the body is compressed
+    pub fn is_compressed(&self) -> bool {
+        self.compressor.encoder.is_some()
+    }
+    /// Write a chunk of the body, see `Response::write_body()`
+    ///
+    /// The compressed data is buffered by the encoder, so the chunk may
+    /// be sent later. Use `flush()` if you need data to be sent right away.
+    pub fn write_body(&mut self, data: &[u8]) {
+        match self.compressor.encoder {
+            Some(ref mut enc) => {
+                let bytes = enc.write(data);
+                self.response.write_body(&bytes);
+            }
+            None => self.response.write_body(data),
+        }
+    }
+    /// Sends all the data buffered by the encoder
+    ///
+    /// This makes compression less efficient, so it's only useful for
+    /// long-lived streaming responses.
+    pub fn flush(&mut self) {
+        if let Some(ref mut enc) = self.compressor.encoder {
+            let bytes = enc.flush();
+            self.response.write_body(&bytes);
+        }
+    }
+    /// Requests `Server::response_flushed()`, see `Response::wait_flush()`
+    pub fn wait_flush(&mut self, max_buffered: usize) {
+        self.response.wait_flush(max_buffered)
+    }
+    /// Returns true if `done()` method is already called
+    pub fn is_complete(&self) -> bool {
+        self.response.is_complete()
+    }
+    /// Finishes compression and the response, see `Response::done()`
+    pub fn done(&mut self) {
+        if let Some(enc) = self.compressor.encoder.take() {
+            let bytes = enc.finish();
+            self.response.write_body(&bytes);
+        }
+        self.response.done()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::str::from_utf8;
+    use httparse::Header;
+    use rotor_stream::Buf;
+    use server::{Head, Response, BodyKind, Version};
+    use super::super::decode::Decoder;
+    use super::{negotiate, is_compressible, Coding, Compressor, Compressed};
+
+    #[test]
+    fn accept_encoding() {
+        assert_eq!(negotiate(b"gzip, deflate"), Some(Coding::Gzip));
+        assert_eq!(negotiate(b"deflate, gzip"), Some(Coding::Gzip));
+        assert_eq!(negotiate(b"deflate"), Some(Coding::Deflate));
+        assert_eq!(negotiate(b"gzip;q=0.5, deflate"), Some(Coding::Deflate));
+        assert_eq!(negotiate(b"gzip;q=0, deflate;q=0"), None);
+        assert_eq!(negotiate(b"br, identity"), None);
+        assert_eq!(negotiate(b"*"), Some(Coding::Gzip));
+        assert_eq!(negotiate(b""), None);
+        assert_eq!(negotiate(b"gzip;q=0, *"), Some(Coding::Deflate));
+        assert_eq!(negotiate(b"*, gzip;q=0"), Some(Coding::Deflate));
+        assert_eq!(negotiate(b"gzip;q=0, deflate;q=0, *"), None);
+        assert_eq!(negotiate(b"identity, gzip;q=0.5"), None);
+        assert_eq!(negotiate(b"identity;q=0, gzip;q=0.5"),
+                   Some(Coding::Gzip));
+        assert_eq!(negotiate(b"*;q=0, identity"), None);
+        assert_eq!(negotiate("gzip;a\u{e9}, deflate;\u{e9}".as_bytes()),
+                   Some(Coding::Gzip));
+    }
+
+    #[test]
+    fn content_types() {
+        assert!(is_compressible(b"application/json"));
+        assert!(is_compressible(b"text/html; charset=utf-8"));
+        assert!(is_compressible(b"image/svg+xml"));
+        assert!(!is_compressible(b"image/png"));
+        assert!(!is_compressible(b"Application/Zip"));
+        assert!(!is_compressible(b"video/mp4"));
+    }
+
+    /// Writes the response through `Compressed`, returns the headers
+    /// and the decoded body
+    fn respond<F>(version: Version, method: &str, accept: &[u8], f: F)
+        -> (String, Vec<u8>)
+        where F: FnOnce(&mut Compressed)
+    {
+        let headers = [Header { name: "Accept-Encoding", value: accept }];
+        let head = Head {
+            client: None,
+            tls: None,
+            version: version,
+            method: method,
+            scheme: "http",
+            path: "/",
+            headers: &headers,
+            body_kind: BodyKind::Fixed(0),
+        };
+        let mut compressor = Compressor::new(&head);
+        let mut buf = Buf::new();
+        {
+            let mut res = Response::new(&mut buf, version,
+                                        method == "HEAD", false);
+            f(&mut compressor.wrap(&mut res));
+        }
+        let end = buf[..].windows(4).position(|x| x == b"\r\n\r\n")
+            .unwrap() + 4;
+        let head = from_utf8(&buf[..end]).unwrap().to_string();
+        let mut body = buf[end..].to_vec();
+        if head.contains("Transfer-Encoding: chunked\r\n") {
+            body = dechunk(&body);
+        }
+        if head.contains("Content-Encoding: gzip\r\n") {
+            let mut dec = Decoder::content_codings(&[
+                Header { name: "Content-Encoding", value: b"gzip" },
+            ]).unwrap().unwrap();
+            let mut data = dec.decode(&body).unwrap();
+            data.extend(dec.finish().unwrap());
+            body = data;
+        }
+        (head, body)
+    }
+
+    fn dechunk(mut data: &[u8]) -> Vec<u8> {
+        let mut body = Vec::new();
+        loop {
+            let line = data.windows(2).position(|x| x == b"\r\n").unwrap();
+            let size = usize::from_str_radix(
+                from_utf8(&data[..line]).unwrap(), 16).unwrap();
+            if size == 0 {
+                assert_eq!(&data[line..], b"\r\n\r\n");
+                return body;
+            }
+            body.extend(&data[line+2..line+2+size]);
+            assert_eq!(&data[line+2+size..line+4+size], b"\r\n");
+            data = &data[line+4+size..];
+        }
+    }
+
+    fn text() -> Vec<u8> {
+        (0..2000).map(|x| b'a' + (x % 7) as u8).collect()
+    }
+
+    #[test]
+    fn buffered() {
+        let data = text();
+        let (head, body) = respond(Version::Http11, "GET", b"gzip", |res| {
+            res.status(200, "OK");
+            res.add_header("Content-Type", b"text/plain").unwrap();
+            res.add_length(data.len() as u64).unwrap();
+            assert!(res.done_headers().unwrap());
+            assert!(res.is_compressed());
+            res.write_body(&data);
+            res.done();
+        });
+        assert!(head.contains("Content-Encoding: gzip\r\n"));
+        assert!(head.contains("Vary: Accept-Encoding\r\n"));
+        assert!(!head.contains("Content-Length"));
+        assert_eq!(body, data);
+    }
+
+    #[test]
+    fn streaming() {
+        let data = text();
+        let (head, body) = respond(Version::Http11, "GET", b"gzip", |res| {
+            res.status(200, "OK");
+            res.add_chunked().unwrap();
+            assert!(res.done_headers().unwrap());
+            for piece in data.chunks(300) {
+                res.write_body(piece);
+                res.flush();
+            }
+            res.done();
+        });
+        assert!(head.contains("Content-Encoding: gzip\r\n"));
+        assert_eq!(body, data);
+    }
+
+    #[test]
+    fn not_compressed() {
+        let data = text();
+        let plain = |version, method, status: u16, header: Option<&str>| {
+            respond(version, method, b"gzip", |res| {
+                res.status(status, "Whatever");
+                if let Some(header) = header {
+                    let mut pair = header.splitn(2, ": ");
+                    res.add_header(pair.next().unwrap(),
+                                   pair.next().unwrap().as_bytes()).unwrap();
+                }
+                res.add_length(data.len() as u64).unwrap();
+                if res.done_headers().unwrap() {
+                    res.write_body(&data);
+                }
+                assert!(!res.is_compressed());
+                res.done();
+            })
+        };
+        let (head, body) = plain(Version::Http10, "GET", 200, None);
+        assert!(head.contains("Content-Length: 2000\r\n"));
+        assert_eq!(body, data);
+        let (head, body) = plain(Version::Http11, "HEAD", 200, None);
+        assert!(head.contains("Content-Length: 2000\r\n"));
+        assert!(!head.contains("Content-Encoding"));
+        assert_eq!(body, b"");
+        let (head, body) = plain(Version::Http11, "GET", 304, None);
+        assert!(!head.contains("Content-Encoding"));
+        assert!(!head.contains("Transfer-Encoding"));
+        assert_eq!(body, b"");
+        let (head, body) = plain(Version::Http11, "GET", 206,
+                                 Some("Content-Range: bytes 0-1999/5000"));
+        assert!(!head.contains("Content-Encoding"));
+        assert_eq!(body, data);
+        let (head, body) = plain(Version::Http11, "GET", 200,
+                                 Some("Content-Encoding: br"));
+        assert!(head.contains("Content-Encoding: br\r\n"));
+        assert!(!head.contains("gzip"));
+        assert_eq!(body, data);
+
+        let (head, body) = respond(Version::Http11, "GET", b"gzip", |res| {
+            res.status(204, "No Content");
+            assert!(!res.done_headers().unwrap());
+            res.done();
+        });
+        assert!(!head.contains("Content-Encoding"));
+        assert!(!head.contains("Transfer-Encoding"));
+        assert_eq!(body, b"");
+    }
+}
@@ -0,0 +1,305 @@
+//! Cookies of requests and the `Set-Cookie` header of responses
+//!
+//! Request cookies are accessed with `Head::cookies()`:
+//!
+//! ```ignore
+//! let session = head.cookies().find(|&(name, _)| name == "session");
+//! ```
+//!
+//! Cookies are set with the `SetCookie` builder:
+//!
+//! ```ignore
+//! SetCookie::new("session", &token)
+//!     .path("/")
+//!     .max_age(Duration::new(86400, 0))
+//!     .http_only()
+//!     .same_site(SameSite::Lax)
+//!     .add_to(response).unwrap();
+//! ```
+//!
+//! Names, values and attributes are validated according to RFC 6265 when
+//! the header is written, cookie values aren't encoded in any way.
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use std::str::from_utf8;
+
+use date::format_date;
+use headers::Values;
+use message::HeaderError;
+use super::Response;
+
+
+quick_error! {
+    /// Error writing `Set-Cookie` header
+    #[derive(Debug)]
+    pub enum CookieError {
+        BadName {
+            description("cookie name is not a valid token")
+        }
+        BadValue {
+            description("cookie value contains invalid characters")
+        }
+        BadAttribute(name: &'static str) {
+            description("cookie attribute contains invalid characters")
+            display("cookie attribute {} contains invalid characters", name)
+        }
+        Header(err: HeaderError) {
+            from()
+            description("error adding header")
+            display("error adding header: {}", err)
+        }
+    }
+}
+
+/// The `SameSite` attribute of the cookie
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SameSite {
+    Strict,
+    Lax,
+    /// Explicitly allows cross-site requests (requires `Secure`)
+    None,
+}
+
+/// An iterator over `(name, value)` pairs of the `Cookie` headers
+///
+/// Returned by `Head::cookies()`. Surrounding double quotes are stripped
+/// from the values, pairs without `=` and headers which are not valid
+/// utf-8 are skipped.
+#[derive(Debug, Clone)]
+pub struct Cookies<'a> {
+    values: Values<'a, 'static>,
+    current: Option<&'a str>,
+}
+
+/// A builder of the `Set-Cookie` header
+#[derive(Debug, Clone)]
+pub struct SetCookie {
+    name: String,
+    value: String,
+    expires: Option<SystemTime>,
+    max_age: Option<Duration>,
+    domain: Option<String>,
+    path: Option<String>,
+    secure: bool,
+    http_only: bool,
+    same_site: Option<SameSite>,
+}
+
+fn is_token(val: &str) -> bool {
+    val.len() > 0 && val.bytes().all(|x| x > 0x20 && x < 0x7F &&
+        !b"()<>@,;:\\\"/[]?={}".contains(&x))
+}
+
+fn is_cookie_value(val: &str) -> bool {
+    let val = if val.len() >= 2 && val.starts_with('"') && val.ends_with('"')
+        { &val[1..val.len()-1] } else { val };
+    val.bytes().all(|x| x > 0x20 && x < 0x7F &&
+        x != b'"' && x != b',' && x != b';' && x != b'\\')
+}
+
+fn is_attribute_value(val: &str) -> bool {
+    val.bytes().all(|x| x >= 0x20 && x < 0x7F && x != b';')
+}
+
+impl<'a> Cookies<'a> {
+    /// Iterates over cookies of the `Cookie` header values
+    pub fn new(values: Values<'a, 'static>) -> Cookies<'a> {
+        Cookies { values: values, current: None }
+    }
+}
+
+impl<'a> Iterator for Cookies<'a> {
+    type Item = (&'a str, &'a str);
+    fn next(&mut self) -> Option<(&'a str, &'a str)> {
+        loop {
+            let value = match self.current.take() {
+                Some(value) => value,
+                None => match self.values.next() {
+                    Some(value) => match from_utf8(value) {
+                        Ok(value) => value,
+                        Err(_) => continue,
+                    },
+                    None => return None,
+                },
+            };
+            let pair = match value.find(';') {
+                Some(idx) => {
+                    self.current = Some(&value[idx+1..]);
+                    &value[..idx]
+                }
+                None => value,
+            };
+            let (name, value) = match pair.find('=') {
+                Some(idx) => (pair[..idx].trim(), pair[idx+1..].trim()),
+                None => continue,
+            };
+            if name.len() == 0 {
+                continue;
+            }
+            if value.len() >= 2 && value.starts_with('"') &&
+                value.ends_with('"')
+            {
+                return Some((name, &value[1..value.len()-1]));
+            }
+            return Some((name, value));
+        }
+    }
+}
+
+impl SetCookie {
+    /// Creates a session cookie without any attributes
+    pub fn new(name: &str, value: &str) -> SetCookie {
+        SetCookie {
+            name: name.to_string(),
+            value: value.to_string(),
+            expires: None,
+            max_age: None,
+            domain: None,
+            path: None,
+            secure: false,
+            http_only: false,
+            same_site: None,
+        }
+    }
+    /// Set the `Expires` attribute
+    pub fn expires(&mut self, time: SystemTime) -> &mut SetCookie {
+        self.expires = Some(time);
+        self
+    }
+    /// Set the `Max-Age` attribute (zero age removes the cookie)
+    pub fn max_age(&mut self, age: Duration) -> &mut SetCookie {
+        self.max_age = Some(age);
+        self
+    }
+    /// Set the `Domain` attribute
+    pub fn domain(&mut self, domain: &str) -> &mut SetCookie {
+        self.domain = Some(domain.to_string());
+        self
+    }
+    /// Set the `Path` attribute
+    pub fn path(&mut self, path: &str) -> &mut SetCookie {
+        self.path = Some(path.to_string());
+        self
+    }
+    /// Add the `Secure` attribute
+    pub fn secure(&mut self) -> &mut SetCookie {
+        self.secure = true;
+        self
+    }
+    /// Add the `HttpOnly` attribute
+    pub fn http_only(&mut self) -> &mut SetCookie {
+        self.http_only = true;
+        self
+    }
+    /// Set the `SameSite` attribute
+    pub fn same_site(&mut self, value: SameSite) -> &mut SetCookie {
+        self.same_site = Some(value);
+        self
+    }
+    /// Validates the cookie and returns the value of the header
+    pub fn to_value(&self) -> Result<String, CookieError> {
+        if !is_token(&self.name) {
+            return Err(CookieError::BadName);
+        }
+        if !is_cookie_value(&self.value) {
+            return Err(CookieError::BadValue);
+        }
+        let mut buf = format!("{}={}", self.name, self.value);
+        if let Some(time) = self.expires {
+            let secs = time.duration_since(UNIX_EPOCH)
+                .map(|x| x.as_secs()).unwrap_or(0);
+            buf.push_str("; Expires=");
+            buf.push_str(&format_date(secs));
+        }
+        if let Some(age) = self.max_age {
+            buf.push_str(&format!("; Max-Age={}", age.as_secs()));
+        }
+        if let Some(ref domain) = self.domain {
+            if domain.len() == 0 || !is_attribute_value(domain) {
+                return Err(CookieError::BadAttribute("Domain"));
+            }
+            buf.push_str("; Domain=");
+            buf.push_str(domain);
+        }
+        if let Some(ref path) = self.path {
+            if !path.starts_with('/') || !is_attribute_value(path) {
+                return Err(CookieError::BadAttribute("Path"));
+            }
+            buf.push_str("; Path=");
+            buf.push_str(path);
+        }
+        if self.secure {
+            buf.push_str("; Secure");
+        }
+        if self.http_only {
+            buf.push_str("; HttpOnly");
+        }
+        match self.same_site {
+            Some(SameSite::Strict) => buf.push_str("; SameSite=Strict"),
+            Some(SameSite::Lax) => buf.push_str("; SameSite=Lax"),
+            Some(SameSite::None) => buf.push_str("; SameSite=None"),
+            None => {}
+        }
+        Ok(buf)
+    }
+    /// Adds the `Set-Cookie` header to the response
+    pub fn add_to(&self, response: &mut Response) -> Result<(), CookieError> {
+        let value = try!(self.to_value());
+        try!(response.add_header("Set-Cookie", value.as_bytes()));
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::time::{Duration, UNIX_EPOCH};
+    use httparse::Header;
+    use headers::{values, Fields};
+    use super::{Cookies, SetCookie, SameSite, CookieError};
+
+    #[test]
+    fn parse() {
+        let headers = [
+            Header { name: "Cookie", value: b"a=1; b=\"x y\";c=; =d; e" },
+            Header { name: "Host", value: b"example.com" },
+            Header { name: "cookie", value: b" f = 2 " },
+            Header { name: "Cookie", value: b"\xff=3" },
+        ];
+        let cookies = Cookies::new(values(Fields::new(&headers), "Cookie"))
+            .collect::<Vec<_>>();
+        assert_eq!(cookies, vec![("a", "1"), ("b", "x y"), ("c", ""),
+                                 ("f", "2")]);
+    }
+
+    #[test]
+    fn set_cookie() {
+        assert_eq!(SetCookie::new("a", "1").to_value().unwrap(), "a=1");
+        assert_eq!(SetCookie::new("sid", "\"abc\"")
+            .expires(UNIX_EPOCH + Duration::new(784111777, 0))
+            .max_age(Duration::new(3600, 0))
+            .domain("example.com")
+            .path("/app")
+            .secure()
+            .http_only()
+            .same_site(SameSite::Lax)
+            .to_value().unwrap(),
+            "sid=\"abc\"; Expires=Sun, 06 Nov 1994 08:49:37 GMT; \
+             Max-Age=3600; Domain=example.com; Path=/app; Secure; \
+             HttpOnly; SameSite=Lax");
+    }
+
+    #[test]
+    fn validation() {
+        assert!(matches!(SetCookie::new("a b", "1").to_value(),
+                         Err(CookieError::BadName)));
+        assert!(matches!(SetCookie::new("", "1").to_value(),
+                         Err(CookieError::BadName)));
+        assert!(matches!(SetCookie::new("a", "1;b=2").to_value(),
+                         Err(CookieError::BadValue)));
+        assert!(matches!(SetCookie::new("a", "x y").to_value(),
+                         Err(CookieError::BadValue)));
+        assert!(matches!(SetCookie::new("a", "1").path("x").to_value(),
+                         Err(CookieError::BadAttribute("Path"))));
+        assert!(matches!(SetCookie::new("a", "1").domain("a;b").to_value(),
+                         Err(CookieError::BadAttribute("Domain"))));
+    }
+}
@@ -0,0 +1,373 @@
+//! Decoding of request bodies
+//!
+//! Transfer codings applied before `chunked` (`Transfer-Encoding: gzip,
+//! chunked`) are always decoded, `Content-Encoding` only if enabled by
+//! `Server::decode_request_body()`. The `gzip` and `deflate` codings are
+//! supported with the `compression` feature. Any other coding (or any coding
+//! at all without the feature) is refused with `501 Not Implemented` for
+//! transfer codings and `415 Unsupported Media Type` for content codings.
+//!
+//! Decoded data is limited by the `Buffered` size or, in the `Progressive`
+//! mode, by `Limits::max_decoded_chunk` for every piece. Inflating stops
+//! as soon as the limit is exceeded, so highly compressed bodies are never
+//! expanded in memory as a whole.
+use std::ascii::AsciiExt;
+use std::cmp::min;
+use std::fmt;
+use std::usize;
+#[cfg(feature="compression")] use std::io::{self, Write};
+#[cfg(feature="compression")] use std::mem;
+
+use httparse::Header;
+#[cfg(feature="compression")] use flate2::write::{GzDecoder, ZlibDecoder};
+
+use headers::{self, Fields};
+use super::RequestError;
+
+
+/// Collects the output of a layer, refusing to grow over `room` bytes
+///
+/// Decoders write the output in pieces of their internal buffer size, so
+/// decoding stops shortly after the limit is reached, without inflating
+/// the rest of the input.
+#[cfg(feature="compression")]
+struct Sink {
+    data: Vec<u8>,
+    room: usize,
+}
+
+enum Layer {
+    #[cfg(feature="compression")]
+    Gzip(GzDecoder<Sink>),
+    #[cfg(feature="compression")]
+    Deflate(ZlibDecoder<Sink>),
+}
+
+/// Decodes the request body, limiting the size of decoded data
+pub struct Decoder {
+    /// Codings in the order of decoding
+    layers: Vec<Layer>,
+    /// The limit of the `Buffered` mode
+    limit: usize,
+    /// The limit of data decoded at once, for the `Progressive` mode
+    chunk_limit: usize,
+    decoded: usize,
+}
+
+#[cfg(feature="compression")]
+fn layer(coding: &str) -> Option<Layer> {
+    let sink = Sink { data: Vec::new(), room: 0 };
+    if coding.eq_ignore_ascii_case("gzip") ||
+       coding.eq_ignore_ascii_case("x-gzip")
+    {
+        Some(Layer::Gzip(GzDecoder::new(sink)))
+    } else if coding.eq_ignore_ascii_case("deflate") {
+        Some(Layer::Deflate(ZlibDecoder::new(sink)))
+    } else {
+        None
+    }
+}
+
+#[cfg(not(feature="compression"))]
+fn layer(_coding: &str) -> Option<Layer> {
+    None
+}
+
+/// Returns true if the transfer coding can be decoded
+pub fn is_supported(coding: &[u8]) -> bool {
+    let coding = String::from_utf8_lossy(coding);
+    layer(coding.trim()).is_some()
+}
+
+/// Returns the list of codings of all headers with the `name`
+fn codings(headers: &[Header], name: &str) -> Vec<String> {
+    headers::list(Fields::new(headers), name)
+        .map(|x| String::from_utf8_lossy(x).into_owned())
+        .collect()
+}
+
+/// Tells the sink overflow from the broken data
+#[cfg(feature="compression")]
+fn error(err: io::Error) -> RequestError {
+    if err.kind() == io::ErrorKind::WriteZero {
+        RequestError::PayloadTooLarge
+    } else {
+        RequestError::BadContentEncoding
+    }
+}
+
+#[cfg(feature="compression")]
+impl Write for Sink {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if buf.len() > self.room - self.data.len() {
+            return Err(io::Error::new(io::ErrorKind::WriteZero,
+                                      "decoded data exceeds the limit"));
+        }
+        self.data.extend(buf);
+        Ok(buf.len())
+    }
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl Layer {
+    #[cfg(feature="compression")]
+    fn sink(&mut self) -> &mut Sink {
+        match *self {
+            Layer::Gzip(ref mut dec) => dec.get_mut(),
+            Layer::Deflate(ref mut dec) => dec.get_mut(),
+        }
+    }
+    /// Decodes the data, fails if the output is larger than `room`
+    #[cfg(feature="compression")]
+    fn decode(&mut self, data: &[u8], room: usize)
+        -> Result<Vec<u8>, RequestError>
+    {
+        self.sink().room = room;
+        try!(match *self {
+            Layer::Gzip(ref mut dec) => dec.write_all(data),
+            Layer::Deflate(ref mut dec) => dec.write_all(data),
+        }.map_err(error));
+        Ok(mem::replace(&mut self.sink().data, Vec::new()))
+    }
+    #[cfg(feature="compression")]
+    fn finish(mut self, room: usize) -> Result<Vec<u8>, RequestError> {
+        self.sink().room = room;
+        let sink = try!(match self {
+            Layer::Gzip(dec) => dec.finish(),
+            Layer::Deflate(dec) => dec.finish(),
+        }.map_err(error));
+        Ok(sink.data)
+    }
+    #[cfg(not(feature="compression"))]
+    fn decode(&mut self, _data: &[u8], _room: usize)
+        -> Result<Vec<u8>, RequestError>
+    {
+        match *self {}
+    }
+    #[cfg(not(feature="compression"))]
+    fn finish(self, _room: usize) -> Result<Vec<u8>, RequestError> {
+        match self {}
+    }
+}
+
+impl Decoder {
+    /// Creates a decoder for codings listed in the order of application
+    fn new(codings: &[String], unsupported: RequestError)
+        -> Result<Option<Decoder>, RequestError>
+    {
+        let mut layers = Vec::new();
+        for coding in codings.iter().rev() {
+            if coding.eq_ignore_ascii_case("identity") {
+                continue;
+            }
+            match layer(coding) {
+                Some(layer) => layers.push(layer),
+                None => return Err(unsupported),
+            }
+        }
+        if layers.len() == 0 {
+            return Ok(None);
+        }
+        Ok(Some(Decoder {
+            layers: layers,
+            limit: usize::MAX,
+            chunk_limit: usize::MAX,
+            decoded: 0,
+        }))
+    }
+    /// Creates a decoder for the `Transfer-Encoding` of the request
+    ///
+    /// Codings should be validated by the parser already, so the final
+    /// `chunked` is just skipped. Returns `Ok(None)` if there is nothing
+    /// to decode.
+    pub fn transfer_codings(headers: &[Header])
+        -> Result<Option<Decoder>, RequestError>
+    {
+        let mut codings = codings(headers, "Transfer-Encoding");
+        if codings.last().map(|x| headers::is_chunked(x.as_bytes()))
+            .unwrap_or(false)
+        {
+            codings.pop();
+        }
+        Decoder::new(&codings, RequestError::UnsupportedTransferCoding)
+    }
+    /// Creates a decoder for the `Content-Encoding` of the request
+    ///
+    /// Returns `Ok(None)` if the body is not encoded (or is `identity`).
+    /// The size of decoded data is not limited until `limit()` or
+    /// `chunk_limit()` is called.
+    pub fn content_codings(headers: &[Header])
+        -> Result<Option<Decoder>, RequestError>
+    {
+        Decoder::new(&codings(headers, "Content-Encoding"),
+                     RequestError::UnsupportedContentEncoding)
+    }
+    /// Joins two decoders, the `outer` one decodes the output of `inner`
+    pub fn join(inner: Option<Decoder>, outer: Option<Decoder>)
+        -> Option<Decoder>
+    {
+        match (inner, outer) {
+            (Some(mut inner), Some(outer)) => {
+                inner.layers.extend(outer.layers);
+                Some(inner)
+            }
+            (inner, None) => inner,
+            (None, outer) => outer,
+        }
+    }
+    /// Sets the maximum size of decoded data
+    pub fn limit(&mut self, limit: usize) {
+        self.limit = limit;
+    }
+    /// Sets the maximum size of data returned by a single `decode()`
+    pub fn chunk_limit(&mut self, limit: usize) {
+        self.chunk_limit = limit;
+    }
+    /// The size of the output allowed for the next call
+    ///
+    /// Intermediate layers (if there are multiple codings) are limited by
+    /// the same size.
+    fn room(&self) -> usize {
+        min(self.limit - self.decoded, self.chunk_limit)
+    }
+    /// Decodes the next part of the body
+    ///
+    /// Returns `PayloadTooLarge` when decoded data exceeds the limit,
+    /// decoding is stopped as soon as that happens.
+    pub fn decode(&mut self, data: &[u8]) -> Result<Vec<u8>, RequestError> {
+        let room = self.room();
+        let mut data = data.to_vec();
+        for layer in self.layers.iter_mut() {
+            data = try!(layer.decode(&data, room));
+        }
+        self.decoded += data.len();
+        Ok(data)
+    }
+    /// Finishes decoding and returns the rest of the data
+    pub fn finish(self) -> Result<Vec<u8>, RequestError> {
+        let room = self.room();
+        let mut data = Vec::new();
+        for mut layer in self.layers {
+            let mut tail = try!(layer.decode(&data, room));
+            let rest = try!(layer.finish(room - tail.len()));
+            tail.extend(rest);
+            data = tail;
+        }
+        Ok(data)
+    }
+}
+
+impl fmt::Debug for Decoder {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Decoder")
+            .field("layers", &self.layers.len())
+            .field("limit", &self.limit)
+            .field("chunk_limit", &self.chunk_limit)
+            .field("decoded", &self.decoded)
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use httparse::Header;
+    use super::Decoder;
+    use super::super::RequestError;
+
+    fn decoder(value: &[u8]) -> Result<Option<Decoder>, RequestError> {
+        Decoder::content_codings(&[
+            Header { name: "Content-Type", value: b"application/json" },
+            Header { name: "Content-Encoding", value: value },
+        ])
+    }
+
+    fn transfer(value: &[u8]) -> Result<Option<Decoder>, RequestError> {
+        Decoder::transfer_codings(&[
+            Header { name: "Transfer-Encoding", value: value },
+        ])
+    }
+
+    #[test]
+    fn no_coding() {
+        assert!(Decoder::content_codings(&[]).unwrap().is_none());
+        assert!(decoder(b"identity").unwrap().is_none());
+        assert!(transfer(b"chunked").unwrap().is_none());
+    }
+
+    #[test]
+    fn unsupported() {
+        assert!(matches!(decoder(b"br"),
+            Err(RequestError::UnsupportedContentEncoding)));
+        assert!(matches!(decoder(b"compress"),
+            Err(RequestError::UnsupportedContentEncoding)));
+        assert!(matches!(decoder(b"gzip, br"),
+            Err(RequestError::UnsupportedContentEncoding)));
+        assert!(matches!(transfer(b"br, chunked"),
+            Err(RequestError::UnsupportedTransferCoding)));
+    }
+
+    #[cfg(feature="compression")]
+    #[test]
+    fn gzip() {
+        // "hello" compressed by gzip
+        let data = b"\x1f\x8b\x08\x00\x00\x00\x00\x00\x00\x03\xcb\x48\xcd\
+                     \xc9\xc9\x07\x00\x86\xa6\x10\x36\x05\x00\x00\x00";
+        let mut dec = decoder(b"gzip").unwrap().unwrap();
+        let mut result = dec.decode(&data[..10]).unwrap();
+        result.extend(dec.decode(&data[10..]).unwrap());
+        result.extend(dec.finish().unwrap());
+        assert_eq!(&result[..], b"hello");
+    }
+
+    #[cfg(feature="compression")]
+    #[test]
+    fn gzip_transfer() {
+        let data = b"\x1f\x8b\x08\x00\x00\x00\x00\x00\x00\x03\xcb\x48\xcd\
+                     \xc9\xc9\x07\x00\x86\xa6\x10\x36\x05\x00\x00\x00";
+        let mut dec = transfer(b"gzip, chunked").unwrap().unwrap();
+        let mut result = dec.decode(&data[..]).unwrap();
+        result.extend(dec.finish().unwrap());
+        assert_eq!(&result[..], b"hello");
+    }
+
+    #[cfg(feature="compression")]
+    #[test]
+    fn limit() {
+        // 1000 zero bytes compressed by zlib
+        let data = b"\x78\x9c\x63\x60\x18\x05\xa3\x60\x14\x0c\x77\x00\x00\
+                     \x03\xe8\x00\x01";
+        let mut dec = decoder(b"deflate").unwrap().unwrap();
+        dec.limit(100);
+        let result = dec.decode(&data[..]).and_then(|_| dec.finish());
+        assert!(matches!(result, Err(RequestError::PayloadTooLarge)));
+    }
+
+    #[cfg(feature="compression")]
+    #[test]
+    fn bomb() {
+        use std::io::Write;
+        use flate2::Compression;
+        use flate2::write::ZlibEncoder;
+        // 16 MiB of zeros compresses to about 16 KiB
+        let mut enc = ZlibEncoder::new(Vec::new(), Compression::Best);
+        for _ in 0..256 {
+            enc.write_all(&[0; 65536]).unwrap();
+        }
+        let data = enc.finish().unwrap();
+
+        let mut dec = decoder(b"deflate").unwrap().unwrap();
+        dec.limit(100000);
+        assert!(matches!(dec.decode(&data[..]),
+            Err(RequestError::PayloadTooLarge)));
+        // Only the data up to the limit is kept
+        assert!(dec.layers[0].sink().data.capacity() < 200000);
+
+        let mut dec = decoder(b"deflate").unwrap().unwrap();
+        dec.chunk_limit(100000);
+        assert!(matches!(dec.decode(&data[..]),
+            Err(RequestError::PayloadTooLarge)));
+        assert!(dec.layers[0].sink().data.capacity() < 200000);
+    }
+}
@@ -15,8 +15,12 @@ quick_error!{
     #[derive(Debug)]
     pub enum RequestError {
         HeadersAreTooLarge {
-            description("headers are larger than \
-                         http::request::MAX_HEADERS_SIZE")
+            description("headers are larger than is allowed by server \
+                         settings")
+        }
+        RequestLineTooLong {
+            description("request line is longer than is allowed by server \
+                         settings")
         }
         BadHeaders(e: httparse::Error) {
             from()
@@ -30,12 +34,26 @@ quick_error!{
         DuplicateContentLength {
             description("duplicate `Content-Length` header in request")
         }
+        ChunkedNotLast {
+            description("`chunked` is not the final transfer coding of the \
+                         request")
+        }
+        UnsupportedTransferCoding {
+            description("transfer coding of the request is not supported")
+        }
         HeadersReceived {
             description("request aborted in `headers_received()` handler")
         }
         PayloadTooLarge {
             description("payload is larger than is allowed by server settings")
         }
+        UnsupportedContentEncoding {
+            description("content encoding of the request body is not \
+                         supported")
+        }
+        BadContentEncoding {
+            description("error decoding request body")
+        }
         PrematureEndOfStream {
             description("premature end of stream")
         }
@@ -73,13 +91,18 @@ impl HttpError for RequestError {
         use self::RequestError::*;
         match *self {
             HeadersAreTooLarge => (431, "Request Header Fields Too Large"),
+            RequestLineTooLong => (414, "URI Too Long"),
             BadHeaders(_) => (400, "Bad Request"),
             BadUtf8(_) => (400, "Bad Request"),
             BadContentLength(_) => (400, "Bad Request"),
             InvalidChunkSize(_) => (400, "Bad Request"),
             DuplicateContentLength => (400, "Bad Request"),
+            ChunkedNotLast => (400, "Bad Request"),
+            UnsupportedTransferCoding => (501, "Not Implemented"),
             HeadersReceived => (400, "Bad Request"),
             PayloadTooLarge => (413, "Payload Too Large"),
+            UnsupportedContentEncoding => (415, "Unsupported Media Type"),
+            BadContentEncoding => (400, "Bad Request"),
             HeadersTimeout => (408, "Request Timeout"),
             RequestTimeout => (408, "Request Timeout"),
             HandlerTimeout => (504, "Gateway Timeout"),
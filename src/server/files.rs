@@ -0,0 +1,768 @@
+//! Serving static files from a directory
+//!
+//! `FileServer` is a complete `Server` which maps the URL prefix of
+//! `Files` to a directory:
+//!
+//! ```ignore
+//! let mut files = Files::new("/static", "/var/www/assets");
+//! files.index_file(Some("index.html"));
+//! let files = Arc::new(files);
+//! loop_inst.add_machine_with(|scope| {
+//!     Fsm::<FileServer<()>, _>::new(lst, files, scope)
+//! }).unwrap();
+//! ```
+//!
+//! Only `GET` and `HEAD` are allowed. Responses carry `ETag` and
+//! `Last-Modified` validators, so `If-None-Match` and `If-Modified-Since`
+//! are answered with `304 Not Modified`. `Range` requests (including
+//! `If-Range`) are answered with `206 Partial Content`, multiple ranges are
+//! sent as `multipart/byteranges`.
+//!
+//! The file is read by `chunk_size()` pieces, the next piece is put into
+//! the output buffer when the previous one is sent (see
+//! `Response::wait_flush()`), so large files are never loaded into memory
+//! at once.
+//!
+//! The `FileServer` may also be used by another handler (e.g. behind the
+//! `Router`) by forwarding the events to it.
+use std::io::{self, Read, Seek, SeekFrom};
+use std::fs::{self, File, Metadata};
+use std::cmp::min;
+use std::ascii::AsciiExt;
+use std::str::from_utf8;
+use std::path::{Path, PathBuf};
+use std::collections::VecDeque;
+use std::marker::PhantomData;
+use std::sync::Arc;
+use std::time::UNIX_EPOCH;
+
+use rand::random;
+use rotor::{Scope, Time};
+
+use date::{format_date, parse_date};
+use recvmode::RecvMode;
+use super::error::HttpError;
+use super::target::percent_decode;
+use super::{Head, Response, Server};
+
+
+/// The default size of the piece of the file written at once
+pub const CHUNK_SIZE: usize = 65536;
+/// More ranges in a request are ignored (the full file is sent)
+pub const MAX_RANGES: usize = 16;
+
+quick_error! {
+    /// Error serving the file
+    #[derive(Debug)]
+    pub enum FileError {
+        NotFound {
+            description("file not found")
+        }
+        Forbidden {
+            description("permission denied")
+        }
+        MethodNotAllowed {
+            description("only GET and HEAD are allowed")
+        }
+        /// None of the ranges is satisfiable, contains the size of the file
+        RangeNotSatisfiable(size: u64) {
+            description("requested range is not satisfiable")
+        }
+        Io(err: io::Error) {
+            description("error reading file")
+            display("error reading file: {}", err)
+        }
+    }
+}
+
+impl HttpError for FileError {
+    fn http_status(&self) -> (u16, &'static str) {
+        use self::FileError::*;
+        match *self {
+            NotFound => (404, "Not Found"),
+            Forbidden => (403, "Forbidden"),
+            MethodNotAllowed => (405, "Method Not Allowed"),
+            RangeNotSatisfiable(_) => (416, "Range Not Satisfiable"),
+            Io(_) => (500, "Internal Server Error"),
+        }
+    }
+}
+
+impl From<io::Error> for FileError {
+    fn from(err: io::Error) -> FileError {
+        match err.kind() {
+            io::ErrorKind::NotFound => FileError::NotFound,
+            io::ErrorKind::PermissionDenied => FileError::Forbidden,
+            _ => FileError::Io(err),
+        }
+    }
+}
+
+/// The mapping of the URL prefix to a directory, the seed of `FileServer`
+#[derive(Debug, Clone)]
+pub struct Files {
+    prefix: String,
+    root: PathBuf,
+    index: Option<String>,
+    chunk_size: usize,
+}
+
+/// A handler serving files, see the module docs
+pub struct FileServer<C> {
+    transfer: Transfer,
+    chunk_size: usize,
+    phantom: PhantomData<C>,
+}
+
+#[derive(Debug)]
+enum Chunk {
+    Data(Vec<u8>),
+    /// Offset and length of the part of the file
+    File(u64, u64),
+}
+
+/// The body which is not written yet
+#[derive(Debug)]
+struct Transfer {
+    file: Option<File>,
+    chunks: VecDeque<Chunk>,
+}
+
+/// Guesses the `Content-Type` by the extension of the file
+///
+/// Returns `application/octet-stream` for unknown extensions.
+pub fn content_type(path: &Path) -> &'static str {
+    let ext = match path.extension().and_then(|x| x.to_str()) {
+        Some(ext) => ext.to_ascii_lowercase(),
+        None => return "application/octet-stream",
+    };
+    match &ext[..] {
+        "html" | "htm" => "text/html; charset=utf-8",
+        "css" => "text/css; charset=utf-8",
+        "js" | "mjs" => "application/javascript; charset=utf-8",
+        "json" => "application/json",
+        "txt" => "text/plain; charset=utf-8",
+        "csv" => "text/csv; charset=utf-8",
+        "xml" => "application/xml",
+        "svg" => "image/svg+xml",
+        "png" => "image/png",
+        "jpg" | "jpeg" => "image/jpeg",
+        "gif" => "image/gif",
+        "webp" => "image/webp",
+        "ico" => "image/x-icon",
+        "pdf" => "application/pdf",
+        "wasm" => "application/wasm",
+        "woff" => "font/woff",
+        "woff2" => "font/woff2",
+        "ttf" => "font/ttf",
+        "otf" => "font/otf",
+        "mp3" => "audio/mpeg",
+        "ogg" => "audio/ogg",
+        "mp4" => "video/mp4",
+        "webm" => "video/webm",
+        "zip" => "application/zip",
+        "gz" => "application/gzip",
+        "tar" => "application/x-tar",
+        _ => "application/octet-stream",
+    }
+}
+
+fn number(value: &str) -> Option<u64> {
+    if value.len() > 0 && value.bytes().all(|x| x >= b'0' && x <= b'9') {
+        value.parse().ok()
+    } else {
+        None
+    }
+}
+
+/// Parses the `Range` header
+///
+/// Returns `None` when the header must be ignored (the unit is not
+/// `bytes`, the syntax is invalid or there are too many ranges), and an
+/// empty list if none of the ranges is satisfiable. Ranges are inclusive
+/// and clipped to the size of the file.
+fn parse_ranges(value: &str, size: u64) -> Option<Vec<(u64, u64)>> {
+    let value = value.trim();
+    if value.len() < 6 ||
+        !value.as_bytes()[..6].eq_ignore_ascii_case(b"bytes=")
+    {
+        return None;
+    }
+    let mut ranges = Vec::new();
+    let mut specs = 0;
+    for spec in value[6..].split(',').map(|x| x.trim()) {
+        if spec.len() == 0 {
+            continue;
+        }
+        specs += 1;
+        if specs > MAX_RANGES {
+            return None;
+        }
+        let dash = match spec.find('-') {
+            Some(dash) => dash,
+            None => return None,
+        };
+        let (first, last) = (spec[..dash].trim(), spec[dash+1..].trim());
+        if first.len() == 0 {
+            let suffix = match number(last) {
+                Some(suffix) => suffix,
+                None => return None,
+            };
+            if suffix > 0 && size > 0 {
+                ranges.push((size - min(suffix, size), size - 1));
+            }
+        } else {
+            let first = match number(first) {
+                Some(first) => first,
+                None => return None,
+            };
+            let last = if last.len() == 0 {
+                None
+            } else {
+                match number(last) {
+                    Some(last) if last >= first => Some(last),
+                    _ => return None,
+                }
+            };
+            if first < size {
+                ranges.push((first, min(last.unwrap_or(size - 1), size - 1)));
+            }
+        }
+    }
+    if specs == 0 {
+        return None;
+    }
+    Some(ranges)
+}
+
+fn header<'a>(head: &Head<'a>, name: &str) -> Option<&'a str> {
+    head.get(name).and_then(|x| from_utf8(x).ok())
+}
+
+/// Weak comparison of the entity tags from `If-None-Match`
+fn etag_matches<'a, I>(tags: I, etag: &str) -> bool
+    where I: Iterator<Item=&'a [u8]>
+{
+    let etag = etag.as_bytes();
+    for tag in tags {
+        if tag == b"*" {
+            return true;
+        }
+        let tag = if tag.starts_with(b"W/") { &tag[2..] } else { tag };
+        if tag == etag {
+            return true;
+        }
+    }
+    false
+}
+
+impl Files {
+    /// Serve files of the `root` directory at the URL `prefix`
+    ///
+    /// The `prefix` is a path like `/static`, `/` serves the whole site.
+    /// The directory index is `index.html` by default.
+    pub fn new<P: Into<PathBuf>>(prefix: &str, root: P) -> Files {
+        let prefix = prefix.trim_right_matches('/');
+        Files {
+            prefix: if prefix.starts_with('/') || prefix.len() == 0 {
+                prefix.to_string()
+            } else {
+                format!("/{}", prefix)
+            },
+            root: root.into(),
+            index: Some("index.html".to_string()),
+            chunk_size: CHUNK_SIZE,
+        }
+    }
+    /// Set the file served for directories, `None` means directories are
+    /// not found
+    pub fn index_file(&mut self, name: Option<&str>) -> &mut Files {
+        self.index = name.map(|x| x.to_string());
+        self
+    }
+    /// Set the size of the piece of the file put into the output buffer
+    /// at once (`CHUNK_SIZE` by default)
+    pub fn chunk_size(&mut self, size: usize) -> &mut Files {
+        assert!(size > 0);
+        self.chunk_size = size;
+        self
+    }
+    /// Maps the path of the request target to the path in the filesystem
+    ///
+    /// Segments are percent-decoded separately, so the encoded slash is
+    /// not a separator. Returns `None` if the path doesn't start with the
+    /// prefix, or if any segment is `..` or contains a slash, a backslash
+    /// or a zero byte after decoding.
+    pub fn resolve(&self, path: &str) -> Option<PathBuf> {
+        if !path.starts_with(&self.prefix[..]) {
+            return None;
+        }
+        let rest = &path[self.prefix.len()..];
+        if rest.len() > 0 && !rest.starts_with('/') {
+            return None;
+        }
+        let mut result = self.root.clone();
+        for segment in rest.split('/') {
+            let segment = percent_decode(segment, false);
+            match &segment[..] {
+                "" | "." => continue,
+                ".." => return None,
+                s if s.contains(|c| c == '/' || c == '\\' || c == '\0') => {
+                    return None;
+                }
+                s => result.push(s),
+            }
+        }
+        Some(result)
+    }
+    fn open(&self, mut path: PathBuf)
+        -> Result<(File, Metadata, PathBuf), FileError>
+    {
+        let mut meta = try!(fs::metadata(&path));
+        if meta.is_dir() {
+            match self.index {
+                Some(ref index) => path.push(index),
+                None => return Err(FileError::NotFound),
+            }
+            meta = try!(fs::metadata(&path));
+        }
+        if !meta.is_file() {
+            return Err(FileError::NotFound);
+        }
+        let file = try!(File::open(&path));
+        Ok((file, meta, path))
+    }
+    /// Writes the response headers, the returned transfer writes the body
+    fn start(&self, head: &Head, response: &mut Response)
+        -> Result<Transfer, FileError>
+    {
+        if head.method != "GET" && head.method != "HEAD" {
+            return Err(FileError::MethodNotAllowed);
+        }
+        let path = match self.resolve(head.target().path()) {
+            Some(path) => path,
+            None => return Err(FileError::NotFound),
+        };
+        let (file, meta, path) = try!(self.open(path));
+        let size = meta.len();
+        let mtime = meta.modified().ok()
+            .and_then(|x| x.duration_since(UNIX_EPOCH).ok())
+            .map(|x| x.as_secs());
+        let etag = format!("\"{:x}-{:x}\"", size, mtime.unwrap_or(0));
+        let last_modified = mtime.map(format_date);
+
+        let not_modified = if head.contains("If-None-Match") {
+            etag_matches(head.get_list("If-None-Match"), &etag)
+        } else {
+            match (header(head, "If-Modified-Since").and_then(parse_date),
+                   mtime)
+            {
+                (Some(since), Some(mtime)) => mtime <= since,
+                _ => false,
+            }
+        };
+        if not_modified {
+            response.status(304, "Not Modified");
+            add_validators(response, &etag, &last_modified);
+            response.done_headers().unwrap();
+            response.done();
+            return Ok(Transfer::empty());
+        }
+
+        let if_range = match header(head, "If-Range").map(|x| x.trim()) {
+            None => true,
+            Some(value) if value.starts_with('"') => value == etag,
+            Some(value) => match (parse_date(value), mtime) {
+                (Some(date), Some(mtime)) => date == mtime,
+                _ => false,
+            },
+        };
+        let ranges = match header(head, "Range") {
+            Some(value) if head.method == "GET" && if_range => {
+                parse_ranges(value, size)
+            }
+            _ => None,
+        };
+        let content_type = content_type(&path);
+        let mut chunks = VecDeque::new();
+        match ranges {
+            None => {
+                response.status(200, "OK");
+                response.add_length(size).unwrap();
+                response.add_header("Content-Type", content_type.as_bytes())
+                    .unwrap();
+                chunks.push_back(Chunk::File(0, size));
+            }
+            Some(ref ranges) if ranges.len() == 0 => {
+                return Err(FileError::RangeNotSatisfiable(size));
+            }
+            Some(ref ranges) if ranges.len() == 1 => {
+                let (first, last) = ranges[0];
+                response.status(206, "Partial Content");
+                response.add_length(last - first + 1).unwrap();
+                response.add_header("Content-Type", content_type.as_bytes())
+                    .unwrap();
+                response.add_header("Content-Range",
+                    format!("bytes {}-{}/{}", first, last, size).as_bytes())
+                    .unwrap();
+                chunks.push_back(Chunk::File(first, last - first + 1));
+            }
+            Some(ref ranges) => {
+                let boundary = format!("{:016x}", random::<u64>());
+                let mut length = 0;
+                for &(first, last) in ranges {
+                    let part = format!("\r\n--{}\r\nContent-Type: {}\r\n\
+                        Content-Range: bytes {}-{}/{}\r\n\r\n",
+                        boundary, content_type, first, last, size);
+                    length += part.len() as u64 + last - first + 1;
+                    chunks.push_back(Chunk::Data(part.into_bytes()));
+                    chunks.push_back(Chunk::File(first, last - first + 1));
+                }
+                let end = format!("\r\n--{}--\r\n", boundary);
+                length += end.len() as u64;
+                chunks.push_back(Chunk::Data(end.into_bytes()));
+                response.status(206, "Partial Content");
+                response.add_length(length).unwrap();
+                response.add_header("Content-Type", format!(
+                    "multipart/byteranges; boundary={}", boundary).as_bytes())
+                    .unwrap();
+            }
+        }
+        response.add_header("Accept-Ranges", b"bytes").unwrap();
+        add_validators(response, &etag, &last_modified);
+        if !response.done_headers().unwrap() {
+            response.done();
+            return Ok(Transfer::empty());
+        }
+        Ok(Transfer { file: Some(file), chunks: chunks })
+    }
+}
+
+fn add_validators(response: &mut Response, etag: &str,
+    last_modified: &Option<String>)
+{
+    response.add_header("ETag", etag.as_bytes()).unwrap();
+    if let Some(ref date) = *last_modified {
+        response.add_header("Last-Modified", date.as_bytes()).unwrap();
+    }
+}
+
+/// Sends the error page with the `Allow` or `Content-Range` header
+fn error_page(err: &FileError, response: &mut Response) {
+    let (status, reason) = err.http_status();
+    let data = format!("<h1>{} {}</h1>\n\
+        <p><small>Served for you by rotor-http</small></p>\n",
+        status, reason);
+    response.status(status, reason);
+    match *err {
+        FileError::MethodNotAllowed => {
+            response.add_header("Allow", b"GET, HEAD").unwrap();
+        }
+        FileError::RangeNotSatisfiable(size) => {
+            response.add_header("Content-Range",
+                format!("bytes */{}", size).as_bytes()).unwrap();
+        }
+        _ => {}
+    }
+    response.add_length(data.len() as u64).unwrap();
+    response.add_header("Content-Type", b"text/html").unwrap();
+    response.done_headers().unwrap();
+    response.write_body(data.as_bytes());
+    response.done();
+}
+
+impl Transfer {
+    fn empty() -> Transfer {
+        Transfer { file: None, chunks: VecDeque::new() }
+    }
+    /// Writes up to `limit` bytes of the file, returns true when the
+    /// body is complete
+    fn write(&mut self, response: &mut Response, limit: usize)
+        -> io::Result<bool>
+    {
+        let mut budget = limit as u64;
+        while budget > 0 {
+            match self.chunks.pop_front() {
+                Some(Chunk::Data(data)) => {
+                    response.write_body(&data);
+                    budget = budget.saturating_sub(data.len() as u64);
+                }
+                Some(Chunk::File(offset, len)) => {
+                    let n = min(len, budget);
+                    let mut buf = vec![0; n as usize];
+                    {
+                        let file = self.file.as_mut()
+                            .expect("file is open when there is a body");
+                        try!(file.seek(SeekFrom::Start(offset)));
+                        // Fails if the file was truncated after `stat()`
+                        try!(file.read_exact(&mut buf));
+                    }
+                    response.write_body(&buf);
+                    if n < len {
+                        self.chunks.push_front(
+                            Chunk::File(offset + n, len - n));
+                    }
+                    budget -= n;
+                }
+                None => break,
+            }
+        }
+        Ok(self.chunks.len() == 0)
+    }
+}
+
+impl<C> FileServer<C> {
+    /// Writes the next piece of the body, the following one is written
+    /// when the output buffer is drained
+    fn send(mut self, response: &mut Response) -> Option<Self> {
+        match self.transfer.write(response, self.chunk_size) {
+            Ok(true) => {
+                response.done();
+                None
+            }
+            Ok(false) => {
+                response.wait_flush(self.chunk_size);
+                Some(self)
+            }
+            // The response is incomplete, so the connection is closed
+            Err(_) => None,
+        }
+    }
+}
+
+impl<C> Server for FileServer<C> {
+    type Seed = Arc<Files>;
+    type Context = C;
+    fn headers_received(seed: Arc<Files>, head: Head, response: &mut Response,
+        scope: &mut Scope<C>)
+        -> Option<(Self, RecvMode, Time)>
+    {
+        let transfer = match seed.start(&head, response) {
+            Ok(transfer) => transfer,
+            Err(e) => {
+                match e {
+                    FileError::MethodNotAllowed |
+                    FileError::RangeNotSatisfiable(_) => {
+                        error_page(&e, response);
+                    }
+                    _ => Self::emit_error_page(&e, response, &seed, scope),
+                }
+                Transfer::empty()
+            }
+        };
+        // The body is written in `request_received()`, after the request
+        // body (if it's small) is skipped
+        let timeout = Self::send_response_timeout(&seed, scope);
+        Some((FileServer {
+                transfer: transfer,
+                chunk_size: seed.chunk_size,
+                phantom: PhantomData,
+              }, RecvMode::Buffered(0), scope.now() + timeout))
+    }
+    fn request_received(self, _data: &[u8], response: &mut Response,
+        _scope: &mut Scope<C>)
+        -> Option<Self>
+    {
+        self.send(response)
+    }
+    fn request_chunk(self, _chunk: &[u8], _response: &mut Response,
+        _scope: &mut Scope<C>)
+        -> Option<Self>
+    {
+        unreachable!();
+    }
+    fn request_end(self, _response: &mut Response, _scope: &mut Scope<C>)
+        -> Option<Self>
+    {
+        unreachable!();
+    }
+    fn timeout(self, _response: &mut Response, _scope: &mut Scope<C>)
+        -> Option<(Self, Time)>
+    {
+        None
+    }
+    fn wakeup(self, response: &mut Response, _scope: &mut Scope<C>)
+        -> Option<Self>
+    {
+        // Keep waiting for the flush
+        response.wait_flush(self.chunk_size);
+        Some(self)
+    }
+    fn response_flushed(self, response: &mut Response,
+        _scope: &mut Scope<C>)
+        -> Option<Self>
+    {
+        self.send(response)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::env;
+    use std::fs::{self, File};
+    use std::io::Write;
+    use std::path::{Path, PathBuf};
+
+    use httparse::Header;
+    use rotor_stream::Buf;
+
+    use server::{Head, Response, BodyKind, Version};
+    use super::{Files, FileError, content_type, parse_ranges};
+
+    #[test]
+    fn resolve() {
+        let files = Files::new("/static/", "/srv");
+        assert_eq!(files.resolve("/static/a/b.css"),
+                   Some(PathBuf::from("/srv/a/b.css")));
+        assert_eq!(files.resolve("/static"), Some(PathBuf::from("/srv")));
+        assert_eq!(files.resolve("/static/./a//%62"),
+                   Some(PathBuf::from("/srv/a/b")));
+        assert_eq!(files.resolve("/statics/a"), None);
+        assert_eq!(files.resolve("/other"), None);
+        assert_eq!(files.resolve("/static/../etc/passwd"), None);
+        assert_eq!(files.resolve("/static/%2e%2e/etc/passwd"), None);
+        assert_eq!(files.resolve("/static/..%2fetc"), None);
+        assert_eq!(files.resolve("/static/..%5cetc"), None);
+        assert_eq!(files.resolve("/static/a%00"), None);
+        let root = Files::new("/", "/srv");
+        assert_eq!(root.resolve("/"), Some(PathBuf::from("/srv")));
+        assert_eq!(root.resolve("/x"), Some(PathBuf::from("/srv/x")));
+        assert_eq!(root.resolve("*"), None);
+    }
+
+    #[test]
+    fn ranges() {
+        assert_eq!(parse_ranges("bytes=0-4", 10), Some(vec![(0, 4)]));
+        assert_eq!(parse_ranges("bytes=5-", 10), Some(vec![(5, 9)]));
+        assert_eq!(parse_ranges("bytes=-3", 10), Some(vec![(7, 9)]));
+        assert_eq!(parse_ranges("bytes=-30", 10), Some(vec![(0, 9)]));
+        assert_eq!(parse_ranges("bytes=8-20", 10), Some(vec![(8, 9)]));
+        assert_eq!(parse_ranges("Bytes=0-0, 2-3", 10),
+                   Some(vec![(0, 0), (2, 3)]));
+        assert_eq!(parse_ranges("bytes=10-, -0", 10), Some(vec![]));
+        assert_eq!(parse_ranges("bytes=3-2", 10), None);
+        assert_eq!(parse_ranges("bytes=x-2", 10), None);
+        assert_eq!(parse_ranges("bytes=+1-2", 10), None);
+        assert_eq!(parse_ranges("bytes=", 10), None);
+        assert_eq!(parse_ranges("items=0-1", 10), None);
+        assert_eq!(parse_ranges("bytes\u{e9}=0-1", 10), None);
+        assert_eq!(parse_ranges(&format!("bytes={}", vec!["0-0"; 17].join(",")),
+                                10), None);
+    }
+
+    #[test]
+    fn types() {
+        assert_eq!(content_type(Path::new("a/index.HTML")),
+                   "text/html; charset=utf-8");
+        assert_eq!(content_type(Path::new("logo.png")), "image/png");
+        assert_eq!(content_type(Path::new("README")),
+                   "application/octet-stream");
+    }
+
+    fn request(files: &Files, method: &str, path: &str, headers: &[Header])
+        -> Result<String, FileError>
+    {
+        let head = Head {
+            client: None,
+            tls: None,
+            version: Version::Http11,
+            method: method,
+            scheme: "http",
+            path: path,
+            headers: headers,
+            body_kind: BodyKind::Fixed(0),
+        };
+        let mut buf = Buf::new();
+        {
+            let mut res = Response::new(&mut buf, Version::Http11,
+                                        method == "HEAD", false);
+            let mut transfer = try!(files.start(&head, &mut res));
+            while !transfer.write(&mut res, 3).unwrap() {}
+        }
+        Ok(String::from_utf8_lossy(&buf[..]).into_owned())
+    }
+
+    fn header<'a>(response: &'a str, name: &str) -> &'a str {
+        let prefix = format!("{}: ", name);
+        response.lines().find(|x| x.starts_with(&prefix))
+            .map(|x| &x[prefix.len()..]).unwrap()
+    }
+
+    #[test]
+    fn serve() {
+        let dir = env::temp_dir().join(format!("rotor-http-files-{}",
+                                               ::rand::random::<u32>()));
+        fs::create_dir_all(dir.join("sub")).unwrap();
+        File::create(dir.join("a.txt")).unwrap()
+            .write_all(b"0123456789").unwrap();
+        File::create(dir.join("sub/index.html")).unwrap()
+            .write_all(b"<html>").unwrap();
+        let files = Files::new("/s", &dir);
+
+        let full = request(&files, "GET", "/s/a.txt?x", &[]).unwrap();
+        assert!(full.starts_with("HTTP/1.1 200 OK\r\n"));
+        assert_eq!(header(&full, "Content-Length"), "10");
+        assert_eq!(header(&full, "Content-Type"), "text/plain; charset=utf-8");
+        assert!(full.ends_with("\r\n\r\n0123456789"));
+        let etag = header(&full, "ETag").to_string();
+        let date = header(&full, "Last-Modified").to_string();
+
+        let head = request(&files, "HEAD", "/s/a.txt", &[]).unwrap();
+        assert_eq!(header(&head, "Content-Length"), "10");
+        assert!(head.ends_with("\r\n\r\n"));
+
+        let index = request(&files, "GET", "/s/sub/", &[]).unwrap();
+        assert!(index.ends_with("\r\n\r\n<html>"));
+
+        let cached = request(&files, "GET", "/s/a.txt",
+            &[Header { name: "If-None-Match", value: etag.as_bytes() }])
+            .unwrap();
+        assert!(cached.starts_with("HTTP/1.1 304 Not Modified\r\n"));
+        assert!(cached.ends_with("\r\n\r\n"));
+        let cached = request(&files, "GET", "/s/a.txt",
+            &[Header { name: "If-Modified-Since", value: date.as_bytes() }])
+            .unwrap();
+        assert!(cached.starts_with("HTTP/1.1 304 Not Modified\r\n"));
+        let stale = request(&files, "GET", "/s/a.txt",
+            &[Header { name: "If-None-Match", value: b"\"x\", W/\"y\"" },
+              Header { name: "If-Modified-Since", value: date.as_bytes() }])
+            .unwrap();
+        assert!(stale.starts_with("HTTP/1.1 200 OK\r\n"));
+
+        let part = request(&files, "GET", "/s/a.txt",
+            &[Header { name: "Range", value: b"bytes=2-5" }]).unwrap();
+        assert!(part.starts_with("HTTP/1.1 206 Partial Content\r\n"));
+        assert_eq!(header(&part, "Content-Range"), "bytes 2-5/10");
+        assert!(part.ends_with("\r\n\r\n2345"));
+        let ignored = request(&files, "GET", "/s/a.txt",
+            &[Header { name: "Range", value: b"bytes=2-5" },
+              Header { name: "If-Range", value: b"\"other\"" }]).unwrap();
+        assert!(ignored.starts_with("HTTP/1.1 200 OK\r\n"));
+
+        let multi = request(&files, "GET", "/s/a.txt",
+            &[Header { name: "Range", value: b"bytes=0-1,-2" }]).unwrap();
+        let boundary = header(&multi, "Content-Type")
+            .trim_left_matches("multipart/byteranges; boundary=")
+            .to_string();
+        let body = &multi[multi.find("\r\n\r\n").unwrap()+4..];
+        assert_eq!(body, format!(
+            "\r\n--{0}\r\nContent-Type: text/plain; charset=utf-8\r\n\
+             Content-Range: bytes 0-1/10\r\n\r\n01\
+             \r\n--{0}\r\nContent-Type: text/plain; charset=utf-8\r\n\
+             Content-Range: bytes 8-9/10\r\n\r\n89\
+             \r\n--{0}--\r\n", boundary));
+        assert_eq!(header(&multi, "Content-Length"),
+                   body.len().to_string());
+
+        assert!(matches!(request(&files, "GET", "/s/a.txt",
+            &[Header { name: "Range", value: b"bytes=20-" }]),
+            Err(FileError::RangeNotSatisfiable(10))));
+        assert!(matches!(request(&files, "GET", "/s/nothing", &[]),
+                         Err(FileError::NotFound)));
+        assert!(matches!(request(&files, "GET", "/s/../a.txt", &[]),
+                         Err(FileError::NotFound)));
+        assert!(matches!(request(&files, "POST", "/s/a.txt", &[]),
+                         Err(FileError::MethodNotAllowed)));
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,179 @@
+//! Decoding of `application/x-www-form-urlencoded` data
+//!
+//! Unlike `Head::query_pairs()`, which is lenient, the `Form` rejects
+//! malformed escapes and invalid UTF-8, so the error may be sent to the
+//! client as is:
+//!
+//! ```ignore
+//! fn request_received(self, data: &[u8], res: &mut Response, ...)
+//!     -> Option<Self>
+//! {
+//!     let form = match Form::parse(data) {
+//!         Ok(form) => form,
+//!         Err(e) => { /* e.http_status() is 400 Bad Request */ }
+//!     };
+//!     let tags = form.get_all("tag").collect::<Vec<_>>();
+//!     // ...
+//! }
+//! ```
+//!
+//! The `Content-Type` is not checked, use `head.content_type()` for that.
+use std::slice::Iter;
+use std::str::from_utf8;
+
+use super::error::HttpError;
+use super::target::hex;
+
+
+quick_error! {
+    /// Error decoding form data
+    #[derive(Debug)]
+    pub enum FormError {
+        BadEscape {
+            description("malformed percent-encoded sequence in form data")
+        }
+        BadUtf8 {
+            description("form data is not valid utf-8")
+        }
+    }
+}
+
+impl HttpError for FormError {
+    fn http_status(&self) -> (u16, &'static str) {
+        (400, "Bad Request")
+    }
+}
+
+/// Decoded `key=value` pairs of the form
+///
+/// Pairs are kept in the original order, a key may be repeated. The value
+/// of a key without `=` is an empty string.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Form(Vec<(String, String)>);
+
+/// An iterator over values of the repeated key, see `Form::get_all()`
+#[derive(Debug, Clone)]
+pub struct FormValues<'a, 'n> {
+    iter: Iter<'a, (String, String)>,
+    name: &'n str,
+}
+
+fn decode(value: &[u8]) -> Result<String, FormError> {
+    let mut buf = Vec::with_capacity(value.len());
+    let mut idx = 0;
+    while idx < value.len() {
+        match value[idx] {
+            b'%' => {
+                if idx + 2 >= value.len() {
+                    return Err(FormError::BadEscape);
+                }
+                match (hex(value[idx+1]), hex(value[idx+2])) {
+                    (Some(hi), Some(lo)) => buf.push(hi << 4 | lo),
+                    _ => return Err(FormError::BadEscape),
+                }
+                idx += 3;
+                continue;
+            }
+            b'+' => buf.push(b' '),
+            ch => buf.push(ch),
+        }
+        idx += 1;
+    }
+    String::from_utf8(buf).map_err(|_| FormError::BadUtf8)
+}
+
+impl Form {
+    /// Decodes the form data (e.g. the request body)
+    pub fn parse(data: &[u8]) -> Result<Form, FormError> {
+        // Only escaped bytes may be non-ascii in the well-formed data
+        try!(from_utf8(data).map_err(|_| FormError::BadUtf8));
+        let mut pairs = Vec::new();
+        for pair in data.split(|&x| x == b'&') {
+            if pair.len() == 0 {
+                continue;
+            }
+            let (key, value) = match pair.iter().position(|&x| x == b'=') {
+                Some(idx) => (&pair[..idx], &pair[idx+1..]),
+                None => (pair, &b""[..]),
+            };
+            pairs.push((try!(decode(key)), try!(decode(value))));
+        }
+        Ok(Form(pairs))
+    }
+    /// Decodes the query string, `head.query()` may be passed directly
+    ///
+    /// The missing query is an empty form.
+    pub fn from_query(query: Option<&str>) -> Result<Form, FormError> {
+        Form::parse(query.unwrap_or("").as_bytes())
+    }
+    /// Returns the value of the first pair with the key
+    pub fn get(&self, name: &str) -> Option<&str> {
+        self.0.iter().find(|&&(ref n, _)| n == name).map(|&(_, ref v)| &v[..])
+    }
+    /// Iterates over the values of all pairs with the key
+    pub fn get_all<'x, 'n>(&'x self, name: &'n str) -> FormValues<'x, 'n> {
+        FormValues { iter: self.0.iter(), name: name }
+    }
+    /// Returns true if there is at least one pair with the key
+    pub fn contains(&self, name: &str) -> bool {
+        self.get(name).is_some()
+    }
+    /// Iterates over all `(key, value)` pairs in the original order
+    pub fn iter(&self) -> Iter<(String, String)> {
+        self.0.iter()
+    }
+    /// The number of pairs
+    pub fn len(&self) -> usize {
+        self.0.len()
+    }
+    /// Returns the decoded pairs
+    pub fn into_pairs(self) -> Vec<(String, String)> {
+        self.0
+    }
+}
+
+impl<'a, 'n> Iterator for FormValues<'a, 'n> {
+    type Item = &'a str;
+    fn next(&mut self) -> Option<&'a str> {
+        while let Some(&(ref key, ref value)) = self.iter.next() {
+            if key == self.name {
+                return Some(value);
+            }
+        }
+        None
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{Form, FormError};
+
+    #[test]
+    fn parse() {
+        let form = Form::parse(b"a=1&tag=x+y&&b&tag=%26%3D&c=%D1%8F&=e")
+            .unwrap();
+        assert_eq!(form.len(), 6);
+        assert_eq!(form.get("a"), Some("1"));
+        assert_eq!(form.get("b"), Some(""));
+        assert_eq!(form.get("c"), Some("\u{44f}"));
+        assert_eq!(form.get(""), Some("e"));
+        assert_eq!(form.get("d"), None);
+        assert!(form.contains("b"));
+        assert_eq!(form.get_all("tag").collect::<Vec<_>>(),
+                   vec!["x y", "&="]);
+        assert_eq!(form.iter().next(),
+                   Some(&("a".to_string(), "1".to_string())));
+        assert_eq!(Form::from_query(None).unwrap().len(), 0);
+        assert_eq!(Form::from_query(Some("x=%20")).unwrap().get("x"),
+                   Some(" "));
+    }
+
+    #[test]
+    fn errors() {
+        assert!(matches!(Form::parse(b"a=%2"), Err(FormError::BadEscape)));
+        assert!(matches!(Form::parse(b"a=%zz"), Err(FormError::BadEscape)));
+        assert!(matches!(Form::parse(b"a=%FF"), Err(FormError::BadUtf8)));
+        assert!(matches!(Form::parse(b"a=\xd1"), Err(FormError::BadUtf8)));
+        assert!(matches!(Form::parse(b"%D1=%8F"), Err(FormError::BadUtf8)));
+    }
+}
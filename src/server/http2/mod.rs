@@ -0,0 +1,526 @@
+//! HTTP/2 server implementation
+//!
+//! HTTP/2 is served over the same `Parser` (and the same `Server`
+//! handlers) as HTTP/1. A connection is switched to HTTP/2 either when it
+//! starts with the HTTP/2 connection preface (the "prior knowledge" mode)
+//! or when the client requests `Upgrade: h2c` with the `HTTP2-Settings`
+//! header.
+//!
+//! Each stream gets its own instance of the `Server` with
+//! `head.version == Version::Http20`. Responses are written the usual way,
+//! rotor-http converts them into HEADERS and DATA frames. So for handler the
+//! only visible differences are:
+//!
+//! 1. Multiple requests of the same connection are processed concurrently
+//! 2. Header names are lowercase, and `Host` header is synthesized from
+//!    `:authority` pseudo-header when absent
+//! 3. The request body of `Progressive` mode is passed as it is received
+//!    in DATA frames (the size hint is ignored)
+//! 4. Upgrades are not supported, `head.body_kind` is never
+//!    `BodyKind::Upgrade`, so `101 Switching Protocols` can't be sent
+//!
+//! HTTP/2 is disabled by default, use `Server::http2_settings()` to enable
+//! and tune it.
+use std::cmp::min;
+use std::collections::BTreeMap;
+use std::fmt;
+use std::net::SocketAddr;
+use std::ascii::AsciiExt;
+use std::str::from_utf8;
+
+use httparse::Header;
+use rotor::{Scope, Time};
+use rotor_stream::{Buf, Expectation, StreamSocket, Transport};
+
+use hpack::HeaderList;
+use http2::frame::{self, ErrorCode};
+use http2::convert::is_connection_specific;
+use http2::{Session, Event, update_window};
+use headers;
+use tls::TlsInfo;
+use version::Version;
+use super::{Server, Head, BodyKind, RequestError};
+use self::stream::Stream;
+
+pub use http2::{Settings, PREFACE};
+
+mod stream;
+
+
+/// A HTTP/2 connection which multiplexes `Server` state machines
+pub struct Connection<M: Server> {
+    session: Session,
+    streams: BTreeMap<u32, Stream<M>>,
+    client: Option<SocketAddr>,
+    tls: Option<TlsInfo>,
+    /// GOAWAY is received, no new streams will come
+    goaway_received: bool,
+}
+
+/// Returns peer settings if request is a valid `h2c` upgrade
+///
+/// Invalid `HTTP2-Settings` header, or the `Connection` header which
+/// doesn't list it (RFC 7540, section 3.2.1), means the upgrade is ignored
+/// and request is served by HTTP/1
+pub fn h2c_settings(headers: &[Header]) -> Option<Settings> {
+    use rustc_serialize::base64::FromBase64;
+    let mut h2c = false;
+    let mut connection = false;
+    let mut payload = None;
+    for header in headers {
+        if headers::is_upgrade(header.name) {
+            h2c = header.value.split(|&x| x == b',')
+                .any(|x| from_utf8(x).map(|x| x.trim() == "h2c")
+                                     .unwrap_or(false));
+        } else if headers::is_connection(header.name) {
+            connection = connection || header.value.split(|&x| x == b',')
+                .any(|x| headers::trim(x)
+                    .eq_ignore_ascii_case(b"HTTP2-Settings"));
+        } else if header.name.eq_ignore_ascii_case("HTTP2-Settings") {
+            if payload.is_some() {
+                return None;
+            }
+            // Both standard and url-safe alphabets are accepted
+            payload = Some(header.value.from_base64().ok());
+        }
+    }
+    if !h2c || !connection {
+        return None;
+    }
+    payload.and_then(|x| x).and_then(|payload| {
+        let mut settings = Settings::default();
+        settings.apply(&payload).ok().map(|()| settings)
+    })
+}
+
+impl<M: Server> Connection<M> {
+    /// Creates a connection which starts with the client preface
+    ///
+    /// Server connection preface is written into the `out` immediately
+    pub fn new(settings: Settings, client: Option<SocketAddr>,
+        tls: Option<TlsInfo>, out: &mut Buf)
+        -> Connection<M>
+    {
+        Connection {
+            session: Session::server(settings, out),
+            streams: BTreeMap::new(),
+            client: client,
+            tls: tls,
+            goaway_received: false,
+        }
+    }
+    /// Creates a connection for the `Upgrade: h2c` request
+    ///
+    /// Writes `101 Switching Protocols` and starts stream 1 for the request.
+    /// The `peer` settings are the ones from the `HTTP2-Settings` header.
+    pub fn upgrade(settings: Settings, peer: Settings, head: Head,
+        out: &mut Buf, seed: &M::Seed, scope: &mut Scope<M::Context>)
+        -> Connection<M>
+    {
+        out.extend(b"HTTP/1.1 101 Switching Protocols\r\n\
+                     Connection: Upgrade\r\n\
+                     Upgrade: h2c\r\n\r\n");
+        let mut conn = Connection::new(settings, head.client,
+                                       head.tls.cloned(), out);
+        conn.session.set_peer_settings(peer);
+        conn.session.last_stream_id = 1;
+        let mut stream = Stream::new(1, head.method == "HEAD",
+            peer.initial_window_size as i64,
+            scope.now() + M::send_response_timeout(seed, scope),
+            seed, scope);
+        stream.start(Head {
+            version: Version::Http20,
+            body_kind: BodyKind::Fixed(0),
+            .. head
+        }, false, seed, scope);
+        stream.data(b"", true, seed, scope);
+        conn.streams.insert(1, stream);
+        conn.flush(out);
+        conn
+    }
+    fn intent(&mut self, input: &Buf, seed: &M::Seed,
+        scope: &mut Scope<M::Context>)
+        -> Option<(Expectation, Time)>
+    {
+        if self.session.closing {
+            return Some((Expectation::Flush(0),
+                         scope.now() + M::send_response_timeout(seed, scope)));
+        }
+        let deadline = match self.streams.values().map(|s| s.deadline()).min()
+        {
+            Some(deadline) => deadline,
+            None => scope.now() + M::idle_timeout(seed, scope),
+        };
+        // Handlers waiting in `Response::wait_flush()` are notified when
+        // the output buffer is drained (reading frames is postponed)
+        match self.streams.values().filter_map(|s| s.flush_threshold()).min()
+        {
+            Some(size) => Some((Expectation::Flush(size), deadline)),
+            None => Some((Expectation::Bytes(
+                self.session.bytes_needed(&input[..])), deadline)),
+        }
+    }
+    /// Sends GOAWAY and closes connection when the buffer is flushed
+    fn go_away(&mut self, out: &mut Buf, code: ErrorCode) {
+        self.session.go_away(out, code);
+        self.streams.clear();
+    }
+    /// Converts responses into frames and removes finished streams
+    fn flush(&mut self, out: &mut Buf) {
+        let max_frame_size = self.session.peer.max_frame_size as usize;
+        let mut done = Vec::new();
+        for (&id, stream) in self.streams.iter_mut() {
+            stream.flush(out, &mut self.session.encoder,
+                         &mut self.session.send_window, max_frame_size);
+            if stream.is_done() {
+                if stream.is_remote_open() {
+                    // Response is complete, tell the client to stop
+                    // sending the request body
+                    frame::write_rst_stream(out, id, ErrorCode::NoError);
+                }
+                done.push(id);
+            }
+        }
+        for id in done {
+            self.streams.remove(&id);
+        }
+        if self.goaway_received && self.streams.len() == 0 {
+            self.go_away(out, ErrorCode::NoError);
+        }
+    }
+    pub fn bytes_read<S: StreamSocket>(&mut self,
+        transport: &mut Transport<S>, seed: &M::Seed,
+        scope: &mut Scope<M::Context>)
+        -> Option<(Expectation, Time)>
+    {
+        {
+            let (inp, out) = transport.buffers();
+            while !self.session.closing {
+                let result = match self.session.read_frame(&inp[..], out) {
+                    Ok(Some((bytes, event))) => {
+                        let result = self.event(event, out, seed, scope);
+                        inp.consume(bytes);
+                        result
+                    }
+                    Ok(None) => break,
+                    Err(code) => Err(code),
+                };
+                if let Err(code) = result {
+                    self.go_away(out, code);
+                }
+            }
+            self.flush(out);
+        }
+        self.intent(transport.input(), seed, scope)
+    }
+    pub fn bytes_flushed<S: StreamSocket>(&mut self,
+        transport: &mut Transport<S>, seed: &M::Seed,
+        scope: &mut Scope<M::Context>)
+        -> Option<(Expectation, Time)>
+    {
+        if self.session.closing {
+            // GOAWAY is sent
+            return None;
+        }
+        {
+            let out = transport.output();
+            let buffered = out.len();
+            for stream in self.streams.values_mut() {
+                stream.flushed(buffered, scope);
+            }
+            self.flush(out);
+        }
+        self.intent(transport.input(), seed, scope)
+    }
+    pub fn timeout<S: StreamSocket>(&mut self, transport: &mut Transport<S>,
+        seed: &M::Seed, scope: &mut Scope<M::Context>)
+        -> Option<(Expectation, Time)>
+    {
+        if self.session.closing {
+            return None;
+        }
+        let now = scope.now();
+        {
+            let out = transport.output();
+            if self.streams.len() == 0 {
+                // Idle timeout
+                self.go_away(out, ErrorCode::NoError);
+            }
+            for (&id, stream) in self.streams.iter_mut() {
+                if stream.deadline() <= now && !stream.timeout(seed, scope) {
+                    frame::write_rst_stream(out, id, ErrorCode::Cancel);
+                    stream.close();
+                }
+            }
+            self.flush(out);
+        }
+        self.intent(transport.input(), seed, scope)
+    }
+    pub fn wakeup<S: StreamSocket>(&mut self, transport: &mut Transport<S>,
+        seed: &M::Seed, scope: &mut Scope<M::Context>)
+        -> Option<(Expectation, Time)>
+    {
+        {
+            let out = transport.output();
+            for stream in self.streams.values_mut() {
+                stream.wakeup(scope);
+            }
+            self.flush(out);
+        }
+        self.intent(transport.input(), seed, scope)
+    }
+    /// Connection is closed by peer or because of an error
+    pub fn end(&mut self, scope: &mut Scope<M::Context>) {
+        for stream in self.streams.values_mut() {
+            stream.abort(scope);
+        }
+    }
+    fn event(&mut self, event: Event, out: &mut Buf, seed: &M::Seed,
+        scope: &mut Scope<M::Context>)
+        -> Result<(), ErrorCode>
+    {
+        match event {
+            Event::Handled => Ok(()),
+            Event::Headers { id, end_stream, list, too_large } => {
+                self.headers(id, end_stream, &list, too_large,
+                             out, seed, scope)
+            }
+            Event::Data { id, data, length, end_stream } => {
+                if id > self.session.last_stream_id {
+                    return Err(ErrorCode::ProtocolError);
+                }
+                let open = match self.streams.get_mut(&id) {
+                    Some(ref mut stream) if stream.is_remote_open() => {
+                        if length > 0 && !end_stream {
+                            frame::write_window_update(out, id,
+                                                       length as u32);
+                        }
+                        stream.data(data, end_stream, seed, scope);
+                        true
+                    }
+                    _ => false,
+                };
+                if !open {
+                    self.reset(out, id, ErrorCode::StreamClosed);
+                }
+                Ok(())
+            }
+            Event::Reset { id, .. } => {
+                if id > self.session.last_stream_id {
+                    return Err(ErrorCode::ProtocolError);
+                }
+                if let Some(mut stream) = self.streams.remove(&id) {
+                    stream.abort(scope);
+                }
+                Ok(())
+            }
+            Event::WindowUpdate { id, increment } => {
+                let result = match self.streams.get_mut(&id) {
+                    Some(stream) => {
+                        update_window(&mut stream.send_window, increment)
+                    }
+                    // Frames for closed streams are ignored
+                    None => Ok(()),
+                };
+                if let Err(code) = result {
+                    self.reset(out, id, code);
+                }
+                Ok(())
+            }
+            Event::WindowDelta(delta) => {
+                for stream in self.streams.values_mut() {
+                    stream.send_window += delta;
+                }
+                Ok(())
+            }
+            Event::GoAway { .. } => {
+                self.goaway_received = true;
+                Ok(())
+            }
+        }
+    }
+    fn reset(&mut self, out: &mut Buf, id: u32, code: ErrorCode) {
+        frame::write_rst_stream(out, id, code);
+        if let Some(mut stream) = self.streams.remove(&id) {
+            stream.close();
+        }
+    }
+    /// Complete header block is received
+    fn headers(&mut self, id: u32, end_stream: bool, list: &HeaderList,
+        too_large: bool, out: &mut Buf, seed: &M::Seed,
+        scope: &mut Scope<M::Context>)
+        -> Result<(), ErrorCode>
+    {
+        let trailers = match self.streams.get_mut(&id) {
+            Some(ref mut stream) if stream.is_remote_open() && end_stream => {
+                match list.headers() {
+                    // Pseudo-headers are not allowed in trailers
+                    Ok(ref trailers) if !trailers.iter()
+                        .any(|h| h.name.starts_with(":")) =>
+                    {
+                        stream.trailers(trailers, too_large, seed, scope);
+                        Some(true)
+                    }
+                    _ => Some(false),
+                }
+            }
+            Some(_) => Some(false),
+            None => None,
+        };
+        match trailers {
+            Some(true) => return Ok(()),
+            Some(false) => {
+                self.reset(out, id, ErrorCode::ProtocolError);
+                return Ok(());
+            }
+            None => {}
+        }
+        if id % 2 == 0 || id <= self.session.last_stream_id {
+            return Err(ErrorCode::ProtocolError);
+        }
+        self.session.last_stream_id = id;
+        if self.goaway_received {
+            return Ok(());
+        }
+        if let Some(limit) = self.session.settings.max_concurrent_streams {
+            if self.streams.len() >= limit as usize {
+                frame::write_rst_stream(out, id, ErrorCode::RefusedStream);
+                return Ok(());
+            }
+        }
+        if too_large {
+            let mut stream = Stream::new(id, false,
+                self.session.peer.initial_window_size as i64,
+                scope.now() + M::send_response_timeout(seed, scope),
+                seed, scope);
+            stream.error(&RequestError::HeadersAreTooLarge, seed, scope);
+            if end_stream {
+                stream.data(b"", true, seed, scope);
+            }
+            self.streams.insert(id, stream);
+            return Ok(());
+        }
+        self.start_stream(id, list, end_stream, out, seed, scope);
+        Ok(())
+    }
+    fn start_stream(&mut self, id: u32, list: &HeaderList, end_stream: bool,
+        out: &mut Buf, seed: &M::Seed, scope: &mut Scope<M::Context>)
+    {
+        let all = match list.headers() {
+            Ok(all) => all,
+            Err(_) => {
+                frame::write_rst_stream(out, id, ErrorCode::ProtocolError);
+                return;
+            }
+        };
+        let mut method = None;
+        let mut scheme = None;
+        let mut path = None;
+        let mut authority = None;
+        let max_headers_num = M::limits(seed, scope).max_headers_num;
+        let mut headers = Vec::with_capacity(min(all.len() + 1,
+                                                 max_headers_num + 1));
+        let mut host = None;
+        let mut content_length = None;
+        let mut expect_continue = false;
+        let mut valid = true;
+        for header in &all {
+            let (name, value) = (header.name, header.value);
+            if name.starts_with(":") {
+                let slot = match name {
+                    ":method" => &mut method,
+                    ":scheme" => &mut scheme,
+                    ":path" => &mut path,
+                    ":authority" => &mut authority,
+                    _ => {
+                        valid = false;
+                        break;
+                    }
+                };
+                // Pseudo-headers can't be repeated and must precede
+                // regular headers
+                if slot.is_some() || headers.len() > 0 {
+                    valid = false;
+                    break;
+                }
+                *slot = Some(value);
+                continue;
+            }
+            if name.bytes().any(|x| x >= b'A' && x <= b'Z') ||
+                is_connection_specific(name) ||
+                name == "te" && value != b"trailers"
+            {
+                valid = false;
+                break;
+            }
+            if name == "host" {
+                host = Some(value);
+            } else if headers::is_content_length(name) {
+                content_length = from_utf8(value).ok()
+                    .and_then(|x| x.parse().ok());
+                if content_length.is_none() {
+                    valid = false;
+                    break;
+                }
+            } else if headers::is_expect(name) {
+                expect_continue = headers::is_continue(value);
+            }
+            headers.push(*header);
+        }
+        if host.is_none() {
+            if let Some(authority) = authority {
+                headers.push(Header { name: "host", value: authority });
+            }
+        }
+        let method = method.and_then(|x| from_utf8(x).ok());
+        let scheme = scheme.and_then(|x| from_utf8(x).ok());
+        // The CONNECT request has only an authority
+        let path = if method == Some("CONNECT") { authority } else { path };
+        let path = path.and_then(|x| from_utf8(x).ok());
+        let (method, scheme, path) = match (method, scheme, path) {
+            (Some(m), Some(s), Some(p)) if valid && p.len() > 0 => (m, s, p),
+            (Some("CONNECT"), None, Some(p)) if valid => ("CONNECT", "", p),
+            _ => {
+                frame::write_rst_stream(out, id, ErrorCode::ProtocolError);
+                return;
+            }
+        };
+        let mut stream = Stream::new(id, method == "HEAD",
+            self.session.peer.initial_window_size as i64,
+            scope.now() + M::send_response_timeout(seed, scope),
+            seed, scope);
+        if headers.len() > max_headers_num {
+            stream.error(&RequestError::HeadersAreTooLarge, seed, scope);
+        } else {
+            let body_kind = match content_length {
+                Some(x) => BodyKind::Fixed(x),
+                None if end_stream => BodyKind::Fixed(0),
+                None => BodyKind::Chunked,
+            };
+            stream.start(Head {
+                client: self.client,
+                tls: self.tls.as_ref(),
+                version: Version::Http20,
+                method: method,
+                scheme: scheme,
+                path: path,
+                headers: &headers,
+                body_kind: body_kind,
+            }, expect_continue, seed, scope);
+            if end_stream {
+                stream.data(b"", true, seed, scope);
+            }
+        }
+        self.streams.insert(id, stream);
+    }
+}
+
+impl<M: Server> fmt::Debug for Connection<M> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Connection")
+            .field("session", &self.session)
+            .field("streams", &self.streams)
+            .field("goaway_received", &self.goaway_received)
+            .finish()
+    }
+}
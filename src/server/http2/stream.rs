@@ -0,0 +1,449 @@
+use std::fmt;
+use std::iter::once;
+use std::mem;
+
+use httparse::Header;
+use rotor::{Scope, Time};
+use rotor_stream::Buf;
+
+use hpack::Encoder;
+use http2::frame::{self, ErrorCode};
+use http2::convert::{take_head, is_connection_specific};
+use http2::convert::{BodyProgress, BodySender};
+use message::MessageState;
+use recvmode::RecvMode;
+use version::Version;
+use super::super::{Server, Head, Response, RequestError, BodyKind};
+use super::super::response::{state, new_response, flush_wait};
+use super::super::decode::Decoder;
+
+
+/// How the request body is delivered to the handler
+#[derive(Debug)]
+enum RequestBody {
+    /// Body is collected into the vector up to the limit
+    Buffered(usize, Vec<u8>),
+    Progressive,
+    /// Handler has finished or has failed, the body is discarded
+    Ignored,
+}
+
+/// Progress of converting the response into frames
+#[derive(Debug)]
+enum Output {
+    /// Waiting for the (final) response head
+    Head,
+    Body(BodySender),
+    /// END_STREAM or RST_STREAM is sent
+    Closed,
+}
+
+/// A single request-response exchange on the HTTP/2 connection
+///
+/// The `Response` writes the message into the stream's own buffer in
+/// HTTP/1 format, which is converted into frames by `flush()`.
+pub struct Stream<M: Server> {
+    id: u32,
+    machine: Option<M>,
+    response: MessageState,
+    buf: Buf,
+    body: RequestBody,
+    /// Decodes `Content-Encoding` if enabled by `decode_request_body()`
+    decoder: Option<Decoder>,
+    output: Output,
+    /// END_STREAM is received from the peer
+    remote_closed: bool,
+    is_head: bool,
+    pub send_window: i64,
+    /// Deadline returned by the handler
+    deadline: Time,
+    /// Deadline for sending response when handler is finished
+    send_deadline: Time,
+    /// The size requested by `Response::wait_flush()`
+    flush_wait: Option<usize>,
+}
+
+impl<M: Server> Stream<M> {
+    pub fn new(id: u32, is_head: bool, send_window: i64,
+        send_deadline: Time, seed: &M::Seed, scope: &mut Scope<M::Context>)
+        -> Stream<M>
+    {
+        let mut buf = Buf::new();
+        let response = state(new_response::<M>(&mut buf, Version::Http20,
+                                               is_head, false, seed, scope));
+        Stream {
+            id: id,
+            machine: None,
+            response: response,
+            buf: buf,
+            body: RequestBody::Ignored,
+            decoder: None,
+            output: Output::Head,
+            remote_closed: false,
+            is_head: is_head,
+            send_window: send_window,
+            deadline: send_deadline,
+            send_deadline: send_deadline,
+            flush_wait: None,
+        }
+    }
+    fn with_response<F, R>(&mut self, f: F) -> R
+        where F: FnOnce(&mut Response) -> R
+    {
+        let mut resp: Response = mem::replace(&mut self.response,
+            MessageState::Done).with(&mut self.buf);
+        let result = f(&mut resp);
+        self.response = state(resp);
+        result
+    }
+    fn call<F>(&mut self, f: F)
+        where F: FnOnce(M, &mut Response) -> Option<M>
+    {
+        if let Some(m) = self.machine.take() {
+            let (machine, flush) = self.with_response(|resp| {
+                let machine = f(m, resp);
+                (machine, flush_wait(resp))
+            });
+            self.machine = machine;
+            self.flush_wait = flush;
+        }
+    }
+    /// The state machine is finished and the response is sent
+    pub fn is_done(&self) -> bool {
+        self.machine.is_none() && matches!(self.output, Output::Closed)
+    }
+    /// The peer is still allowed to send frames on the stream
+    pub fn is_remote_open(&self) -> bool {
+        !self.remote_closed
+    }
+    pub fn deadline(&self) -> Time {
+        if self.machine.is_some() {
+            self.deadline
+        } else {
+            self.send_deadline
+        }
+    }
+    /// Calls `headers_received()` for the request
+    pub fn start(&mut self, head: Head, expect_continue: bool,
+        seed: &M::Seed, scope: &mut Scope<M::Context>)
+    {
+        if head.body_kind != BodyKind::Fixed(0) &&
+            M::decode_request_body(seed, scope)
+        {
+            match Decoder::content_codings(head.headers) {
+                Ok(decoder) => self.decoder = decoder,
+                Err(e) => return self.error(&e, seed, scope),
+            }
+        }
+        let body_kind = head.body_kind;
+        let res = self.with_response(|resp| {
+            let res = M::headers_received(seed.clone(), head, resp, scope);
+            let res = match (res, body_kind) {
+                (Some((m, RecvMode::Buffered(limit), _)),
+                 BodyKind::Fixed(size)) if size > limit as u64 =>
+                {
+                    // Refuse before reading the body (and before
+                    // `100 Continue`)
+                    m.bad_request(resp, scope);
+                    if !resp.is_started() {
+                        M::emit_error_page(&RequestError::PayloadTooLarge,
+                            resp, seed, scope);
+                    }
+                    None
+                }
+                (res, _) => res,
+            };
+            if res.is_none() && !resp.is_started() {
+                M::emit_error_page(&RequestError::HeadersReceived,
+                    resp, seed, scope);
+            } else if res.is_some() && expect_continue &&
+                !resp.is_started()
+            {
+                resp.response_continue();
+            }
+            res
+        });
+        if let Some((m, mode, deadline)) = res {
+            self.machine = Some(m);
+            self.deadline = deadline;
+            self.body = match mode {
+                RecvMode::Buffered(limit) => {
+                    if let Some(ref mut decoder) = self.decoder {
+                        decoder.limit(limit);
+                    }
+                    RequestBody::Buffered(limit, Vec::new())
+                }
+                RecvMode::Progressive(_) => {
+                    if let Some(ref mut decoder) = self.decoder {
+                        let limits = M::limits(seed, scope);
+                        decoder.chunk_limit(limits.max_decoded_chunk);
+                    }
+                    RequestBody::Progressive
+                }
+            };
+        }
+    }
+    /// Writes an error page for the request which is not passed to handler
+    pub fn error(&mut self, err: &RequestError, seed: &M::Seed,
+        scope: &mut Scope<M::Context>)
+    {
+        self.with_response(|resp| M::emit_error_page(err, resp, seed, scope));
+    }
+    /// Calls `bad_request()` and writes an error page if it's not started
+    ///
+    /// The rest of the request body is ignored.
+    fn fail(&mut self, err: &RequestError, seed: &M::Seed,
+        scope: &mut Scope<M::Context>)
+    {
+        let machine = self.machine.take();
+        self.body = RequestBody::Ignored;
+        self.with_response(|resp| {
+            machine.map(|m| m.bad_request(resp, scope));
+            if !resp.is_started() {
+                M::emit_error_page(err, resp, seed, scope);
+            }
+        });
+    }
+    /// Decodes the part of the request body if there is a decoder
+    fn decode(&mut self, data: &[u8], end_stream: bool)
+        -> Result<Option<Vec<u8>>, RequestError>
+    {
+        let mut decoded = match self.decoder {
+            Some(ref mut decoder) => try!(decoder.decode(data)),
+            None => return Ok(None),
+        };
+        if end_stream {
+            if let Some(decoder) = self.decoder.take() {
+                decoded.extend(try!(decoder.finish()));
+            }
+        }
+        Ok(Some(decoded))
+    }
+    /// Passes the request body to the handler
+    ///
+    /// Progressive handlers receive a chunk per DATA frame (the size hint
+    /// is not used, as data is already buffered in the frame anyway).
+    pub fn data(&mut self, data: &[u8], end_stream: bool,
+        seed: &M::Seed, scope: &mut Scope<M::Context>)
+    {
+        if end_stream {
+            self.remote_closed = true;
+        }
+        if matches!(self.body, RequestBody::Ignored) {
+            return;
+        }
+        let decoded = match self.decode(data, end_stream) {
+            Ok(decoded) => decoded,
+            Err(e) => return self.fail(&e, seed, scope),
+        };
+        let data = decoded.as_ref().map(|x| &x[..]).unwrap_or(data);
+        match mem::replace(&mut self.body, RequestBody::Ignored) {
+            RequestBody::Buffered(limit, mut buf) => {
+                if buf.len() + data.len() > limit {
+                    self.fail(&RequestError::PayloadTooLarge, seed, scope);
+                } else if end_stream {
+                    buf.extend(data);
+                    self.call(|m, resp| {
+                        m.request_received(&buf, resp, scope)
+                    });
+                } else {
+                    buf.extend(data);
+                    self.body = RequestBody::Buffered(limit, buf);
+                }
+            }
+            RequestBody::Progressive => {
+                if data.len() > 0 {
+                    self.call(|m, resp| m.request_chunk(data, resp, scope));
+                }
+                if end_stream {
+                    self.call(|m, resp| m.request_end(resp, scope));
+                } else if self.machine.is_some() {
+                    self.body = RequestBody::Progressive;
+                }
+            }
+            RequestBody::Ignored => {}
+        }
+    }
+    /// Trailer section is received, which also ends the request
+    ///
+    /// If the section is `too_large` the request fails like a body larger
+    /// than the limit.
+    pub fn trailers(&mut self, trailers: &[Header], too_large: bool,
+        seed: &M::Seed, scope: &mut Scope<M::Context>)
+    {
+        if too_large {
+            self.fail(&RequestError::HeadersAreTooLarge, seed, scope);
+        } else if trailers.len() > 0 &&
+            !matches!(self.body, RequestBody::Ignored)
+        {
+            self.call(|m, resp| m.request_trailers(trailers, resp, scope));
+        }
+        self.data(b"", true, seed, scope);
+    }
+    pub fn wakeup(&mut self, scope: &mut Scope<M::Context>) {
+        self.call(|m, resp| m.wakeup(resp, scope));
+    }
+    /// The number of bytes the connection buffer must be drained to, for
+    /// the handler waiting in `Response::wait_flush()`
+    ///
+    /// Returns `None` if the handler doesn't wait, or if the data of the
+    /// stream is blocked by flow control.
+    pub fn flush_threshold(&self) -> Option<usize> {
+        match self.flush_wait {
+            Some(size) if self.machine.is_some() && self.buf.len() <= size => {
+                Some(size - self.buf.len())
+            }
+            _ => None,
+        }
+    }
+    /// Calls `response_flushed()` if the handler waits for it, and both
+    /// the unsent data of the stream and the connection buffer (which has
+    /// `buffered` bytes) fit the requested size
+    pub fn flushed(&mut self, buffered: usize,
+        scope: &mut Scope<M::Context>)
+    {
+        if let Some(threshold) = self.flush_threshold() {
+            if buffered <= threshold {
+                self.call(|m, resp| m.response_flushed(resp, scope));
+            }
+        }
+    }
+    /// The deadline of the stream has passed
+    ///
+    /// Returns `false` if the stream should be reset
+    pub fn timeout(&mut self, seed: &M::Seed, scope: &mut Scope<M::Context>)
+        -> bool
+    {
+        let machine = match self.machine.take() {
+            Some(m) => m,
+            // Response is not flushed in time
+            None => return false,
+        };
+        let err = if self.remote_closed {
+            RequestError::HandlerTimeout
+        } else {
+            RequestError::RequestTimeout
+        };
+        let (res, flush) = self.with_response(|resp| {
+            let res = machine.timeout(resp, scope);
+            if res.is_none() && !resp.is_started() {
+                M::emit_error_page(&err, resp, seed, scope);
+            }
+            (res, flush_wait(resp))
+        });
+        self.flush_wait = flush;
+        if let Some((m, deadline)) = res {
+            self.machine = Some(m);
+            self.deadline = deadline;
+        }
+        true
+    }
+    /// Converts the response into frames and writes them into `out`
+    ///
+    /// DATA frames are limited by both the stream and connection flow
+    /// control windows, the rest of the body stays in the stream buffer
+    /// until WINDOW_UPDATE is received.
+    pub fn flush(&mut self, out: &mut Buf, encoder: &mut Encoder,
+        conn_window: &mut i64, max_frame_size: usize)
+    {
+        if self.machine.is_none() && matches!(self.output, Output::Head) &&
+            !self.with_response(|resp| resp.is_started())
+        {
+            // Handler has finished without sending anything
+            let resp: Response = mem::replace(&mut self.response,
+                MessageState::Done).with(&mut self.buf);
+            resp.finish();
+        }
+        loop {
+            match self.output {
+                Output::Head => {
+                    let head = match take_head(&mut self.buf) {
+                        Some(head) => head,
+                        None => break,
+                    };
+                    let code = head.line.split(' ').nth(1)
+                        .and_then(|x| x.parse().ok()).unwrap_or(500);
+                    let status = code.to_string();
+                    let fields = head.headers.iter()
+                        .filter(|&&(ref name, _)| !is_connection_specific(name))
+                        .map(|&(ref name, ref value)| {
+                            (name.as_bytes(), &value[..])
+                        });
+                    let mut block = Vec::new();
+                    encoder.encode(once((&b":status"[..], status.as_bytes()))
+                                   .chain(fields), &mut block);
+                    if code < 200 {
+                        // Interim response, e.g. 100 Continue
+                        frame::write_headers(out, self.id, &block, false,
+                                             max_frame_size);
+                        continue;
+                    }
+                    let body = if self.is_head || code == 204 || code == 304
+                    {
+                        BodyProgress::Done
+                    } else {
+                        BodyProgress::new(&head.headers)
+                    };
+                    let end = body == BodyProgress::Done;
+                    frame::write_headers(out, self.id, &block, end,
+                                         max_frame_size);
+                    self.output = if end {
+                        Output::Closed
+                    } else {
+                        Output::Body(BodySender::new(body))
+                    };
+                }
+                Output::Body(ref mut sender) => {
+                    if sender.send(&mut self.buf, out, self.id, encoder,
+                        &mut self.send_window, conn_window, max_frame_size)
+                    {
+                        self.output = Output::Closed;
+                        return;
+                    }
+                    break;
+                }
+                Output::Closed => return,
+            }
+        }
+        if self.machine.is_none() &&
+            !self.with_response(|resp| resp.is_complete())
+        {
+            // Handler has finished in the middle of the response
+            self.reset(out, ErrorCode::InternalError);
+        }
+    }
+    /// Sends RST_STREAM and drops the state machine
+    pub fn reset(&mut self, out: &mut Buf, code: ErrorCode) {
+        frame::write_rst_stream(out, self.id, code);
+        self.close();
+    }
+    /// Drops the state machine, no more frames are sent for the stream
+    pub fn close(&mut self) {
+        self.machine = None;
+        self.body = RequestBody::Ignored;
+        self.output = Output::Closed;
+    }
+    /// Connection is closed, gives handler a chance to clean up
+    pub fn abort(&mut self, scope: &mut Scope<M::Context>) {
+        if !self.remote_closed {
+            let machine = self.machine.take();
+            self.with_response(|resp| {
+                machine.map(|m| m.bad_request(resp, scope));
+            });
+        }
+    }
+}
+
+impl<M: Server> fmt::Debug for Stream<M> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Stream")
+            .field("id", &self.id)
+            .field("active", &self.machine.is_some())
+            .field("response", &self.response)
+            .field("body", &self.body)
+            .field("output", &self.output)
+            .field("remote_closed", &self.remote_closed)
+            .field("send_window", &self.send_window)
+            .finish()
+    }
+}
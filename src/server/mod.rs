@@ -1,37 +1,55 @@
 //! HTTP Server implementation
 //!
-//! Currently there is only HTTP/1.x implementation. We want to provide
-//! HTTP/2.0 and HTTPS
+//! The same `Server` state machine serves both HTTP/1.x and HTTP/2
+//! (see the `http2` module). HTTPS is supported with the `tls` feature, use
+//! `tls::TlsListener` in place of `TcpListener` for that. Responses may be
+//! compressed with the `compress` module (the `compression` feature).
+//!
+//! The `router` module dispatches requests to handlers by method and path,
+//! the `cookie` module parses request cookies and builds `Set-Cookie`,
+//! the `multipart` module parses `multipart/form-data` bodies progressively
+//! and the `form` module decodes urlencoded forms. Static files are served
+//! by the `files` module.
 //!
 use rotor::mio::TryAccept;
 pub use rotor_stream::{Accept, Stream};
 
 pub use recvmode::RecvMode;
+pub use limits::Limits;
 pub use version::Version;
 pub use self::body::BodyKind;
 pub use self::parser::Parser;
 pub use self::protocol::Server;
-pub use self::request::Head;
+pub use self::request::{Head, OwnedHead};
+pub use headers::{Fields, Values, List, MediaType, MediaTypes};
+pub use headers::{Authorization, HeaderMap, HeaderLookup};
+pub use self::target::{Target, QueryPairs};
 pub use self::response::Response;
 pub use self::error::{RequestError, HttpError};
 
+pub mod http2;
+pub mod router;
+pub mod cookie;
+pub mod multipart;
+pub mod form;
+pub mod files;
+#[cfg(feature="compression")] pub mod compress;
+
 mod body;
+mod decode;
 mod parser;
 mod protocol;
 mod request;
+mod target;
 mod response;
 mod error;
 
 
-// TODO(tailhook) MAX_HEADERS_SIZE can be moved to Protocol
-// (i.e. made non-constant), but it's more of a problem for MAX_HEADERS_NUM
-// because that would mean we can't allocate array of headers on the stack
-// so performance will degrade. Customizing MAX_HEADERS_SIZE is not very
-// useful on it's own
-
 /// The maximum allowed number of headers in a request.
 ///
 /// Note httparse requires we preallocate array of this size so be wise.
+/// This is also the largest `Limits::max_headers_num` which doesn't need
+/// a heap allocation for each request.
 pub const MAX_HEADERS_NUM: usize = 256;
 
 /// The maximum size of request headers in bytes.
@@ -40,6 +58,19 @@ pub const MAX_HEADERS_NUM: usize = 256;
 /// because of `MAX_HEADERS_NUM` parameter.
 pub const MAX_HEADERS_SIZE: usize = 16384;
 
+/// The maximum length of the request line in bytes.
+///
+/// It's the method, the request target (URI) and the version.
+pub const MAX_REQUEST_LINE: usize = 8192;
+
+/// The maximum size of the decoded request body passed at once.
+///
+/// Only applies to the `Content-Encoding` (or transfer coding other than
+/// chunked) decoded in the `Progressive` mode, which isn't limited by the
+/// total size. Larger pieces are refused with `413 Payload Too Large` as
+/// they are likely a decompression bomb.
+pub const MAX_DECODED_CHUNK: usize = 1048576;
+
 /// The maximum length of chunk size line in bytes.
 ///
 /// It would be okay with 12 bytes, but in theory there might be some extensions
@@ -49,6 +80,9 @@ pub const MAX_HEADERS_SIZE: usize = 16384;
 /// it's limited by either memory or artificial limit returned from handler.
 /// In unbuffered mode we can process chunk of unlimited size as long as
 /// request handler is able to handle it.
+///
+/// All of the limits above are defaults for `Limits` and may be changed
+/// with `Server::limits()`.
 pub const MAX_CHUNK_HEAD: usize = 128;
 
 /// Shortcut type for server state machines.
@@ -0,0 +1,498 @@
+//! Incremental parser of `multipart/form-data` request bodies
+//!
+//! The parser is fed with the chunks as they arrive in
+//! `Server::request_chunk()`, so uploads don't need to be buffered:
+//!
+//! ```ignore
+//! // in headers_received()
+//! let parser = match Multipart::from_head(&head) {
+//!     Ok(parser) => parser,
+//!     Err(_) => { /* send 400 or 415 */ }
+//! };
+//! // ... return RecvMode::Progressive(16384)
+//!
+//! fn request_chunk(mut self, chunk: &[u8], ...) -> Option<Self> {
+//!     let ref mut files = self.files;
+//!     let res = self.parser.feed(chunk, |event| match event {
+//!         Event::Start(part) => files.start(part.filename.as_ref()),
+//!         Event::Data(data) => files.write(data),
+//!         Event::End => files.finish(),
+//!     });
+//!     // ...
+//! }
+//!
+//! fn request_end(self, ...) -> Option<Self> {
+//!     if self.parser.finish().is_err() { /* the body is truncated */ }
+//!     // ...
+//! }
+//! ```
+//!
+//! Boundaries and part headers may be split between chunks arbitrarily.
+//! Only the part headers and the tail of the data which may be the start
+//! of a boundary are buffered.
+use std::str::from_utf8;
+use std::ascii::AsciiExt;
+
+use httparse::{self, EMPTY_HEADER, Status};
+
+use headers::{HeaderMap, HeaderLookup};
+use super::Head;
+use super::target::percent_decode;
+
+
+/// The default limit on the number of parts
+pub const MAX_PARTS: usize = 1000;
+/// The default limit on the size of headers of a single part
+pub const MAX_PART_HEADERS_SIZE: usize = 8192;
+/// The maximum number of headers of a single part
+pub const MAX_PART_HEADERS_NUM: usize = 16;
+
+quick_error! {
+    /// Error parsing multipart body
+    #[derive(Debug)]
+    pub enum MultipartError {
+        NotMultipart {
+            description("content type is not multipart or has no boundary")
+        }
+        BadBoundary {
+            description("invalid characters after the boundary")
+        }
+        TooManyParts {
+            description("number of parts exceeds the limit")
+        }
+        HeadersTooLarge {
+            description("part headers are larger than the limit")
+        }
+        BadHeaders(err: httparse::Error) {
+            description("error parsing part headers")
+            display("error parsing part headers: {:?}", err)
+        }
+        UnexpectedEnd {
+            description("body ends before the closing boundary")
+        }
+    }
+}
+
+/// A part of the multipart body
+#[derive(Debug, Clone)]
+pub struct Part {
+    /// The `name` parameter of `Content-Disposition`
+    pub name: Option<String>,
+    /// The `filename` (or `filename*`) parameter of `Content-Disposition`
+    pub filename: Option<String>,
+    /// The value of the `Content-Type` header of the part
+    pub content_type: Option<String>,
+    pub headers: HeaderMap,
+}
+
+/// An event emitted by the `Multipart::feed()`
+#[derive(Debug)]
+pub enum Event<'a> {
+    /// Headers of the next part are received
+    Start(&'a Part),
+    /// A piece of the data of the current part
+    Data(&'a [u8]),
+    /// The current part is complete
+    End,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum State {
+    Preamble,
+    /// Delimiter is found, waiting for CRLF or `--`
+    Boundary,
+    Headers,
+    Body,
+    Done,
+}
+
+/// The incremental parser of a multipart body
+#[derive(Debug)]
+pub struct Multipart {
+    /// `CRLF--boundary`
+    delimiter: Vec<u8>,
+    state: State,
+    buf: Vec<u8>,
+    parts: usize,
+    max_parts: usize,
+    max_header_size: usize,
+}
+
+fn find(haystack: &[u8], needle: &[u8]) -> Option<usize> {
+    if haystack.len() < needle.len() {
+        return None;
+    }
+    haystack.windows(needle.len()).position(|w| w == needle)
+}
+
+/// Returns the length of the longest suffix of `data` which may be the
+/// start of the `delimiter`
+fn partial_match(data: &[u8], delimiter: &[u8]) -> usize {
+    let max = if data.len() < delimiter.len() { data.len() }
+              else { delimiter.len() - 1 };
+    (1..max+1).rev()
+        .find(|&n| data[data.len()-n..] == delimiter[..n])
+        .unwrap_or(0)
+}
+
+/// Splits parameters of `Content-Disposition`, quoted strings may contain
+/// semicolons and backslash escapes
+fn disposition_params(value: &str) -> Vec<(String, String)> {
+    let mut result = Vec::new();
+    let mut chars = match value.find(';') {
+        Some(idx) => value[idx+1..].chars().peekable(),
+        None => return result,
+    };
+    loop {
+        let mut name = String::new();
+        while let Some(&ch) = chars.peek() {
+            if ch == '=' || ch == ';' {
+                break;
+            }
+            name.push(ch);
+            chars.next();
+        }
+        let mut val = String::new();
+        if chars.peek() == Some(&'=') {
+            chars.next();
+            while chars.peek() == Some(&' ') {
+                chars.next();
+            }
+            if chars.peek() == Some(&'"') {
+                chars.next();
+                while let Some(ch) = chars.next() {
+                    match ch {
+                        '"' => break,
+                        '\\' => { chars.next().map(|x| val.push(x)); }
+                        _ => val.push(ch),
+                    }
+                }
+            }
+            while let Some(&ch) = chars.peek() {
+                if ch == ';' {
+                    break;
+                }
+                val.push(ch);
+                chars.next();
+            }
+        }
+        let name = name.trim();
+        if name.len() > 0 {
+            result.push((name.to_ascii_lowercase(), val.trim().to_string()));
+        }
+        if chars.next().is_none() {
+            return result;
+        }
+    }
+}
+
+/// Decodes the RFC 5987 value, e.g. `UTF-8''na%C3%AFve.txt`
+fn ext_value(value: &str) -> Option<String> {
+    let mut parts = value.splitn(3, '\'');
+    let charset = parts.next().unwrap_or("");
+    let _language = parts.next();
+    match parts.next() {
+        Some(text) if charset.eq_ignore_ascii_case("utf-8") => {
+            Some(percent_decode(text, false).into_owned())
+        }
+        _ => None,
+    }
+}
+
+impl Part {
+    fn new(headers: &[httparse::Header]) -> Part {
+        let headers = HeaderMap::new(headers);
+        let mut name = None;
+        let mut filename = None;
+        let mut filename_ext = None;
+        if let Some(value) = headers.get("Content-Disposition")
+            .and_then(|x| from_utf8(x).ok())
+        {
+            for (key, val) in disposition_params(value) {
+                match &key[..] {
+                    "name" => name = Some(val),
+                    "filename" => filename = Some(val),
+                    "filename*" => filename_ext = ext_value(&val),
+                    _ => {}
+                }
+            }
+        }
+        let content_type = headers.get("Content-Type")
+            .and_then(|x| from_utf8(x).ok())
+            .map(|x| x.trim().to_string());
+        Part {
+            name: name,
+            filename: filename_ext.or(filename),
+            content_type: content_type,
+            headers: headers,
+        }
+    }
+}
+
+impl Multipart {
+    /// Creates a parser for the boundary (without leading dashes)
+    pub fn new(boundary: &str) -> Multipart {
+        let mut delimiter = b"\r\n--".to_vec();
+        delimiter.extend(boundary.as_bytes());
+        Multipart {
+            delimiter: delimiter,
+            state: State::Preamble,
+            // So the first boundary looks like every other one
+            buf: b"\r\n".to_vec(),
+            parts: 0,
+            max_parts: MAX_PARTS,
+            max_header_size: MAX_PART_HEADERS_SIZE,
+        }
+    }
+    /// Creates a parser for the `multipart/form-data` request
+    ///
+    /// Returns `NotMultipart` when the `Content-Type` is different or it
+    /// has no boundary.
+    pub fn from_head(head: &Head) -> Result<Multipart, MultipartError> {
+        match head.content_type() {
+            Some(ref mime) if mime.is("multipart", "form-data") => {
+                match mime.param("boundary") {
+                    Some(b) if b.len() > 0 && b.len() <= 70 => {
+                        Ok(Multipart::new(b))
+                    }
+                    _ => Err(MultipartError::NotMultipart),
+                }
+            }
+            _ => Err(MultipartError::NotMultipart),
+        }
+    }
+    /// Set the maximum number of parts (`MAX_PARTS` by default)
+    pub fn max_parts(&mut self, num: usize) -> &mut Multipart {
+        self.max_parts = num;
+        self
+    }
+    /// Set the maximum size of headers of a part
+    /// (`MAX_PART_HEADERS_SIZE` by default)
+    pub fn max_header_size(&mut self, size: usize) -> &mut Multipart {
+        self.max_header_size = size;
+        self
+    }
+    /// Returns true when the closing boundary is received
+    pub fn is_complete(&self) -> bool {
+        self.state == State::Done
+    }
+    /// Parses the next chunk of the body
+    ///
+    /// Events are passed to the callback as soon as they are parsed. After
+    /// an error the parser must not be used any more. Data after the
+    /// closing boundary is ignored.
+    pub fn feed<F>(&mut self, data: &[u8], mut callback: F)
+        -> Result<(), MultipartError>
+        where F: FnMut(Event)
+    {
+        if self.state == State::Done {
+            return Ok(());
+        }
+        self.buf.extend(data);
+        let mut pos = 0;
+        let result = self.parse(&mut pos, &mut callback);
+        self.buf.drain(..pos);
+        result
+    }
+    fn parse<F>(&mut self, pos: &mut usize, callback: &mut F)
+        -> Result<(), MultipartError>
+        where F: FnMut(Event)
+    {
+        loop {
+            let buf = &self.buf[*pos..];
+            match self.state {
+                State::Preamble => {
+                    match find(buf, &self.delimiter) {
+                        Some(idx) => {
+                            *pos += idx + self.delimiter.len();
+                            self.state = State::Boundary;
+                        }
+                        None => {
+                            *pos += buf.len() -
+                                partial_match(buf, &self.delimiter);
+                            return Ok(());
+                        }
+                    }
+                }
+                State::Boundary => {
+                    if buf.starts_with(b"--") {
+                        *pos = self.buf.len();
+                        self.state = State::Done;
+                        return Ok(());
+                    }
+                    // Transport padding is allowed before CRLF
+                    let spaces = buf.iter()
+                        .take_while(|&&x| x == b' ' || x == b'\t').count();
+                    if buf.len() < spaces + 2 {
+                        if buf.len() > 0 && buf[0] != b'-' && buf[0] != b' '
+                            && buf[0] != b'\t' && buf[0] != b'\r'
+                        {
+                            return Err(MultipartError::BadBoundary);
+                        }
+                        return Ok(());
+                    }
+                    if &buf[spaces..spaces+2] != b"\r\n" {
+                        return Err(MultipartError::BadBoundary);
+                    }
+                    if self.parts >= self.max_parts {
+                        return Err(MultipartError::TooManyParts);
+                    }
+                    self.parts += 1;
+                    *pos += spaces + 2;
+                    self.state = State::Headers;
+                }
+                State::Headers => {
+                    let mut headers = [EMPTY_HEADER; MAX_PART_HEADERS_NUM];
+                    match httparse::parse_headers(buf, &mut headers) {
+                        Ok(Status::Complete((len, headers))) => {
+                            if len > self.max_header_size {
+                                return Err(MultipartError::HeadersTooLarge);
+                            }
+                            callback(Event::Start(&Part::new(headers)));
+                            *pos += len;
+                            self.state = State::Body;
+                        }
+                        Ok(Status::Partial) => {
+                            if buf.len() > self.max_header_size {
+                                return Err(MultipartError::HeadersTooLarge);
+                            }
+                            return Ok(());
+                        }
+                        Err(httparse::Error::TooManyHeaders) => {
+                            return Err(MultipartError::HeadersTooLarge);
+                        }
+                        Err(e) => return Err(MultipartError::BadHeaders(e)),
+                    }
+                }
+                State::Body => {
+                    match find(buf, &self.delimiter) {
+                        Some(idx) => {
+                            if idx > 0 {
+                                callback(Event::Data(&buf[..idx]));
+                            }
+                            callback(Event::End);
+                            *pos += idx + self.delimiter.len();
+                            self.state = State::Boundary;
+                        }
+                        None => {
+                            let len = buf.len() -
+                                partial_match(buf, &self.delimiter);
+                            if len > 0 {
+                                callback(Event::Data(&buf[..len]));
+                            }
+                            *pos += len;
+                            return Ok(());
+                        }
+                    }
+                }
+                State::Done => return Ok(()),
+            }
+        }
+    }
+    /// Checks that the body is complete, call it in `request_end()`
+    pub fn finish(&self) -> Result<(), MultipartError> {
+        if self.state == State::Done {
+            Ok(())
+        } else {
+            Err(MultipartError::UnexpectedEnd)
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{Multipart, MultipartError, Event};
+
+    const BODY: &'static [u8] = b"preamble\r\n\
+        --XyZ\r\n\
+        Content-Disposition: form-data; name=\"text\"\r\n\
+        \r\n\
+        hello\r\n--Xy\r\n\
+        --XyZ  \r\n\
+        Content-Disposition: form-data; name=\"file\"; \
+            filename=\"a;\\\"b\\\".txt\"\r\n\
+        Content-Type: text/plain\r\n\
+        \r\n\
+        \r\n\r\n--XyZ\r\n\
+        Content-Disposition: form-data; name=\"empty\"; \
+            filename*=UTF-8''%C3%A9.txt\r\n\
+        \r\n\
+        \r\n--XyZ--\r\nepilogue";
+
+    fn parse(parser: &mut Multipart, chunks: &[&[u8]])
+        -> Result<Vec<String>, MultipartError>
+    {
+        let mut events = Vec::new();
+        for chunk in chunks {
+            try!(parser.feed(chunk, |e| events.push(match e {
+                Event::Start(part) => format!("start {:?} {:?} {:?}",
+                    part.name, part.filename, part.content_type),
+                Event::Data(data) => String::from_utf8_lossy(data)
+                                     .into_owned(),
+                Event::End => "end".to_string(),
+            })));
+        }
+        try!(parser.finish());
+        // Merge data split between chunks
+        let mut merged: Vec<String> = Vec::new();
+        for e in events {
+            let is_data = |x: &String| !x.starts_with("start ") && x != "end";
+            if is_data(&e) && merged.last().map(&is_data).unwrap_or(false) {
+                merged.last_mut().unwrap().push_str(&e);
+            } else {
+                merged.push(e);
+            }
+        }
+        Ok(merged)
+    }
+
+    fn expected() -> Vec<String> {
+        vec![
+            "start Some(\"text\") None None".to_string(),
+            "hello\r\n--Xy".to_string(),
+            "end".to_string(),
+            "start Some(\"file\") Some(\"a;\\\"b\\\".txt\") \
+                Some(\"text/plain\")".to_string(),
+            "\r\n".to_string(),
+            "end".to_string(),
+            "start Some(\"empty\") Some(\"\u{e9}.txt\") None".to_string(),
+            "end".to_string(),
+        ]
+    }
+
+    #[test]
+    fn whole_body() {
+        let mut parser = Multipart::new("XyZ");
+        assert_eq!(parse(&mut parser, &[BODY]).unwrap(), expected());
+        assert!(parser.is_complete());
+    }
+
+    #[test]
+    fn byte_by_byte() {
+        let chunks = BODY.chunks(1).collect::<Vec<_>>();
+        let mut parser = Multipart::new("XyZ");
+        assert_eq!(parse(&mut parser, &chunks).unwrap(), expected());
+    }
+
+    #[test]
+    fn truncated() {
+        let mut parser = Multipart::new("XyZ");
+        assert!(matches!(parse(&mut parser, &[&BODY[..100]]),
+                         Err(MultipartError::UnexpectedEnd)));
+    }
+
+    #[test]
+    fn limits() {
+        let mut parser = Multipart::new("XyZ");
+        parser.max_parts(2);
+        assert!(matches!(parse(&mut parser, &[BODY]),
+                         Err(MultipartError::TooManyParts)));
+        let mut parser = Multipart::new("XyZ");
+        parser.max_header_size(40);
+        assert!(matches!(parse(&mut parser, &[BODY]),
+                         Err(MultipartError::HeadersTooLarge)));
+        let mut parser = Multipart::new("XyZ");
+        assert!(matches!(parse(&mut parser, &[b"--XyZx\r\n"]),
+                         Err(MultipartError::BadBoundary)));
+    }
+}
@@ -1,22 +1,30 @@
 use std::any::Any;
 use std::cmp::min;
 use std::marker::PhantomData;
+use std::net::SocketAddr;
 use std::str::from_utf8;
 
-use httparse::{EMPTY_HEADER, Request, parse_chunk_size};
+use httparse::{self, EMPTY_HEADER, Request, parse_chunk_size};
 use rotor::{Scope, Time};
 use rotor::mio::tcp::TcpStream;
-use rotor_stream::{Exception, Intent, Protocol, StreamSocket, Transport};
+use rotor_stream::{Exception, Expectation, Intent, Protocol, StreamSocket};
+use rotor_stream::{Transport};
 
 use version::Version;
+use date;
 use headers;
 use message::MessageState;
 use recvmode::RecvMode;
-use super::{MAX_HEADERS_NUM, MAX_HEADERS_SIZE, MAX_CHUNK_HEAD};
-use super::{Head, Response, Server};
+use super::MAX_HEADERS_NUM;
+use super::{Head, Response, Server, Target};
 use super::body::BodyKind;
-use super::response::state;
+use super::decode::{self, Decoder};
+use super::response::{state, new_response, flush_wait};
+use super::response::{allow_upgrade, close_connection};
+use super::http2::{Connection, PREFACE, h2c_settings};
 use super::error::RequestError;
+use tls::TlsInfo;
+#[cfg(feature="tls")] use tls::TlsStream;
 
 #[derive(Debug)]
 pub struct ReadBody<M: Server> {
@@ -25,6 +33,9 @@ pub struct ReadBody<M: Server> {
     response: MessageState,
     progress: BodyProgress,
     connection_close: bool,
+    /// Decodes transfer codings and `Content-Encoding` (if enabled by
+    /// `decode_request_body()`)
+    decoder: Option<Box<Decoder>>,
 }
 
 #[derive(Debug)]
@@ -39,6 +50,10 @@ pub enum BodyProgress {
     /// Progressive with chunked encoding
     /// (hint, offset, bytes left for current chunk)
     ProgressiveChunked(usize, usize, u64),
+    /// Trailer section of buffered chunked request (bytes buffered)
+    BufferTrailers(usize),
+    /// Trailer section of progressive chunked request
+    ProgressiveTrailers,
 }
 
 fn start_body(mode: RecvMode, body: BodyKind) -> BodyProgress {
@@ -47,12 +62,14 @@ fn start_body(mode: RecvMode, body: BodyKind) -> BodyProgress {
     use self::BodyProgress::*;
 
     match (mode, body) {
-        // The size of Fixed(x) is checked in parse_headers
+        // The size of Fixed(x) is checked in bytes_read
         (Buffered(_), Fixed(y)) => BufferFixed(y as usize),
         (Buffered(x), Chunked) => BufferChunked(x, 0, 0),
         (Progressive(x), Fixed(y)) => ProgressiveFixed(x, y),
         (Progressive(x), Chunked) => ProgressiveChunked(x, 0, 0),
-        (_, Upgrade) => unimplemented!(),
+        // Upgrade is not accepted by handler, so it's just an empty request
+        (Buffered(_), Upgrade) => BufferFixed(0),
+        (Progressive(x), Upgrade) => ProgressiveFixed(x, 0),
     }
 }
 
@@ -65,8 +82,10 @@ fn scan_raw_request(raw_request: &Request)
     // The length of a request body is determined by one of the following
     // (in order of precedence):
     //
-    // 1. If the request contains a valid `Transfer-Encoding` header
-    //    with `chunked` as the last encoding the request is chunked
+    // 1. If the request contains a `Transfer-Encoding` header the request
+    //    is chunked, `chunked` must be the last (and the only chunked)
+    //    coding, otherwise it's a bad request. Codings before `chunked`
+    //    must be supported by the decoder, otherwise it's not implemented
     //    (3rd option in RFC).
     // 2. If the request contains a valid `Content-Length` header
     //    the request has the given length in octets
@@ -75,51 +94,238 @@ fn scan_raw_request(raw_request: &Request)
     //    present the request has an empty body
     //    (6th option in RFC).
     // 4. In all other cases the request is a bad request.
+    //
+    // Empty HTTP/1.1 request with both `Upgrade` header and `upgrade`
+    // option in `Connection` header is marked as `Upgrade`.
     use super::body::BodyKind::*;
     use super::RequestError::*;
     let is_head = raw_request.method.unwrap() == "HEAD";
-    let mut has_content_length = false;
     let mut close = raw_request.version.unwrap() == 0;
     let mut expect_continue = false;
+    let mut has_upgrade = false;
+    let mut connection_upgrade = false;
+    let mut content_length = None;
+    let mut has_transfer_encoding = false;
+    let mut transfer_encoding = Vec::new();
     let mut body = Fixed(0);
     for header in raw_request.headers.iter() {
         if headers::is_transfer_encoding(header.name) {
-            if let Some(enc) = header.value.split(|&x| x == b',').last() {
-                if headers::is_chunked(enc) {
-                    if has_content_length {
-                        // override but don't allow keep-alive
-                        close = true;
-                    }
-                    body = Chunked;
-                }
-            }
+            // multiple headers are concatenated into a single list
+            has_transfer_encoding = true;
+            transfer_encoding.extend(header.value.split(|&x| x == b',')
+                    .filter(|x| x.iter().any(|&c| !matches!(c,
+                        b'\r' | b'\n' | b' ' | b'\t'))));
         } else if headers::is_content_length(header.name) {
-            if has_content_length {
+            if content_length.is_some() {
                 // duplicate content_length
                 return Err(DuplicateContentLength);
             }
-            has_content_length = true;
-            if body != Chunked {
-                let s = try!(from_utf8(header.value));
-                let len = try!(s.parse().map_err(BadContentLength));
-                body = Fixed(len);
-            } else {
-                // transfer-encoding has preference and don't allow keep-alive
-                close = true;
-            }
+            content_length = Some(header.value);
         } else if headers::is_connection(header.name) {
             if header.value.split(|&x| x == b',').any(headers::is_close) {
                 close = true;
             }
+            if header.value.split(|&x| x == b',')
+                .any(headers::is_upgrade_token)
+            {
+                connection_upgrade = true;
+            }
         } else if headers::is_expect(header.name) {
             if headers::is_continue(header.value) {
                 expect_continue = true;
             }
+        } else if headers::is_upgrade(header.name) {
+            has_upgrade = true;
         }
     }
+    if has_transfer_encoding {
+        match transfer_encoding.split_last() {
+            Some((last, rest)) if headers::is_chunked(last) => {
+                if rest.iter().any(|x| headers::is_chunked(x)) {
+                    return Err(ChunkedNotLast);
+                }
+                if !rest.iter().all(|x| decode::is_supported(x)) {
+                    return Err(UnsupportedTransferCoding);
+                }
+            }
+            _ => return Err(ChunkedNotLast),
+        }
+        if content_length.is_some() {
+            // transfer-encoding has preference and don't allow keep-alive
+            close = true;
+        }
+        body = Chunked;
+    } else if let Some(value) = content_length {
+        let s = try!(from_utf8(value));
+        let len = try!(s.parse().map_err(BadContentLength));
+        body = Fixed(len);
+    }
+    if has_upgrade && connection_upgrade && !close && body == Fixed(0) {
+        body = Upgrade;
+    }
     Ok((body, is_head, expect_continue, close))
 }
 
+/// Returns the scheme of the request
+///
+/// It's taken from the absolute-form target if there is one.
+fn request_scheme<'h, 'b>(raw_request: &Request<'h, 'b>, tls: bool)
+    -> &'b str
+{
+    match Target::parse(raw_request.path.unwrap()) {
+        Target::Absolute { scheme, .. } => scheme,
+        _ => if tls { "https" } else { "http" },
+    }
+}
+
+/// Returns the client address and TLS session info of the socket
+#[cfg(feature="tls")]
+fn socket_info<S: StreamSocket>(sock: &S)
+    -> (Option<SocketAddr>, Option<TlsInfo>)
+{
+    if let Some(sock) = Any::downcast_ref::<TlsStream<TcpStream>>(sock) {
+        return (sock.peer_addr().ok(), Some(sock.info()));
+    }
+    (Any::downcast_ref::<TcpStream>(sock).and_then(|x| x.peer_addr().ok()),
+     None)
+}
+
+/// Returns the client address and TLS session info of the socket
+#[cfg(not(feature="tls"))]
+fn socket_info<S: StreamSocket>(sock: &S)
+    -> (Option<SocketAddr>, Option<TlsInfo>)
+{
+    (Any::downcast_ref::<TcpStream>(sock).and_then(|x| x.peer_addr().ok()),
+     None)
+}
+
+/// Returns true if the first line of `input` is longer than `limit`
+///
+/// The line may be incomplete yet, in this case it's too long if the
+/// limit is already exceeded.
+fn request_line_too_long(input: &[u8], limit: usize) -> bool {
+    // The line itself is up to `limit` bytes and then CRLF
+    match input.iter().take(limit + 2).position(|&x| x == b'\n') {
+        Some(_) => false,
+        None => input.len() >= limit + 2,
+    }
+}
+
+/// Parses the trailer section and passes it to the handler
+///
+/// The `block` starts with CRLF of the last chunk line, so that the empty
+/// trailer section ends with `\r\n\r\n` too. The handler is only called
+/// if there is at least one trailer field. On error `bad_request()` is
+/// called and the error page should be sent.
+fn parse_trailers<M: Server>(machine: Option<M>, block: &[u8],
+    max_headers_num: usize, response: &mut Response,
+    scope: &mut Scope<M::Context>)
+    -> Result<Option<M>, RequestError>
+{
+    use httparse::Status::*;
+    if block.len() == 4 {
+        return Ok(machine);
+    }
+    let mut stack = [EMPTY_HEADER; MAX_HEADERS_NUM];
+    let mut heap;
+    let headers = if max_headers_num <= MAX_HEADERS_NUM {
+        &mut stack[..max_headers_num]
+    } else {
+        heap = vec![EMPTY_HEADER; max_headers_num];
+        &mut heap[..]
+    };
+    match httparse::parse_headers(&block[2..], headers) {
+        Ok(Complete((_, trailers))) => {
+            Ok(machine.and_then(|m| {
+                m.request_trailers(trailers, response, scope)
+            }))
+        }
+        Ok(Partial) => unreachable!(),
+        Err(e) => {
+            machine.map(|m| m.bad_request(response, scope));
+            Err(match e {
+                httparse::Error::TooManyHeaders => {
+                    RequestError::HeadersAreTooLarge
+                }
+                e => RequestError::from(e),
+            })
+        }
+    }
+}
+
+/// Passes the part of the body to `request_chunk()`, decoding it if needed
+fn body_chunk<M: Server>(machine: Option<M>,
+    decoder: &mut Option<Box<Decoder>>, data: &[u8],
+    response: &mut Response, scope: &mut Scope<M::Context>)
+    -> Result<Option<M>, RequestError>
+{
+    let decoded = match *decoder {
+        Some(ref mut dec) => match dec.decode(data) {
+            Ok(decoded) => decoded,
+            Err(e) => {
+                machine.map(|m| m.bad_request(response, scope));
+                return Err(e);
+            }
+        },
+        None => {
+            return Ok(machine.and_then(|m| {
+                m.request_chunk(data, response, scope)
+            }));
+        }
+    };
+    if decoded.len() == 0 {
+        return Ok(machine);
+    }
+    Ok(machine.and_then(|m| m.request_chunk(&decoded, response, scope)))
+}
+
+/// Passes the rest of the decoded data and calls `request_end()`
+fn body_end<M: Server>(machine: Option<M>, decoder: Option<Box<Decoder>>,
+    response: &mut Response, scope: &mut Scope<M::Context>)
+    -> Result<Option<M>, RequestError>
+{
+    let machine = match decoder.map(|dec| dec.finish()) {
+        Some(Ok(ref decoded)) if decoded.len() > 0 => {
+            machine.and_then(|m| m.request_chunk(decoded, response, scope))
+        }
+        Some(Ok(_)) | None => machine,
+        Some(Err(e)) => {
+            machine.map(|m| m.bad_request(response, scope));
+            return Err(e);
+        }
+    };
+    Ok(machine.and_then(|m| m.request_end(response, scope)))
+}
+
+/// Passes the whole body to `request_received()`, decoding it if needed
+fn body_received<M: Server>(machine: Option<M>,
+    decoder: Option<Box<Decoder>>, data: &[u8],
+    response: &mut Response, scope: &mut Scope<M::Context>)
+    -> Result<Option<M>, RequestError>
+{
+    let decoded = match decoder {
+        Some(mut dec) => {
+            let res = dec.decode(data).and_then(|mut decoded| {
+                decoded.extend(try!(dec.finish()));
+                Ok(decoded)
+            });
+            match res {
+                Ok(decoded) => decoded,
+                Err(e) => {
+                    machine.map(|m| m.bad_request(response, scope));
+                    return Err(e);
+                }
+            }
+        }
+        None => {
+            return Ok(machine.and_then(|m| {
+                m.request_received(data, response, scope)
+            }));
+        }
+    };
+    Ok(machine.and_then(|m| m.request_received(&decoded, response, scope)))
+}
+
 #[inline]
 fn consumed(off: usize) -> usize {
     // If buffer is not empty it has final '\r\n' at the
@@ -135,6 +341,8 @@ pub enum ParserImpl<M: Server> {
     ReadingBody(ReadBody<M>),
     Processing(M, MessageState, bool, Time),
     DoneResponse,
+    Upgraded(M),
+    Http2(Box<Connection<M>>),
 }
 
 impl <M: Server>ParserImpl<M> {
@@ -184,20 +392,66 @@ impl<M: Server, S: StreamSocket> Parser<M, S> {
             .expect_flush()
             .deadline(deadline)
     }
-    fn intent_body(seed: M::Seed, body: ReadBody<M>) -> Intent<Self> {
+    /// Sends the error page unless the handler has started the response
+    fn fail(seed: M::Seed, err: &RequestError, response: &mut Response,
+        scope: &mut Scope<M::Context>)
+        -> Intent<Self>
+    {
+        if !response.is_started() {
+            M::emit_error_page(err, response, &seed, scope);
+        }
+        Parser::intent_flush(seed, scope)
+    }
+    fn intent_upgraded(seed: M::Seed, res: Option<(M, Expectation, Time)>)
+        -> Intent<Self>
+    {
+        match res {
+            Some((m, exp, deadline)) => {
+                Intent::of(ParserImpl::Upgraded(m).wrap(seed))
+                    .expect(exp)
+                    .deadline(deadline)
+            }
+            None => Intent::done(),
+        }
+    }
+    fn intent_http2(seed: M::Seed, conn: Box<Connection<M>>,
+        res: Option<(Expectation, Time)>)
+        -> Intent<Self>
+    {
+        match res {
+            Some((exp, deadline)) => {
+                Intent::of(ParserImpl::Http2(conn).wrap(seed))
+                    .expect(exp)
+                    .deadline(deadline)
+            }
+            None => Intent::done(),
+        }
+    }
+    fn intent_body(seed: M::Seed, scope: &mut Scope<M::Context>,
+        body: ReadBody<M>)
+        -> Intent<Self>
+    {
         use rotor_stream::Expectation::*;
         use self::BodyProgress::*;
+        let limits = M::limits(&seed, scope);
+        let max_chunk_head = limits.max_chunk_head;
         let exp = match *&body.progress {
             BufferFixed(x) => Bytes(x),
             BufferChunked(_, off, 0) => {
-                Delimiter(consumed(off), b"\r\n", consumed(off) + MAX_CHUNK_HEAD)
+                Delimiter(consumed(off), b"\r\n", consumed(off) + max_chunk_head)
             }
             BufferChunked(_, off, y) => Bytes(off + y + 2),
             ProgressiveFixed(hint, left) => Bytes(min(hint as u64, left) as usize),
-            ProgressiveChunked(_, off, 0) => Delimiter(off, b"\r\n", off + MAX_CHUNK_HEAD),
+            ProgressiveChunked(_, off, 0) => Delimiter(off, b"\r\n", off + max_chunk_head),
             ProgressiveChunked(hint, off, left) => {
                 Bytes(min(hint as u64, off as u64 + left) as usize + 2)
             }
+            BufferTrailers(off) => {
+                Delimiter(off, b"\r\n\r\n", off + limits.max_headers_size)
+            }
+            ProgressiveTrailers => {
+                Delimiter(0, b"\r\n\r\n", limits.max_headers_size)
+            }
         };
         let deadline = body.deadline;
         Intent::of(ParserImpl::ReadingBody(body).wrap(seed))
@@ -211,15 +465,21 @@ impl<M: Server, S: StreamSocket> Parser<M, S> {
                     -> Intent<Parser<M, S>> {
         match machine {
             Some(m) => {
-                Intent::of(ParserImpl::Processing(m, state(response),
-                                    connection_close, deadline).wrap(seed))
-                    .sleep()
-                    .deadline(deadline)
+                let flush = flush_wait(&response);
+                let intent = Intent::of(ParserImpl::Processing(m,
+                    state(response), connection_close, deadline).wrap(seed));
+                match flush {
+                    Some(size) => intent.expect(Expectation::Flush(size)),
+                    None => intent.sleep(),
+                }.deadline(deadline)
             }
             None => {
-                // TODO(tailhook) probably we should do something better than
-                // an assert?
-                assert!(response.is_complete());
+                if !response.is_complete() {
+                    // Handler has finished in the middle of the response,
+                    // closing the connection is the only way to tell
+                    // the client
+                    return Intent::done();
+                }
                 if connection_close {
                     Parser::intent_flush(seed, scope)
                 } else {
@@ -245,26 +505,56 @@ impl<M: Server, S: StreamSocket> Protocol for Parser<M, S> {
                   end: usize,
                   scope: &mut Scope<Self::Context>)
                   -> Intent<Self> {
+        date::refresh(scope.now());
         use self::ParserImpl::*;
         use super::RequestError::*;
         match self.0 {
             Idle | ReadHeaders => {
                 use httparse::Status::*;
                 let n;
-                let client = Any::downcast_ref::<TcpStream>(transport.socket())
-                                 .and_then(|x| x.peer_addr().ok());
+                let (client, tls) = socket_info(transport.socket());
+                let len = min(transport.input().len(), PREFACE.len());
+                if &transport.input()[..len] == &PREFACE[..len] {
+                    if let Some(settings) = M::http2_settings(&self.1, scope) {
+                        if len < PREFACE.len() {
+                            return Parser::intent_headers(self.1, scope, len);
+                        }
+                        // HTTP/2 with prior knowledge
+                        let mut conn = Box::new(Connection::new(settings,
+                            client, tls, transport.output()));
+                        let res = conn.bytes_read(transport, &self.1, scope);
+                        return Parser::intent_http2(self.1, conn, res);
+                    }
+                }
+                let mut upgrade = None;
+                let limits = M::limits(&self.1, scope);
                 let (input, output) = transport.buffers();
-                let ((machine, mode, deadline), response, body, close) = {
-                    let mut headers = [EMPTY_HEADER; MAX_HEADERS_NUM];
-                    let mut raw_request = Request::new(&mut headers);
+                if request_line_too_long(&input[..], limits.max_request_line) {
+                    let mut response = new_response::<M>(output,
+                        Version::Http10, false, true,
+                        &self.1, scope);
+                    M::emit_error_page(&RequestLineTooLong,
+                        &mut response, &self.1, scope);
+                    return Parser::intent_flush(self.1, scope);
+                }
+                let parsed = {
+                    // The common case doesn't need an allocation
+                    let mut stack = [EMPTY_HEADER; MAX_HEADERS_NUM];
+                    let mut heap;
+                    let headers = if limits.max_headers_num <= MAX_HEADERS_NUM {
+                        &mut stack[..limits.max_headers_num]
+                    } else {
+                        heap = vec![EMPTY_HEADER; limits.max_headers_num];
+                        &mut heap[..]
+                    };
+                    let mut raw_request = Request::new(headers);
                     n = match raw_request.parse(&input[..]) {
                         Ok(Complete(n)) => n,
                         Ok(Partial) => {
-                            if input.len() > MAX_HEADERS_SIZE {
-                                let mut response = Response::new(output,
-                                                                 Version::Http10,
-                                                                 false,
-                                                                 true);
+                            if input.len() > limits.max_headers_size {
+                                let mut response = new_response::<M>(
+                                    output, Version::Http10, false, true,
+                                    &self.1, scope);
                                 M::emit_error_page(&HeadersAreTooLarge,
                                     &mut response, &self.1, scope);
                                 return Parser::intent_flush(self.1, scope);
@@ -273,9 +563,15 @@ impl<M: Server, S: StreamSocket> Protocol for Parser<M, S> {
                                 scope, input.len());
                         }
                         Err(e) => {
-                            let mut response = Response::new(output,
-                                Version::Http10, false, true);
-                            M::emit_error_page(&RequestError::from(e),
+                            let mut response = new_response::<M>(output,
+                                Version::Http10, false, true,
+                                &self.1, scope);
+                            let err = match e {
+                                httparse::Error::TooManyHeaders
+                                => HeadersAreTooLarge,
+                                e => RequestError::from(e),
+                            };
+                            M::emit_error_page(&err,
                                 &mut response, &self.1, scope);
                             return Parser::intent_flush(self.1, scope);
                         }
@@ -287,38 +583,124 @@ impl<M: Server, S: StreamSocket> Protocol for Parser<M, S> {
                             } else {
                                 Version::Http10
                             };
+                            let transfer = if body == BodyKind::Chunked {
+                                Decoder::transfer_codings(raw_request.headers)
+                            } else {
+                                Ok(None)
+                            };
+                            let content = if body != BodyKind::Fixed(0) &&
+                                M::decode_request_body(&self.1, scope)
+                            {
+                                Decoder::content_codings(raw_request.headers)
+                            } else {
+                                Ok(None)
+                            };
+                            let decoder = match transfer.and_then(|t| {
+                                content.map(|c| Decoder::join(t, c))
+                            }) {
+                                Ok(decoder) => decoder,
+                                Err(e) => {
+                                    let mut response = new_response::<M>(
+                                        output, version, is_head, true,
+                                        &self.1, scope);
+                                    M::emit_error_page(&e,
+                                        &mut response, &self.1, scope);
+                                    return Parser::intent_flush(self.1,
+                                                                scope);
+                                }
+                            };
+                            let scheme = request_scheme(&raw_request,
+                                                        tls.is_some());
                             let request = Head {
                                 client: client,
+                                tls: tls.as_ref(),
                                 version: version,
                                 method: raw_request.method.unwrap(),
-                                scheme: "http",
+                                scheme: scheme,
                                 path: raw_request.path.unwrap(),
                                 headers: raw_request.headers,
                                 body_kind: body,
                             };
-                            let mut response = Response::new(output,
-                                request.version, is_head, close);
-                            let triple = M::headers_received(self.1.clone(),
-                                request, &mut response, scope);
-                            if triple.is_none() && response.is_started() {
-                                if !expect_continue {
-                                    return Intent::done();
-                                } else {
+                            let h2c = if body == BodyKind::Upgrade {
+                                h2c_settings(request.headers).and_then(|peer| {
+                                    M::http2_settings(&self.1, scope)
+                                        .map(|settings| (settings, peer))
+                                })
+                            } else {
+                                None
+                            };
+                            if let Some((settings, peer)) = h2c {
+                                upgrade = Some(Box::new(Connection::upgrade(
+                                    settings, peer, request, output,
+                                    &self.1, scope)));
+                                None
+                            } else {
+                                let mut response = new_response::<M>(output,
+                                    request.version, is_head, close,
+                                    &self.1, scope);
+                                if body == BodyKind::Upgrade {
+                                    allow_upgrade(&mut response);
+                                }
+                                let triple = M::headers_received(self.1.clone(),
+                                    request, &mut response, scope);
+                                if triple.is_none() && response.is_started() {
+                                    if !expect_continue {
+                                        return Intent::done();
+                                    } else {
+                                        return Parser::intent_flush(self.1, scope);
+                                    }
+                                } else if triple.is_none() {
+                                    M::emit_error_page(&HeadersReceived,
+                                        &mut response, &self.1, scope);
                                     return Parser::intent_flush(self.1, scope);
                                 }
-                            } else if triple.is_none() {
-                                M::emit_error_page(&HeadersReceived,
-                                    &mut response, &self.1, scope);
-                                return Parser::intent_flush(self.1, scope);
-                            }
-                            if expect_continue {
-                                response.response_continue();
+                                let (m, mode, deadline) = triple.unwrap();
+                                if let (RecvMode::Buffered(limit),
+                                        BodyKind::Fixed(size)) = (mode, body)
+                                {
+                                    if size > limit as u64 {
+                                        // Refuse before reading the body
+                                        // (and before `100 Continue`), the
+                                        // unread body can't be skipped, so
+                                        // the connection is closed
+                                        close_connection(&mut response);
+                                        m.bad_request(&mut response, scope);
+                                        if !response.is_started() {
+                                            M::emit_error_page(
+                                                &PayloadTooLarge,
+                                                &mut response, &self.1,
+                                                scope);
+                                        }
+                                        if response.is_complete() {
+                                            return Parser::intent_flush(
+                                                self.1, scope);
+                                        }
+                                        return Intent::done();
+                                    }
+                                }
+                                if expect_continue && !response.is_started() {
+                                    response.response_continue();
+                                }
+                                let decoder = decoder.map(|mut dec| {
+                                    match mode {
+                                        RecvMode::Buffered(limit) => {
+                                            dec.limit(limit);
+                                        }
+                                        RecvMode::Progressive(_) => {
+                                            dec.chunk_limit(
+                                                limits.max_decoded_chunk);
+                                        }
+                                    }
+                                    Box::new(dec)
+                                });
+                                Some(((m, mode, deadline), response, body,
+                                      close, decoder))
                             }
-                            (triple.unwrap(), response, body, close)
                         }
                         Err(e) => {
-                            let mut response = Response::new(output,
-                                Version::Http10, false, true);
+                            let mut response = new_response::<M>(output,
+                                Version::Http10, false, true,
+                                &self.1, scope);
                             M::emit_error_page(&e, &mut response,
                                 &self.1, scope);
                             return Parser::intent_flush(self.1, scope);
@@ -326,44 +708,63 @@ impl<M: Server, S: StreamSocket> Protocol for Parser<M, S> {
                     }
                 };
                 input.consume(n);
-                return Parser::intent_body(self.1, ReadBody {
+                let ((machine, mode, deadline), response, body, close, decoder) =
+                    match (parsed, upgrade) {
+                        (Some(parsed), _) => parsed,
+                        (None, Some(mut conn)) => {
+                            let res = conn.bytes_read(transport, &self.1,
+                                                      scope);
+                            return Parser::intent_http2(self.1, conn, res);
+                        }
+                        (None, None) => unreachable!(),
+                    };
+                if response.is_upgraded() {
+                    let res = machine.upgrade_started(transport, scope);
+                    return Parser::intent_upgraded(self.1, res);
+                }
+                return Parser::intent_body(self.1, scope, ReadBody {
                     machine: Some(machine),
                     deadline: deadline,
                     progress: start_body(mode, body),
                     response: state(response),
                     connection_close: close,
+                    decoder: decoder,
                 });
             }
             ReadingBody(rb) => {
                 use self::BodyProgress::*;
                 let (inp, out) = transport.buffers();
                 let mut resp = rb.response.with(out);
+                let mut decoder = rb.decoder;
                 let (m, progress) = match rb.progress {
                     BufferFixed(x) => {
-                        let m = rb.machine
-                                  .and_then(|m| m.request_received(&inp[..x], &mut resp, scope));
+                        let res = body_received(rb.machine, decoder.take(),
+                            &inp[..x], &mut resp, scope);
                         inp.consume(x);
-                        (m, None)
+                        match res {
+                            Ok(m) => (m, None),
+                            Err(e) => {
+                                return Parser::fail(self.1, &e, &mut resp,
+                                                    scope);
+                            }
+                        }
                     }
                     BufferChunked(limit, off, 0) => {
                         use httparse::Status::*;
                         let lenstart = consumed(off);
                         match parse_chunk_size(&inp[lenstart..lenstart + end + 2]) {
                             Ok(Complete((_, 0))) => {
-                                inp.remove_range(off..lenstart + end + 2);
-                                let m = rb.machine.and_then(|m| {
-                                    m.request_received(&inp[..off], &mut resp, scope)
-                                });
-                                inp.consume(off);
-                                (m, None)
+                                // Keep CRLF of the line to find the end of
+                                // (possibly empty) trailer section
+                                inp.remove_range(off..lenstart + end);
+                                (rb.machine, Some(BufferTrailers(off)))
                             }
                             Ok(Complete((_, chunk_len))) => {
                                 if off as u64 + chunk_len > limit as u64 {
                                     inp.consume(lenstart + end + 2);
                                     rb.machine.map(|m| m.bad_request(&mut resp, scope));
-                                    M::emit_error_page(&PayloadTooLarge,
-                                        &mut resp, &self.1, scope);
-                                    return Parser::intent_flush(self.1, scope);
+                                    return Parser::fail(self.1,
+                                        &PayloadTooLarge, &mut resp, scope);
                                 }
                                 inp.remove_range(off..lenstart + end + 2);
                                 (rb.machine,
@@ -373,9 +774,8 @@ impl<M: Server, S: StreamSocket> Protocol for Parser<M, S> {
                             Err(e) => {
                                 inp.consume(lenstart + end + 2);
                                 rb.machine.map(|m| m.bad_request(&mut resp, scope));
-                                M::emit_error_page(&RequestError::from(e),
-                                    &mut resp, &self.1, scope);
-                                return Parser::intent_flush(self.1, scope);
+                                return Parser::fail(self.1,
+                                    &RequestError::from(e), &mut resp, scope);
                             }
                         }
                     }
@@ -388,32 +788,44 @@ impl<M: Server, S: StreamSocket> Protocol for Parser<M, S> {
                     }
                     ProgressiveFixed(hint, mut left) => {
                         let real_bytes = min(inp.len() as u64, left) as usize;
-                        let m = rb.machine.and_then(|m| {
-                            m.request_chunk(&inp[..real_bytes], &mut resp, scope)
-                        });
+                        let res = body_chunk(rb.machine, &mut decoder,
+                            &inp[..real_bytes], &mut resp, scope);
                         inp.consume(real_bytes);
                         left -= real_bytes as u64;
-                        if left == 0 {
-                            let m = m.and_then(|m| m.request_end(&mut resp, scope));
-                            (m, None)
+                        let res = if left == 0 {
+                            res.and_then(|m| {
+                                body_end(m, decoder.take(), &mut resp, scope)
+                            }).map(|m| (m, None))
                         } else {
-                            (m, Some(ProgressiveFixed(hint, left)))
+                            res.map(|m| (m, Some(ProgressiveFixed(hint, left))))
+                        };
+                        match res {
+                            Ok(pair) => pair,
+                            Err(e) => {
+                                return Parser::fail(self.1, &e, &mut resp,
+                                                    scope);
+                            }
                         }
                     }
                     ProgressiveChunked(hint, off, 0) => {
                         use httparse::Status::*;
                         match parse_chunk_size(&inp[off..off + end + 2]) {
                             Ok(Complete((_, 0))) => {
-                                inp.remove_range(off..off + end + 2);
+                                inp.remove_range(off..off + end);
                                 let mut m = rb.machine;
                                 if off > 0 {
-                                    m = m.and_then(|m| {
-                                        m.request_chunk(&inp[..off], &mut resp, scope)
-                                    });
+                                    let res = body_chunk(m, &mut decoder,
+                                        &inp[..off], &mut resp, scope);
+                                    m = match res {
+                                        Ok(m) => m,
+                                        Err(e) => {
+                                            return Parser::fail(self.1, &e,
+                                                &mut resp, scope);
+                                        }
+                                    };
                                 }
-                                m = m.and_then(|m| m.request_end(&mut resp, scope));
                                 inp.consume(off);
-                                (m, None)
+                                (m, Some(ProgressiveTrailers))
                             }
                             Ok(Complete((_, chunk_len))) => {
                                 inp.remove_range(off..off + end + 2);
@@ -423,9 +835,8 @@ impl<M: Server, S: StreamSocket> Protocol for Parser<M, S> {
                             Err(e) => {
                                 inp.consume(off + end + 2);
                                 rb.machine.map(|m| m.bad_request(&mut resp, scope));
-                                M::emit_error_page(&RequestError::from(e),
-                                    &mut resp, &self.1, scope);
-                                return Parser::intent_flush(self.1, scope);
+                                return Parser::fail(self.1,
+                                    &RequestError::from(e), &mut resp, scope);
                             }
                         }
                     }
@@ -443,21 +854,74 @@ impl<M: Server, S: StreamSocket> Protocol for Parser<M, S> {
                         if ln < hint {
                             (rb.machine, Some(ProgressiveChunked(hint, ln, left)))
                         } else {
-                            let m = rb.machine
-                                      .and_then(|m| m.request_chunk(&inp[..ln], &mut resp, scope));
+                            let res = body_chunk(rb.machine, &mut decoder,
+                                &inp[..ln], &mut resp, scope);
                             inp.consume(ln);
-                            (m, Some(ProgressiveChunked(hint, 0, left)))
+                            match res {
+                                Ok(m) => (m, Some(ProgressiveChunked(hint, 0, left))),
+                                Err(e) => {
+                                    return Parser::fail(self.1, &e, &mut resp,
+                                                        scope);
+                                }
+                            }
+                        }
+                    }
+                    BufferTrailers(off) => {
+                        let max_headers_num = M::limits(&self.1, scope)
+                            .max_headers_num;
+                        let res = parse_trailers(rb.machine,
+                            &inp[off..off + end + 4], max_headers_num,
+                            &mut resp, scope);
+                        let res = res.and_then(|m| {
+                            body_received(m, decoder.take(), &inp[..off],
+                                          &mut resp, scope)
+                        });
+                        match res {
+                            Ok(m) => {
+                                inp.consume(off + end + 4);
+                                (m, None)
+                            }
+                            Err(e) => {
+                                inp.consume(off + end + 4);
+                                if !resp.is_started() {
+                                    M::emit_error_page(&e, &mut resp,
+                                        &self.1, scope);
+                                }
+                                return Parser::intent_flush(self.1, scope);
+                            }
+                        }
+                    }
+                    ProgressiveTrailers => {
+                        let max_headers_num = M::limits(&self.1, scope)
+                            .max_headers_num;
+                        let res = parse_trailers(rb.machine,
+                            &inp[..end + 4], max_headers_num,
+                            &mut resp, scope);
+                        inp.consume(end + 4);
+                        let res = res.and_then(|m| {
+                            body_end(m, decoder.take(), &mut resp, scope)
+                        });
+                        match res {
+                            Ok(m) => (m, None),
+                            Err(e) => {
+                                if !resp.is_started() {
+                                    M::emit_error_page(&e, &mut resp,
+                                        &self.1, scope);
+                                }
+                                return Parser::intent_flush(self.1, scope);
+                            }
                         }
                     }
                 };
                 match progress {
                     Some(p) => {
-                        Parser::intent_body(self.1, ReadBody {
+                        Parser::intent_body(self.1, scope, ReadBody {
                             machine: m,
                             deadline: rb.deadline,
                             progress: p,
                             response: state(resp),
                             connection_close: rb.connection_close,
+                            decoder: decoder,
                         })
                     }
                     None => Parser::complete(self.1, scope,
@@ -470,14 +934,36 @@ impl<M: Server, S: StreamSocket> Protocol for Parser<M, S> {
             },
             /// TODO(tailhook) fix output timeout
             DoneResponse => Parser::intent_flush(self.1, scope),
+            Upgraded(m) => {
+                let res = m.upgrade_bytes_read(transport, end, scope);
+                Parser::intent_upgraded(self.1, res)
+            }
+            Http2(mut conn) => {
+                let res = conn.bytes_read(transport, &self.1, scope);
+                Parser::intent_http2(self.1, conn, res)
+            }
         }
     }
     fn bytes_flushed(self,
-                     _transport: &mut Transport<Self::Socket>,
-                     _scope: &mut Scope<Self::Context>)
+                     transport: &mut Transport<Self::Socket>,
+                     scope: &mut Scope<Self::Context>)
                      -> Intent<Self> {
+        date::refresh(scope.now());
         match self.0 {
             ParserImpl::DoneResponse => Intent::done(),
+            ParserImpl::Processing(m, respimp, close, dline) => {
+                let mut resp = respimp.with(transport.output());
+                let mres = m.response_flushed(&mut resp, scope);
+                Parser::complete(self.1, scope, mres, resp, close, dline)
+            }
+            ParserImpl::Upgraded(m) => {
+                let res = m.upgrade_bytes_flushed(transport, scope);
+                Parser::intent_upgraded(self.1, res)
+            }
+            ParserImpl::Http2(mut conn) => {
+                let res = conn.bytes_flushed(transport, &self.1, scope);
+                Parser::intent_http2(self.1, conn, res)
+            }
             _ => unreachable!(),
         }
     }
@@ -485,14 +971,16 @@ impl<M: Server, S: StreamSocket> Protocol for Parser<M, S> {
                transport: &mut Transport<Self::Socket>,
                scope: &mut Scope<Self::Context>)
                -> Intent<Self> {
+        date::refresh(scope.now());
         use self::ParserImpl::*;
         use super::RequestError::*;
         match self.0 {
             Idle | DoneResponse => Intent::done(),
             ReadHeaders => {
                 let output = transport.output();
-                let mut response = Response::new(output,
-                    Version::Http10, false, true);
+                let mut response = new_response::<M>(output,
+                    Version::Http10, false, true,
+                    &self.1, scope);
                 M::emit_error_page(&HeadersTimeout, &mut response,
                     &self.1, scope);
                 Parser::intent_flush(self.1, scope)
@@ -502,12 +990,13 @@ impl<M: Server, S: StreamSocket> Protocol for Parser<M, S> {
                 let res = rb.machine.and_then(|m| m.timeout(&mut resp, scope));
                 match res {
                     Some((m, deadline)) => {
-                        Parser::intent_body(self.1, ReadBody {
+                        Parser::intent_body(self.1, scope, ReadBody {
                             machine: Some(m),
                             deadline: deadline,
                             progress: rb.progress,
                             response: state(resp),
                             connection_close: rb.connection_close,
+                            decoder: rb.decoder,
                         })
                     }
                     None => {
@@ -537,12 +1026,21 @@ impl<M: Server, S: StreamSocket> Protocol for Parser<M, S> {
                     }
                 }
             }
+            Upgraded(m) => {
+                let res = m.upgrade_timeout(transport, scope);
+                Parser::intent_upgraded(self.1, res)
+            }
+            Http2(mut conn) => {
+                let res = conn.timeout(transport, &self.1, scope);
+                Parser::intent_http2(self.1, conn, res)
+            }
         }
     }
     fn wakeup(self,
               transport: &mut Transport<Self::Socket>,
               scope: &mut Scope<Self::Context>)
               -> Intent<Self> {
+        date::refresh(scope.now());
         use self::ParserImpl::*;
         match self.0 {
             Idle => Parser::intent_idle(self.1, scope),
@@ -552,12 +1050,13 @@ impl<M: Server, S: StreamSocket> Protocol for Parser<M, S> {
             ReadingBody(rb) => {
                 let mut resp = rb.response.with(transport.output());
                 let m = rb.machine.and_then(|m| m.wakeup(&mut resp, scope));
-                Parser::intent_body(self.1, ReadBody {
+                Parser::intent_body(self.1, scope, ReadBody {
                     machine: m,
                     deadline: rb.deadline,
                     progress: rb.progress,
                     response: state(resp),
                     connection_close: rb.connection_close,
+                    decoder: rb.decoder,
                 })
             }
             Processing(m, respimp, close, dline) => {
@@ -565,6 +1064,14 @@ impl<M: Server, S: StreamSocket> Protocol for Parser<M, S> {
                 let mres = m.wakeup(&mut resp, scope);
                 Parser::complete(self.1, scope, mres, resp, close, dline)
             }
+            Upgraded(m) => {
+                let res = m.upgrade_wakeup(transport, scope);
+                Parser::intent_upgraded(self.1, res)
+            }
+            Http2(mut conn) => {
+                let res = conn.wakeup(transport, &self.1, scope);
+                Parser::intent_http2(self.1, conn, res)
+            }
         }
     }
 
@@ -573,20 +1080,37 @@ impl<M: Server, S: StreamSocket> Protocol for Parser<M, S> {
                  reason: Exception,
                  scope: &mut Scope<Self::Context>)
                  -> Intent<Self> {
+        date::refresh(scope.now());
         use rotor_stream::Exception::*;
         use self::BodyProgress::*;
         use self::ParserImpl::*;
         use super::error::RequestError::*;
+        let state = match self.0 {
+            Upgraded(m) => {
+                m.upgrade_end(transport, reason, scope);
+                return Intent::done();
+            }
+            Http2(mut conn) => {
+                conn.end(scope);
+                return Intent::done();
+            }
+            state => state,
+        };
         match reason {
             LimitReached => {
-                if let ReadingBody(rb) = self.0 {
-                    assert!(matches!(rb.progress,
+                if let ReadingBody(rb) = state {
+                    let err = match rb.progress {
                         ProgressiveChunked(_, _, 0) |  // TODO(tailhook) why?
-                        BufferChunked(_, _, 0)));
+                        BufferChunked(_, _, 0) => PayloadTooLarge,
+                        BufferTrailers(_) | ProgressiveTrailers => {
+                            HeadersAreTooLarge
+                        }
+                        _ => unreachable!(),
+                    };
                     let mut resp = rb.response.with(transport.output());
                     rb.machine.map(|m| m.bad_request(&mut resp, scope));
                     if !resp.is_started() {
-                        M::emit_error_page(&PayloadTooLarge, &mut resp,
+                        M::emit_error_page(&err, &mut resp,
                             &self.1, scope);
                     }
                     if resp.is_complete() {
@@ -595,7 +1119,7 @@ impl<M: Server, S: StreamSocket> Protocol for Parser<M, S> {
                 }
             }
             EndOfStream => {
-                if let ReadingBody(rb) = self.0 {
+                if let ReadingBody(rb) = state {
                     let mut resp = rb.response.with(transport.output());
                     rb.machine.map(|m| m.bad_request(&mut resp, scope));
                     if !resp.is_started() {
@@ -621,10 +1145,14 @@ mod test {
     use std::time::Duration;
     use std::str::from_utf8;
     use rotor_test::{MemIo, MockLoop};
-    use rotor_stream::{Stream, Accepted};
+    use rotor_stream::{Stream, Accepted, Expectation, StreamSocket};
+    use rotor_stream::{Transport};
     use rotor::{Scope, Time, EventSet, Machine};
+    use httparse::Header;
     use super::Parser;
-    use super::super::{Server, Head, Response, RecvMode};
+    use super::super::{Server, Head, Response, RecvMode, BodyKind};
+    use super::super::{Limits, HttpError};
+    use super::super::http2::{Settings, h2c_settings};
 
     #[derive(Debug, PartialEq, Eq, Default)]
     pub struct Context {
@@ -677,6 +1205,245 @@ mod test {
             scope.requests_received += 1;
             Some(Proto::Done)
         }
+        fn request_trailers(self, trailers: &[Header],
+            _response: &mut Response, scope: &mut Scope<Self::Context>)
+            -> Option<Self>
+        {
+            for t in trailers {
+                scope.body.push_str(&format!("[{}: {}]",
+                    t.name, from_utf8(t.value).unwrap()));
+            }
+            Some(self)
+        }
+        fn timeout(self, _response: &mut Response,
+            _scope: &mut Scope<Self::Context>) -> Option<(Self, Time)>
+        { unimplemented!(); }
+        fn wakeup(self, _response: &mut Response,
+            _scope: &mut Scope<Self::Context>) -> Option<Self>
+        { unimplemented!(); }
+        fn http2_settings(_seed: &(), _scope: &mut Scope<Self::Context>)
+            -> Option<Settings>
+        {
+            Some(Settings::default())
+        }
+    }
+
+    /// Accepts any upgrade and echoes data back in 5 byte pieces
+    #[derive(Debug, PartialEq, Eq)]
+    pub struct Echo;
+
+    impl Server for Echo {
+        type Seed = ();
+        type Context = Context;
+        fn headers_received((): (), head: Head, response: &mut Response,
+            scope: &mut Scope<Self::Context>)
+            -> Option<(Self, RecvMode, Time)>
+        {
+            scope.headers_received += 1;
+            assert_eq!(head.body_kind, BodyKind::Upgrade);
+            response.status(101, "Switching Protocols");
+            response.add_header("Connection", b"upgrade").unwrap();
+            response.add_header("Upgrade", b"echo").unwrap();
+            response.done_headers().unwrap();
+            Some((Echo, RecvMode::Buffered(0),
+                scope.now() + Duration::new(10, 0)))
+        }
+        fn request_received(self, _data: &[u8], _response: &mut Response,
+            _scope: &mut Scope<Self::Context>) -> Option<Self>
+        { unreachable!(); }
+        fn request_chunk(self, _chunk: &[u8], _response: &mut Response,
+            _scope: &mut Scope<Self::Context>) -> Option<Self>
+        { unreachable!(); }
+        fn request_end(self, _response: &mut Response,
+            _scope: &mut Scope<Self::Context>) -> Option<Self>
+        { unreachable!(); }
+        fn timeout(self, _response: &mut Response,
+            _scope: &mut Scope<Self::Context>) -> Option<(Self, Time)>
+        { unimplemented!(); }
+        fn wakeup(self, _response: &mut Response,
+            _scope: &mut Scope<Self::Context>) -> Option<Self>
+        { unimplemented!(); }
+        fn upgrade_started<S: StreamSocket>(self,
+            _transport: &mut Transport<S>, scope: &mut Scope<Self::Context>)
+            -> Option<(Self, Expectation, Time)>
+        {
+            Some((Echo, Expectation::Bytes(5),
+                scope.now() + Duration::new(10, 0)))
+        }
+        fn upgrade_bytes_read<S: StreamSocket>(self,
+            transport: &mut Transport<S>, end: usize,
+            scope: &mut Scope<Self::Context>)
+            -> Option<(Self, Expectation, Time)>
+        {
+            let (inp, out) = transport.buffers();
+            scope.body.push_str(from_utf8(&inp[..end]).unwrap());
+            scope.chunks_received += 1;
+            out.extend(&inp[..end]);
+            inp.consume(end);
+            Some((Echo, Expectation::Bytes(5),
+                scope.now() + Duration::new(10, 0)))
+        }
+    }
+
+    /// Writes a part of the response body (chunked if the seed is true)
+    /// and stops, records each request received
+    #[derive(Debug, PartialEq, Eq)]
+    pub struct Truncated;
+
+    impl Server for Truncated {
+        type Seed = bool;
+        type Context = Vec<u16>;
+        fn headers_received(chunked: bool, _head: Head,
+            response: &mut Response, scope: &mut Scope<Self::Context>)
+            -> Option<(Self, RecvMode, Time)>
+        {
+            scope.push(1);
+            response.status(200, "OK");
+            if chunked {
+                response.add_chunked().unwrap();
+            } else {
+                response.add_length(10).unwrap();
+            }
+            response.done_headers().unwrap();
+            response.write_body(b"abc");
+            None
+        }
+        fn request_received(self, _data: &[u8], _response: &mut Response,
+            _scope: &mut Scope<Self::Context>) -> Option<Self>
+        { unreachable!(); }
+        fn request_chunk(self, _chunk: &[u8], _response: &mut Response,
+            _scope: &mut Scope<Self::Context>) -> Option<Self>
+        { unreachable!(); }
+        fn request_end(self, _response: &mut Response,
+            _scope: &mut Scope<Self::Context>) -> Option<Self>
+        { unreachable!(); }
+        fn timeout(self, _response: &mut Response,
+            _scope: &mut Scope<Self::Context>) -> Option<(Self, Time)>
+        { unimplemented!(); }
+        fn wakeup(self, _response: &mut Response,
+            _scope: &mut Scope<Self::Context>) -> Option<Self>
+        { unimplemented!(); }
+    }
+
+    /// Allows the number of headers passed in the seed and records
+    /// the status code of each response (and zero for `bad_request()`)
+    #[derive(Debug, PartialEq, Eq)]
+    pub struct Limited;
+
+    impl Server for Limited {
+        type Seed = usize;
+        type Context = Vec<u16>;
+        fn headers_received(_seed: usize, _head: Head,
+            _response: &mut Response, scope: &mut Scope<Self::Context>)
+            -> Option<(Self, RecvMode, Time)>
+        {
+            Some((Limited, RecvMode::Buffered(1000),
+                scope.now() + Duration::new(10, 0)))
+        }
+        fn request_received(self, _data: &[u8], response: &mut Response,
+            scope: &mut Scope<Self::Context>) -> Option<Self>
+        {
+            scope.push(200);
+            response.status(200, "OK");
+            response.add_length(0).unwrap();
+            response.done_headers().unwrap();
+            response.done();
+            None
+        }
+        fn request_chunk(self, _chunk: &[u8], _response: &mut Response,
+            _scope: &mut Scope<Self::Context>) -> Option<Self>
+        { unreachable!(); }
+        fn request_end(self, _response: &mut Response,
+            _scope: &mut Scope<Self::Context>) -> Option<Self>
+        { unreachable!(); }
+        fn bad_request(self, _response: &mut Response,
+            scope: &mut Scope<Self::Context>)
+        {
+            scope.push(0);
+        }
+        fn timeout(self, _response: &mut Response,
+            _scope: &mut Scope<Self::Context>) -> Option<(Self, Time)>
+        { unimplemented!(); }
+        fn wakeup(self, _response: &mut Response,
+            _scope: &mut Scope<Self::Context>) -> Option<Self>
+        { unimplemented!(); }
+        fn emit_error_page(code: &HttpError, response: &mut Response,
+            _seed: &usize, scope: &mut Scope<Self::Context>)
+        {
+            let (status, reason) = code.http_status();
+            scope.push(status);
+            response.status(status, reason);
+            response.add_length(0).unwrap();
+            response.done_headers().unwrap();
+            response.done();
+        }
+        fn limits(seed: &usize, _scope: &mut Scope<Self::Context>)
+            -> Limits
+        {
+            Limits {
+                max_headers_num: *seed,
+                max_request_line: 40,
+                .. Limits::default()
+            }
+        }
+        fn decode_request_body(_seed: &usize,
+            _scope: &mut Scope<Self::Context>)
+            -> bool
+        {
+            true
+        }
+    }
+
+    /// Writes the body in three pieces, each one after the previous one is
+    /// flushed, and records the number of the piece
+    #[derive(Debug, PartialEq, Eq)]
+    pub struct Pieces(u16);
+
+    impl Pieces {
+        fn write(self, response: &mut Response,
+            scope: &mut Scope<Vec<u16>>) -> Option<Self>
+        {
+            scope.push(self.0 + 1);
+            response.write_body(b"abc");
+            if self.0 == 2 {
+                response.done();
+                None
+            } else {
+                response.wait_flush(0);
+                Some(Pieces(self.0 + 1))
+            }
+        }
+    }
+
+    impl Server for Pieces {
+        type Seed = ();
+        type Context = Vec<u16>;
+        fn headers_received(_seed: (), _head: Head,
+            _response: &mut Response, scope: &mut Scope<Self::Context>)
+            -> Option<(Self, RecvMode, Time)>
+        {
+            Some((Pieces(0), RecvMode::Buffered(0),
+                scope.now() + Duration::new(10, 0)))
+        }
+        fn request_received(self, _data: &[u8], response: &mut Response,
+            scope: &mut Scope<Self::Context>) -> Option<Self>
+        {
+            response.status(200, "OK");
+            response.add_length(9).unwrap();
+            response.done_headers().unwrap();
+            self.write(response, scope)
+        }
+        fn response_flushed(self, response: &mut Response,
+            scope: &mut Scope<Self::Context>) -> Option<Self>
+        {
+            self.write(response, scope)
+        }
+        fn request_chunk(self, _chunk: &[u8], _response: &mut Response,
+            _scope: &mut Scope<Self::Context>) -> Option<Self>
+        { unreachable!(); }
+        fn request_end(self, _response: &mut Response,
+            _scope: &mut Scope<Self::Context>) -> Option<Self>
+        { unreachable!(); }
         fn timeout(self, _response: &mut Response,
             _scope: &mut Scope<Self::Context>) -> Option<(Self, Time)>
         { unimplemented!(); }
@@ -685,10 +1452,19 @@ mod test {
         { unimplemented!(); }
     }
 
+    fn request_with_headers(num: usize) -> String {
+        let mut req = String::from("GET / HTTP/1.1\r\n");
+        for i in 0..num {
+            req.push_str(&format!("X-Header-{}: value\r\n", i));
+        }
+        req.push_str("\r\n");
+        req
+    }
+
     #[test]
     fn parser_size() {
         // Just to keep track of size of structure
-        assert_eq!(::std::mem::size_of::<Parser<Proto, MemIo>>(), 88);
+        assert_eq!(::std::mem::size_of::<Parser<Proto, MemIo>>(), 96);
     }
 
 
@@ -834,6 +1610,76 @@ mod test {
         });
     }
 
+    #[test]
+    fn test_chunked_trailers() {
+        let mut io = MemIo::new();
+        let mut lp = MockLoop::new(Default::default());
+        io.push_bytes("POST / HTTP/1.1\r\nTransfer-Encoding: chunked\r\n\
+                       Connection: close\r\n\r\n\
+                       5\r\nhello\r\n0\r\nDigest: x\r\n".as_bytes());
+        let m = Stream::<Parser<Proto, MemIo>>::accepted(
+            io.clone(), (), &mut lp.scope(1)).expect_machine();
+        let m = m.ready(EventSet::readable(), &mut lp.scope(1))
+            .expect_machine();
+        assert_eq!(lp.ctx().requests_received, 0);
+        io.push_bytes("X-Status: 0\r\n\r\n".as_bytes());
+        m.ready(EventSet::readable(), &mut lp.scope(1))
+            .expect_machine();
+        assert_eq!(*lp.ctx(), Context {
+            progressive: false,
+            headers_received: 1,
+            chunks_received: 0,
+            body: String::from("[Digest: x][X-Status: 0]hello"),
+            requests_received: 1,
+        });
+    }
+
+    #[test]
+    fn test_progressive_trailers() {
+        let mut io = MemIo::new();
+        let mut lp = MockLoop::new(
+            Context { progressive: true, ..Default::default() });
+        io.push_bytes("POST / HTTP/1.1\r\nTransfer-Encoding: chunked\r\n\
+                       Connection: close\r\n\r\n\
+                       5\r\nhello\r\n0\r\nDigest: x\r\n\r\n".as_bytes());
+        let m = Stream::<Parser<Proto, MemIo>>::accepted(
+            io.clone(), (), &mut lp.scope(1)).expect_machine();
+        m.ready(EventSet::readable(), &mut lp.scope(1))
+            .expect_machine();
+        assert_eq!(*lp.ctx(), Context {
+            progressive: true,
+            headers_received: 1,
+            chunks_received: 1,
+            body: String::from("hello[Digest: x]"),
+            requests_received: 1,
+        });
+    }
+
+    #[test]
+    fn test_trailers_keep_alive() {
+        let mut lp = MockLoop::new(Default::default());
+        let mut io = MemIo::new();
+        io.push_bytes("POST / HTTP/1.1\r\nTransfer-Encoding: chunked\r\n\r\n\
+                       5\r\nhello\r\n0\r\nDigest: x\r\n\r\n\
+                       GET / HTTP/1.1\r\n\r\n".as_bytes());
+        let m = Stream::<Parser<Limited, MemIo>>::accepted(
+            io.clone(), 10, &mut lp.scope(1)).expect_machine();
+        m.ready(EventSet::readable(), &mut lp.scope(1));
+        assert_eq!(*lp.ctx(), vec![200, 200]);
+    }
+
+    #[test]
+    fn test_too_many_trailers() {
+        let mut lp = MockLoop::new(Default::default());
+        let mut io = MemIo::new();
+        io.push_bytes("POST / HTTP/1.1\r\nTransfer-Encoding: chunked\r\n\r\n\
+                       0\r\nA: 1\r\nB: 2\r\nC: 3\r\n\r\n".as_bytes());
+        let m = Stream::<Parser<Limited, MemIo>>::accepted(
+            io.clone(), 2, &mut lp.scope(1)).expect_machine();
+        m.ready(EventSet::readable(), &mut lp.scope(1));
+        assert_eq!(*lp.ctx(), vec![0, 431]);
+    }
+
     #[test]
     fn test_progressive_chunked() {
         let mut io = MemIo::new();
@@ -917,6 +1763,154 @@ mod test {
                    });
     }
 
+    #[test]
+    fn test_http2_prior_knowledge() {
+        let mut io = MemIo::new();
+        let mut lp = MockLoop::new(Default::default());
+        io.push_bytes(b"PRI * HTTP/2.0\r\n\r\nSM\r\n\r\n");
+        // Empty SETTINGS
+        io.push_bytes(b"\x00\x00\x00\x04\x00\x00\x00\x00\x00");
+        // HEADERS: GET http://example.com/
+        io.push_bytes(b"\x00\x00\x10\x01\x04\x00\x00\x00\x01\
+                        \x82\x84\x86\x41\x0bexample.com");
+        let m = Stream::<Parser<Proto, MemIo>>::accepted(
+            io.clone(), (), &mut lp.scope(1)).expect_machine();
+        let m = m.ready(EventSet::readable(), &mut lp.scope(1))
+            .expect_machine();
+        assert_eq!(*lp.ctx(), Context {
+            progressive: false,
+            headers_received: 1,
+            body: String::new(),
+            chunks_received: 0,
+            requests_received: 0,
+        });
+        // DATA with END_STREAM
+        io.push_bytes(b"\x00\x00\x05\x00\x01\x00\x00\x00\x01hello");
+        m.ready(EventSet::readable(), &mut lp.scope(1))
+            .expect_machine();
+        assert_eq!(*lp.ctx(), Context {
+            progressive: false,
+            headers_received: 1,
+            body: String::from("hello"),
+            chunks_received: 0,
+            requests_received: 1,
+        });
+    }
+
+    #[test]
+    fn test_http2_upgrade() {
+        let mut io = MemIo::new();
+        let mut lp = MockLoop::new(Default::default());
+        io.push_bytes("GET / HTTP/1.1\r\nHost: example.com\r\n\
+                       Connection: Upgrade, HTTP2-Settings\r\n\
+                       Upgrade: h2c\r\n\
+                       HTTP2-Settings: AAMAAABkAAQAAP__\r\n\r\n\
+                       PRI * HTTP/2.0\r\n\r\nSM\r\n\r\n".as_bytes());
+        io.push_bytes(b"\x00\x00\x00\x04\x00\x00\x00\x00\x00");
+        let m = Stream::<Parser<Proto, MemIo>>::accepted(
+            io.clone(), (), &mut lp.scope(1)).expect_machine();
+        m.ready(EventSet::readable(), &mut lp.scope(1))
+            .expect_machine();
+        // The upgrade request itself is the stream 1
+        assert_eq!(*lp.ctx(), Context {
+            progressive: false,
+            headers_received: 1,
+            body: String::new(),
+            chunks_received: 0,
+            requests_received: 1,
+        });
+    }
+
+    #[test]
+    fn test_h2c_settings() {
+        let upgrade = Header { name: "Upgrade", value: b"h2c" };
+        let settings = Header { name: "HTTP2-Settings",
+                                value: b"AAMAAABkAAQAAP__" };
+        let conn = |value| Header { name: "Connection", value: value };
+        assert!(h2c_settings(&[conn(b"Upgrade, HTTP2-Settings"),
+                               upgrade, settings]).is_some());
+        assert!(h2c_settings(&[conn(b"upgrade"), conn(b" http2-settings"),
+                               upgrade, settings]).is_some());
+        // The token is required by RFC 7540, section 3.2.1
+        assert!(h2c_settings(&[conn(b"Upgrade"), upgrade, settings])
+                .is_none());
+        assert!(h2c_settings(&[upgrade, settings]).is_none());
+    }
+
+    #[test]
+    fn test_http2_payload_too_large() {
+        let mut io = MemIo::new();
+        let mut lp = MockLoop::new(Default::default());
+        io.push_bytes(b"PRI * HTTP/2.0\r\n\r\nSM\r\n\r\n");
+        io.push_bytes(b"\x00\x00\x00\x04\x00\x00\x00\x00\x00");
+        // HEADERS: POST http://example.com/, content-length: 2000
+        io.push_bytes(b"\x00\x00\x16\x01\x04\x00\x00\x00\x01\
+                        \x83\x84\x86\x41\x0bexample.com\x5c\x042000");
+        io.push_bytes(b"\x00\x00\x05\x00\x01\x00\x00\x00\x01hello");
+        let m = Stream::<Parser<Proto, MemIo>>::accepted(
+            io.clone(), (), &mut lp.scope(1)).expect_machine();
+        m.ready(EventSet::readable(), &mut lp.scope(1))
+            .expect_machine();
+        // The body over the `Buffered` limit is refused without reading it
+        assert_eq!(*lp.ctx(), Context {
+            progressive: false,
+            headers_received: 1,
+            body: String::new(),
+            chunks_received: 0,
+            requests_received: 0,
+        });
+    }
+
+    #[test]
+    fn test_upgrade() {
+        let mut io = MemIo::new();
+        let mut lp = MockLoop::new(Default::default());
+        io.push_bytes("GET /chat HTTP/1.1\r\nHost: example.com\r\n\
+                       Connection: Upgrade\r\nUpgrade: echo\r\n\r\n\
+                       hello".as_bytes());
+        let m = Stream::<Parser<Echo, MemIo>>::accepted(
+            io.clone(), (), &mut lp.scope(1)).expect_machine();
+        let m = m.ready(EventSet::readable(), &mut lp.scope(1))
+            .expect_machine();
+        assert_eq!(*lp.ctx(), Context {
+            progressive: false,
+            headers_received: 1,
+            body: String::from("hello"),
+            chunks_received: 1,
+            requests_received: 0,
+        });
+        io.push_bytes("world".as_bytes());
+        m.ready(EventSet::readable(), &mut lp.scope(1))
+            .expect_machine();
+        assert_eq!(*lp.ctx(), Context {
+            progressive: false,
+            headers_received: 1,
+            body: String::from("helloworld"),
+            chunks_received: 2,
+            requests_received: 0,
+        });
+    }
+
+    #[test]
+    fn test_upgrade_not_accepted() {
+        let mut io = MemIo::new();
+        let mut lp = MockLoop::new(Default::default());
+        io.push_bytes("GET /chat HTTP/1.1\r\nHost: example.com\r\n\
+                       Connection: Upgrade\r\nUpgrade: echo\r\n\r\n"
+                       .as_bytes());
+        let m = Stream::<Parser<Proto, MemIo>>::accepted(
+            io.clone(), (), &mut lp.scope(1)).expect_machine();
+        m.ready(EventSet::readable(), &mut lp.scope(1))
+            .expect_machine();
+        assert_eq!(*lp.ctx(), Context {
+            progressive: false,
+            headers_received: 1,
+            body: String::new(),
+            chunks_received: 0,
+            requests_received: 1,
+        });
+    }
+
     #[test]
     fn test_crazy() {
         let mut io = MemIo::new();
@@ -936,6 +1930,164 @@ mod test {
                        requests_received: 1,
                    });
     }
+
+    #[test]
+    fn test_headers_limit() {
+        let mut lp = MockLoop::new(Default::default());
+        for &(limit, num) in &[(2, 2), (2, 3), (300, 300), (300, 301)] {
+            let mut io = MemIo::new();
+            io.push_bytes(request_with_headers(num).as_bytes());
+            let m = Stream::<Parser<Limited, MemIo>>::accepted(
+                io.clone(), limit, &mut lp.scope(1)).expect_machine();
+            m.ready(EventSet::readable(), &mut lp.scope(1));
+        }
+        assert_eq!(*lp.ctx(), vec![200, 431, 200, 431]);
+    }
+
+    #[test]
+    fn test_request_line_limit() {
+        let mut lp = MockLoop::new(Default::default());
+        // Request line is checked before it's complete
+        for req in &["GET /0123456789/0123456789 HTTP/1.1\r\n\r\n",
+                     "GET /0123456789/0123456789/0123456789 HTTP/1.1\r\n\r\n",
+                     "GET /0123456789/0123456789/0123456789/0123456789"]
+        {
+            let mut io = MemIo::new();
+            io.push_bytes(req.as_bytes());
+            let m = Stream::<Parser<Limited, MemIo>>::accepted(
+                io.clone(), 10, &mut lp.scope(1)).expect_machine();
+            m.ready(EventSet::readable(), &mut lp.scope(1));
+        }
+        assert_eq!(*lp.ctx(), vec![200, 414, 414]);
+    }
+
+    #[test]
+    fn test_fixed_body_too_large() {
+        let mut lp = MockLoop::new(Default::default());
+        let mut io = MemIo::new();
+        io.push_bytes("POST / HTTP/1.1\r\nContent-Length: 1001\r\n\r\n"
+                      .as_bytes());
+        let m = Stream::<Parser<Limited, MemIo>>::accepted(
+            io.clone(), 10, &mut lp.scope(1)).expect_machine();
+        m.ready(EventSet::readable(), &mut lp.scope(1));
+        assert_eq!(*lp.ctx(), vec![0, 413]);
+    }
+
+    #[test]
+    fn test_handler_stopped_mid_body() {
+        let mut lp = MockLoop::new(Default::default());
+        for &chunked in &[false, true] {
+            let mut io = MemIo::new();
+            // The second request must not be answered: the first response
+            // is incomplete, so the connection is closed
+            io.push_bytes("GET / HTTP/1.1\r\n\r\nGET / HTTP/1.1\r\n\r\n"
+                          .as_bytes());
+            let m = Stream::<Parser<Truncated, MemIo>>::accepted(
+                io.clone(), chunked, &mut lp.scope(1)).expect_machine();
+            assert!(m.ready(EventSet::readable(), &mut lp.scope(1))
+                .is_stopped());
+        }
+        assert_eq!(*lp.ctx(), vec![1, 1]);
+    }
+
+    #[test]
+    fn test_response_flushed() {
+        let mut lp = MockLoop::new(Default::default());
+        let mut io = MemIo::new();
+        io.push_bytes("GET / HTTP/1.1\r\n\r\n".as_bytes());
+        let m = Stream::<Parser<Pieces, MemIo>>::accepted(
+            io.clone(), (), &mut lp.scope(1)).expect_machine();
+        m.ready(EventSet::readable(), &mut lp.scope(1)).expect_machine();
+        assert_eq!(*lp.ctx(), vec![1, 2, 3]);
+    }
+
+    #[test]
+    fn test_unsupported_encoding() {
+        let mut lp = MockLoop::new(Default::default());
+        let mut io = MemIo::new();
+        io.push_bytes("POST / HTTP/1.1\r\nContent-Length: 5\r\n\
+                       Content-Encoding: br\r\n\r\nhello".as_bytes());
+        let m = Stream::<Parser<Limited, MemIo>>::accepted(
+            io.clone(), 10, &mut lp.scope(1)).expect_machine();
+        m.ready(EventSet::readable(), &mut lp.scope(1));
+        assert_eq!(*lp.ctx(), vec![415]);
+    }
+
+    #[test]
+    fn test_identity_encoding() {
+        let mut lp = MockLoop::new(Default::default());
+        let mut io = MemIo::new();
+        io.push_bytes("POST / HTTP/1.1\r\nContent-Length: 5\r\n\
+                       Content-Encoding: identity\r\n\r\nhello".as_bytes());
+        let m = Stream::<Parser<Limited, MemIo>>::accepted(
+            io.clone(), 10, &mut lp.scope(1)).expect_machine();
+        m.ready(EventSet::readable(), &mut lp.scope(1));
+        assert_eq!(*lp.ctx(), vec![200]);
+    }
+
+    #[test]
+    fn test_chunked_not_last() {
+        let mut lp = MockLoop::new(Default::default());
+        let mut io = MemIo::new();
+        io.push_bytes("POST / HTTP/1.1\r\nTransfer-Encoding: gzip\r\n\r\n\
+                       hello".as_bytes());
+        let m = Stream::<Parser<Limited, MemIo>>::accepted(
+            io.clone(), 10, &mut lp.scope(1)).expect_machine();
+        m.ready(EventSet::readable(), &mut lp.scope(1));
+        assert_eq!(*lp.ctx(), vec![400]);
+    }
+
+    #[test]
+    fn test_double_chunked() {
+        let mut lp = MockLoop::new(Default::default());
+        let mut io = MemIo::new();
+        io.push_bytes("POST / HTTP/1.1\r\nTransfer-Encoding: chunked\r\n\
+                       Transfer-Encoding: chunked\r\n\r\n\
+                       0\r\n\r\n".as_bytes());
+        let m = Stream::<Parser<Limited, MemIo>>::accepted(
+            io.clone(), 10, &mut lp.scope(1)).expect_machine();
+        m.ready(EventSet::readable(), &mut lp.scope(1));
+        assert_eq!(*lp.ctx(), vec![400]);
+    }
+
+    #[test]
+    fn test_unknown_transfer_coding() {
+        let mut lp = MockLoop::new(Default::default());
+        let mut io = MemIo::new();
+        io.push_bytes("POST / HTTP/1.1\r\n\
+                       Transfer-Encoding: foo, chunked\r\n\r\n\
+                       0\r\n\r\n".as_bytes());
+        let m = Stream::<Parser<Limited, MemIo>>::accepted(
+            io.clone(), 10, &mut lp.scope(1)).expect_machine();
+        m.ready(EventSet::readable(), &mut lp.scope(1));
+        assert_eq!(*lp.ctx(), vec![501]);
+    }
+
+    #[test]
+    fn test_fixed_body_expect_continue() {
+        let mut lp = MockLoop::new(Default::default());
+        let mut io = MemIo::new();
+        io.push_bytes("POST / HTTP/1.1\r\nContent-Length: 5000000000\r\n\
+                       Expect: 100-continue\r\n\r\n".as_bytes());
+        let m = Stream::<Parser<Limited, MemIo>>::accepted(
+            io.clone(), 10, &mut lp.scope(1)).expect_machine();
+        m.ready(EventSet::readable(), &mut lp.scope(1));
+        assert_eq!(*lp.ctx(), vec![0, 413]);
+    }
+
+    #[test]
+    fn test_fixed_body_at_limit() {
+        let mut lp = MockLoop::new(Default::default());
+        let mut io = MemIo::new();
+        io.push_bytes("POST / HTTP/1.1\r\nContent-Length: 1000\r\n\r\n"
+                      .as_bytes());
+        io.push_bytes(&[b'x'; 1000]);
+        let m = Stream::<Parser<Limited, MemIo>>::accepted(
+            io.clone(), 10, &mut lp.scope(1)).expect_machine();
+        m.ready(EventSet::readable(), &mut lp.scope(1));
+        assert_eq!(*lp.ctx(), vec![200]);
+    }
+
     #[cfg(feature="nightly")]
     #[bench]
     fn bench_parse1(b: &mut Bencher) {
@@ -1,11 +1,15 @@
 use std::time::Duration;
 
+use httparse::Header;
 use rotor::{Scope, Time};
+use rotor_stream::{Exception, Expectation, StreamSocket, Transport};
 
 use recvmode::RecvMode;
 use super::error::HttpError;
 use super::request::Head;
 use super::Response;
+use super::http2::Settings;
+use super::Limits;
 
 
 /// A handler of server-side HTTP
@@ -23,6 +27,11 @@ pub trait Server: Sized {
     /// we need to read request body by chunk. It's recommended to return
     /// Buffered up to certain size, or at least for zero-length requests.
     ///
+    /// If `Content-Length` of the request is larger than the `Buffered`
+    /// limit, the request is rejected right away: `bad_request()` is called
+    /// and `413 Payload Too Large` is sent (without `100 Continue`) unless
+    /// you've already started the response.
+    ///
     /// You may start building a response right here, or wait for
     /// the next event.
     ///
@@ -32,9 +41,15 @@ pub trait Server: Sized {
     /// normal response code is good enough for browser. (Are there ugly
     /// proxies that propagate 100 Expect but does buffer response headers?)
     ///
+    /// If `head.body_kind` is `BodyKind::Upgrade` you may accept the upgrade
+    /// by writing a complete `101 Switching Protocols` response here
+    /// (`status()`, headers, `done_headers()`). In this case the returned
+    /// mode is ignored and the connection is handed over to the
+    /// `upgrade_started()` handler.
+    ///
     /// Note that `head` is passed here once, and forgotten by the
     /// protocol. If you need it later it's your responsibility to store it
-    /// somewhere.
+    /// somewhere, `head.to_owned()` makes a copy which can be stored.
     fn headers_received(seed: Self::Seed, head: Head, response: &mut Response,
         scope: &mut Scope<Self::Context>)
         -> Option<(Self, RecvMode, Time)>;
@@ -89,6 +104,21 @@ pub trait Server: Sized {
         scope: &mut Scope<Self::Context>)
         -> Option<Self>;
 
+    /// Trailer fields of the request are received
+    ///
+    /// Only called for chunked (or HTTP/2) requests which have non-empty
+    /// trailer section, right before `request_received()` or
+    /// `request_end()`. Trailer section is limited the same way as request
+    /// headers (see `limits()`).
+    ///
+    /// Default implementation ignores trailers.
+    fn request_trailers(self, _trailers: &[Header],
+        _response: &mut Response, _scope: &mut Scope<Self::Context>)
+        -> Option<Self>
+    {
+        Some(self)
+    }
+
     /// Request timeout occurred
     ///
     /// This is only called if headers are already received but state machine
@@ -108,6 +138,21 @@ pub trait Server: Sized {
     fn wakeup(self, response: &mut Response, scope: &mut Scope<Self::Context>)
         -> Option<Self>;
 
+    /// Output buffer is drained after `Response::wait_flush()`
+    ///
+    /// Write the next piece of the body here and call `wait_flush()` again
+    /// if there is more, or finish the response. The deadline returned
+    /// from `headers_received()` (or `timeout()`) still applies.
+    ///
+    /// Default implementation does nothing, it's only called if you
+    /// request it.
+    fn response_flushed(self, _response: &mut Response,
+        _scope: &mut Scope<Self::Context>)
+        -> Option<Self>
+    {
+        Some(self)
+    }
+
     /// A bad request occured
     ///
     /// You should send a complete response in this handler.
@@ -139,6 +184,52 @@ pub trait Server: Sized {
         response.done();
     }
 
+    /// Whether to decode request bodies with `Content-Encoding`
+    ///
+    /// When enabled, the body is inflated before it's passed to
+    /// `request_received()` or `request_chunk()`, and the `Buffered` limit
+    /// applies to the decoded size (in the `Progressive` mode each decoded
+    /// piece is limited by `Limits::max_decoded_chunk`). The
+    /// `Content-Encoding` header is still present in the `head`.
+    ///
+    /// Only `gzip` and `deflate` are supported, and only with the
+    /// `compression` feature. Requests with other codings are replied with
+    /// `415 Unsupported Media Type`.
+    ///
+    /// Transfer codings (e.g. `Transfer-Encoding: gzip, chunked`) are
+    /// decoded regardless of this setting.
+    ///
+    /// Default is `false`, the body is passed as is.
+    fn decode_request_body(_seed: &Self::Seed,
+        _scope: &mut Scope<Self::Context>)
+        -> bool
+    {
+        false
+    }
+    /// Whether to add the `Date` header to responses
+    ///
+    /// The header is added by `done_headers()` unless you've added `Date`
+    /// yourself. The value is formatted at most once per second and is
+    /// cached per thread (i.e. per event loop).
+    ///
+    /// Default is `true`, as RFC 7231 requires the header for the origin
+    /// servers which have a clock.
+    fn date_header(_seed: &Self::Seed, _scope: &mut Scope<Self::Context>)
+        -> bool
+    {
+        true
+    }
+    /// The value of the `Server` header added to responses
+    ///
+    /// The header is written right after the status line, so don't add it
+    /// in the handler when it's set here.
+    ///
+    /// Default is `None` (no header)
+    fn server_name(_seed: &Self::Seed, _scope: &mut Scope<Self::Context>)
+        -> Option<&'static str>
+    {
+        None
+    }
     /// A timeout for idle keep-alive connection
     ///
     /// Default is 120 seconds
@@ -165,4 +256,105 @@ pub trait Server: Sized {
     {
         return Duration::new(3600, 0);
     }
+    /// Limits on the size of request headers
+    ///
+    /// Called for each request before parsing headers, so it may depend on
+    /// the seed (i.e. on the listening socket). Requests exceeding limits
+    /// are replied with an error page.
+    ///
+    /// Default is `Limits::default()`
+    fn limits(_seed: &Self::Seed, _scope: &mut Scope<Self::Context>)
+        -> Limits
+    {
+        Limits::default()
+    }
+    /// Settings for HTTP/2 connections
+    ///
+    /// Returning `Some` enables HTTP/2, both the connection preface
+    /// ("prior knowledge") and the `Upgrade: h2c` request are accepted.
+    ///
+    /// By default HTTP/2 is disabled, so the preface and the upgrade are
+    /// treated as plain HTTP/1 requests. Reasonable settings are:
+    ///
+    /// ```ignore
+    /// Some(Settings {
+    ///     max_concurrent_streams: Some(100),
+    ///     max_header_list_size: Some(limits.max_headers_size as u32),
+    ///     .. Settings::default()
+    /// })
+    /// ```
+    fn http2_settings(_seed: &Self::Seed, _scope: &mut Scope<Self::Context>)
+        -> Option<Settings>
+    {
+        None
+    }
+
+    /// Connection is switched to another protocol
+    ///
+    /// Called right after `headers_received()` has written the
+    /// `101 Switching Protocols` response. From now on the connection is
+    /// not HTTP any more and is driven by the `upgrade_*` handlers, which
+    /// receive raw `transport`: everything the client has sent after the
+    /// request headers is in the input buffer, and anything you put into
+    /// the output buffer is sent as is (the response itself is already
+    /// there).
+    ///
+    /// Returns the new state, the expectation for the next event and a
+    /// deadline for it, much like `rotor_stream::Protocol` does. Returning
+    /// `None` closes the connection.
+    ///
+    /// Default implementation closes the connection, you only need to
+    /// implement `upgrade_*` handlers if you ever send `101` responses.
+    fn upgrade_started<S: StreamSocket>(self, _transport: &mut Transport<S>,
+        _scope: &mut Scope<Self::Context>)
+        -> Option<(Self, Expectation, Time)>
+    {
+        None
+    }
+
+    /// Expected number of bytes (or delimiter) received on upgraded
+    /// connection
+    ///
+    /// The `end` is the same as in `rotor_stream::Protocol::bytes_read`
+    fn upgrade_bytes_read<S: StreamSocket>(self,
+        _transport: &mut Transport<S>, _end: usize,
+        _scope: &mut Scope<Self::Context>)
+        -> Option<(Self, Expectation, Time)>
+    {
+        None
+    }
+
+    /// Output buffer is flushed on upgraded connection
+    ///
+    /// Only called if `Expectation::Flush` is returned from one of
+    /// `upgrade_*` handlers
+    fn upgrade_bytes_flushed<S: StreamSocket>(self,
+        _transport: &mut Transport<S>, _scope: &mut Scope<Self::Context>)
+        -> Option<(Self, Expectation, Time)>
+    {
+        None
+    }
+
+    /// Deadline returned from one of `upgrade_*` handlers is reached
+    fn upgrade_timeout<S: StreamSocket>(self, _transport: &mut Transport<S>,
+        _scope: &mut Scope<Self::Context>)
+        -> Option<(Self, Expectation, Time)>
+    {
+        None
+    }
+
+    /// Standard rotor's wakeup handler for upgraded connection
+    fn upgrade_wakeup<S: StreamSocket>(self, _transport: &mut Transport<S>,
+        _scope: &mut Scope<Self::Context>)
+        -> Option<(Self, Expectation, Time)>
+    {
+        None
+    }
+
+    /// Upgraded connection is closed because of an error or end of stream
+    ///
+    /// The state machine is dropped after the call.
+    fn upgrade_end<S: StreamSocket>(self, _transport: &mut Transport<S>,
+        _reason: Exception, _scope: &mut Scope<Self::Context>)
+    {}
 }
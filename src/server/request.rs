@@ -1,7 +1,13 @@
+use std::borrow::Cow;
 use std::net::SocketAddr;
 use httparse;
 
+use headers::{Fields, Values, List, MediaType, MediaTypes};
+use headers::{Authorization, HeaderMap, HeaderLookup};
 use super::body::BodyKind;
+use super::cookie::Cookies;
+use super::target::{Target, QueryPairs, percent_decode};
+use tls::TlsInfo;
 use version::Version;
 
 
@@ -16,6 +22,8 @@ pub struct Head<'a> {
     /// The client IP and port. If the connection is not using a standard
     /// TCP-IP connection this field will be `None`.
     pub client: Option<SocketAddr>,
+    /// The TLS session info, `None` for plain-text connections.
+    pub tls: Option<&'a TlsInfo>,
     /// The HTTP protocol version.
     pub version: Version,
     /// The HTTP method. It is restricted to token chars.
@@ -23,11 +31,208 @@ pub struct Head<'a> {
     /// The HTTP scheme is  a sequence of characters beginning with a
     /// letter and followed by any combination of letters, digits, plus,
     /// period or hyphen.
+    ///
+    /// Taken from the absolute-form request target if there is one,
+    /// otherwise it's `http` or `https` depending on the connection.
     pub scheme: &'a str,
-    /// The path points to a specific resource.
+    /// The raw request target, usually the path and the query
+    ///
+    /// Use `target()`, `decoded_path()` and `query_pairs()` to get its
+    /// parts.
     pub path: &'a str,
     /// A slice of HTTP headers.
     pub headers: &'a [httparse::Header<'a>],
     /// The body kind is either fixed, chunked or upgrade.
     pub body_kind: BodyKind,
 }
+
+/// An owned copy of the request head
+///
+/// Returned by `Head::to_owned()`, so you can store it in the state
+/// machine. All headers are kept in a single buffer and are accessed with
+/// the same methods as in `Head`.
+#[derive(Debug, Clone)]
+pub struct OwnedHead {
+    pub client: Option<SocketAddr>,
+    pub tls: Option<TlsInfo>,
+    pub version: Version,
+    pub method: String,
+    pub scheme: String,
+    pub path: String,
+    pub headers: HeaderMap,
+    pub body_kind: BodyKind,
+}
+
+impl<'a> Head<'a> {
+    /// Returns the value of the first header with the name
+    ///
+    /// Header names are case-insensitive in all of the methods, these are
+    /// the same as in `HeaderLookup`.
+    pub fn get(&self, name: &str) -> Option<&'a [u8]> {
+        HeaderLookup::get(self, name)
+    }
+    /// Iterates over values of all headers with the name
+    pub fn get_all<'n>(&self, name: &'n str) -> Values<'a, 'n> {
+        HeaderLookup::get_all(self, name)
+    }
+    /// Returns true if there is a header with the name
+    pub fn contains(&self, name: &str) -> bool {
+        HeaderLookup::contains(self, name)
+    }
+    /// Iterates over elements of comma-separated lists of all headers with
+    /// the name
+    pub fn get_list<'n>(&self, name: &'n str) -> List<'a, 'n> {
+        HeaderLookup::get_list(self, name)
+    }
+    /// The value of the `Host` header
+    pub fn host(&self) -> Option<&'a str> {
+        HeaderLookup::host(self)
+    }
+    /// The value of the `Content-Type` header
+    pub fn content_type(&self) -> Option<MediaType<'a>> {
+        HeaderLookup::content_type(self)
+    }
+    /// The value of the `User-Agent` header
+    pub fn user_agent(&self) -> Option<&'a str> {
+        HeaderLookup::user_agent(self)
+    }
+    /// Iterates over media ranges of all `Accept` headers
+    pub fn accept(&self) -> MediaTypes<'a> {
+        HeaderLookup::accept(self)
+    }
+    /// The value of the `Authorization` header
+    pub fn authorization(&self) -> Option<Authorization<'a>> {
+        HeaderLookup::authorization(self)
+    }
+    /// Iterates over `(name, value)` pairs of all `Cookie` headers
+    pub fn cookies(&self) -> Cookies<'a> {
+        Cookies::new(self.get_all("Cookie"))
+    }
+    /// Parses the request target
+    pub fn target(&self) -> Target<'a> {
+        Target::parse(self.path)
+    }
+    /// The host (and port) of the request
+    ///
+    /// Taken from the absolute-form or authority-form request target,
+    /// otherwise from the `Host` header (RFC 7230, section 5.4). For HTTP/2
+    /// the `Host` header is synthesized from `:authority` when absent.
+    pub fn authority(&self) -> Option<&'a str> {
+        match self.target() {
+            Target::Absolute { authority, .. } => Some(authority),
+            Target::Authority(authority) => Some(authority),
+            Target::Origin { .. } | Target::Asterisk => self.host(),
+        }
+    }
+    /// The percent-decoded path of the request target
+    ///
+    /// Note that encoded slashes (`%2F`) are indistinguishable from the
+    /// real ones in the decoded path.
+    pub fn decoded_path(&self) -> Cow<'a, str> {
+        percent_decode(self.target().path(), false)
+    }
+    /// The raw query string (without the question mark)
+    pub fn query(&self) -> Option<&'a str> {
+        self.target().query()
+    }
+    /// Iterates over the percent-decoded `key=value` pairs of the query
+    pub fn query_pairs(&self) -> QueryPairs<'a> {
+        QueryPairs::new(self.query())
+    }
+    /// Copies the head, so it can be stored in the state machine
+    pub fn to_owned(&self) -> OwnedHead {
+        OwnedHead {
+            client: self.client,
+            tls: self.tls.cloned(),
+            version: self.version,
+            method: self.method.to_string(),
+            scheme: self.scheme.to_string(),
+            path: self.path.to_string(),
+            headers: HeaderMap::new(self.headers),
+            body_kind: self.body_kind,
+        }
+    }
+}
+
+impl<'a, 'b> HeaderLookup<'a> for &'b Head<'a> {
+    fn fields(self) -> Fields<'a> {
+        Fields::new(self.headers)
+    }
+}
+
+impl<'a> HeaderLookup<'a> for &'a OwnedHead {
+    fn fields(self) -> Fields<'a> {
+        self.headers.iter()
+    }
+}
+
+impl OwnedHead {
+    /// Returns the value of the first header with the name
+    ///
+    /// Header names are case-insensitive in all of the methods, these are
+    /// the same as in `HeaderLookup`.
+    pub fn get(&self, name: &str) -> Option<&[u8]> {
+        HeaderLookup::get(self, name)
+    }
+    /// Iterates over values of all headers with the name
+    pub fn get_all<'a, 'n>(&'a self, name: &'n str) -> Values<'a, 'n> {
+        HeaderLookup::get_all(self, name)
+    }
+    /// Returns true if there is a header with the name
+    pub fn contains(&self, name: &str) -> bool {
+        HeaderLookup::contains(self, name)
+    }
+    /// Iterates over elements of comma-separated lists of all headers with
+    /// the name
+    pub fn get_list<'a, 'n>(&'a self, name: &'n str) -> List<'a, 'n> {
+        HeaderLookup::get_list(self, name)
+    }
+    /// The value of the `Host` header
+    pub fn host(&self) -> Option<&str> {
+        HeaderLookup::host(self)
+    }
+    /// The value of the `Content-Type` header
+    pub fn content_type(&self) -> Option<MediaType> {
+        HeaderLookup::content_type(self)
+    }
+    /// The value of the `User-Agent` header
+    pub fn user_agent(&self) -> Option<&str> {
+        HeaderLookup::user_agent(self)
+    }
+    /// Iterates over media ranges of all `Accept` headers
+    pub fn accept(&self) -> MediaTypes {
+        HeaderLookup::accept(self)
+    }
+    /// The value of the `Authorization` header
+    pub fn authorization(&self) -> Option<Authorization> {
+        HeaderLookup::authorization(self)
+    }
+    /// Iterates over `(name, value)` pairs of all `Cookie` headers
+    pub fn cookies(&self) -> Cookies {
+        Cookies::new(self.get_all("Cookie"))
+    }
+    /// Parses the request target
+    pub fn target(&self) -> Target {
+        Target::parse(&self.path)
+    }
+    /// The host (and port) of the request, see `Head::authority()`
+    pub fn authority(&self) -> Option<&str> {
+        match self.target() {
+            Target::Absolute { authority, .. } => Some(authority),
+            Target::Authority(authority) => Some(authority),
+            Target::Origin { .. } | Target::Asterisk => self.host(),
+        }
+    }
+    /// The percent-decoded path of the request target
+    pub fn decoded_path(&self) -> Cow<str> {
+        percent_decode(self.target().path(), false)
+    }
+    /// The raw query string (without the question mark)
+    pub fn query(&self) -> Option<&str> {
+        self.target().query()
+    }
+    /// Iterates over the percent-decoded `key=value` pairs of the query
+    pub fn query_pairs(&self) -> QueryPairs {
+        QueryPairs::new(self.query())
+    }
+}
@@ -1,7 +1,9 @@
+use rotor::Scope;
 use rotor_stream::Buf;
 
 use message::{MessageState, Message, HeaderError};
 use version::Version;
+use super::Server;
 
 
 /// This response is returned when Response is dropping without writing
@@ -22,27 +24,25 @@ pub const NOT_IMPLEMENTED_HEAD: &'static str = concat!(
     "\r\n",
     );
 
-pub struct Response<'a>(Message<'a>);
+// The second field is the size requested by `wait_flush()`
+pub struct Response<'a>(Message<'a>, Option<usize>);
 
 impl<'a> From<Message<'a>> for Response<'a> {
     fn from(msg: Message) -> Response {
-        Response(msg)
+        Response(msg, None)
     }
 }
 
 impl<'a> Response<'a> {
     /// Creates new response message by extracting needed fields from Head
+    ///
+    /// The response has no automatic `Date` and `Server` headers, the
+    /// protocol parser uses `Server::date_header()` and
+    /// `Server::server_name()` to set them up.
     pub fn new(out_buf: &mut Buf, version: Version,
         is_head: bool, do_close: bool) -> Response
     {
-        use message::Body::*;
-        // TODO(tailhook) implement Connection: Close,
-        // (including explicit one in HTTP/1.0) and maybe others
-        MessageState::ResponseStart {
-            body: if is_head { Ignored } else { Normal },
-            version: version,
-            close: do_close || version == Version::Http10,
-        }.with(out_buf)
+        new_with_headers(out_buf, version, is_head, do_close, false, None)
     }
     /// Returns true if it's okay too proceed with keep-alive connection
     pub fn finish(self) -> bool {
@@ -91,6 +91,10 @@ impl<'a> Response<'a> {
     /// When status line is already written. It's expected that your request
     /// handler state machine will never call the method twice.
     ///
+    /// When status is 101 and the request doesn't ask for an upgrade
+    /// (`head.body_kind` is not `BodyKind::Upgrade`), in particular for
+    /// any HTTP/2 request.
+    ///
     /// When status is 100x
     pub fn status(&mut self, code: u16, reason: &str) {
         self.0.response_status(code, reason)
@@ -107,6 +111,10 @@ impl<'a> Response<'a> {
     /// Note that there is currently no way to use a transfer encoding other
     /// than chunked.
     ///
+    /// The `Trailer` header announces fields which are sent with
+    /// `add_trailer()` after the body, the fields listed there are checked
+    /// to be allowed in trailers.
+    ///
     /// We return Result here to make implementing proxies easier. In the
     /// application handler it's okay to unwrap the result and to get
     /// a meaningful panic (that is basically an assertion).
@@ -193,11 +201,54 @@ impl<'a> Response<'a> {
     pub fn write_body(&mut self, data: &[u8]) {
         self.0.write_body(data)
     }
+    /// Add a trailer field after the chunked body
+    ///
+    /// The first call terminates the body with the last chunk, so no more
+    /// `write_body()` calls are allowed afterwards. The trailer section is
+    /// finished by `done()`. Fields should be announced in the `Trailer`
+    /// header beforehand.
+    ///
+    /// Fields needed for framing, routing or authentication (e.g.
+    /// `Content-Length`, `Host`, `Authorization`) are rejected with
+    /// `ForbiddenTrailer`. When the body is not chunked,
+    /// `TrailersWithoutChunked` is returned, except for the ignored body
+    /// (HEAD request or 304 response) where the trailer is just skipped.
+    ///
+    /// # Panics
+    ///
+    /// When message is in the wrong state.
+    pub fn add_trailer(&mut self, name: &str, value: &[u8])
+        -> Result<(), HeaderError>
+    {
+        self.0.add_trailer(name, value)
+    }
+    /// Writes the trailer fields and finishes the message
+    ///
+    /// All the fields are validated before anything is written, so on
+    /// error the message is left intact. See `add_trailer()` for details.
+    ///
+    /// # Panics
+    ///
+    /// When message is in the wrong state or when Content-Length bytes
+    /// are not written yet
+    pub fn done_with_trailers(&mut self, trailers: &[(&str, &[u8])])
+        -> Result<(), HeaderError>
+    {
+        self.0.done_with_trailers(trailers)
+    }
     /// Returns true if `done()` method is already called and everything
     /// was okay.
     pub fn is_complete(&self) -> bool {
         self.0.is_complete()
     }
+    /// Returns true if `101 Switching Protocols` response has been sent
+    ///
+    /// When this is true after `headers_received()` the connection is
+    /// switched to the protocol requested in the `Upgrade` header and is
+    /// handled by `upgrade_*` methods of the `Server`.
+    pub fn is_upgraded(&self) -> bool {
+        self.0.is_upgraded()
+    }
     /// Writes needed final finalization data into the buffer and asserts
     /// that response is in the appropriate state for that.
     ///
@@ -210,8 +261,66 @@ impl<'a> Response<'a> {
     pub fn done(&mut self) {
         self.0.done()
     }
+    /// Requests a `Server::response_flushed()` call when the output buffer
+    /// is drained to `max_buffered` bytes
+    ///
+    /// This allows to stream a large body by pieces, writing the next one
+    /// only when the client has received enough of the previous ones. The
+    /// request is valid until the next event, so call it again in
+    /// `response_flushed()` if there is more data. Other events (e.g.
+    /// `wakeup()` and `timeout()`) are delivered as usual in the meantime.
+    ///
+    /// Only works after the request is received (i.e. in
+    /// `request_received()`, `request_end()` and later), for HTTP/2 the
+    /// size limits the data of the stream which is not sent yet because
+    /// of flow control.
+    pub fn wait_flush(&mut self, max_buffered: usize) {
+        self.1 = Some(max_buffered);
+    }
 }
 
 pub fn state(resp: Response) -> MessageState {
     resp.0.state()
 }
+
+/// Adds `Connection: close` unless the headers are already sent
+pub fn close_connection(resp: &mut Response) {
+    resp.0.close_connection()
+}
+
+/// Allows `101 Switching Protocols`, the request asks for an upgrade
+pub fn allow_upgrade(resp: &mut Response) {
+    resp.0.allow_upgrade()
+}
+
+/// The size requested by `Response::wait_flush()`
+pub fn flush_wait(resp: &Response) -> Option<usize> {
+    resp.1
+}
+
+fn new_with_headers<'a>(out_buf: &'a mut Buf, version: Version,
+    is_head: bool, do_close: bool, date: bool, server: Option<&'static str>)
+    -> Response<'a>
+{
+    use message::Body::*;
+    // TODO(tailhook) implement Connection: Close,
+    // (including explicit one in HTTP/1.0) and maybe others
+    MessageState::ResponseStart {
+        body: if is_head { Ignored } else { Normal },
+        version: version,
+        close: do_close || version == Version::Http10,
+        date: date,
+        server: server,
+        upgrade: false,
+    }.with(out_buf)
+}
+
+/// Creates a response with `Date` and `Server` headers set up by `M`
+pub fn new_response<'a, M: Server>(out_buf: &'a mut Buf, version: Version,
+    is_head: bool, do_close: bool, seed: &M::Seed,
+    scope: &mut Scope<M::Context>)
+    -> Response<'a>
+{
+    new_with_headers(out_buf, version, is_head, do_close,
+        M::date_header(seed, scope), M::server_name(seed, scope))
+}
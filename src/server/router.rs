@@ -0,0 +1,721 @@
+//! Routing requests by method and path
+//!
+//! Routes are registered in `Routes` with a method and a path pattern.
+//! Each route carries a seed for the handler, which is a usual `Server`
+//! state machine with `Seed = Route<S>`, so the handler gets both the seed
+//! and the parameters captured from the path:
+//!
+//! ```ignore
+//! let mut routes = Routes::new(Page::Index);
+//! routes.get("/", Page::Index)
+//!       .get("/users/:id", Page::User)
+//!       .post("/users/:id/avatar", Page::Avatar)
+//!       .get("/static/*path", Page::Static);
+//! let routes = Arc::new(routes);
+//! loop_inst.add_machine_with(|scope| {
+//!     Fsm::<Router<Handler>, _>::new(lst, routes, scope)
+//! }).unwrap();
+//! ```
+//!
+//! Pattern segments are either static strings, `:name` which matches any
+//! non-empty segment, or `*name` (must be the last one) which matches the
+//! rest of the path. Static segments take precedence over parameters and
+//! parameters over wildcards, regardless of the order of registration.
+//! `HEAD` requests are routed to `GET` handlers unless there is a `HEAD`
+//! route. The query string is ignored (as well as the scheme and the
+//! authority of the absolute-form target) and parameters are not
+//! percent-decoded.
+//!
+//! Requests which match no route are replied with `404 Not Found` (by the
+//! `emit_error_page()` of the handler), and requests which match the path
+//! but not the method are replied with `405 Method Not Allowed` with the
+//! `Allow` header listing the methods of the path. Request bodies up to
+//! `DRAIN_LIMIT` are read and ignored to keep the connection alive, the
+//! connection is closed after the error response if the body is larger.
+use std::error::Error;
+use std::sync::Arc;
+use std::slice::Iter;
+use std::time::Duration;
+
+use httparse::Header;
+use rotor::{Scope, Time};
+use rotor_stream::{Exception, Expectation, StreamSocket, Transport};
+
+use recvmode::RecvMode;
+use super::error::HttpError;
+use super::http2::Settings;
+use super::response::close_connection;
+use super::{Head, Limits, Response, Server, BodyKind};
+
+
+/// The maximum size of the request body skipped for the error response
+pub const DRAIN_LIMIT: usize = 65536;
+
+
+quick_error!{
+    /// The request doesn't match any route
+    #[derive(Debug)]
+    pub enum RoutingError {
+        NotFound {
+            description("no route matches the path")
+        }
+        /// The path matches, but the method doesn't, contains the value
+        /// for the `Allow` header
+        MethodNotAllowed(allow: String) {
+            description("the method is not allowed for the path")
+            display(me) -> ("{} (allowed: {})", me.description(), allow)
+        }
+    }
+}
+
+impl HttpError for RoutingError {
+    fn http_status(&self) -> (u16, &'static str) {
+        use self::RoutingError::*;
+        match *self {
+            NotFound => (404, "Not Found"),
+            MethodNotAllowed(_) => (405, "Method Not Allowed"),
+        }
+    }
+}
+
+/// Parameters captured from the path by `:name` and `*name` segments
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Params(Vec<(String, String)>);
+
+impl Params {
+    /// Returns the value of the parameter
+    pub fn get(&self, name: &str) -> Option<&str> {
+        self.0.iter().find(|&&(ref n, _)| n == name).map(|&(_, ref v)| &v[..])
+    }
+    /// Iterates over `(name, value)` pairs in the order of the pattern
+    pub fn iter(&self) -> Iter<(String, String)> {
+        self.0.iter()
+    }
+}
+
+/// The seed of the route handler
+#[derive(Debug, Clone)]
+pub struct Route<S> {
+    /// The seed registered for the route (or the default one)
+    pub seed: S,
+    /// Parameters captured from the path (empty for the default seed)
+    pub params: Params,
+}
+
+struct Handler<S> {
+    method: String,
+    /// Names of the parameters, empty for the unnamed wildcard
+    names: Vec<String>,
+    seed: S,
+}
+
+struct Node<S> {
+    children: Vec<(String, Node<S>)>,
+    param: Option<Box<Node<S>>>,
+    /// Routes which end at this node
+    handlers: Vec<Handler<S>>,
+    /// Routes which end with a wildcard at this node
+    wildcard: Vec<Handler<S>>,
+}
+
+/// The routing table, a seed of the `Router`
+///
+/// Patterns are compiled into a prefix tree of path segments, so lookup
+/// doesn't depend on the number of routes.
+pub struct Routes<S> {
+    root: Node<S>,
+    default: Route<S>,
+}
+
+/// The `Server` which dispatches requests to the `H` by the `Routes`
+///
+/// The `default` seed of the routes is used for everything which is not
+/// bound to a request: error pages of bad requests, timeouts and limits.
+pub struct Router<H>(Option<H>);
+
+impl<S> Node<S> {
+    fn new() -> Node<S> {
+        Node {
+            children: Vec::new(),
+            param: None,
+            handlers: Vec::new(),
+            wildcard: Vec::new(),
+        }
+    }
+    fn find<'a, 'p>(&'a self, rest: Option<&'p str>, method: &str,
+        values: &mut Vec<&'p str>, allow: &mut Vec<&'a str>)
+        -> Option<&'a Handler<S>>
+    {
+        let rest = match rest {
+            Some(rest) => rest,
+            None => return select(&self.handlers, method, allow),
+        };
+        let (segment, next) = match rest.find('/') {
+            Some(idx) => (&rest[..idx], Some(&rest[idx+1..])),
+            None => (rest, None),
+        };
+        for &(ref name, ref child) in &self.children {
+            if name == segment {
+                if let Some(h) = child.find(next, method, values, allow) {
+                    return Some(h);
+                }
+            }
+        }
+        if let Some(ref child) = self.param {
+            if segment.len() > 0 {
+                values.push(segment);
+                if let Some(h) = child.find(next, method, values, allow) {
+                    return Some(h);
+                }
+                values.pop();
+            }
+        }
+        if self.wildcard.len() > 0 {
+            values.push(rest);
+            if let Some(h) = select(&self.wildcard, method, allow) {
+                return Some(h);
+            }
+            values.pop();
+        }
+        None
+    }
+}
+
+fn select<'a, S>(handlers: &'a [Handler<S>], method: &str,
+    allow: &mut Vec<&'a str>)
+    -> Option<&'a Handler<S>>
+{
+    for h in handlers {
+        if !allow.contains(&&h.method[..]) {
+            allow.push(&h.method);
+        }
+    }
+    handlers.iter().find(|h| h.method == method)
+    .or_else(|| if method == "HEAD" {
+        handlers.iter().find(|h| h.method == "GET")
+    } else {
+        None
+    })
+}
+
+impl<S: Clone> Routes<S> {
+    /// Creates an empty routing table
+    ///
+    /// The `default` seed is passed to the handler's static methods
+    /// (`emit_error_page()`, `limits()`, ...) when there is no route.
+    pub fn new(default: S) -> Routes<S> {
+        Routes {
+            root: Node::new(),
+            default: Route { seed: default, params: Params::default() },
+        }
+    }
+    /// Adds a route
+    ///
+    /// # Panics
+    ///
+    /// When the pattern doesn't start with a slash, has an unnamed
+    /// parameter or a wildcard which is not the last segment, or when the
+    /// same route is already added.
+    pub fn add(&mut self, method: &str, pattern: &str, seed: S)
+        -> &mut Routes<S>
+    {
+        assert!(pattern.starts_with('/'),
+            "pattern {:?} must start with a slash", pattern);
+        let mut node = &mut self.root;
+        let mut names = Vec::new();
+        let mut segments = pattern[1..].split('/').peekable();
+        while let Some(segment) = segments.next() {
+            if segment.starts_with(':') {
+                assert!(segment.len() > 1,
+                    "parameter must be named in {:?}", pattern);
+                names.push(segment[1..].to_string());
+                let tmp = node;
+                if tmp.param.is_none() {
+                    tmp.param = Some(Box::new(Node::new()));
+                }
+                node = &mut **tmp.param.as_mut().unwrap();
+            } else if segment.starts_with('*') {
+                assert!(segments.peek().is_none(),
+                    "wildcard must be the last segment in {:?}", pattern);
+                names.push(segment[1..].to_string());
+                assert!(!node.wildcard.iter().any(|h| h.method == method),
+                    "duplicate route {} {:?}", method, pattern);
+                node.wildcard.push(Handler {
+                    method: method.to_string(),
+                    names: names,
+                    seed: seed,
+                });
+                return self;
+            } else {
+                let tmp = node;
+                let idx = match tmp.children.iter()
+                    .position(|&(ref name, _)| name == segment)
+                {
+                    Some(idx) => idx,
+                    None => {
+                        tmp.children.push((segment.to_string(), Node::new()));
+                        tmp.children.len() - 1
+                    }
+                };
+                node = &mut tmp.children[idx].1;
+            }
+        }
+        assert!(!node.handlers.iter().any(|h| h.method == method),
+            "duplicate route {} {:?}", method, pattern);
+        node.handlers.push(Handler {
+            method: method.to_string(),
+            names: names,
+            seed: seed,
+        });
+        self
+    }
+    /// Adds a route for the `GET` method (also serves `HEAD`)
+    pub fn get(&mut self, pattern: &str, seed: S) -> &mut Routes<S> {
+        self.add("GET", pattern, seed)
+    }
+    /// Adds a route for the `POST` method
+    pub fn post(&mut self, pattern: &str, seed: S) -> &mut Routes<S> {
+        self.add("POST", pattern, seed)
+    }
+    /// Adds a route for the `PUT` method
+    pub fn put(&mut self, pattern: &str, seed: S) -> &mut Routes<S> {
+        self.add("PUT", pattern, seed)
+    }
+    /// Adds a route for the `DELETE` method
+    pub fn delete(&mut self, pattern: &str, seed: S) -> &mut Routes<S> {
+        self.add("DELETE", pattern, seed)
+    }
+    /// Finds the route for the request
+    ///
+    /// The `path` is a request target, the query string is stripped.
+    pub fn find(&self, method: &str, path: &str)
+        -> Result<Route<S>, RoutingError>
+    {
+        let path = match path.find('?') {
+            Some(idx) => &path[..idx],
+            None => path,
+        };
+        if !path.starts_with('/') {
+            return Err(RoutingError::NotFound);
+        }
+        let mut values = Vec::new();
+        let mut allow = Vec::new();
+        match self.root.find(Some(&path[1..]), method,
+                             &mut values, &mut allow)
+        {
+            Some(h) => Ok(Route {
+                seed: h.seed.clone(),
+                params: Params(h.names.iter().zip(values)
+                    .filter(|&(name, _)| name.len() > 0)
+                    .map(|(name, value)| (name.clone(), value.to_string()))
+                    .collect()),
+            }),
+            None if allow.len() == 0 => Err(RoutingError::NotFound),
+            None => {
+                if allow.contains(&"GET") && !allow.contains(&"HEAD") {
+                    allow.push("HEAD");
+                }
+                Err(RoutingError::MethodNotAllowed(allow.join(", ")))
+            }
+        }
+    }
+    /// The route which is used when the request matches no route
+    pub fn default_route(&self) -> &Route<S> {
+        &self.default
+    }
+}
+
+/// Sends the `405 Method Not Allowed` page with the `Allow` header
+fn method_not_allowed(allow: &str, response: &mut Response) {
+    let data = "<h1>405 Method Not Allowed</h1>\n\
+        <p><small>Served for you by rotor-http</small></p>\n";
+    response.status(405, "Method Not Allowed");
+    response.add_header("Allow", allow.as_bytes()).unwrap();
+    response.add_length(data.len() as u64).unwrap();
+    response.add_header("Content-Type", b"text/html").unwrap();
+    response.done_headers().unwrap();
+    response.write_body(data.as_bytes());
+    response.done();
+}
+
+impl<S, H> Server for Router<H>
+    where S: Clone, H: Server<Seed=Route<S>>
+{
+    type Context = H::Context;
+    type Seed = Arc<Routes<S>>;
+    fn headers_received(seed: Arc<Routes<S>>, head: Head,
+        response: &mut Response, scope: &mut Scope<H::Context>)
+        -> Option<(Self, RecvMode, Time)>
+    {
+        match seed.find(head.method, head.target().path()) {
+            Ok(route) => {
+                H::headers_received(route, head, response, scope)
+                .map(|(m, mode, dline)| (Router(Some(m)), mode, dline))
+            }
+            Err(e) => {
+                // Skip the body if it's small to keep the connection. The
+                // larger one is refused after the response is sent, and the
+                // connection is closed (the chunked body is skipped up to
+                // the limit too, there is no way to know its size upfront)
+                if let BodyKind::Fixed(n) = head.body_kind {
+                    if n > DRAIN_LIMIT as u64 {
+                        close_connection(response);
+                    }
+                }
+                match e {
+                    RoutingError::MethodNotAllowed(ref allow) => {
+                        method_not_allowed(allow, response);
+                    }
+                    _ => {
+                        H::emit_error_page(&e, response,
+                                           &seed.default, scope);
+                    }
+                }
+                let timeout = H::send_response_timeout(&seed.default, scope);
+                Some((Router(None), RecvMode::Buffered(DRAIN_LIMIT),
+                      scope.now() + timeout))
+            }
+        }
+    }
+    fn request_received(self, data: &[u8], response: &mut Response,
+        scope: &mut Scope<H::Context>)
+        -> Option<Self>
+    {
+        self.0.and_then(|m| m.request_received(data, response, scope))
+            .map(|m| Router(Some(m)))
+    }
+    fn bad_request(self, response: &mut Response,
+        scope: &mut Scope<H::Context>)
+    {
+        self.0.map(|m| m.bad_request(response, scope));
+    }
+    fn request_chunk(self, chunk: &[u8], response: &mut Response,
+        scope: &mut Scope<H::Context>)
+        -> Option<Self>
+    {
+        self.0.and_then(|m| m.request_chunk(chunk, response, scope))
+            .map(|m| Router(Some(m)))
+    }
+    fn request_end(self, response: &mut Response,
+        scope: &mut Scope<H::Context>)
+        -> Option<Self>
+    {
+        self.0.and_then(|m| m.request_end(response, scope))
+            .map(|m| Router(Some(m)))
+    }
+    fn request_trailers(self, trailers: &[Header],
+        response: &mut Response, scope: &mut Scope<H::Context>)
+        -> Option<Self>
+    {
+        match self.0 {
+            Some(m) => m.request_trailers(trailers, response, scope)
+                .map(|m| Router(Some(m))),
+            None => Some(self),
+        }
+    }
+    fn timeout(self, response: &mut Response, scope: &mut Scope<H::Context>)
+        -> Option<(Self, Time)>
+    {
+        self.0.and_then(|m| m.timeout(response, scope))
+            .map(|(m, dline)| (Router(Some(m)), dline))
+    }
+    fn wakeup(self, response: &mut Response, scope: &mut Scope<H::Context>)
+        -> Option<Self>
+    {
+        match self.0 {
+            Some(m) => m.wakeup(response, scope).map(|m| Router(Some(m))),
+            None => Some(self),
+        }
+    }
+    fn response_flushed(self, response: &mut Response,
+        scope: &mut Scope<H::Context>)
+        -> Option<Self>
+    {
+        match self.0 {
+            Some(m) => m.response_flushed(response, scope)
+                .map(|m| Router(Some(m))),
+            None => Some(self),
+        }
+    }
+    fn emit_error_page(code: &HttpError, response: &mut Response,
+        seed: &Arc<Routes<S>>, scope: &mut Scope<H::Context>)
+    {
+        H::emit_error_page(code, response, &seed.default, scope)
+    }
+    fn decode_request_body(seed: &Arc<Routes<S>>,
+        scope: &mut Scope<H::Context>)
+        -> bool
+    {
+        H::decode_request_body(&seed.default, scope)
+    }
+    fn date_header(seed: &Arc<Routes<S>>, scope: &mut Scope<H::Context>)
+        -> bool
+    {
+        H::date_header(&seed.default, scope)
+    }
+    fn server_name(seed: &Arc<Routes<S>>, scope: &mut Scope<H::Context>)
+        -> Option<&'static str>
+    {
+        H::server_name(&seed.default, scope)
+    }
+    fn idle_timeout(seed: &Arc<Routes<S>>, scope: &mut Scope<H::Context>)
+        -> Duration
+    {
+        H::idle_timeout(&seed.default, scope)
+    }
+    fn header_byte_timeout(seed: &Arc<Routes<S>>,
+        scope: &mut Scope<H::Context>)
+        -> Duration
+    {
+        H::header_byte_timeout(&seed.default, scope)
+    }
+    fn send_response_timeout(seed: &Arc<Routes<S>>,
+        scope: &mut Scope<H::Context>)
+        -> Duration
+    {
+        H::send_response_timeout(&seed.default, scope)
+    }
+    fn limits(seed: &Arc<Routes<S>>, scope: &mut Scope<H::Context>)
+        -> Limits
+    {
+        H::limits(&seed.default, scope)
+    }
+    fn http2_settings(seed: &Arc<Routes<S>>, scope: &mut Scope<H::Context>)
+        -> Option<Settings>
+    {
+        H::http2_settings(&seed.default, scope)
+    }
+    fn upgrade_started<T: StreamSocket>(self, transport: &mut Transport<T>,
+        scope: &mut Scope<H::Context>)
+        -> Option<(Self, Expectation, Time)>
+    {
+        self.0.and_then(|m| m.upgrade_started(transport, scope))
+            .map(|(m, exp, dline)| (Router(Some(m)), exp, dline))
+    }
+    fn upgrade_bytes_read<T: StreamSocket>(self,
+        transport: &mut Transport<T>, end: usize,
+        scope: &mut Scope<H::Context>)
+        -> Option<(Self, Expectation, Time)>
+    {
+        self.0.and_then(|m| m.upgrade_bytes_read(transport, end, scope))
+            .map(|(m, exp, dline)| (Router(Some(m)), exp, dline))
+    }
+    fn upgrade_bytes_flushed<T: StreamSocket>(self,
+        transport: &mut Transport<T>, scope: &mut Scope<H::Context>)
+        -> Option<(Self, Expectation, Time)>
+    {
+        self.0.and_then(|m| m.upgrade_bytes_flushed(transport, scope))
+            .map(|(m, exp, dline)| (Router(Some(m)), exp, dline))
+    }
+    fn upgrade_timeout<T: StreamSocket>(self, transport: &mut Transport<T>,
+        scope: &mut Scope<H::Context>)
+        -> Option<(Self, Expectation, Time)>
+    {
+        self.0.and_then(|m| m.upgrade_timeout(transport, scope))
+            .map(|(m, exp, dline)| (Router(Some(m)), exp, dline))
+    }
+    fn upgrade_wakeup<T: StreamSocket>(self, transport: &mut Transport<T>,
+        scope: &mut Scope<H::Context>)
+        -> Option<(Self, Expectation, Time)>
+    {
+        self.0.and_then(|m| m.upgrade_wakeup(transport, scope))
+            .map(|(m, exp, dline)| (Router(Some(m)), exp, dline))
+    }
+    fn upgrade_end<T: StreamSocket>(self, transport: &mut Transport<T>,
+        reason: Exception, scope: &mut Scope<H::Context>)
+    {
+        self.0.map(|m| m.upgrade_end(transport, reason, scope));
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::sync::Arc;
+    use std::time::Duration;
+    use rotor::{Scope, Time, EventSet, Machine};
+    use rotor_stream::{Stream, Accepted};
+    use rotor_test::{MemIo, MockLoop};
+    use server::{Server, Head, Response, RecvMode, HttpError, Parser};
+    use super::{Routes, Route, Router, RoutingError};
+
+    fn routes() -> Routes<u32> {
+        let mut routes = Routes::new(0);
+        routes.get("/", 1)
+              .get("/users", 2)
+              .get("/users/:id", 3)
+              .post("/users/:id", 4)
+              .get("/users/me", 5)
+              .get("/users/:id/posts/:post", 6)
+              .get("/static/*path", 7)
+              .get("/files/*", 8)
+              .get("/users/me/*rest", 9);
+        routes
+    }
+
+    fn find(method: &str, path: &str) -> Result<(u32, Vec<(String, String)>),
+                                                RoutingError>
+    {
+        routes().find(method, path)
+            .map(|r| (r.seed, r.params.iter().cloned().collect()))
+    }
+
+    fn param(name: &str, value: &str) -> (String, String) {
+        (name.to_string(), value.to_string())
+    }
+
+    #[test]
+    fn static_routes() {
+        assert_eq!(find("GET", "/").unwrap(), (1, vec![]));
+        assert_eq!(find("GET", "/users").unwrap(), (2, vec![]));
+        assert_eq!(find("GET", "/users?x=1").unwrap(), (2, vec![]));
+        assert_eq!(find("GET", "/users/me").unwrap(), (5, vec![]));
+        assert!(matches!(find("GET", "/users/"), Err(RoutingError::NotFound)));
+        assert!(matches!(find("GET", "/user"), Err(RoutingError::NotFound)));
+        assert!(matches!(find("GET", "*"), Err(RoutingError::NotFound)));
+    }
+
+    #[test]
+    fn params() {
+        assert_eq!(find("GET", "/users/12").unwrap(),
+                   (3, vec![param("id", "12")]));
+        assert_eq!(find("POST", "/users/me").unwrap(),
+                   (4, vec![param("id", "me")]));
+        assert_eq!(find("GET", "/users/1/posts/7").unwrap(),
+                   (6, vec![param("id", "1"), param("post", "7")]));
+        // static segment takes precedence, even over a longer route
+        assert_eq!(find("GET", "/users/me/posts/7").unwrap(),
+                   (9, vec![param("rest", "posts/7")]));
+        let route = routes().find("GET", "/users/1/posts/2").unwrap();
+        assert_eq!(route.params.get("post"), Some("2"));
+        assert_eq!(route.params.get("x"), None);
+    }
+
+    #[test]
+    fn wildcard() {
+        assert_eq!(find("GET", "/static/css/main.css").unwrap(),
+                   (7, vec![param("path", "css/main.css")]));
+        assert_eq!(find("GET", "/static/").unwrap(),
+                   (7, vec![param("path", "")]));
+        assert_eq!(find("GET", "/files/a/b").unwrap(), (8, vec![]));
+        assert_eq!(find("GET", "/users/me/x/y").unwrap(),
+                   (9, vec![param("rest", "x/y")]));
+        assert!(matches!(find("GET", "/static"), Err(RoutingError::NotFound)));
+    }
+
+    #[test]
+    fn methods() {
+        assert_eq!(find("HEAD", "/users/1").unwrap().0, 3);
+        match find("DELETE", "/users/1") {
+            Err(RoutingError::MethodNotAllowed(allow)) => {
+                assert_eq!(allow, "GET, POST, HEAD");
+            }
+            res => panic!("unexpected {:?}", res),
+        }
+        match find("POST", "/") {
+            Err(RoutingError::MethodNotAllowed(allow)) => {
+                assert_eq!(allow, "GET, HEAD");
+            }
+            res => panic!("unexpected {:?}", res),
+        }
+    }
+
+    #[test]
+    #[should_panic(expected="duplicate route")]
+    fn duplicate() {
+        routes().get("/users/:name", 10);
+    }
+
+    /// Replies with an empty `200 OK` and records status of each response
+    struct Page;
+
+    impl Server for Page {
+        type Seed = Route<u32>;
+        type Context = Vec<u16>;
+        fn headers_received(_seed: Route<u32>, _head: Head,
+            _response: &mut Response, scope: &mut Scope<Vec<u16>>)
+            -> Option<(Self, RecvMode, Time)>
+        {
+            Some((Page, RecvMode::Buffered(1000),
+                  scope.now() + Duration::new(10, 0)))
+        }
+        fn request_received(self, _data: &[u8], response: &mut Response,
+            scope: &mut Scope<Vec<u16>>) -> Option<Self>
+        {
+            scope.push(200);
+            response.status(200, "OK");
+            response.add_length(0).unwrap();
+            response.done_headers().unwrap();
+            response.done();
+            None
+        }
+        fn request_chunk(self, _chunk: &[u8], _response: &mut Response,
+            _scope: &mut Scope<Vec<u16>>) -> Option<Self>
+        { unreachable!(); }
+        fn request_end(self, _response: &mut Response,
+            _scope: &mut Scope<Vec<u16>>) -> Option<Self>
+        { unreachable!(); }
+        fn timeout(self, _response: &mut Response,
+            _scope: &mut Scope<Vec<u16>>) -> Option<(Self, Time)>
+        { unimplemented!(); }
+        fn wakeup(self, _response: &mut Response,
+            _scope: &mut Scope<Vec<u16>>) -> Option<Self>
+        { unimplemented!(); }
+        fn emit_error_page(code: &HttpError, response: &mut Response,
+            _seed: &Route<u32>, scope: &mut Scope<Vec<u16>>)
+        {
+            let (status, reason) = code.http_status();
+            scope.push(status);
+            response.status(status, reason);
+            response.add_length(0).unwrap();
+            response.done_headers().unwrap();
+            response.done();
+        }
+    }
+
+    #[test]
+    fn not_found_with_body() {
+        let mut lp = MockLoop::new(Vec::new());
+        let mut io = MemIo::new();
+        io.push_bytes(b"POST /nothing HTTP/1.1\r\nContent-Length: 5\r\n\r\n\
+                        hello\
+                        POST / HTTP/1.1\r\nContent-Length: 5\r\n\r\n\
+                        hello\
+                        GET / HTTP/1.1\r\n\r\n");
+        let m = Stream::<Parser<Router<Page>, MemIo>>::accepted(
+            io.clone(), Arc::new(routes()), &mut lp.scope(1))
+            .expect_machine();
+        m.ready(EventSet::readable(), &mut lp.scope(1)).expect_machine();
+        // Bodies are skipped and the connection is kept (the 405 page is
+        // written by the router itself, so it's not recorded)
+        assert_eq!(*lp.ctx(), vec![404, 200]);
+    }
+
+    #[test]
+    fn not_found_with_chunked_body() {
+        let mut lp = MockLoop::new(Vec::new());
+        let mut io = MemIo::new();
+        io.push_bytes(b"POST /nothing HTTP/1.1\r\n\
+                        Transfer-Encoding: chunked\r\n\r\n\
+                        5\r\nhello\r\n0\r\n\r\n\
+                        GET / HTTP/1.1\r\n\r\n");
+        let m = Stream::<Parser<Router<Page>, MemIo>>::accepted(
+            io.clone(), Arc::new(routes()), &mut lp.scope(1))
+            .expect_machine();
+        m.ready(EventSet::readable(), &mut lp.scope(1)).expect_machine();
+        assert_eq!(*lp.ctx(), vec![404, 200]);
+        // A chunk over the drain limit or a bad chunk size must not send
+        // the second error page for the response which is already written
+        for chunk in &[&b"10001\r\n"[..], &b"zz\r\n"[..]] {
+            let mut lp = MockLoop::new(Vec::new());
+            let mut io = MemIo::new();
+            io.push_bytes(b"POST /nothing HTTP/1.1\r\n\
+                            Transfer-Encoding: chunked\r\n\r\n");
+            io.push_bytes(chunk);
+            let m = Stream::<Parser<Router<Page>, MemIo>>::accepted(
+                io.clone(), Arc::new(routes()), &mut lp.scope(1))
+                .expect_machine();
+            m.ready(EventSet::readable(), &mut lp.scope(1));
+            assert_eq!(*lp.ctx(), vec![404]);
+        }
+    }
+}
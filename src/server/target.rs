@@ -0,0 +1,246 @@
+//! Parsing of the request target
+//!
+//! Implements the forms of request target of RFC 7230 (section 5.3), and
+//! percent-decoding of the path and the query string.
+use std::borrow::Cow;
+
+
+/// The parsed request target (the `path` of the `Head`)
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Target<'a> {
+    /// The usual `/path?query` form
+    Origin {
+        path: &'a str,
+        query: Option<&'a str>,
+    },
+    /// `scheme://authority/path?query`, usually sent to proxies
+    ///
+    /// The `path` is `/` if it's empty in the target.
+    Absolute {
+        scheme: &'a str,
+        authority: &'a str,
+        path: &'a str,
+        query: Option<&'a str>,
+    },
+    /// The `host:port` of the `CONNECT` request
+    Authority(&'a str),
+    /// The `*` of the server-wide `OPTIONS` request
+    Asterisk,
+}
+
+/// An iterator over percent-decoded `key=value` pairs of the query
+///
+/// Returned by `Head::query_pairs()`. Pluses are decoded as spaces, the
+/// value of a key without `=` is an empty string.
+#[derive(Debug, Clone)]
+pub struct QueryPairs<'a> {
+    rest: Option<&'a str>,
+}
+
+fn split_query(target: &str) -> (&str, Option<&str>) {
+    match target.find('?') {
+        Some(idx) => (&target[..idx], Some(&target[idx+1..])),
+        None => (target, None),
+    }
+}
+
+fn scheme_len(target: &str) -> Option<usize> {
+    let end = match target.find("://") {
+        Some(end) => end,
+        None => return None,
+    };
+    let mut chars = target[..end].bytes();
+    match chars.next() {
+        Some(b'a'...b'z') | Some(b'A'...b'Z') => {}
+        _ => return None,
+    }
+    if chars.all(|ch| matches!(ch, b'a'...b'z' | b'A'...b'Z' | b'0'...b'9' |
+                                   b'+' | b'-' | b'.'))
+    {
+        Some(end)
+    } else {
+        None
+    }
+}
+
+impl<'a> Target<'a> {
+    /// Parses the request target
+    ///
+    /// Anything which is not an origin, absolute or asterisk form is
+    /// considered an authority form.
+    pub fn parse(target: &'a str) -> Target<'a> {
+        if target.starts_with('/') {
+            let (path, query) = split_query(target);
+            return Target::Origin { path: path, query: query };
+        }
+        if target == "*" {
+            return Target::Asterisk;
+        }
+        if let Some(end) = scheme_len(target) {
+            let rest = &target[end+3..];
+            let auth_end = rest.find(|c| c == '/' || c == '?')
+                .unwrap_or(rest.len());
+            let (path, query) = split_query(&rest[auth_end..]);
+            return Target::Absolute {
+                scheme: &target[..end],
+                authority: &rest[..auth_end],
+                path: if path.len() == 0 { "/" } else { path },
+                query: query,
+            };
+        }
+        Target::Authority(target)
+    }
+    /// The path without the query
+    ///
+    /// It's an empty string for the authority form and `*` for asterisk.
+    pub fn path(&self) -> &'a str {
+        match *self {
+            Target::Origin { path, .. } => path,
+            Target::Absolute { path, .. } => path,
+            Target::Authority(_) => "",
+            Target::Asterisk => "*",
+        }
+    }
+    /// The raw query string (without the question mark)
+    pub fn query(&self) -> Option<&'a str> {
+        match *self {
+            Target::Origin { query, .. } => query,
+            Target::Absolute { query, .. } => query,
+            Target::Authority(_) | Target::Asterisk => None,
+        }
+    }
+}
+
+pub fn hex(ch: u8) -> Option<u8> {
+    match ch {
+        b'0'...b'9' => Some(ch - b'0'),
+        b'a'...b'f' => Some(ch - b'a' + 10),
+        b'A'...b'F' => Some(ch - b'A' + 10),
+        _ => None,
+    }
+}
+
+/// Decodes `%XX` escapes (and pluses if `plus_is_space`)
+///
+/// Malformed escapes are left as is, invalid UTF-8 is replaced by
+/// `U+FFFD`. The value is borrowed if there is nothing to decode.
+pub fn percent_decode(value: &str, plus_is_space: bool) -> Cow<str> {
+    if !value.bytes().any(|x| x == b'%' || plus_is_space && x == b'+') {
+        return Cow::Borrowed(value);
+    }
+    let bytes = value.as_bytes();
+    let mut buf = Vec::with_capacity(bytes.len());
+    let mut idx = 0;
+    while idx < bytes.len() {
+        match bytes[idx] {
+            b'%' if idx + 2 < bytes.len() => {
+                match (hex(bytes[idx+1]), hex(bytes[idx+2])) {
+                    (Some(hi), Some(lo)) => {
+                        buf.push(hi << 4 | lo);
+                        idx += 3;
+                        continue;
+                    }
+                    _ => buf.push(b'%'),
+                }
+            }
+            b'+' if plus_is_space => buf.push(b' '),
+            ch => buf.push(ch),
+        }
+        idx += 1;
+    }
+    match String::from_utf8(buf) {
+        Ok(value) => Cow::Owned(value),
+        Err(e) => Cow::Owned(String::from_utf8_lossy(&e.into_bytes())
+                             .into_owned()),
+    }
+}
+
+impl<'a> QueryPairs<'a> {
+    /// Iterates over pairs of the raw query string
+    pub fn new(query: Option<&'a str>) -> QueryPairs<'a> {
+        QueryPairs { rest: query }
+    }
+}
+
+impl<'a> Iterator for QueryPairs<'a> {
+    type Item = (Cow<'a, str>, Cow<'a, str>);
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            let rest = match self.rest {
+                Some(rest) => rest,
+                None => return None,
+            };
+            let pair = match rest.find('&') {
+                Some(idx) => {
+                    self.rest = Some(&rest[idx+1..]);
+                    &rest[..idx]
+                }
+                None => {
+                    self.rest = None;
+                    rest
+                }
+            };
+            if pair.len() == 0 {
+                continue;
+            }
+            let (key, value) = match pair.find('=') {
+                Some(idx) => (&pair[..idx], &pair[idx+1..]),
+                None => (pair, ""),
+            };
+            return Some((percent_decode(key, true),
+                         percent_decode(value, true)));
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::borrow::Cow;
+    use super::{Target, QueryPairs, percent_decode};
+
+    #[test]
+    fn forms() {
+        assert_eq!(Target::parse("/a/b?x=1"),
+                   Target::Origin { path: "/a/b", query: Some("x=1") });
+        assert_eq!(Target::parse("/"),
+                   Target::Origin { path: "/", query: None });
+        assert_eq!(Target::parse("http://example.com:80/a?"),
+                   Target::Absolute { scheme: "http",
+                                      authority: "example.com:80",
+                                      path: "/a", query: Some("") });
+        assert_eq!(Target::parse("https://example.com?q"),
+                   Target::Absolute { scheme: "https",
+                                      authority: "example.com",
+                                      path: "/", query: Some("q") });
+        assert_eq!(Target::parse("example.com:443"),
+                   Target::Authority("example.com:443"));
+        assert_eq!(Target::parse("*"), Target::Asterisk);
+        assert_eq!(Target::parse("*").path(), "*");
+    }
+
+    #[test]
+    fn decode() {
+        assert!(matches!(percent_decode("/a/b", false), Cow::Borrowed(_)));
+        assert_eq!(percent_decode("/a%20b%2fc", false), "/a b/c");
+        assert_eq!(percent_decode("a+b", false), "a+b");
+        assert_eq!(percent_decode("a+b", true), "a b");
+        assert_eq!(percent_decode("100%", false), "100%");
+        assert_eq!(percent_decode("%zz%4", false), "%zz%4");
+        assert_eq!(percent_decode("%D1%8F", false), "\u{44f}");
+        assert_eq!(percent_decode("%FF", false), "\u{fffd}");
+    }
+
+    #[test]
+    fn query() {
+        let pairs: Vec<_> = QueryPairs::new(Some("a=1&&b=x+y&c&d=%26="))
+            .map(|(k, v)| (k.into_owned(), v.into_owned()))
+            .collect();
+        assert_eq!(pairs, vec![
+            ("a".to_string(), "1".to_string()),
+            ("b".to_string(), "x y".to_string()),
+            ("c".to_string(), "".to_string()),
+            ("d".to_string(), "&=".to_string()),
+        ]);
+        assert_eq!(QueryPairs::new(None).count(), 0);
+    }
+}
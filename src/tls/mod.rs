@@ -0,0 +1,37 @@
+//! TLS transport
+//!
+//! The `TlsInfo` structure describes the negotiated TLS session. It's passed
+//! to the server handlers as `Head::tls`, and it's always there, so that
+//! handlers compile both with and without TLS.
+//!
+//! Everything else requires the `tls` feature (which uses OpenSSL):
+//!
+//! * `TlsStream` is a `StreamSocket` wrapper which may be used by both
+//!   server and client state machines
+//! * `TlsListener` accepts TCP connections and starts TLS handshake on them,
+//!   it's used with `server::Fsm` the same way as plain `TcpListener`
+//! * `ClientContext` holds certificate verification options for the
+//!   `client::connect_tls`
+//!
+//! The handshake is made by the first reads and writes on the socket, so
+//! the handshake is always complete when handlers are called. For HTTPS
+//! requests `Head::scheme` is `https`. HTTP/2 is negotiated using ALPN
+//! (see `server_context()`) and works the same as the one over cleartext.
+//!
+//! If the handshake fails on the client side, `Requester::bad_response` is
+//! called with `ResponseError::Tls`.
+#[cfg(feature="tls")] pub use self::stream::{TlsStream, TlsListener};
+#[cfg(feature="tls")] pub use self::stream::{ClientContext};
+#[cfg(feature="tls")] pub use self::stream::{server_context, ALPN_PROTOCOLS};
+
+#[cfg(feature="tls")] mod stream;
+
+
+/// Information about TLS session of the connection
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TlsInfo {
+    /// Hostname requested by client with the SNI extension
+    pub server_name: Option<String>,
+    /// Protocol negotiated with ALPN, e.g. `h2` or `http/1.1`
+    pub alpn_protocol: Option<Vec<u8>>,
+}
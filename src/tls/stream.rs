@@ -0,0 +1,408 @@
+use std::io::{self, Read, Write};
+use std::net::SocketAddr;
+use std::path::Path;
+
+use openssl::ssl::{SslContext, SslMethod, Ssl, IntoSsl, NonblockingSslStream};
+use openssl::ssl::SSL_VERIFY_PEER;
+use openssl::ssl::error::{SslError, NonblockingSslError};
+use openssl::x509::X509FileType;
+use openssl_verify::verify_callback;
+use rotor::mio::{Evented, Selector, Token, EventSet, PollOpt, TryAccept};
+use rotor::mio::tcp::{TcpStream, TcpListener};
+use rotor_stream::SocketError;
+
+use super::TlsInfo;
+
+
+/// Protocols to announce with ALPN when HTTP/2 is enabled, in the order of
+/// preference
+pub const ALPN_PROTOCOLS: &'static [&'static [u8]] = &[b"h2", b"http/1.1"];
+
+/// Creates server context with certificate and private key in PEM files
+///
+/// The context announces only `http/1.1` with ALPN. If HTTP/2 is enabled by
+/// `Server::http2_settings` you should call `set_alpn_protocols` again
+/// with `ALPN_PROTOCOLS`. Other options (e.g. the SNI callback) may be set
+/// on the returned context too.
+pub fn server_context<C, K>(certificate: C, private_key: K)
+    -> Result<SslContext, SslError>
+    where C: AsRef<Path>, K: AsRef<Path>
+{
+    let mut ctx = try!(SslContext::new(SslMethod::Sslv23));
+    try!(ctx.set_certificate_chain_file(certificate, X509FileType::PEM));
+    try!(ctx.set_private_key_file(private_key, X509FileType::PEM));
+    try!(ctx.check_private_key());
+    ctx.set_alpn_protocols(&[b"http/1.1"]);
+    Ok(ctx)
+}
+
+/// TLS options for client connections
+///
+/// By default server certificate is verified against system root
+/// certificates, and the hostname passed to `connect_tls` is checked
+/// against the certificate.
+pub struct ClientContext {
+    context: SslContext,
+    verify_hostname: bool,
+}
+
+impl ClientContext {
+    pub fn new() -> Result<ClientContext, SslError> {
+        let mut ctx = try!(SslContext::new(SslMethod::Sslv23));
+        try!(ctx.set_default_verify_paths());
+        ctx.set_verify(SSL_VERIFY_PEER, None);
+        Ok(ClientContext {
+            context: ctx,
+            verify_hostname: true,
+        })
+    }
+    /// Trusts certificates signed by root CAs from the PEM file
+    ///
+    /// System root certificates are trusted too
+    pub fn add_root_certificates<P: AsRef<Path>>(&mut self, file: P)
+        -> Result<(), SslError>
+    {
+        self.context.set_CA_file(file)
+    }
+    /// Sets certificate and private key (PEM files) for client
+    /// authentication
+    pub fn set_client_certificate<C, K>(&mut self, certificate: C,
+        private_key: K)
+        -> Result<(), SslError>
+        where C: AsRef<Path>, K: AsRef<Path>
+    {
+        try!(self.context.set_certificate_chain_file(certificate,
+                                                     X509FileType::PEM));
+        try!(self.context.set_private_key_file(private_key,
+                                               X509FileType::PEM));
+        self.context.check_private_key()
+    }
+    /// Enables or disables hostname verification
+    ///
+    /// The certificate chain is verified anyway, use `set_verify` on the
+    /// `context_mut()` to change that.
+    pub fn set_verify_hostname(&mut self, verify: bool) {
+        self.verify_hostname = verify;
+    }
+    /// Gives access to other options, e.g. ALPN protocols
+    pub fn context_mut(&mut self) -> &mut SslContext {
+        &mut self.context
+    }
+}
+
+/// A TLS stream which can be used as `rotor_stream::StreamSocket`
+///
+/// Handshake is continued on each read and write, until it's complete.
+/// Note that the socket is never shut down gracefully (i.e. `close_notify`
+/// alert is not sent), this is fine for HTTP as it has its own means to
+/// find out the end of the message.
+pub struct TlsStream<S>(NonblockingSslStream<S>);
+
+fn convert_error(err: NonblockingSslError) -> io::Error {
+    match err {
+        NonblockingSslError::WantRead | NonblockingSslError::WantWrite => {
+            io::Error::new(io::ErrorKind::WouldBlock, "TLS would block")
+        }
+        NonblockingSslError::SslError(SslError::StreamError(e)) => e,
+        NonblockingSslError::SslError(e) => {
+            io::Error::new(io::ErrorKind::Other, e)
+        }
+    }
+}
+
+impl<S: Read + Write> TlsStream<S> {
+    /// Starts server side handshake on the accepted socket
+    pub fn accept<T: IntoSsl>(ssl: T, sock: S)
+        -> Result<TlsStream<S>, SslError>
+    {
+        NonblockingSslStream::accept(ssl, sock).map(TlsStream)
+    }
+    /// Starts client side handshake
+    ///
+    /// The `hostname` is sent in SNI extension and, unless disabled in the
+    /// `ctx`, is verified against the server certificate.
+    pub fn connect(ctx: &ClientContext, hostname: &str, sock: S)
+        -> Result<TlsStream<S>, SslError>
+    {
+        let mut ssl = try!(Ssl::new(&ctx.context));
+        try!(ssl.set_hostname(hostname));
+        if ctx.verify_hostname {
+            let hostname = hostname.to_string();
+            ssl.set_verify_callback(SSL_VERIFY_PEER,
+                move |ok, x509| verify_callback(&hostname, ok, x509));
+        }
+        NonblockingSslStream::connect(ssl, sock).map(TlsStream)
+    }
+    /// Returns a reference to the underlying socket
+    pub fn get_ref(&self) -> &S {
+        self.0.get_ref()
+    }
+    /// Returns TLS session info
+    ///
+    /// Only meaningful after handshake is complete. For the client side
+    /// `server_name` is the one sent in SNI.
+    pub fn info(&self) -> TlsInfo {
+        let ssl = self.0.ssl();
+        TlsInfo {
+            server_name: ssl.get_servername(),
+            alpn_protocol: ssl.selected_alpn_protocol().map(|x| x.to_vec()),
+        }
+    }
+}
+
+impl TlsStream<TcpStream> {
+    pub fn peer_addr(&self) -> io::Result<SocketAddr> {
+        self.0.get_ref().peer_addr()
+    }
+}
+
+impl<S: Read + Write> Read for TlsStream<S> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.0.read(buf).map_err(convert_error)
+    }
+}
+
+impl<S: Read + Write> Write for TlsStream<S> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0.write(buf).map_err(convert_error)
+    }
+    fn flush(&mut self) -> io::Result<()> {
+        self.0.get_mut().flush()
+    }
+}
+
+impl<S: Evented> Evented for TlsStream<S> {
+    fn register(&self, selector: &mut Selector, token: Token,
+        interest: EventSet, opts: PollOpt)
+        -> io::Result<()>
+    {
+        self.0.get_ref().register(selector, token, interest, opts)
+    }
+    fn reregister(&self, selector: &mut Selector, token: Token,
+        interest: EventSet, opts: PollOpt)
+        -> io::Result<()>
+    {
+        self.0.get_ref().reregister(selector, token, interest, opts)
+    }
+    fn deregister(&self, selector: &mut Selector) -> io::Result<()> {
+        self.0.get_ref().deregister(selector)
+    }
+}
+
+impl<S: SocketError> SocketError for TlsStream<S> {
+    fn take_socket_error(&self) -> io::Result<()> {
+        self.0.get_ref().take_socket_error()
+    }
+}
+
+/// A listener which starts TLS handshake on accepted connections
+///
+/// Use it as a listener for `server::Fsm`:
+///
+/// ```ignore
+/// let ctx = tls::server_context("cert.pem", "key.pem").unwrap();
+/// let lst = TlsListener::new(TcpListener::bind(&addr).unwrap(), ctx);
+/// Fsm::<MyServer, _>::new(lst, seed, scope)
+/// ```
+pub struct TlsListener {
+    listener: TcpListener,
+    context: SslContext,
+}
+
+impl TlsListener {
+    pub fn new(listener: TcpListener, context: SslContext) -> TlsListener {
+        TlsListener {
+            listener: listener,
+            context: context,
+        }
+    }
+    pub fn get_ref(&self) -> &TcpListener {
+        &self.listener
+    }
+}
+
+impl TryAccept for TlsListener {
+    type Output = TlsStream<TcpStream>;
+    fn accept(&self) -> io::Result<Option<TlsStream<TcpStream>>> {
+        loop {
+            let sock = match try!(self.listener.accept()) {
+                Some((sock, _)) => sock,
+                None => return Ok(None),
+            };
+            match TlsStream::accept(&self.context, sock) {
+                Ok(stream) => return Ok(Some(stream)),
+                // Only this connection is broken, the listener is fine
+                Err(_) => continue,
+            }
+        }
+    }
+}
+
+impl Evented for TlsListener {
+    fn register(&self, selector: &mut Selector, token: Token,
+        interest: EventSet, opts: PollOpt)
+        -> io::Result<()>
+    {
+        self.listener.register(selector, token, interest, opts)
+    }
+    fn reregister(&self, selector: &mut Selector, token: Token,
+        interest: EventSet, opts: PollOpt)
+        -> io::Result<()>
+    {
+        self.listener.reregister(selector, token, interest, opts)
+    }
+    fn deregister(&self, selector: &mut Selector) -> io::Result<()> {
+        self.listener.deregister(selector)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::fs::File;
+    use std::io::{self, Read, Write, ErrorKind};
+    use std::env::temp_dir;
+    use std::net::{self, SocketAddr};
+    use std::path::PathBuf;
+    use std::thread;
+    use std::time::Duration;
+
+    use openssl::crypto::hash::Type::SHA256;
+    use openssl::crypto::pkey::PKey;
+    use openssl::ssl::{SslContext, SslMethod, Ssl, SslStream};
+    use openssl::x509::{X509, X509Generator};
+    use rand::random;
+    use rotor::mio::TryAccept;
+    use rotor::mio::tcp::{TcpListener, TcpStream};
+
+    use super::{TlsListener, TlsStream, ClientContext, ALPN_PROTOCOLS};
+
+    /// Generates self-signed certificate, which is also used as the root CA
+    fn self_signed() -> (X509, PKey) {
+        X509Generator::new()
+            .set_bitlength(2048)
+            .set_valid_period(1)
+            .add_name("CN".to_string(), "localhost".to_string())
+            .set_sign_hash(SHA256)
+            .generate().unwrap()
+    }
+
+    fn server_context(cert: &X509, key: &PKey) -> SslContext {
+        let mut ctx = SslContext::new(SslMethod::Sslv23).unwrap();
+        ctx.set_certificate(cert).unwrap();
+        ctx.set_private_key(key).unwrap();
+        ctx.set_alpn_protocols(ALPN_PROTOCOLS);
+        ctx
+    }
+
+    fn ca_file(cert: &X509) -> PathBuf {
+        let path = temp_dir().join(format!("rotor-http-ca-{}.pem",
+                                           random::<u32>()));
+        cert.write_pem(&mut File::create(&path).unwrap()).unwrap();
+        path
+    }
+
+    /// Repeats nonblocking operation until it's complete
+    fn retry<T, F: FnMut() -> io::Result<T>>(mut f: F) -> io::Result<T> {
+        loop {
+            match f() {
+                Err(ref e) if e.kind() == ErrorKind::WouldBlock => {
+                    thread::sleep(Duration::from_millis(1));
+                }
+                result => return result,
+            }
+        }
+    }
+
+    /// Runs blocking echo server for a single connection
+    fn echo_server(ctx: SslContext) -> SocketAddr {
+        let lst = net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = lst.local_addr().unwrap();
+        thread::spawn(move || {
+            let (sock, _) = lst.accept().unwrap();
+            if let Ok(mut stream) = SslStream::accept(&ctx, sock) {
+                let mut buf = [0u8; 5];
+                stream.read_exact(&mut buf).unwrap();
+                stream.write_all(&buf).unwrap();
+            }
+        });
+        addr
+    }
+
+    fn client(ctx: &ClientContext, addr: &SocketAddr, hostname: &str)
+        -> io::Result<Vec<u8>>
+    {
+        let sock = TcpStream::connect(addr).unwrap();
+        let mut stream = TlsStream::connect(ctx, hostname, sock).unwrap();
+        try!(retry(|| stream.write(b"hello")));
+        let mut buf = [0u8; 5];
+        let mut bytes = 0;
+        while bytes < buf.len() {
+            bytes += try!(retry(|| stream.read(&mut buf[bytes..])));
+        }
+        Ok(buf.to_vec())
+    }
+
+    #[test]
+    fn loopback() {
+        let (cert, key) = self_signed();
+        let lst = TcpListener::bind(&"127.0.0.1:0".parse().unwrap()).unwrap();
+        let addr = lst.local_addr().unwrap();
+        let lst = TlsListener::new(lst, server_context(&cert, &key));
+        let client = thread::spawn(move || {
+            let mut ctx = SslContext::new(SslMethod::Sslv23).unwrap();
+            ctx.set_alpn_protocols(&[b"http/1.1"]);
+            let mut ssl = Ssl::new(&ctx).unwrap();
+            ssl.set_hostname("localhost").unwrap();
+            let sock = net::TcpStream::connect(addr).unwrap();
+            let mut stream = SslStream::connect(ssl, sock).unwrap();
+            stream.write_all(b"hello").unwrap();
+            stream.flush().unwrap();
+        });
+        let mut stream = None;
+        while stream.is_none() {
+            stream = lst.accept().unwrap();
+            thread::sleep(Duration::from_millis(1));
+        }
+        let mut stream = stream.unwrap();
+        let mut buf = [0u8; 5];
+        let mut bytes = 0;
+        while bytes < buf.len() {
+            bytes += retry(|| stream.read(&mut buf[bytes..])).unwrap();
+        }
+        client.join().unwrap();
+        assert_eq!(&buf, b"hello");
+        let info = stream.info();
+        assert_eq!(info.server_name, Some("localhost".to_string()));
+        assert_eq!(info.alpn_protocol, Some(b"http/1.1".to_vec()));
+        assert_eq!(stream.peer_addr().unwrap().ip(), addr.ip());
+    }
+
+    #[test]
+    fn verified_client() {
+        let (cert, key) = self_signed();
+        let addr = echo_server(server_context(&cert, &key));
+        let mut ctx = ClientContext::new().unwrap();
+        ctx.add_root_certificates(ca_file(&cert)).unwrap();
+        assert_eq!(client(&ctx, &addr, "localhost").unwrap(), b"hello");
+    }
+
+    #[test]
+    fn unknown_ca() {
+        let (cert, key) = self_signed();
+        let addr = echo_server(server_context(&cert, &key));
+        let ctx = ClientContext::new().unwrap();
+        assert!(client(&ctx, &addr, "localhost").is_err());
+    }
+
+    #[test]
+    fn wrong_hostname() {
+        let (cert, key) = self_signed();
+        let addr = echo_server(server_context(&cert, &key));
+        let mut ctx = ClientContext::new().unwrap();
+        ctx.add_root_certificates(ca_file(&cert)).unwrap();
+        assert!(client(&ctx, &addr, "example.com").is_err());
+        ctx.set_verify_hostname(false);
+        let addr = echo_server(server_context(&cert, &key));
+        assert_eq!(client(&ctx, &addr, "example.com").unwrap(), b"hello");
+    }
+}
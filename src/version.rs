@@ -13,8 +13,9 @@ pub enum Version {
     Http11,
     /// HTTP/2 protocol version as described in RFC7540.
     ///
-    /// HTTP/2 switches HTTP to a binary transport and it is planned
-    /// to support it in rotor-http.
+    /// HTTP/2 switches HTTP to a binary transport. Both server and client
+    /// support it over cleartext connections, see `server::http2` and
+    /// `client::http2`.
     Http20,
 }
 
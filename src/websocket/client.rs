@@ -0,0 +1,329 @@
+use std::time::Duration;
+
+use rotor::{Scope, Time};
+use rotor_stream::{Exception, Expectation, StreamSocket, Transport};
+
+use client::{Requester, Request, Head, RecvMode, Version};
+use super::handshake::{new_key, check_response, VERSION};
+use super::error::ClientHandshakeError;
+use super::connection::{Handler, Connection};
+
+
+/// A time to wait for the handshake response
+const HANDSHAKE_TIMEOUT: u64 = 30;
+
+/// A handler of client-side websocket connection
+///
+/// Only the handshake is client-specific, the rest of the connection is
+/// handled by the `Handler` trait.
+pub trait ClientHandler: Handler {
+    /// Server accepted the connection
+    ///
+    /// The `protocol` is the subprotocol chosen by the server (always one
+    /// of the requested ones). This is called before `Handler::opened()`.
+    /// Returning `None` closes the connection without the close frame.
+    fn accepted(self, _head: &Head, _protocol: Option<&str>,
+        _scope: &mut Scope<Self::Context>)
+        -> Option<Self>
+    {
+        Some(self)
+    }
+
+    /// Handshake has failed
+    ///
+    /// Called either when server responded with something other than a
+    /// valid `101 Switching Protocols` response or when there was no
+    /// response in a reasonable time. The connection is closed afterwards.
+    fn handshake_failed(self, _error: Option<&ClientHandshakeError>,
+        _scope: &mut Scope<Self::Context>)
+    {}
+}
+
+/// A `client::Requester` implementation which opens a websocket
+///
+/// Return it from `Client::connection_idle()` as a request. Connection is
+/// never returned back to the `Client` after websocket is established.
+#[derive(Debug)]
+pub enum WebsocketRequester<H: ClientHandler> {
+    /// Request is not sent yet
+    Prepare {
+        handler: H,
+        host: String,
+        path: String,
+        protocols: Vec<String>,
+    },
+    /// Waiting for response
+    Handshake {
+        handler: H,
+        key: String,
+        protocols: Vec<String>,
+    },
+    Active(Connection<H>),
+}
+
+impl<H: ClientHandler> WebsocketRequester<H> {
+    /// Creates a requester for `ws://{host}{path}`
+    ///
+    /// The `protocols` are the subprotocols in order of preference, may be
+    /// empty
+    pub fn new(handler: H, host: &str, path: &str, protocols: &[&str])
+        -> WebsocketRequester<H>
+    {
+        WebsocketRequester::Prepare {
+            handler: handler,
+            host: host.to_string(),
+            path: path.to_string(),
+            protocols: protocols.iter().map(|x| x.to_string()).collect(),
+        }
+    }
+}
+
+fn active<H: ClientHandler>(res: Option<(Connection<H>, Expectation, Time)>)
+    -> Option<(WebsocketRequester<H>, Expectation, Time)>
+{
+    res.map(|(conn, exp, dline)| {
+        (WebsocketRequester::Active(conn), exp, dline)
+    })
+}
+
+impl<H: ClientHandler> Requester for WebsocketRequester<H> {
+    type Context = H::Context;
+    fn prepare_request(self, req: &mut Request,
+        _scope: &mut Scope<H::Context>)
+        -> Option<Self>
+    {
+        let (handler, host, path, protocols) = match self {
+            WebsocketRequester::Prepare { handler, host, path, protocols }
+            => (handler, host, path, protocols),
+            _ => unreachable!(),
+        };
+        let key = new_key();
+        req.start("GET", &path, Version::Http11);
+        req.add_header("Host", host.as_bytes()).unwrap();
+        req.add_header("Upgrade", b"websocket").unwrap();
+        req.add_header("Connection", b"Upgrade").unwrap();
+        req.add_header("Sec-WebSocket-Key", key.as_bytes()).unwrap();
+        req.add_header("Sec-WebSocket-Version", VERSION.as_bytes()).unwrap();
+        if protocols.len() > 0 {
+            req.add_header("Sec-WebSocket-Protocol",
+                protocols.join(", ").as_bytes()).unwrap();
+        }
+        req.done_headers().unwrap();
+        req.done();
+        Some(WebsocketRequester::Handshake {
+            handler: handler,
+            key: key,
+            protocols: protocols,
+        })
+    }
+    fn headers_received(self, head: Head, _request: &mut Request,
+        scope: &mut Scope<H::Context>)
+        -> Option<(Self, RecvMode, Time)>
+    {
+        let (handler, key, protocols) = match self {
+            WebsocketRequester::Handshake { handler, key, protocols }
+            => (handler, key, protocols),
+            _ => unreachable!(),
+        };
+        let handler = match check_response(&head, &key, &protocols) {
+            Ok(protocol) => handler.accepted(&head, protocol, scope),
+            Err(e) => {
+                handler.handshake_failed(Some(&e), scope);
+                return None;
+            }
+        };
+        handler.map(|h| {
+            let deadline = scope.now() + h.idle_timeout(scope);
+            (WebsocketRequester::Active(Connection::new(h, true, scope)),
+             RecvMode::Buffered(0), deadline)
+        })
+    }
+    fn response_received(self, _data: &[u8], _request: &mut Request,
+        _scope: &mut Scope<H::Context>)
+    {
+        // Only 101 responses are accepted in `headers_received`
+        unreachable!();
+    }
+    fn response_chunk(self, _chunk: &[u8], _request: &mut Request,
+        _scope: &mut Scope<H::Context>)
+        -> Option<Self>
+    {
+        unreachable!();
+    }
+    fn response_end(self, _request: &mut Request,
+        _scope: &mut Scope<H::Context>)
+    {
+        unreachable!();
+    }
+    fn timeout(self, _request: &mut Request, scope: &mut Scope<H::Context>)
+        -> Option<(Self, Time)>
+    {
+        if let WebsocketRequester::Handshake { handler, .. } = self {
+            handler.handshake_failed(None, scope);
+        }
+        None
+    }
+    fn wakeup(self, _request: &mut Request, _scope: &mut Scope<H::Context>)
+        -> Option<Self>
+    {
+        Some(self)
+    }
+    fn byte_timeout(&self, _scope: &mut Scope<H::Context>) -> Duration {
+        Duration::new(HANDSHAKE_TIMEOUT, 0)
+    }
+    fn upgrade_started<S: StreamSocket>(self, transport: &mut Transport<S>,
+        scope: &mut Scope<H::Context>)
+        -> Option<(Self, Expectation, Time)>
+    {
+        match self {
+            WebsocketRequester::Active(conn) => {
+                active(conn.started(transport, scope))
+            }
+            _ => None,
+        }
+    }
+    fn upgrade_bytes_read<S: StreamSocket>(self,
+        transport: &mut Transport<S>, _end: usize,
+        scope: &mut Scope<H::Context>)
+        -> Option<(Self, Expectation, Time)>
+    {
+        match self {
+            WebsocketRequester::Active(conn) => {
+                active(conn.bytes_read(transport, scope))
+            }
+            _ => None,
+        }
+    }
+    fn upgrade_bytes_flushed<S: StreamSocket>(self,
+        _transport: &mut Transport<S>, _scope: &mut Scope<H::Context>)
+        -> Option<(Self, Expectation, Time)>
+    {
+        match self {
+            WebsocketRequester::Active(conn) => active(conn.bytes_flushed()),
+            _ => None,
+        }
+    }
+    fn upgrade_timeout<S: StreamSocket>(self, transport: &mut Transport<S>,
+        scope: &mut Scope<H::Context>)
+        -> Option<(Self, Expectation, Time)>
+    {
+        match self {
+            WebsocketRequester::Active(conn) => {
+                active(conn.timeout(transport, scope))
+            }
+            _ => None,
+        }
+    }
+    fn upgrade_wakeup<S: StreamSocket>(self, transport: &mut Transport<S>,
+        scope: &mut Scope<H::Context>)
+        -> Option<(Self, Expectation, Time)>
+    {
+        match self {
+            WebsocketRequester::Active(conn) => {
+                active(conn.wakeup(transport, scope))
+            }
+            _ => None,
+        }
+    }
+    fn upgrade_end<S: StreamSocket>(self, _transport: &mut Transport<S>,
+        _reason: Exception, scope: &mut Scope<H::Context>)
+    {
+        if let WebsocketRequester::Active(conn) = self {
+            conn.end(scope);
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use rotor::{Scope, EventSet, Machine};
+    use rotor_test::{MemIo, MockLoop};
+    use client::{Client, Connection, Task, Head, Fsm};
+    use websocket::{Handler, ClientHandler, WebsocketRequester};
+    use websocket::{ClientHandshakeError, Message, Writer};
+
+    #[derive(Debug, PartialEq, Eq, Default)]
+    struct Context {
+        accepted: bool,
+        failed: bool,
+    }
+
+    #[derive(Debug)]
+    struct Chat;
+
+    impl Handler for Chat {
+        type Context = Context;
+        fn message_received(self, _message: Message, _output: &mut Writer,
+            _scope: &mut Scope<Context>)
+            -> Option<Self>
+        {
+            unreachable!();
+        }
+        fn timeout(self, _output: &mut Writer, _scope: &mut Scope<Context>)
+            -> Option<Self>
+        {
+            unimplemented!();
+        }
+        fn wakeup(self, _output: &mut Writer, _scope: &mut Scope<Context>)
+            -> Option<Self>
+        {
+            unimplemented!();
+        }
+    }
+
+    impl ClientHandler for Chat {
+        fn accepted(self, _head: &Head, _protocol: Option<&str>,
+            scope: &mut Scope<Context>)
+            -> Option<Self>
+        {
+            scope.accepted = true;
+            Some(Chat)
+        }
+        fn handshake_failed(self, _error: Option<&ClientHandshakeError>,
+            scope: &mut Scope<Context>)
+        {
+            scope.failed = true;
+        }
+    }
+
+    struct Cli;
+
+    impl Client for Cli {
+        type Requester = WebsocketRequester<Chat>;
+        type Seed = ();
+        fn create((): (), _scope: &mut Scope<Context>) -> Self {
+            Cli
+        }
+        fn connection_idle(self, _conn: &Connection,
+            _scope: &mut Scope<Context>)
+            -> Task<Cli>
+        {
+            Task::Request(Cli, WebsocketRequester::new(Chat,
+                "example.com", "/chat", &["chat"]))
+        }
+        fn wakeup(self, _conn: &Connection, _scope: &mut Scope<Context>)
+            -> Task<Cli>
+        {
+            unimplemented!();
+        }
+        fn timeout(self, _conn: &Connection, _scope: &mut Scope<Context>)
+            -> Task<Cli>
+        {
+            unimplemented!();
+        }
+    }
+
+    #[test]
+    fn rejected() {
+        let mut io = MemIo::new();
+        let mut lp = MockLoop::new(Default::default());
+        io.push_bytes(b"HTTP/1.1 403 Forbidden\r\nContent-Length: 0\r\n\r\n");
+        let m = Fsm::<Cli, MemIo>::connected(io.clone(), (), &mut lp.scope(1))
+            .expect_machine();
+        let _ = m.ready(EventSet::readable(), &mut lp.scope(1));
+        assert_eq!(*lp.ctx(), Context {
+            accepted: false,
+            failed: true,
+        });
+    }
+}
@@ -0,0 +1,278 @@
+use std::time::Duration;
+
+use rotor::{Scope, Time};
+use rotor_stream::{Buf, Expectation, StreamSocket, Transport};
+
+use super::reader::{Reader, Message, Event, Status};
+use super::writer::Writer;
+
+
+/// A time to wait for the peer to close connection after close frame is sent
+const CLOSE_TIMEOUT: u64 = 10;
+
+/// A handler of an established websocket connection
+///
+/// This part of the interface is the same for client and server, see
+/// `WebsocketHandler` and `ClientHandler` for the handshake part.
+///
+/// Any handler returning `None` closes the connection. If close frame
+/// is not sent yet, the normal closure (1000) is sent before closing.
+pub trait Handler: Sized {
+    type Context;
+
+    /// Handshake is done, you may start sending messages
+    fn opened(self, _output: &mut Writer, _scope: &mut Scope<Self::Context>)
+        -> Option<Self>
+    {
+        Some(self)
+    }
+
+    /// Complete message received
+    ///
+    /// Fragmented messages are assembled before the call, and text
+    /// messages are validated to be utf-8.
+    fn message_received(self, message: Message, output: &mut Writer,
+        scope: &mut Scope<Self::Context>)
+        -> Option<Self>;
+
+    /// Ping received
+    ///
+    /// Pong is already put into the output buffer when the handler
+    /// is called.
+    fn ping(self, _data: &[u8], _output: &mut Writer,
+        _scope: &mut Scope<Self::Context>)
+        -> Option<Self>
+    {
+        Some(self)
+    }
+
+    /// Pong received
+    fn pong(self, _data: &[u8], _output: &mut Writer,
+        _scope: &mut Scope<Self::Context>)
+        -> Option<Self>
+    {
+        Some(self)
+    }
+
+    /// Connection is closed
+    ///
+    /// The `code` is either received from the peer in a close frame,
+    /// or the one we've sent because of protocol error. The 1005 is used
+    /// when close frame contains no code and 1006 when connection is closed
+    /// abnormally (i.e. without close frame).
+    fn close(self, _code: u16, _reason: &str,
+        _scope: &mut Scope<Self::Context>)
+    {}
+
+    /// Nothing is received during `idle_timeout()`
+    ///
+    /// This is a good place to send a ping.
+    fn timeout(self, output: &mut Writer, scope: &mut Scope<Self::Context>)
+        -> Option<Self>;
+
+    /// Standard rotor's wakeup handler
+    fn wakeup(self, output: &mut Writer, scope: &mut Scope<Self::Context>)
+        -> Option<Self>;
+
+    /// Time to wait for the next frame before calling `timeout()`
+    ///
+    /// Default is 120 seconds
+    fn idle_timeout(&self, _scope: &mut Scope<Self::Context>) -> Duration {
+        Duration::new(120, 0)
+    }
+
+    /// Maximum size of the message (after defragmentation)
+    ///
+    /// When exceeded connection is closed with 1009 code. Default is 1MiB.
+    fn max_message_size(&self, _scope: &mut Scope<Self::Context>) -> usize {
+        1048576
+    }
+}
+
+/// The state of the connection after handshake
+///
+/// Implements `upgrade_*` handlers for both `server::Server` and
+/// `client::Requester`.
+#[derive(Debug)]
+pub enum Connection<H: Handler> {
+    Open {
+        handler: H,
+        reader: Reader,
+        close_sent: bool,
+        /// Frames we send are masked (i.e. we are the client)
+        client: bool,
+    },
+    /// Close frame is sent, flushing the buffer before closing connection
+    Closing,
+}
+
+fn new_writer(buf: &mut Buf, close_sent: bool, client: bool) -> Writer {
+    if client {
+        Writer::client(buf, close_sent)
+    } else {
+        Writer::new(buf, close_sent)
+    }
+}
+
+impl<H: Handler> Connection<H> {
+    pub fn new(handler: H, client: bool, scope: &mut Scope<H::Context>)
+        -> Connection<H>
+    {
+        let limit = handler.max_message_size(scope);
+        Connection::Open {
+            handler: handler,
+            // Only frames sent by client are masked
+            reader: Reader::new(!client, limit),
+            close_sent: false,
+            client: client,
+        }
+    }
+    fn closing(scope: &mut Scope<H::Context>)
+        -> Option<(Self, Expectation, Time)>
+    {
+        Some((Connection::Closing, Expectation::Flush(0),
+              scope.now() + Duration::new(CLOSE_TIMEOUT, 0)))
+    }
+    fn open(handler: H, reader: Reader, close_sent: bool, client: bool,
+        need: usize, scope: &mut Scope<H::Context>)
+        -> Option<(Self, Expectation, Time)>
+    {
+        let deadline = scope.now() + handler.idle_timeout(scope);
+        Some((Connection::Open {
+            handler: handler,
+            reader: reader,
+            close_sent: close_sent,
+            client: client,
+        }, Expectation::Bytes(need), deadline))
+    }
+    /// Calls the handler with the writer and proceeds to the next state
+    fn call<S, F>(self, transport: &mut Transport<S>,
+        scope: &mut Scope<H::Context>, f: F)
+        -> Option<(Self, Expectation, Time)>
+        where S: StreamSocket,
+              F: FnOnce(H, &mut Writer, &mut Scope<H::Context>) -> Option<H>
+    {
+        match self {
+            Connection::Open { handler, reader, close_sent, client } => {
+                let mut writer = new_writer(transport.output(),
+                                        close_sent, client);
+                match f(handler, &mut writer, scope) {
+                    Some(h) => {
+                        let close_sent = writer.is_closed();
+                        // Expectation is updated in `bytes_read` by reading
+                        // frame header
+                        Connection::open(h, reader, close_sent, client,
+                                         2, scope)
+                    }
+                    None => {
+                        writer.close(1000, "");
+                        Connection::closing(scope)
+                    }
+                }
+            }
+            Connection::Closing => None,
+        }
+    }
+    /// Handshake is complete, calls `Handler::opened`
+    pub fn started<S: StreamSocket>(self, transport: &mut Transport<S>,
+        scope: &mut Scope<H::Context>)
+        -> Option<(Self, Expectation, Time)>
+    {
+        self.call(transport, scope, |h, out, scope| h.opened(out, scope))
+    }
+    /// Reads frames from the input buffer and calls the handler for each
+    pub fn bytes_read<S: StreamSocket>(self, transport: &mut Transport<S>,
+        scope: &mut Scope<H::Context>)
+        -> Option<(Self, Expectation, Time)>
+    {
+        let (mut handler, mut reader, mut close_sent, client) = match self {
+            Connection::Open { handler, reader, close_sent, client }
+            => (handler, reader, close_sent, client),
+            Connection::Closing => return None,
+        };
+        let need;
+        {
+            let (inp, out) = transport.buffers();
+            loop {
+                let mut writer = new_writer(out, close_sent, client);
+                let res = match reader.read(inp) {
+                    Ok(Status::Partial(bytes)) => {
+                        need = bytes;
+                        break;
+                    }
+                    Ok(Status::Complete(Event::Message(_))) if close_sent => {
+                        // Discard messages after close frame is sent
+                        Some(handler)
+                    }
+                    Ok(Status::Complete(Event::Message(msg))) => {
+                        handler.message_received(msg, &mut writer, scope)
+                    }
+                    Ok(Status::Complete(Event::Ping(data))) => {
+                        if !close_sent {
+                            writer.pong(data);
+                        }
+                        handler.ping(data, &mut writer, scope)
+                    }
+                    Ok(Status::Complete(Event::Pong(data))) => {
+                        handler.pong(data, &mut writer, scope)
+                    }
+                    Ok(Status::Complete(Event::Close(code, reason))) => {
+                        writer.close(if code == 1005 { 1000 } else { code },
+                                     "");
+                        handler.close(code, reason, scope);
+                        return Connection::closing(scope);
+                    }
+                    Err(e) => {
+                        let code = e.close_code();
+                        writer.close(code, "");
+                        handler.close(code, &e.to_string(), scope);
+                        return Connection::closing(scope);
+                    }
+                };
+                handler = match res {
+                    Some(h) => h,
+                    None => {
+                        writer.close(1000, "");
+                        return Connection::closing(scope);
+                    }
+                };
+                close_sent = writer.is_closed();
+            }
+        }
+        Connection::open(handler, reader, close_sent, client, need, scope)
+    }
+    /// Output is flushed, only `Closing` state waits for it
+    pub fn bytes_flushed(self) -> Option<(Self, Expectation, Time)> {
+        None
+    }
+    pub fn timeout<S: StreamSocket>(self, transport: &mut Transport<S>,
+        scope: &mut Scope<H::Context>)
+        -> Option<(Self, Expectation, Time)>
+    {
+        match self {
+            Connection::Open { handler, close_sent: true, .. } => {
+                // Peer has not replied to our close frame
+                handler.close(1006, "", scope);
+                None
+            }
+            me => me.call(transport, scope,
+                          |h, out, scope| h.timeout(out, scope)),
+        }
+    }
+    pub fn wakeup<S: StreamSocket>(self, transport: &mut Transport<S>,
+        scope: &mut Scope<H::Context>)
+        -> Option<(Self, Expectation, Time)>
+    {
+        match self {
+            Connection::Closing => Connection::closing(scope),
+            me => me.call(transport, scope,
+                          |h, out, scope| h.wakeup(out, scope)),
+        }
+    }
+    /// Connection is closed by the peer or because of an error
+    pub fn end(self, scope: &mut Scope<H::Context>) {
+        if let Connection::Open { handler, .. } = self {
+            handler.close(1006, "", scope);
+        }
+    }
+}
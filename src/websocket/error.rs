@@ -0,0 +1,138 @@
+use std::str::Utf8Error;
+
+use server::HttpError;
+
+
+quick_error!{
+    /// Error which violates websocket protocol
+    ///
+    /// The connection is closed with a close code returned by `close_code()`
+    ///
+    /// Note, you should not match the enum values and/or make an exhaustive
+    /// match over the enum. More errors will be added at will.
+    #[derive(Debug)]
+    pub enum ProtocolError {
+        ReservedBits {
+            description("reserved bits are set in frame header")
+        }
+        UnknownOpcode(opcode: u8) {
+            description("unknown opcode")
+            display("unknown opcode {:x}", opcode)
+        }
+        FragmentedControlFrame {
+            description("control frame is fragmented")
+        }
+        ControlFrameTooLarge {
+            description("control frame payload is larger than 125 bytes")
+        }
+        FrameTooLarge {
+            description("most significant bit of payload length is set")
+        }
+        UnmaskedFrame {
+            description("client sent unmasked frame")
+        }
+        MaskedFrame {
+            description("server sent masked frame")
+        }
+        UnexpectedContinuation {
+            description("continuation frame without a message to continue")
+        }
+        ExpectedContinuation {
+            description("new message started before previous one finished")
+        }
+        MessageTooLarge(size: u64, limit: usize) {
+            description("message is larger than allowed")
+            display("message size is at least {} but maximum is {}",
+                    size, limit)
+        }
+        BadUtf8(err: Utf8Error) {
+            from()
+            description("bad utf8 in text message or close reason")
+        }
+        BadCloseFrame {
+            description("close frame payload is one byte long")
+        }
+        BadCloseCode(code: u16) {
+            description("invalid close code")
+            display("invalid close code {}", code)
+        }
+    }
+}
+
+impl ProtocolError {
+    /// A close code which is sent to the peer on this error
+    pub fn close_code(&self) -> u16 {
+        use self::ProtocolError::*;
+        match *self {
+            MessageTooLarge(..) => 1009,
+            BadUtf8(..) => 1007,
+            _ => 1002,
+        }
+    }
+}
+
+quick_error!{
+    /// Error in websocket opening handshake on the server side
+    ///
+    /// Error page is rendered by `emit_error_page` through the `HttpError`
+    /// trait.
+    #[derive(Debug)]
+    pub enum HandshakeError {
+        NotUpgrade {
+            description("request is not a connection upgrade")
+        }
+        BadMethod {
+            description("websocket handshake must be a GET request")
+        }
+        NotWebsocket {
+            description("`Upgrade` header doesn't contain `websocket`")
+        }
+        NoHost {
+            description("no `Host` header")
+        }
+        BadKey {
+            description("`Sec-WebSocket-Key` is absent or invalid")
+        }
+        UnsupportedVersion {
+            description("`Sec-WebSocket-Version` is absent or unsupported")
+        }
+        Rejected {
+            description("websocket connection rejected by handler")
+        }
+    }
+}
+
+impl HttpError for HandshakeError {
+    fn http_status(&self) -> (u16, &'static str) {
+        use self::HandshakeError::*;
+        match *self {
+            NotUpgrade => (426, "Upgrade Required"),
+            BadMethod => (405, "Method Not Allowed"),
+            NotWebsocket => (400, "Bad Request"),
+            NoHost => (400, "Bad Request"),
+            BadKey => (400, "Bad Request"),
+            UnsupportedVersion => (426, "Upgrade Required"),
+            Rejected => (403, "Forbidden"),
+        }
+    }
+}
+
+quick_error!{
+    /// Error in websocket opening handshake on the client side
+    #[derive(Debug)]
+    pub enum ClientHandshakeError {
+        BadStatus(code: u16) {
+            description("server responded with a status other than 101")
+            display("server responded with status {}", code)
+        }
+        NotWebsocket {
+            description("response is not a websocket upgrade")
+        }
+        BadAccept {
+            description("`Sec-WebSocket-Accept` is absent or invalid")
+        }
+        BadProtocol {
+            description("server chose a subprotocol which was not requested")
+        }
+    }
+}
@@ -0,0 +1,289 @@
+use std::io::Write;
+
+use rotor_stream::Buf;
+
+use super::error::ProtocolError;
+
+
+/// Maximum payload length of a control frame (ping, pong, close)
+pub const MAX_CONTROL_PAYLOAD: usize = 125;
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Opcode {
+    Continuation,
+    Text,
+    Binary,
+    Close,
+    Ping,
+    Pong,
+}
+
+/// Parsed frame header
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Header {
+    /// This is the final fragment of the message
+    pub fin: bool,
+    pub opcode: Opcode,
+    /// Masking key, always present in frames sent by client
+    pub mask: Option<[u8; 4]>,
+    /// Size of the header itself in bytes
+    pub header_size: usize,
+    /// Size of the payload in bytes (excluding header)
+    pub payload_size: u64,
+}
+
+impl Opcode {
+    pub fn from_u8(val: u8) -> Option<Opcode> {
+        use self::Opcode::*;
+        match val {
+            0x0 => Some(Continuation),
+            0x1 => Some(Text),
+            0x2 => Some(Binary),
+            0x8 => Some(Close),
+            0x9 => Some(Ping),
+            0xA => Some(Pong),
+            _ => None,
+        }
+    }
+    pub fn as_u8(self) -> u8 {
+        use self::Opcode::*;
+        match self {
+            Continuation => 0x0,
+            Text => 0x1,
+            Binary => 0x2,
+            Close => 0x8,
+            Ping => 0x9,
+            Pong => 0xA,
+        }
+    }
+    /// Returns true for close, ping and pong frames
+    pub fn is_control(self) -> bool {
+        self.as_u8() & 0x8 != 0
+    }
+}
+
+/// Returns the size of the frame header
+///
+/// Returns `None` if there are less than two bytes in the `data`, which
+/// is the minimum required to determine the size of the header.
+pub fn header_size(data: &[u8]) -> Option<usize> {
+    if data.len() < 2 {
+        return None;
+    }
+    let len_size = match data[1] & 0x7F {
+        126 => 2,
+        127 => 8,
+        _ => 0,
+    };
+    let mask_size = if data[1] & 0x80 != 0 { 4 } else { 0 };
+    Some(2 + len_size + mask_size)
+}
+
+/// Parses frame header
+///
+/// The `data` must contain at least `header_size()` bytes.
+///
+/// Only validates the header itself: reserved bits, opcode and properties
+/// of control frames. Whether frame must be masked and whether the opcode
+/// makes sense in the current state is checked by the caller.
+pub fn parse_header(data: &[u8]) -> Result<Header, ProtocolError> {
+    use super::error::ProtocolError::*;
+    let hsize = header_size(data).expect("at least two bytes of a header");
+    assert!(data.len() >= hsize);
+    if data[0] & 0x70 != 0 {
+        // No extensions are supported yet, so any reserved bit is an error
+        return Err(ReservedBits);
+    }
+    let fin = data[0] & 0x80 != 0;
+    let opcode = match Opcode::from_u8(data[0] & 0x0F) {
+        Some(opcode) => opcode,
+        None => return Err(UnknownOpcode(data[0] & 0x0F)),
+    };
+    let (payload_size, off) = match data[1] & 0x7F {
+        126 => (((data[2] as u64) << 8) | data[3] as u64, 4),
+        127 => {
+            let mut size = 0u64;
+            for &byte in &data[2..10] {
+                size = (size << 8) | byte as u64;
+            }
+            if size & (1 << 63) != 0 {
+                return Err(FrameTooLarge);
+            }
+            (size, 10)
+        }
+        x => (x as u64, 2),
+    };
+    let mask = if data[1] & 0x80 != 0 {
+        Some([data[off], data[off+1], data[off+2], data[off+3]])
+    } else {
+        None
+    };
+    if opcode.is_control() {
+        if !fin {
+            return Err(FragmentedControlFrame);
+        }
+        if payload_size > MAX_CONTROL_PAYLOAD as u64 {
+            return Err(ControlFrameTooLarge);
+        }
+    }
+    Ok(Header {
+        fin: fin,
+        opcode: opcode,
+        mask: mask,
+        header_size: hsize,
+        payload_size: payload_size,
+    })
+}
+
+/// Applies (or removes, which is the same) a mask to the data
+///
+/// The `offset` is the position of `data[0]` in the frame payload, so
+/// payload may be unmasked piece by piece.
+pub fn apply_mask(data: &mut [u8], mask: [u8; 4], offset: usize) {
+    for (idx, byte) in data.iter_mut().enumerate() {
+        *byte ^= mask[(offset + idx) & 3];
+    }
+}
+
+/// Writes a single frame into the buffer
+///
+/// Client must supply a `mask`, server must not.
+pub fn write_frame(buf: &mut Buf, fin: bool, opcode: Opcode,
+    mask: Option<[u8; 4]>, data: &[u8])
+{
+    let first = opcode.as_u8() | if fin { 0x80 } else { 0 };
+    let mask_bit = if mask.is_some() { 0x80 } else { 0 };
+    let len = data.len() as u64;
+    if len < 126 {
+        buf.write_all(&[first, mask_bit | len as u8]).unwrap();
+    } else if len < 65536 {
+        buf.write_all(&[first, mask_bit | 126,
+                        (len >> 8) as u8, len as u8]).unwrap();
+    } else {
+        buf.write_all(&[first, mask_bit | 127,
+                        (len >> 56) as u8, (len >> 48) as u8,
+                        (len >> 40) as u8, (len >> 32) as u8,
+                        (len >> 24) as u8, (len >> 16) as u8,
+                        (len >> 8) as u8, len as u8]).unwrap();
+    }
+    match mask {
+        Some(mask) => {
+            buf.write_all(&mask).unwrap();
+            let mut tmp = [0u8; 4096];
+            let mut offset = 0;
+            for piece in data.chunks(tmp.len()) {
+                let tmp = &mut tmp[..piece.len()];
+                tmp.copy_from_slice(piece);
+                apply_mask(tmp, mask, offset);
+                buf.write_all(tmp).unwrap();
+                offset += piece.len();
+            }
+        }
+        None => {
+            buf.write_all(data).unwrap();
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use rotor_stream::Buf;
+    use super::{Header, Opcode, header_size, parse_header};
+    use super::{apply_mask, write_frame};
+    use websocket::error::ProtocolError;
+
+    // Examples are from RFC 6455 Section 5.7
+
+    #[test]
+    fn unmasked_text() {
+        let data = b"\x81\x05\x48\x65\x6c\x6c\x6f";
+        assert_eq!(header_size(data), Some(2));
+        assert_eq!(parse_header(data).unwrap(), Header {
+            fin: true,
+            opcode: Opcode::Text,
+            mask: None,
+            header_size: 2,
+            payload_size: 5,
+        });
+        let mut buf = Buf::new();
+        write_frame(&mut buf, true, Opcode::Text, None, b"Hello");
+        assert_eq!(&buf[..], &data[..]);
+    }
+
+    #[test]
+    fn masked_text() {
+        let data = b"\x81\x85\x37\xfa\x21\x3d\x7f\x9f\x4d\x51\x58";
+        assert_eq!(header_size(data), Some(6));
+        let hdr = parse_header(data).unwrap();
+        assert_eq!(hdr, Header {
+            fin: true,
+            opcode: Opcode::Text,
+            mask: Some([0x37, 0xfa, 0x21, 0x3d]),
+            header_size: 6,
+            payload_size: 5,
+        });
+        let mut payload = data[6..].to_vec();
+        apply_mask(&mut payload[..2], hdr.mask.unwrap(), 0);
+        apply_mask(&mut payload[2..], hdr.mask.unwrap(), 2);
+        assert_eq!(&payload[..], b"Hello");
+        let mut buf = Buf::new();
+        write_frame(&mut buf, true, Opcode::Text, hdr.mask, b"Hello");
+        assert_eq!(&buf[..], &data[..]);
+    }
+
+    #[test]
+    fn fragmented() {
+        let first = b"\x01\x03\x48\x65\x6c";
+        let second = b"\x80\x02\x6c\x6f";
+        let hdr = parse_header(first).unwrap();
+        assert_eq!((hdr.fin, hdr.opcode), (false, Opcode::Text));
+        let hdr = parse_header(second).unwrap();
+        assert_eq!((hdr.fin, hdr.opcode), (true, Opcode::Continuation));
+    }
+
+    #[test]
+    fn ping() {
+        let data = b"\x89\x05\x48\x65\x6c\x6c\x6f";
+        let hdr = parse_header(data).unwrap();
+        assert_eq!((hdr.fin, hdr.opcode), (true, Opcode::Ping));
+        assert!(hdr.opcode.is_control());
+    }
+
+    #[test]
+    fn medium_length() {
+        let data = vec![0u8; 256];
+        let mut buf = Buf::new();
+        write_frame(&mut buf, true, Opcode::Binary, None, &data);
+        assert_eq!(&buf[..4], b"\x82\x7E\x01\x00");
+        assert_eq!(header_size(&buf[..]), Some(4));
+        assert_eq!(parse_header(&buf[..]).unwrap().payload_size, 256);
+        assert_eq!(buf.len(), 260);
+    }
+
+    #[test]
+    fn long_length() {
+        let data = vec![0u8; 65536];
+        let mut buf = Buf::new();
+        write_frame(&mut buf, true, Opcode::Binary, None, &data);
+        assert_eq!(&buf[..10],
+                   b"\x82\x7F\x00\x00\x00\x00\x00\x01\x00\x00");
+        assert_eq!(header_size(&buf[..]), Some(10));
+        assert_eq!(parse_header(&buf[..]).unwrap().payload_size, 65536);
+    }
+
+    #[test]
+    fn errors() {
+        assert!(matches!(parse_header(b"\xC1\x00"),
+            Err(ProtocolError::ReservedBits)));
+        assert!(matches!(parse_header(b"\x83\x00"),
+            Err(ProtocolError::UnknownOpcode(3))));
+        assert!(matches!(parse_header(b"\x09\x00"),
+            Err(ProtocolError::FragmentedControlFrame)));
+        assert!(matches!(parse_header(b"\x89\x7E\x00\x7E"),
+            Err(ProtocolError::ControlFrameTooLarge)));
+        assert!(matches!(
+            parse_header(b"\x82\x7F\x80\x00\x00\x00\x00\x00\x00\x00"),
+            Err(ProtocolError::FrameTooLarge)));
+        assert_eq!(header_size(b"\x81"), None);
+    }
+}
@@ -0,0 +1,269 @@
+use std::str::from_utf8;
+use std::ascii::AsciiExt;
+
+use rand::random;
+use rustc_serialize::base64::{ToBase64, FromBase64, STANDARD};
+use sha1::Sha1;
+
+use client;
+use headers;
+use server::{Head, BodyKind};
+use super::error::{HandshakeError, ClientHandshakeError};
+
+
+/// The GUID which is appended to the key to compute accept header
+pub const GUID: &'static str = "258EAFA5-E914-47DA-95CA-C5AB0DC85B11";
+
+/// The only websocket version supported
+pub const VERSION: &'static str = "13";
+
+/// Parsed websocket opening handshake request
+#[derive(Debug)]
+pub struct Handshake<'a> {
+    /// The value of `Sec-WebSocket-Key` header
+    pub key: &'a str,
+    /// Subprotocols requested by the client in order of preference
+    pub protocols: Vec<&'a str>,
+}
+
+/// Computes the value of `Sec-WebSocket-Accept` for the key
+pub fn accept_key(key: &[u8]) -> String {
+    let mut sha = Sha1::new();
+    sha.update(key);
+    sha.update(GUID.as_bytes());
+    sha.digest().bytes().to_base64(STANDARD)
+}
+
+/// Generates a random value for `Sec-WebSocket-Key` header
+pub fn new_key() -> String {
+    random::<[u8; 16]>().to_base64(STANDARD)
+}
+
+fn has_token(value: &[u8], token: &str) -> bool {
+    value.split(|&x| x == b',')
+        .filter_map(|x| from_utf8(x).ok())
+        .any(|x| x.trim().eq_ignore_ascii_case(token))
+}
+
+/// Validates websocket opening handshake
+///
+/// Note `Connection: upgrade` header is already checked when
+/// `head.body_kind` is `BodyKind::Upgrade`.
+pub fn parse_request<'a>(head: &Head<'a>)
+    -> Result<Handshake<'a>, HandshakeError>
+{
+    use super::error::HandshakeError::*;
+    if head.body_kind != BodyKind::Upgrade {
+        return Err(NotUpgrade);
+    }
+    if head.method != "GET" {
+        return Err(BadMethod);
+    }
+    let mut websocket = false;
+    let mut host = false;
+    let mut version = false;
+    let mut key = None;
+    let mut protocols = Vec::new();
+    for header in head.headers {
+        if header.name.eq_ignore_ascii_case("Upgrade") {
+            websocket = has_token(header.value, "websocket");
+        } else if header.name.eq_ignore_ascii_case("Host") {
+            host = true;
+        } else if header.name.eq_ignore_ascii_case("Sec-WebSocket-Version") {
+            version = from_utf8(header.value).ok()
+                .map(|x| x.trim() == VERSION).unwrap_or(false);
+        } else if header.name.eq_ignore_ascii_case("Sec-WebSocket-Key") {
+            if key.is_some() {
+                return Err(BadKey);
+            }
+            key = from_utf8(header.value).ok().map(|x| x.trim());
+        } else if header.name.eq_ignore_ascii_case("Sec-WebSocket-Protocol")
+        {
+            if let Ok(value) = from_utf8(header.value) {
+                protocols.extend(value.split(',')
+                    .map(|x| x.trim()).filter(|x| x.len() > 0));
+            }
+        }
+    }
+    if !websocket {
+        return Err(NotWebsocket);
+    }
+    if !host {
+        return Err(NoHost);
+    }
+    if !version {
+        return Err(UnsupportedVersion);
+    }
+    let key = match key {
+        Some(key) if key.from_base64().map(|x| x.len() == 16)
+                        .unwrap_or(false) => key,
+        _ => return Err(BadKey),
+    };
+    Ok(Handshake {
+        key: key,
+        protocols: protocols,
+    })
+}
+
+/// Validates websocket opening handshake response
+///
+/// The `key` is the one sent in the request, and the `protocols` are the
+/// requested subprotocols. Returns the subprotocol chosen by server if any.
+pub fn check_response<'a, S: AsRef<str>>(head: &client::Head<'a>, key: &str,
+    protocols: &[S])
+    -> Result<Option<&'a str>, ClientHandshakeError>
+{
+    use super::error::ClientHandshakeError::*;
+    if head.code != 101 {
+        return Err(BadStatus(head.code));
+    }
+    let mut websocket = false;
+    let mut upgrade = false;
+    let mut accept = false;
+    let mut protocol = None;
+    for header in head.headers {
+        if header.name.eq_ignore_ascii_case("Upgrade") {
+            websocket = has_token(header.value, "websocket");
+        } else if headers::is_connection(header.name) {
+            upgrade = header.value.split(|&x| x == b',')
+                .any(headers::is_upgrade_token);
+        } else if header.name.eq_ignore_ascii_case("Sec-WebSocket-Accept") {
+            accept = from_utf8(header.value).ok()
+                .map(|x| x.trim() == accept_key(key.as_bytes()))
+                .unwrap_or(false);
+        } else if header.name.eq_ignore_ascii_case("Sec-WebSocket-Protocol")
+        {
+            let value = try!(from_utf8(header.value).map_err(|_| BadProtocol))
+                .trim();
+            if protocol.is_some() ||
+                !protocols.iter().any(|x| x.as_ref() == value)
+            {
+                return Err(BadProtocol);
+            }
+            protocol = Some(value);
+        }
+    }
+    if !websocket || !upgrade {
+        return Err(NotWebsocket);
+    }
+    if !accept {
+        return Err(BadAccept);
+    }
+    Ok(protocol)
+}
+
+#[cfg(test)]
+mod test {
+    use httparse::Header;
+    use client;
+    use server::{Head, BodyKind, Version};
+    use websocket::error::{HandshakeError, ClientHandshakeError};
+    use super::{accept_key, parse_request, check_response};
+
+    fn head<'x>(headers: &'x [Header<'x>]) -> Head<'x> {
+        Head {
+            client: None,
+            tls: None,
+            version: Version::Http11,
+            method: "GET",
+            scheme: "http",
+            path: "/chat",
+            headers: headers,
+            body_kind: BodyKind::Upgrade,
+        }
+    }
+
+    #[test]
+    fn rfc_example() {
+        assert_eq!(accept_key(b"dGhlIHNhbXBsZSBub25jZQ=="),
+                   "s3pPLMBiTxaQ9kYGzzhZRbK+xOo=");
+    }
+
+    #[test]
+    fn valid() {
+        let headers = [
+            Header { name: "Host", value: b"server.example.com" },
+            Header { name: "Upgrade", value: b"websocket" },
+            Header { name: "Connection", value: b"Upgrade" },
+            Header { name: "Sec-WebSocket-Key",
+                     value: b"dGhlIHNhbXBsZSBub25jZQ==" },
+            Header { name: "Sec-WebSocket-Protocol", value: b"chat, superchat" },
+            Header { name: "Sec-WebSocket-Version", value: b"13" },
+        ];
+        let hs = parse_request(&head(&headers)).unwrap();
+        assert_eq!(hs.key, "dGhlIHNhbXBsZSBub25jZQ==");
+        assert_eq!(hs.protocols, vec!["chat", "superchat"]);
+    }
+
+    #[test]
+    fn bad_version() {
+        let headers = [
+            Header { name: "Host", value: b"server.example.com" },
+            Header { name: "Upgrade", value: b"WebSocket" },
+            Header { name: "Sec-WebSocket-Key",
+                     value: b"dGhlIHNhbXBsZSBub25jZQ==" },
+            Header { name: "Sec-WebSocket-Version", value: b"8" },
+        ];
+        assert!(matches!(parse_request(&head(&headers)),
+            Err(HandshakeError::UnsupportedVersion)));
+    }
+
+    #[test]
+    fn bad_key() {
+        let headers = [
+            Header { name: "Host", value: b"server.example.com" },
+            Header { name: "Upgrade", value: b"websocket" },
+            Header { name: "Sec-WebSocket-Key", value: b"c2hvcnQ=" },
+            Header { name: "Sec-WebSocket-Version", value: b"13" },
+        ];
+        assert!(matches!(parse_request(&head(&headers)),
+            Err(HandshakeError::BadKey)));
+    }
+
+    fn response<'x>(code: u16, headers: &'x [Header<'x>])
+        -> client::Head<'x>
+    {
+        client::Head {
+            version: Version::Http11,
+            code: code,
+            reason: "Switching Protocols",
+            headers: headers,
+            body_kind: client::BodyKind::Upgrade,
+            close: true,
+        }
+    }
+
+    #[test]
+    fn valid_response() {
+        let headers = [
+            Header { name: "Upgrade", value: b"websocket" },
+            Header { name: "Connection", value: b"Upgrade" },
+            Header { name: "Sec-WebSocket-Accept",
+                     value: b"s3pPLMBiTxaQ9kYGzzhZRbK+xOo=" },
+            Header { name: "Sec-WebSocket-Protocol", value: b"chat" },
+        ];
+        assert_eq!(check_response(&response(101, &headers),
+            "dGhlIHNhbXBsZSBub25jZQ==", &["superchat", "chat"]).unwrap(),
+            Some("chat"));
+    }
+
+    #[test]
+    fn bad_response() {
+        let headers = [
+            Header { name: "Upgrade", value: b"websocket" },
+            Header { name: "Connection", value: b"Upgrade" },
+            Header { name: "Sec-WebSocket-Accept",
+                     value: b"s3pPLMBiTxaQ9kYGzzhZRbK+xOo=" },
+            Header { name: "Sec-WebSocket-Protocol", value: b"chat" },
+        ];
+        let key = "dGhlIHNhbXBsZSBub25jZQ==";
+        assert!(matches!(check_response(&response(101, &headers), key,
+            &["superchat"]), Err(ClientHandshakeError::BadProtocol)));
+        assert!(matches!(check_response(&response(200, &headers), key,
+            &["chat"]), Err(ClientHandshakeError::BadStatus(200))));
+        assert!(matches!(check_response(&response(101, &headers),
+            "c2hvcnQ=", &["chat"]), Err(ClientHandshakeError::BadAccept)));
+        assert!(matches!(check_response(&response(101, &headers[1..]), key,
+            &["chat"]), Err(ClientHandshakeError::NotWebsocket)));
+    }
+}
@@ -0,0 +1,30 @@
+//! Websocket (RFC 6455) implementation
+//!
+//! Server-side websockets work on top of the connection upgrade support of
+//! the `server` module. Implement `Handler` and `WebsocketHandler` and use
+//! `WebsocketServer<YourHandler>` as a `server::Server`.
+//!
+//! Client-side websockets work on top of the `client` module. Implement
+//! `Handler` and `ClientHandler` and return
+//! `WebsocketRequester<YourHandler>` as a request from your
+//! `client::Client`.
+//!
+//! Extensions (i.e. compression) are not supported yet.
+//!
+pub use self::frame::Opcode;
+pub use self::error::{ProtocolError, HandshakeError, ClientHandshakeError};
+pub use self::handshake::{Handshake, accept_key};
+pub use self::reader::Message;
+pub use self::writer::Writer;
+pub use self::connection::Handler;
+pub use self::server::{WebsocketHandler, WebsocketServer};
+pub use self::client::{ClientHandler, WebsocketRequester};
+
+mod frame;
+mod error;
+mod handshake;
+mod reader;
+mod writer;
+mod connection;
+mod server;
+mod client;
@@ -0,0 +1,248 @@
+use std::str::from_utf8;
+
+use rotor_stream::Buf;
+
+use super::error::ProtocolError;
+use super::frame::{Opcode, header_size, parse_header, apply_mask};
+
+
+/// A complete (defragmented) websocket message
+#[derive(Debug, PartialEq, Eq)]
+pub enum Message<'a> {
+    /// Text message, utf-8 is already validated
+    Text(&'a str),
+    Binary(&'a [u8]),
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum Event<'a> {
+    Message(Message<'a>),
+    Ping(&'a [u8]),
+    Pong(&'a [u8]),
+    /// Close frame (code, reason)
+    ///
+    /// Code is 1005 if there was no code in the frame
+    Close(u16, &'a str),
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum Status<'a> {
+    Complete(Event<'a>),
+    /// Number of bytes needed in the input buffer to continue
+    Partial(usize),
+}
+
+/// Assembles messages out of frames in the input buffer
+///
+/// Frame payloads are moved out of input buffer as soon as the frame is
+/// received completely, so the input buffer never contains more than one
+/// frame (plus whatever is received after it).
+#[derive(Debug)]
+pub struct Reader {
+    message: Vec<u8>,
+    control: Vec<u8>,
+    /// Opcode of the first frame of the message being assembled
+    opcode: Option<Opcode>,
+    /// Message in the buffer is already delivered
+    complete: bool,
+    /// True for server side: client frames must be masked
+    masked: bool,
+    limit: usize,
+}
+
+/// Returns true if the code may be sent in a close frame
+pub fn is_valid_close_code(code: u16) -> bool {
+    match code {
+        1000...1003 | 1007...1014 | 3000...4999 => true,
+        _ => false,
+    }
+}
+
+impl Reader {
+    /// Creates a reader
+    ///
+    /// The `masked` is true for server side (frames must be masked) and
+    /// false for the client side (frames must not be masked). The `limit` is
+    /// the maximum size of the message
+    pub fn new(masked: bool, limit: usize) -> Reader {
+        Reader {
+            message: Vec::new(),
+            control: Vec::with_capacity(125),
+            opcode: None,
+            complete: false,
+            masked: masked,
+            limit: limit,
+        }
+    }
+    /// Reads frames from the input buffer until an event is encountered
+    ///
+    /// Consumes all the frames which are read from the input buffer.
+    pub fn read<'x>(&'x mut self, input: &mut Buf)
+        -> Result<Status<'x>, ProtocolError>
+    {
+        use self::Status::*;
+        use super::frame::Opcode::*;
+        use super::error::ProtocolError::*;
+        if self.complete {
+            self.message.clear();
+            self.opcode = None;
+            self.complete = false;
+        }
+        loop {
+            let hsize = match header_size(&input[..]) {
+                Some(x) if x <= input.len() => x,
+                Some(x) => return Ok(Partial(x)),
+                None => return Ok(Partial(2)),
+            };
+            let hdr = try!(parse_header(&input[..hsize]));
+            match (self.masked, hdr.mask.is_some()) {
+                (true, false) => return Err(UnmaskedFrame),
+                (false, true) => return Err(MaskedFrame),
+                _ => {}
+            }
+            match (hdr.opcode, self.opcode) {
+                (Continuation, None) => return Err(UnexpectedContinuation),
+                (Text, Some(_)) | (Binary, Some(_)) => {
+                    return Err(ExpectedContinuation);
+                }
+                _ => {}
+            }
+            if !hdr.opcode.is_control() {
+                let size = self.message.len() as u64 + hdr.payload_size;
+                if size > self.limit as u64 {
+                    return Err(MessageTooLarge(size, self.limit));
+                }
+            }
+            let fsize = hsize + hdr.payload_size as usize;
+            if input.len() < fsize {
+                return Ok(Partial(fsize));
+            }
+            {
+                let target = if hdr.opcode.is_control() {
+                    self.control.clear();
+                    &mut self.control
+                } else {
+                    if hdr.opcode != Continuation {
+                        self.opcode = Some(hdr.opcode);
+                    }
+                    &mut self.message
+                };
+                let start = target.len();
+                target.extend_from_slice(&input[hsize..fsize]);
+                if let Some(mask) = hdr.mask {
+                    apply_mask(&mut target[start..], mask, 0);
+                }
+            }
+            input.consume(fsize);
+            match hdr.opcode {
+                Ping => return Ok(Complete(Event::Ping(&self.control))),
+                Pong => return Ok(Complete(Event::Pong(&self.control))),
+                Close => {
+                    let data = &self.control[..];
+                    if data.len() == 0 {
+                        return Ok(Complete(Event::Close(1005, "")));
+                    } else if data.len() == 1 {
+                        return Err(BadCloseFrame);
+                    }
+                    let code = ((data[0] as u16) << 8) | data[1] as u16;
+                    if !is_valid_close_code(code) {
+                        return Err(BadCloseCode(code));
+                    }
+                    let reason = try!(from_utf8(&data[2..]));
+                    return Ok(Complete(Event::Close(code, reason)));
+                }
+                _ if hdr.fin => {
+                    self.complete = true;
+                    let msg = match self.opcode {
+                        Some(Text) => {
+                            Message::Text(try!(from_utf8(&self.message)))
+                        }
+                        Some(Binary) => Message::Binary(&self.message),
+                        _ => unreachable!(),
+                    };
+                    return Ok(Complete(Event::Message(msg)));
+                }
+                _ => continue,
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use rotor_stream::Buf;
+    use websocket::error::ProtocolError;
+    use super::{Reader, Status, Event, Message};
+
+    fn buf(data: &[u8]) -> Buf {
+        let mut buf = Buf::new();
+        buf.extend(data);
+        buf
+    }
+
+    #[test]
+    fn single_frame() {
+        let mut inp = buf(b"\x81\x85\x37\xfa\x21\x3d\x7f\x9f\x4d\x51\x58");
+        let mut reader = Reader::new(true, 100);
+        assert_eq!(reader.read(&mut inp).unwrap(),
+            Status::Complete(Event::Message(Message::Text("Hello"))));
+        assert_eq!(inp.len(), 0);
+        assert_eq!(reader.read(&mut inp).unwrap(), Status::Partial(2));
+    }
+
+    #[test]
+    fn partial() {
+        let mut inp = buf(b"\x81\x05\x48\x65");
+        let mut reader = Reader::new(false, 100);
+        assert_eq!(reader.read(&mut inp).unwrap(), Status::Partial(7));
+        inp.extend(b"\x6c\x6c\x6f");
+        assert_eq!(reader.read(&mut inp).unwrap(),
+            Status::Complete(Event::Message(Message::Text("Hello"))));
+    }
+
+    #[test]
+    fn fragmented_with_ping() {
+        let mut inp = buf(b"\x01\x03\x48\x65\x6c\
+                            \x89\x02hi\
+                            \x80\x02\x6c\x6f\
+                            \x82\x01\x00");
+        let mut reader = Reader::new(false, 100);
+        assert_eq!(reader.read(&mut inp).unwrap(),
+            Status::Complete(Event::Ping(b"hi")));
+        assert_eq!(reader.read(&mut inp).unwrap(),
+            Status::Complete(Event::Message(Message::Text("Hello"))));
+        assert_eq!(reader.read(&mut inp).unwrap(),
+            Status::Complete(Event::Message(Message::Binary(b"\x00"))));
+    }
+
+    #[test]
+    fn close() {
+        let mut inp = buf(b"\x88\x00\x88\x04\x03\xe8ok\x88\x02\x03\xec");
+        let mut reader = Reader::new(false, 100);
+        assert_eq!(reader.read(&mut inp).unwrap(),
+            Status::Complete(Event::Close(1005, "")));
+        assert_eq!(reader.read(&mut inp).unwrap(),
+            Status::Complete(Event::Close(1000, "ok")));
+        assert!(matches!(reader.read(&mut inp),
+            Err(ProtocolError::BadCloseCode(1004))));
+    }
+
+    #[test]
+    fn errors() {
+        let mut reader = Reader::new(true, 100);
+        assert!(matches!(reader.read(&mut buf(b"\x81\x00")),
+            Err(ProtocolError::UnmaskedFrame)));
+        let mut reader = Reader::new(false, 100);
+        assert!(matches!(reader.read(&mut buf(b"\x80\x00")),
+            Err(ProtocolError::UnexpectedContinuation)));
+        let mut reader = Reader::new(false, 100);
+        assert!(matches!(reader.read(&mut buf(b"\x01\x00\x01\x00")),
+            Err(ProtocolError::ExpectedContinuation)));
+        let mut reader = Reader::new(false, 4);
+        assert!(matches!(reader.read(&mut buf(b"\x82\x05")),
+            Err(ProtocolError::MessageTooLarge(5, 4))));
+        let mut reader = Reader::new(false, 100);
+        assert!(matches!(reader.read(&mut buf(b"\x81\x02\xc3\x28")),
+            Err(ProtocolError::BadUtf8(_))));
+    }
+}
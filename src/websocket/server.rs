@@ -0,0 +1,342 @@
+use std::time::Duration;
+
+use rotor::{Scope, Time};
+use rotor_stream::{Exception, Expectation, StreamSocket, Transport};
+
+use server::{Server, Head, Response, RecvMode, HttpError};
+use super::handshake::{Handshake, parse_request, accept_key};
+use super::connection::{Handler, Connection};
+
+
+/// A time to wait for the request body of rejected request
+const REJECT_TIMEOUT: u64 = 10;
+
+/// A handler of server-side websocket connection
+///
+/// Only the handshake is server-specific, the rest of the connection is
+/// handled by the `Handler` trait.
+pub trait WebsocketHandler: Handler {
+    /// Each connection gets a clone of a Seed in `accept()` handler
+    type Seed: Clone;
+
+    /// Called when valid websocket handshake is received
+    ///
+    /// Returns new handler and (optionally) one of the
+    /// `handshake.protocols` that is chosen as a subprotocol. If `None` is
+    /// returned the client receives `403 Forbidden`.
+    fn accept(seed: Self::Seed, head: &Head, handshake: &Handshake,
+        scope: &mut Scope<Self::Context>)
+        -> Option<(Self, Option<&'static str>)>;
+}
+
+/// A `server::Server` implementation which serves websockets
+///
+/// All plain HTTP requests are answered with an error, use it as the
+/// server state machine directly (`server::Fsm<WebsocketServer<H>, _>`),
+/// or wrap it into your own `Server` to mix websockets with ordinary
+/// requests.
+#[derive(Debug)]
+pub enum WebsocketServer<H: WebsocketHandler> {
+    /// Request is rejected, error response is already written
+    Rejected,
+    Active(Connection<H>),
+}
+
+fn write_error(err: &HttpError, response: &mut Response) {
+    let (status, reason) = err.http_status();
+    response.status(status, reason);
+    if status == 426 {
+        response.add_header("Upgrade", b"websocket").unwrap();
+        response.add_header("Sec-WebSocket-Version", b"13").unwrap();
+    }
+    let data = format!("<h1>{} {}</h1>\n\
+        <p><small>Served for you by rotor-http</small></p>\n",
+        status, reason);
+    let bytes = data.as_bytes();
+    response.add_length(bytes.len() as u64).unwrap();
+    response.add_header("Content-Type", b"text/html").unwrap();
+    response.done_headers().unwrap();
+    response.write_body(bytes);
+    response.done();
+}
+
+fn active<H: WebsocketHandler>(res: Option<(Connection<H>, Expectation, Time)>)
+    -> Option<(WebsocketServer<H>, Expectation, Time)>
+{
+    res.map(|(conn, exp, dline)| (WebsocketServer::Active(conn), exp, dline))
+}
+
+impl<H: WebsocketHandler> Server for WebsocketServer<H> {
+    type Seed = <H as WebsocketHandler>::Seed;
+    type Context = H::Context;
+    fn headers_received(seed: Self::Seed, head: Head, response: &mut Response,
+        scope: &mut Scope<H::Context>)
+        -> Option<(Self, RecvMode, Time)>
+    {
+        let deadline = scope.now() + Duration::new(REJECT_TIMEOUT, 0);
+        let handshake = match parse_request(&head) {
+            Ok(handshake) => handshake,
+            Err(e) => {
+                write_error(&e, response);
+                // Read and ignore the request body if any
+                return Some((WebsocketServer::Rejected,
+                             RecvMode::Progressive(4096), deadline));
+            }
+        };
+        match H::accept(seed, &head, &handshake, scope) {
+            Some((handler, protocol)) => {
+                response.status(101, "Switching Protocols");
+                response.add_header("Upgrade", b"websocket").unwrap();
+                response.add_header("Connection", b"Upgrade").unwrap();
+                response.add_header("Sec-WebSocket-Accept",
+                    accept_key(handshake.key.as_bytes()).as_bytes()).unwrap();
+                if let Some(protocol) = protocol {
+                    response.add_header("Sec-WebSocket-Protocol",
+                        protocol.as_bytes()).unwrap();
+                }
+                response.done_headers().unwrap();
+                let conn = Connection::new(handler, false, scope);
+                Some((WebsocketServer::Active(conn),
+                      RecvMode::Buffered(0), deadline))
+            }
+            None => {
+                write_error(&super::HandshakeError::Rejected, response);
+                Some((WebsocketServer::Rejected,
+                      RecvMode::Progressive(4096), deadline))
+            }
+        }
+    }
+    fn request_received(self, _data: &[u8], _response: &mut Response,
+        _scope: &mut Scope<H::Context>)
+        -> Option<Self>
+    {
+        None
+    }
+    fn request_chunk(self, _chunk: &[u8], _response: &mut Response,
+        _scope: &mut Scope<H::Context>)
+        -> Option<Self>
+    {
+        Some(self)
+    }
+    fn request_end(self, _response: &mut Response,
+        _scope: &mut Scope<H::Context>)
+        -> Option<Self>
+    {
+        None
+    }
+    fn timeout(self, _response: &mut Response,
+        _scope: &mut Scope<H::Context>)
+        -> Option<(Self, Time)>
+    {
+        None
+    }
+    fn wakeup(self, _response: &mut Response,
+        _scope: &mut Scope<H::Context>)
+        -> Option<Self>
+    {
+        Some(self)
+    }
+    fn upgrade_started<S: StreamSocket>(self, transport: &mut Transport<S>,
+        scope: &mut Scope<H::Context>)
+        -> Option<(Self, Expectation, Time)>
+    {
+        match self {
+            WebsocketServer::Active(conn) => {
+                active(conn.started(transport, scope))
+            }
+            WebsocketServer::Rejected => None,
+        }
+    }
+    fn upgrade_bytes_read<S: StreamSocket>(self,
+        transport: &mut Transport<S>, _end: usize,
+        scope: &mut Scope<H::Context>)
+        -> Option<(Self, Expectation, Time)>
+    {
+        match self {
+            WebsocketServer::Active(conn) => {
+                active(conn.bytes_read(transport, scope))
+            }
+            WebsocketServer::Rejected => None,
+        }
+    }
+    fn upgrade_bytes_flushed<S: StreamSocket>(self,
+        _transport: &mut Transport<S>, _scope: &mut Scope<H::Context>)
+        -> Option<(Self, Expectation, Time)>
+    {
+        match self {
+            WebsocketServer::Active(conn) => active(conn.bytes_flushed()),
+            WebsocketServer::Rejected => None,
+        }
+    }
+    fn upgrade_timeout<S: StreamSocket>(self, transport: &mut Transport<S>,
+        scope: &mut Scope<H::Context>)
+        -> Option<(Self, Expectation, Time)>
+    {
+        match self {
+            WebsocketServer::Active(conn) => {
+                active(conn.timeout(transport, scope))
+            }
+            WebsocketServer::Rejected => None,
+        }
+    }
+    fn upgrade_wakeup<S: StreamSocket>(self, transport: &mut Transport<S>,
+        scope: &mut Scope<H::Context>)
+        -> Option<(Self, Expectation, Time)>
+    {
+        match self {
+            WebsocketServer::Active(conn) => {
+                active(conn.wakeup(transport, scope))
+            }
+            WebsocketServer::Rejected => None,
+        }
+    }
+    fn upgrade_end<S: StreamSocket>(self, _transport: &mut Transport<S>,
+        _reason: Exception, scope: &mut Scope<H::Context>)
+    {
+        if let WebsocketServer::Active(conn) = self {
+            conn.end(scope);
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::time::Duration;
+    use rotor::{Scope, EventSet, Machine};
+    use rotor_test::{MemIo, MockLoop};
+    use rotor_stream::{Stream, Accepted};
+    use server::{Head, Parser};
+    use websocket::{Handler, WebsocketHandler, WebsocketServer, Handshake};
+    use websocket::{Message, Writer};
+
+    #[derive(Debug, PartialEq, Eq, Default)]
+    struct Context {
+        accepted: usize,
+        messages: Vec<String>,
+        pings: usize,
+        closed: Option<u16>,
+    }
+
+    struct Echo;
+
+    impl WebsocketHandler for Echo {
+        type Seed = ();
+        fn accept((): (), _head: &Head, handshake: &Handshake,
+            scope: &mut Scope<Context>)
+            -> Option<(Self, Option<&'static str>)>
+        {
+            scope.accepted += 1;
+            if handshake.protocols.iter().any(|&x| x == "echo") {
+                Some((Echo, Some("echo")))
+            } else {
+                Some((Echo, None))
+            }
+        }
+    }
+
+    impl Handler for Echo {
+        type Context = Context;
+        fn message_received(self, message: Message, output: &mut Writer,
+            scope: &mut Scope<Context>)
+            -> Option<Self>
+        {
+            match message {
+                Message::Text(text) => {
+                    scope.messages.push(text.to_string());
+                    output.text(text);
+                }
+                Message::Binary(data) => output.binary(data),
+            }
+            Some(Echo)
+        }
+        fn ping(self, _data: &[u8], _output: &mut Writer,
+            scope: &mut Scope<Context>)
+            -> Option<Self>
+        {
+            scope.pings += 1;
+            Some(Echo)
+        }
+        fn close(self, code: u16, _reason: &str, scope: &mut Scope<Context>)
+        {
+            scope.closed = Some(code);
+        }
+        fn timeout(self, _output: &mut Writer, _scope: &mut Scope<Context>)
+            -> Option<Self>
+        {
+            unimplemented!();
+        }
+        fn wakeup(self, _output: &mut Writer, _scope: &mut Scope<Context>)
+            -> Option<Self>
+        {
+            unimplemented!();
+        }
+        fn idle_timeout(&self, _scope: &mut Scope<Context>) -> Duration {
+            Duration::new(10, 0)
+        }
+    }
+
+    type Fsm = Stream<Parser<WebsocketServer<Echo>, MemIo>>;
+
+    #[test]
+    fn echo() {
+        let mut io = MemIo::new();
+        let mut lp = MockLoop::new(Default::default());
+        io.push_bytes("GET /chat HTTP/1.1\r\n\
+                       Host: server.example.com\r\n\
+                       Upgrade: websocket\r\n\
+                       Connection: Upgrade\r\n\
+                       Sec-WebSocket-Key: dGhlIHNhbXBsZSBub25jZQ==\r\n\
+                       Sec-WebSocket-Protocol: chat, echo\r\n\
+                       Sec-WebSocket-Version: 13\r\n\r\n".as_bytes());
+        let m = Fsm::accepted(io.clone(), (), &mut lp.scope(1))
+            .expect_machine();
+        let m = m.ready(EventSet::readable(), &mut lp.scope(1))
+            .expect_machine();
+        assert_eq!(*lp.ctx(), Context {
+            accepted: 1,
+            .. Default::default()
+        });
+        // Masked "Hello" fragmented into two frames with a ping in between
+        io.push_bytes(b"\x01\x83\x37\xfa\x21\x3d\x7f\x9f\x4d\
+                        \x89\x80\x00\x00\x00\x00\
+                        \x80\x82\x37\xfa\x21\x3d\x5b\x95");
+        let m = m.ready(EventSet::readable(), &mut lp.scope(1))
+            .expect_machine();
+        assert_eq!(*lp.ctx(), Context {
+            accepted: 1,
+            messages: vec![String::from("Hello")],
+            pings: 1,
+            closed: None,
+        });
+        io.push_bytes(b"\x88\x82\x00\x00\x00\x00\x03\xe8");
+        let _ = m.ready(EventSet::readable(), &mut lp.scope(1));
+        assert_eq!(*lp.ctx(), Context {
+            accepted: 1,
+            messages: vec![String::from("Hello")],
+            pings: 1,
+            closed: Some(1000),
+        });
+    }
+
+    #[test]
+    fn protocol_error() {
+        let mut io = MemIo::new();
+        let mut lp = MockLoop::new(Default::default());
+        io.push_bytes("GET /chat HTTP/1.1\r\n\
+                       Host: server.example.com\r\n\
+                       Upgrade: websocket\r\n\
+                       Connection: Upgrade\r\n\
+                       Sec-WebSocket-Key: dGhlIHNhbXBsZSBub25jZQ==\r\n\
+                       Sec-WebSocket-Version: 13\r\n\r\n".as_bytes());
+        // Unmasked frame from the client
+        io.push_bytes(b"\x81\x05Hello");
+        let m = Fsm::accepted(io.clone(), (), &mut lp.scope(1))
+            .expect_machine();
+        let _ = m.ready(EventSet::readable(), &mut lp.scope(1));
+        assert_eq!(*lp.ctx(), Context {
+            accepted: 1,
+            closed: Some(1002),
+            .. Default::default()
+        });
+    }
+}
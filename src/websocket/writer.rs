@@ -0,0 +1,164 @@
+use std::io::Write;
+
+use rand::random;
+use rotor_stream::Buf;
+
+use super::frame::{Opcode, write_frame, MAX_CONTROL_PAYLOAD};
+use super::reader::is_valid_close_code;
+
+
+/// Writes websocket frames into the output buffer
+///
+/// Similarly to `server::Response` everything is put into the buffer
+/// immediately, and is sent to the peer at the next loop iteration.
+pub struct Writer<'a> {
+    buf: &'a mut Buf,
+    close_sent: bool,
+    masked: bool,
+}
+
+impl<'a> Writer<'a> {
+    /// Creates a writer on top of output buffer
+    ///
+    /// The `close_sent` is true if close frame is already sent
+    pub fn new(buf: &mut Buf, close_sent: bool) -> Writer {
+        Writer {
+            buf: buf,
+            close_sent: close_sent,
+            masked: false,
+        }
+    }
+    /// Creates a writer for the client side of the connection
+    ///
+    /// Every frame is masked with a new random key
+    pub fn client(buf: &mut Buf, close_sent: bool) -> Writer {
+        Writer {
+            buf: buf,
+            close_sent: close_sent,
+            masked: true,
+        }
+    }
+    /// Send a text message in a single frame
+    ///
+    /// # Panics
+    ///
+    /// When close frame is already sent
+    pub fn text(&mut self, data: &str) {
+        self.frame(true, Opcode::Text, data.as_bytes());
+    }
+    /// Send a binary message in a single frame
+    ///
+    /// # Panics
+    ///
+    /// When close frame is already sent
+    pub fn binary(&mut self, data: &[u8]) {
+        self.frame(true, Opcode::Binary, data);
+    }
+    /// Send a ping
+    ///
+    /// # Panics
+    ///
+    /// When close frame is already sent or data is longer than 125 bytes
+    pub fn ping(&mut self, data: &[u8]) {
+        self.frame(true, Opcode::Ping, data);
+    }
+    /// Send a pong
+    ///
+    /// Note: pongs for received pings are sent automatically.
+    ///
+    /// # Panics
+    ///
+    /// When close frame is already sent or data is longer than 125 bytes
+    pub fn pong(&mut self, data: &[u8]) {
+        self.frame(true, Opcode::Pong, data);
+    }
+    /// Start closing handshake
+    ///
+    /// Connection is closed when the peer replies with close frame. Messages
+    /// received in the meantime are discarded.
+    ///
+    /// The method may be called multiple times, only the first call sends
+    /// the close frame.
+    ///
+    /// # Panics
+    ///
+    /// When code is not allowed to be sent or reason is longer than
+    /// 123 bytes
+    pub fn close(&mut self, code: u16, reason: &str) {
+        assert!(is_valid_close_code(code),
+            "Close code {} is not allowed to be sent", code);
+        if self.close_sent {
+            return;
+        }
+        let mut data = [0u8; MAX_CONTROL_PAYLOAD];
+        let len = {
+            let mut cur = &mut data[..];
+            cur.write_all(&[(code >> 8) as u8, code as u8]).unwrap();
+            cur.write_all(reason.as_bytes())
+                .expect("close reason is longer than 123 bytes");
+            MAX_CONTROL_PAYLOAD - cur.len()
+        };
+        self.frame(true, Opcode::Close, &data[..len]);
+        self.close_sent = true;
+    }
+    /// Send a raw frame
+    ///
+    /// This is useful for sending fragmented messages: first frame has
+    /// `Text` or `Binary` opcode and `fin == false`, then zero or more
+    /// `Continuation` frames follow, and the last frame has `fin == true`.
+    ///
+    /// # Panics
+    ///
+    /// When close frame is already sent or control frame is invalid
+    pub fn frame(&mut self, fin: bool, opcode: Opcode, data: &[u8]) {
+        assert!(!self.close_sent,
+            "Can't send {:?} frame after close frame", opcode);
+        if opcode.is_control() {
+            assert!(fin, "Control frame can't be fragmented");
+            assert!(data.len() <= MAX_CONTROL_PAYLOAD,
+                "Control frame payload is longer than 125 bytes");
+        }
+        let mask = if self.masked { Some(random()) } else { None };
+        write_frame(self.buf, fin, opcode, mask, data);
+    }
+    /// Returns true if close frame is already sent
+    pub fn is_closed(&self) -> bool {
+        self.close_sent
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use rotor_stream::Buf;
+    use websocket::reader::{Reader, Status, Event, Message};
+    use super::Writer;
+
+    #[test]
+    fn close() {
+        let mut buf = Buf::new();
+        {
+            let mut writer = Writer::new(&mut buf, false);
+            writer.close(1000, "ok");
+            writer.close(1001, "twice");
+            assert!(writer.is_closed());
+        }
+        assert_eq!(&buf[..], b"\x88\x04\x03\xe8ok");
+    }
+
+    #[test]
+    fn masked() {
+        let mut buf = Buf::new();
+        Writer::client(&mut buf, false).text("Hello");
+        let mut reader = Reader::new(true, 100);
+        assert_eq!(reader.read(&mut buf).unwrap(),
+            Status::Complete(Event::Message(Message::Text("Hello"))));
+    }
+
+    #[test]
+    #[should_panic]
+    fn message_after_close() {
+        let mut buf = Buf::new();
+        let mut writer = Writer::new(&mut buf, true);
+        writer.text("hello");
+    }
+}